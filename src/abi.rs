@@ -0,0 +1,90 @@
+use crate::types::Type;
+
+// How values cross function boundaries. Backends (and builtins written
+// in the target language) take their calling convention from here
+// rather than deciding it per type themselves, so that code compiled by
+// different backends, or written by hand against them, agrees on it.
+//
+// Every type the language has today fits in a register: numbers and
+// bools are passed as themselves, strings as a pointer to their UTF-8
+// bytes followed by a NUL, and functions as a code pointer. Types with
+// no values aren't passed at all.
+
+// A value held in a single register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    I32,
+    I64,
+    U32,
+    U64,
+    F32,
+    F64,
+    // One byte, 0 or 1
+    Bool,
+    // 64 bit address
+    Ptr,
+}
+
+impl Scalar {
+    pub fn size(&self) -> u64 {
+        match self {
+            Scalar::Bool => 1,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::I64 | Scalar::U64 | Scalar::F64 | Scalar::Ptr => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassMode {
+    Direct(Scalar),
+    // Borrowed by the callee, which mustn't write through it or keep it
+    // past the call
+    Pointer,
+    // Nothing is passed, as for the result of a function returning Nil
+    Ignore,
+}
+
+impl PassMode {
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            PassMode::Direct(scalar) => Some(scalar.size()),
+            PassMode::Pointer => Some(Scalar::Ptr.size()),
+            PassMode::Ignore => None,
+        }
+    }
+}
+
+// Returns None for types that only exist during type checking
+pub fn classify(type_t: &Type) -> Option<PassMode> {
+    Some(match type_t {
+        Type::Int32 => PassMode::Direct(Scalar::I32),
+        Type::Int64 => PassMode::Direct(Scalar::I64),
+        Type::UInt32 => PassMode::Direct(Scalar::U32),
+        Type::UInt64 => PassMode::Direct(Scalar::U64),
+        Type::Float32 => PassMode::Direct(Scalar::F32),
+        Type::Float64 => PassMode::Direct(Scalar::F64),
+        Type::Bool => PassMode::Direct(Scalar::Bool),
+        Type::String => PassMode::Pointer,
+        Type::Function(_) => PassMode::Direct(Scalar::Ptr),
+        Type::Nil | Type::Never => PassMode::Ignore,
+        // Arrays are only ever held by variables, never passed
+        Type::Array(..) | Type::Program | Type::Unknown | Type::TypeVar(_) => return None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnAbi {
+    pub params: Vec<PassMode>,
+    pub ret: PassMode,
+    // The function never returns to its caller
+    pub noreturn: bool,
+}
+
+pub fn fn_abi(params_t: &[Type], return_t: &Type) -> Option<FnAbi> {
+    Some(FnAbi {
+        params: params_t.iter().map(classify).collect::<Option<_>>()?,
+        ret: classify(return_t)?,
+        noreturn: *return_t == Type::Never,
+    })
+}
@@ -0,0 +1,193 @@
+use crate::ast::{
+    AssignOp, Expr, Func, IfCase, Num, Param, Program, Root, Span, Stmt, Term, TypedExpr, TypedTerm,
+};
+use crate::symbol::{Symbol, Var};
+use crate::types::Type;
+use proptest::prelude::*;
+
+// Strategies generating ASTs, as the parser would produce them, for
+// property tests of the passes that follow it. Only a subset of the
+// language is generated, but everything generated is well-typed: numbers
+// are int32, the names are those of the variables and function the
+// program starts with, and conditions are bools.
+
+const INTS: [&str; 2] = ["a", "b"];
+const BOOLS: [&str; 1] = ["p"];
+
+fn expr(expr: Expr) -> Box<TypedExpr> {
+    Box::new(TypedExpr {
+        type_t: Type::Unknown,
+        expr,
+        span: Span::default(),
+    })
+}
+
+fn term(term: Term) -> Box<TypedExpr> {
+    expr(Expr::Term(Box::new(TypedTerm {
+        type_t: Type::Unknown,
+        term,
+    })))
+}
+
+fn int(n: i32) -> Box<TypedExpr> {
+    term(Term::Num(Num::Int32(n), false))
+}
+
+fn var() -> Box<Var> {
+    Box::new(Var {
+        type_t: Type::Unknown,
+        node: crate::ast::Node::Null,
+    })
+}
+
+fn symbol(ident: &str) -> Symbol {
+    Symbol {
+        ident: ident.into(),
+    }
+}
+
+fn let_stmt(ident: &str, value: Box<TypedExpr>) -> Box<Stmt> {
+    Box::new(Stmt::Assign(
+        symbol(ident),
+        var(),
+        value,
+        vec![],
+        Span::default(),
+        Span::default(),
+    ))
+}
+
+// int32 expressions, calling `sub(int32, int32) -> int32`
+pub fn int_expr() -> impl Strategy<Value = Box<TypedExpr>> {
+    let leaf = prop_oneof![
+        any::<i32>().prop_map(int),
+        prop::sample::select(&INTS[..]).prop_map(|id| term(Term::Id(id.into()))),
+    ];
+    leaf.prop_recursive(3, 8, 2, |inner| {
+        let pair = || (inner.clone(), inner.clone());
+        prop_oneof![
+            pair().prop_map(|(l, r)| expr(Expr::Add(l, r))),
+            pair().prop_map(|(l, r)| expr(Expr::Sub(l, r))),
+            pair().prop_map(|(l, r)| expr(Expr::Mult(l, r))),
+            pair().prop_map(|(l, r)| expr(Expr::Div(l, r))),
+            pair().prop_map(|(l, r)| expr(Expr::Mod(l, r))),
+            pair().prop_map(|(l, r)| expr(Expr::BitAnd(l, r))),
+            pair().prop_map(|(l, r)| expr(Expr::BitOr(l, r))),
+            pair().prop_map(|(l, r)| expr(Expr::BitXor(l, r))),
+            pair().prop_map(|(l, r)| expr(Expr::Call(symbol("sub"), vec![l, r]))),
+            inner.clone().prop_map(|e| term(Term::Expr(e))),
+        ]
+    })
+}
+
+pub fn bool_expr() -> impl Strategy<Value = Box<TypedExpr>> {
+    let comparison = (int_expr(), int_expr(), 0..6).prop_map(|(l, r, op)| {
+        expr(match op {
+            0 => Expr::Eq(l, r),
+            1 => Expr::Neq(l, r),
+            2 => Expr::Leq(l, r),
+            3 => Expr::Geq(l, r),
+            4 => Expr::LessThan(l, r),
+            _ => Expr::GreaterThan(l, r),
+        })
+    });
+    let leaf = prop_oneof![
+        any::<bool>().prop_map(|b| term(Term::Bool(b))),
+        prop::sample::select(&BOOLS[..]).prop_map(|id| term(Term::Id(id.into()))),
+        comparison,
+    ];
+    leaf.prop_recursive(2, 4, 2, |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone()).prop_map(|(l, r)| expr(Expr::And(l, r))),
+            (inner.clone(), inner.clone()).prop_map(|(l, r)| expr(Expr::Or(l, r))),
+            inner.prop_map(|e| expr(Expr::Not(e))),
+        ]
+    })
+}
+
+// Assignments to the variables, returns, and ifs and loops around them
+pub fn stmt() -> impl Strategy<Value = Box<Stmt>> {
+    let reassign = |ident: &'static str, value: Box<TypedExpr>| {
+        Box::new(Stmt::Reassign(
+            symbol(ident),
+            var(),
+            AssignOp::Assign,
+            value,
+            Span::default(),
+        ))
+    };
+    let leaf = prop_oneof![
+        (prop::sample::select(&INTS[..]), int_expr()).prop_map(move |(id, e)| reassign(id, e)),
+        (prop::sample::select(&BOOLS[..]), bool_expr()).prop_map(move |(id, e)| reassign(id, e)),
+        int_expr().prop_map(|e| Box::new(Stmt::Return(e))),
+    ];
+    leaf.prop_recursive(2, 8, 3, |inner| {
+        let block = || prop::collection::vec(inner.clone(), 0..3);
+        prop_oneof![
+            (bool_expr(), block(), prop::option::of(block())).prop_map(
+                |(condition, block, otherwise)| {
+                    let mut cases = vec![Box::new(IfCase {
+                        condition,
+                        block,
+                        is_else: false,
+                    })];
+                    if let Some(block) = otherwise {
+                        cases.push(Box::new(IfCase {
+                            condition: term(Term::Bool(true)),
+                            block,
+                            is_else: true,
+                        }));
+                    }
+                    Box::new(Stmt::If(cases))
+                }
+            ),
+            (block(), bool_expr())
+                .prop_map(|(block, until)| Box::new(Stmt::Repeat(block, until, None))),
+            (bool_expr(), block())
+                .prop_map(|(condition, block)| Box::new(Stmt::While(condition, block, None))),
+        ]
+    })
+}
+
+// A whole program: the variables and `sub`, then the generated statements
+// and a final return
+pub fn root() -> impl Strategy<Value = Box<Root>> {
+    (prop::collection::vec(stmt(), 0..6), int_expr()).prop_map(|(mut stmts, ret)| {
+        let param = |ident: &str| {
+            Box::new(Param {
+                type_t: Type::Int32,
+                ident: ident.into(),
+                span: Span::default(),
+            })
+        };
+        let sub = Func {
+            return_t: Type::Int32,
+            params: vec![param("x"), param("y")],
+            ident: "sub".into(),
+            block: vec![Box::new(Stmt::Return(expr(Expr::Sub(
+                term(Term::Id("x".into())),
+                term(Term::Id("y".into())),
+            ))))],
+            docs: vec![],
+            attrs: vec![],
+            span: Span::default(),
+        };
+        let mut block = vec![
+            let_stmt("a", int(1)),
+            let_stmt("b", int(2)),
+            let_stmt("p", term(Term::Bool(false))),
+        ];
+        block.append(&mut stmts);
+        block.push(Box::new(Stmt::Return(ret)));
+        Box::new(Root {
+            docs: vec![],
+            preblock: vec![Box::new(Stmt::FuncDef(sub))],
+            program: Some(Box::new(Program(
+                symbol("generated"),
+                block,
+                Span::default(),
+            ))),
+            postblock: vec![],
+        })
+    })
+}
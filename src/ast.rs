@@ -1,4 +1,5 @@
 use crate::ir;
+use crate::parser::{ParseError, SyntaxError, IDENT_LIMIT};
 use crate::symbol::{Symbol, Var};
 use crate::types::Type;
 use serde::{Deserialize, Serialize};
@@ -12,6 +13,9 @@ pub enum ASTError {
 
 pub type Block = Vec<Box<Stmt>>;
 
+// Lines of `///` (or `//!`) comments with the comment marker removed
+pub type Docs = Vec<String>;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Node {
     RootNode(Box<Root>),
@@ -32,18 +36,58 @@ pub enum Node {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Root {
+    pub docs: Docs,
     pub preblock: Block,
-    pub program: Box<Program>,
+    // `None` for units that are only linked into other programs
+    pub program: Option<Box<Program>>,
     pub postblock: Block,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Program(pub Symbol, pub Block);
+// The span is that of the program's name
+pub struct Program(pub Symbol, pub Block, pub Span);
+
+// Byte offsets into the source file, `start` inclusive and `end` exclusive
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TypedExpr {
     pub type_t: Type,
     pub expr: Expr,
+    pub span: Span,
+}
+
+// Used by the grammar to build an untyped expression spanning `start..end`
+pub fn new_expr(start: usize, expr: Expr, end: usize) -> Box<TypedExpr> {
+    Box::new(TypedExpr {
+        type_t: Type::Unknown,
+        expr,
+        span: Span::new(start, end),
+    })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,17 +97,87 @@ pub enum Expr {
     Sub(Box<TypedExpr>, Box<TypedExpr>),
     Mult(Box<TypedExpr>, Box<TypedExpr>),
     Div(Box<TypedExpr>, Box<TypedExpr>),
+    // The remainder of integer division, with the sign of the dividend
+    Mod(Box<TypedExpr>, Box<TypedExpr>),
+    // `a ** b`, a raised to the power b
+    Pow(Box<TypedExpr>, Box<TypedExpr>),
+    // Bitwise, on integers only
+    BitAnd(Box<TypedExpr>, Box<TypedExpr>),
+    BitOr(Box<TypedExpr>, Box<TypedExpr>),
+    BitXor(Box<TypedExpr>, Box<TypedExpr>),
+    Shl(Box<TypedExpr>, Box<TypedExpr>),
+    Shr(Box<TypedExpr>, Box<TypedExpr>),
     Eq(Box<TypedExpr>, Box<TypedExpr>),
     Neq(Box<TypedExpr>, Box<TypedExpr>),
     Leq(Box<TypedExpr>, Box<TypedExpr>),
     Geq(Box<TypedExpr>, Box<TypedExpr>),
     LessThan(Box<TypedExpr>, Box<TypedExpr>),
     GreaterThan(Box<TypedExpr>, Box<TypedExpr>),
+    // Short-circuiting, the right operand is only evaluated when the left
+    // one doesn't decide the result
+    And(Box<TypedExpr>, Box<TypedExpr>),
+    Or(Box<TypedExpr>, Box<TypedExpr>),
     // Unary Operators
     Not(Box<TypedExpr>),
     Neg(Box<TypedExpr>),
+    BitNot(Box<TypedExpr>),
     Call(Symbol, Args),
+    // `xs[i]`, an element of the array named. The span is that of the
+    // name.
+    Index(Symbol, Span, Box<TypedExpr>),
     LambdaFunc(LambdaFunc),
+    // Introspection, folded to constants during semantic analysis.
+    // The operand of type_name is checked but never evaluated.
+    SizeOf(Type),
+    TypeName(Box<TypedExpr>),
+    // `int32(x)`, the number converted to a numeric type, see
+    // Value::convert
+    Convert(Type, Box<TypedExpr>),
+}
+
+impl Expr {
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Expr::Eq(_, _)
+                | Expr::Neq(_, _)
+                | Expr::Leq(_, _)
+                | Expr::Geq(_, _)
+                | Expr::LessThan(_, _)
+                | Expr::GreaterThan(_, _)
+        )
+    }
+}
+
+// Used by the grammar to check the length of identifiers
+pub fn new_ident(lo: usize, ident: String, hi: usize) -> Result<String, ParseError> {
+    if ident.chars().count() > IDENT_LIMIT {
+        return Err(ParseError::User {
+            error: SyntaxError::IdentifierTooLong(lo, hi, IDENT_LIMIT),
+        });
+    }
+    Ok(ident)
+}
+
+// Used by the grammar to build comparisons, which don't chain:
+// `a < b < c` is an error rather than `(a < b) < c`
+pub fn new_comparison(
+    lhs: Box<TypedExpr>,
+    op_loc: usize,
+    rhs: Box<TypedExpr>,
+    op: fn(Box<TypedExpr>, Box<TypedExpr>) -> Expr,
+) -> Result<Box<TypedExpr>, ParseError> {
+    if lhs.expr.is_comparison() {
+        return Err(ParseError::User {
+            error: SyntaxError::ChainedComparison(op_loc),
+        });
+    }
+    let span = Span::new(lhs.span.start, rhs.span.end);
+    Ok(Box::new(TypedExpr {
+        type_t: Type::Unknown,
+        expr: op(lhs, rhs),
+        span,
+    }))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -75,12 +189,83 @@ pub struct TypedTerm {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Term {
     Id(String),
-    Num(Num),
+    // The flag is set when the number was written with a type suffix
+    Num(Num, bool),
     Bool(bool),
     String(String),
     Expr(Box<TypedExpr>),
 }
 
+// Used by the grammar to build `let` statements from the documentation
+// preceding them and the binding itself
+pub fn new_let(
+    docs: Docs,
+    (symbol, type_t, expr, span): (Symbol, Type, Box<TypedExpr>, Span),
+) -> Box<Stmt> {
+    Box::new(Stmt::Assign(
+        symbol,
+        Box::new(Var {
+            type_t,
+            node: Node::Null,
+        }),
+        expr,
+        docs,
+        span,
+        Span::default(),
+    ))
+}
+
+// Used by the grammar to give a `let`, or a `static let`, the span of the
+// whole statement once it's been parsed
+pub fn with_extent(mut stmt: Box<Stmt>, start: usize, end: usize) -> Box<Stmt> {
+    match stmt.as_mut() {
+        Stmt::Assign(.., extent) => *extent = Span::new(start, end),
+        Stmt::Static(inner) => {
+            if let Stmt::Assign(.., extent) = inner.as_mut() {
+                *extent = Span::new(start, end);
+            }
+        }
+        _ => {}
+    }
+    stmt
+}
+
+// Used by the grammar to build array types, whose length is a positive
+// integer literal
+pub fn new_array_type(
+    element_t: Type,
+    (lo, length, hi): (usize, Num, usize),
+) -> Result<Type, ParseError> {
+    match length.integer().map(usize::try_from) {
+        Some(Ok(length)) if length > 0 => Ok(Type::Array(Box::new(element_t), length)),
+        _ => Err(ParseError::User {
+            error: SyntaxError::ArrayLength(lo, hi),
+        }),
+    }
+}
+
+// Used by the grammar to build simultaneous assignments, which need
+// exactly one value per target and distinct targets
+pub fn new_multi_assign(
+    targets: Vec<(Symbol, Span)>,
+    eq_loc: usize,
+    values: Args,
+) -> Result<Box<Stmt>, ParseError> {
+    if targets.len() != values.len() {
+        return Err(ParseError::User {
+            error: SyntaxError::AssignmentArity(eq_loc, targets.len(), values.len()),
+        });
+    }
+    for (n, (symbol, span)) in targets.iter().enumerate() {
+        if targets[..n].iter().any(|(other, _)| other == symbol) {
+            return Err(ParseError::User {
+                error: SyntaxError::DuplicateTarget(span.start, symbol.ident.clone()),
+            });
+        }
+    }
+    Ok(Box::new(Stmt::MultiAssign(targets, values)))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AssignOp {
     Assign,
@@ -90,6 +275,19 @@ pub enum AssignOp {
     DivAssign,
 }
 
+impl AssignOp {
+    // The operator a compound assignment applies, None for a plain one
+    pub fn operator(&self) -> Option<&'static str> {
+        match self {
+            AssignOp::Assign => None,
+            AssignOp::AddAssign => Some("+"),
+            AssignOp::SubAssign => Some("-"),
+            AssignOp::MultAssign => Some("*"),
+            AssignOp::DivAssign => Some("/"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IfCase {
     pub condition: Box<TypedExpr>,
@@ -100,11 +298,66 @@ pub struct IfCase {
 pub type IfCases = Vec<Box<IfCase>>;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Label {
+    pub ident: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchCase {
+    // Integer literals, empty for the `default` case
+    pub labels: Vec<Box<TypedExpr>>,
+    pub block: Block,
+    pub is_default: bool,
+    // Span of the `case` or `default` keyword
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+// The spans on Assign, Reassign and Call are those of the statement's name
 pub enum Stmt {
-    Assign(Symbol, Box<Var>, Box<TypedExpr>),
-    Reassign(Symbol, Box<Var>, AssignOp, Box<TypedExpr>),
+    // Assign has the span of the whole statement too, last, from its doc
+    // comments to its semicolon
+    Assign(Symbol, Box<Var>, Box<TypedExpr>, Docs, Span, Span),
+    Reassign(Symbol, Box<Var>, AssignOp, Box<TypedExpr>, Span),
+    // `a, b = b, a;` assigns every target at once, pairing the names and
+    // values by position. All values are evaluated before any is stored.
+    MultiAssign(Vec<(Symbol, Span)>, Args),
+    // `let xs: array[int32, 10];` declares an array without a value, its
+    // elements all starting out as zero
+    Declare(Symbol, Box<Var>, Docs, Span),
+    // `xs[i] = value;` assigns an element of an array, the span being
+    // that of its name
+    Store(Symbol, Box<TypedExpr>, AssignOp, Box<TypedExpr>, Span),
     If(IfCases),
-    Call(Symbol, Args),
+    // The span is that of the `switch` keyword
+    Switch(Box<TypedExpr>, Vec<SwitchCase>, Span),
+    // `repeat ... until cond;` runs the block at least once, stopping
+    // once the condition holds. The condition can't see the block's names.
+    Repeat(Block, Box<TypedExpr>, Option<Label>),
+    // `while cond do ... end` runs the block for as long as the condition
+    // holds, testing it before each run, so possibly never
+    While(Box<TypedExpr>, Block, Option<Label>),
+    // `bench "label", n do ... end` runs the block n times, once without
+    // the count, then prints the label, the count and how long the runs
+    // took by the clock. The span is that of the `bench` keyword.
+    Bench(String, Option<Box<TypedExpr>>, Block, Span),
+    // `break;` leaves the innermost loop, `break outer;` the loop labeled
+    // `outer:`. The span covers the whole statement.
+    Break(Option<String>, Span),
+    // `defer stmt;` runs the statement when control leaves the enclosing
+    // block, after any statements deferred later in it
+    Defer(Box<Stmt>),
+    // `static let` inside a function: an Assign whose variable keeps its
+    // value between calls, initialized once before the first
+    Static(Box<Stmt>),
+    // `static_assert(cond, "message");` fails compilation with the message
+    // unless the condition is a constant that holds. The span covers the
+    // whole statement.
+    StaticAssert(Box<TypedExpr>, String, Span),
+    // A statement preceded by attributes such as `@allow(deprecated)`
+    Attributed(Vec<Attribute>, Box<Stmt>),
+    Call(Symbol, Args, Span),
     FuncDef(Func),
     Return(Box<TypedExpr>),
 }
@@ -117,6 +370,7 @@ pub type Params = Vec<Box<Param>>;
 pub struct Param {
     pub type_t: Type,
     pub ident: String,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -125,6 +379,36 @@ pub struct Func {
     pub params: Params,
     pub ident: String,
     pub block: Block,
+    pub docs: Docs,
+    pub attrs: Vec<Attribute>,
+    // Span of the function's name
+    pub span: Span,
+}
+
+impl Func {
+    // Marked `@extern`: the function is only declared here and defined
+    // in another unit
+    pub fn is_extern(&self) -> bool {
+        self.attrs.iter().any(|attr| attr.name == "extern")
+    }
+
+    // Marked `@intrinsic`: the function is the C function named by the
+    // attribute's argument, `Some("")` if it has none
+    pub fn intrinsic(&self) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|attr| attr.name == "intrinsic")
+            .map(|attr| attr.args.first().map_or("", String::as_str))
+    }
+}
+
+// `@deprecated("use g")` before a function or `@allow(deprecated)`
+// before a statement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attribute {
+    pub name: String,
+    pub args: Vec<String>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,7 +418,7 @@ pub struct LambdaFunc {
     pub block: Block,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Num {
     Int32(i32),
     Int64(i64),
@@ -144,6 +428,57 @@ pub enum Num {
     Float64(f64),
 }
 
+impl Num {
+    // Converts an unsuffixed literal to `type_t` when that loses no
+    // information, e.g. `1` to float64 but not `1.5` to int32 or `-1`
+    // to uint32. Float literals only convert to float32 when exact.
+    pub fn coerce(&self, type_t: &Type) -> Option<Num> {
+        if let Some(n) = self.integer() {
+            return match type_t {
+                Type::Float32 => {
+                    let f = n as f32;
+                    (f as i128 == n).then_some(Num::Float32(f))
+                }
+                Type::Float64 => {
+                    let f = n as f64;
+                    (f as i128 == n).then_some(Num::Float64(f))
+                }
+                _ => Num::fit(n, type_t),
+            };
+        }
+        match (self, type_t) {
+            (Num::Float64(n), Type::Float64) => Some(Num::Float64(*n)),
+            (Num::Float64(n), Type::Float32) => {
+                let f = *n as f32;
+                (f64::from(f) == *n).then_some(Num::Float32(f))
+            }
+            _ => None,
+        }
+    }
+
+    // The value of an integer literal
+    pub fn integer(&self) -> Option<i128> {
+        match self {
+            Num::Int32(n) => Some((*n).into()),
+            Num::Int64(n) => Some((*n).into()),
+            Num::UInt32(n) => Some((*n).into()),
+            Num::UInt64(n) => Some((*n).into()),
+            Num::Float32(_) | Num::Float64(_) => None,
+        }
+    }
+
+    // An integer literal of `type_t`, if the value is in its range
+    pub fn fit(n: i128, type_t: &Type) -> Option<Num> {
+        match type_t {
+            Type::Int32 => i32::try_from(n).ok().map(Num::Int32),
+            Type::Int64 => i64::try_from(n).ok().map(Num::Int64),
+            Type::UInt32 => u32::try_from(n).ok().map(Num::UInt32),
+            Type::UInt64 => u64::try_from(n).ok().map(Num::UInt64),
+            _ => None,
+        }
+    }
+}
+
 impl TryFrom<Num> for ir::Value {
     type Error = ASTError;
     fn try_from(num: Num) -> Result<Self, Self::Error> {
@@ -173,3 +508,123 @@ impl TryFrom<Num> for Type {
         }
     }
 }
+
+// Programs can nest far deeper than check_nesting allows, and the tree
+// of a rejected one still has to be dropped, as does whatever the parser
+// had built when it gave up. Dropping it recursively would run out of
+// stack, so expressions and statements hand their children to a
+// worklist, and each is dropped only once it has none left.
+enum Child {
+    Expr(Box<TypedExpr>),
+    Stmt(Box<Stmt>),
+}
+
+fn expr_children(expr: &mut Expr, work: &mut Vec<Child>) {
+    let exprs = match std::mem::replace(expr, Expr::SizeOf(Type::Unknown)) {
+        Expr::Term(mut term) => match std::mem::replace(&mut term.term, Term::Bool(false)) {
+            Term::Expr(inner) => vec![inner],
+            _ => vec![],
+        },
+        Expr::Add(l, r)
+        | Expr::Sub(l, r)
+        | Expr::Mult(l, r)
+        | Expr::Div(l, r)
+        | Expr::Mod(l, r)
+        | Expr::Pow(l, r)
+        | Expr::BitAnd(l, r)
+        | Expr::BitOr(l, r)
+        | Expr::BitXor(l, r)
+        | Expr::Shl(l, r)
+        | Expr::Shr(l, r)
+        | Expr::Eq(l, r)
+        | Expr::Neq(l, r)
+        | Expr::Leq(l, r)
+        | Expr::Geq(l, r)
+        | Expr::LessThan(l, r)
+        | Expr::GreaterThan(l, r)
+        | Expr::And(l, r)
+        | Expr::Or(l, r) => vec![l, r],
+        Expr::Not(u) | Expr::Neg(u) | Expr::BitNot(u) | Expr::TypeName(u) => vec![u],
+        Expr::Convert(_, u) => vec![u],
+        Expr::Call(_, args) => args,
+        Expr::Index(_, _, index) => vec![index],
+        Expr::LambdaFunc(lf) => {
+            work.extend(lf.block.into_iter().map(Child::Stmt));
+            vec![]
+        }
+        Expr::SizeOf(_) => vec![],
+    };
+    work.extend(exprs.into_iter().map(Child::Expr));
+}
+
+fn stmt_children(stmt: &mut Stmt, work: &mut Vec<Child>) {
+    use std::mem::take;
+    // A statement can't be moved out of, having a Drop of its own, so its
+    // expressions are emptied where they are and its blocks taken
+    let mut exprs: Vec<&mut Box<TypedExpr>> = vec![];
+    let mut blocks: Vec<Block> = vec![];
+    match stmt {
+        Stmt::Assign(_, _, expr, ..) | Stmt::Reassign(_, _, _, expr, _) => exprs.push(expr),
+        Stmt::Return(expr) | Stmt::StaticAssert(expr, ..) => exprs.push(expr),
+        Stmt::Store(_, index, _, value, _) => exprs.extend([index, value]),
+        Stmt::MultiAssign(_, args) | Stmt::Call(_, args, _) => {
+            work.extend(take(args).into_iter().map(Child::Expr))
+        }
+        Stmt::If(cases) => {
+            for case in cases {
+                exprs.push(&mut case.condition);
+                blocks.push(take(&mut case.block));
+            }
+        }
+        Stmt::Switch(scrutinee, cases, _) => {
+            exprs.push(scrutinee);
+            for case in cases {
+                work.extend(take(&mut case.labels).into_iter().map(Child::Expr));
+                blocks.push(take(&mut case.block));
+            }
+        }
+        Stmt::Repeat(block, condition, _) | Stmt::While(condition, block, _) => {
+            exprs.push(condition);
+            blocks.push(take(block));
+        }
+        Stmt::Bench(_, count, block, _) => {
+            work.extend(count.take().map(Child::Expr));
+            blocks.push(take(block));
+        }
+        Stmt::FuncDef(func) => blocks.push(take(&mut func.block)),
+        Stmt::Defer(inner) | Stmt::Static(inner) | Stmt::Attributed(_, inner) => {
+            let inner = std::mem::replace(inner, Box::new(Stmt::Break(None, Span::default())));
+            work.push(Child::Stmt(inner));
+        }
+        Stmt::Break(..) | Stmt::Declare(..) => {}
+    }
+    for expr in exprs {
+        expr_children(&mut expr.expr, work);
+    }
+    work.extend(blocks.into_iter().flatten().map(Child::Stmt));
+}
+
+fn drop_children(mut work: Vec<Child>) {
+    while let Some(child) = work.pop() {
+        match child {
+            Child::Expr(mut expr) => expr_children(&mut expr.expr, &mut work),
+            Child::Stmt(mut stmt) => stmt_children(&mut stmt, &mut work),
+        }
+    }
+}
+
+impl Drop for TypedExpr {
+    fn drop(&mut self) {
+        let mut work = vec![];
+        expr_children(&mut self.expr, &mut work);
+        drop_children(work);
+    }
+}
+
+impl Drop for Stmt {
+    fn drop(&mut self) {
+        let mut work = vec![];
+        stmt_children(self, &mut work);
+        drop_children(work);
+    }
+}
@@ -1,2 +1,156 @@
+use crate::codegen::CodeGenError;
+use crate::ir::IRNode;
+use crate::types::Type;
+use std::fmt;
+
 pub mod c;
+pub mod interp;
 pub mod wasm;
+
+// What a program can use that not every backend can generate code for.
+// Each backend declares the ones it supports as its FEATURES, and checks
+// the IR against them before generating anything, so that a program it
+// can't handle is rejected up front rather than halfway through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Functions,
+    ExternFunctions,
+    StaticVariables,
+    If,
+    Switch,
+    Repeat,
+    While,
+    Floats,
+    Strings,
+    Arrays,
+    // Statement locations, in IR lowered for debugging
+    DebugInfo,
+    // Compiling a unit to an object to be linked later
+    SeparateCompilation,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 12] = [
+        Feature::Functions,
+        Feature::ExternFunctions,
+        Feature::StaticVariables,
+        Feature::If,
+        Feature::Switch,
+        Feature::Repeat,
+        Feature::While,
+        Feature::Floats,
+        Feature::Strings,
+        Feature::Arrays,
+        Feature::DebugInfo,
+        Feature::SeparateCompilation,
+    ];
+
+    // Whether the node is one that needs the feature. Separate
+    // compilation is asked for by the build, not found in the IR.
+    fn used_by(self, node: &IRNode) -> bool {
+        let float = |type_t: &Type| matches!(type_t, Type::Float32 | Type::Float64);
+        match (self, node) {
+            (Feature::Functions, IRNode::FuncDef(..)) => true,
+            (Feature::ExternFunctions, IRNode::ExternFuncDef(_) | IRNode::Intrinsic(..)) => true,
+            (Feature::StaticVariables, IRNode::StaticAssign(_)) => true,
+            (Feature::If, IRNode::If(_)) => true,
+            (Feature::Switch, IRNode::Switch(_)) => true,
+            (Feature::Repeat, IRNode::Repeat(_)) => true,
+            (Feature::While, IRNode::While(_)) => true,
+            (Feature::Floats, IRNode::Term(term)) => float(&term.type_t),
+            (Feature::Floats, IRNode::Assign(assign) | IRNode::StaticAssign(assign)) => {
+                float(&assign.type_t)
+            }
+            (Feature::Strings, IRNode::Term(term)) => term.type_t == Type::String,
+            (Feature::Arrays, IRNode::Declare(_) | IRNode::Load(_) | IRNode::Store(_)) => true,
+            (Feature::DebugInfo, IRNode::Loc(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Feature::Functions => "functions",
+            Feature::ExternFunctions => "extern functions",
+            Feature::StaticVariables => "static variables",
+            Feature::If => "if",
+            Feature::Switch => "switch",
+            Feature::Repeat => "repeat",
+            Feature::While => "while",
+            Feature::Floats => "floats",
+            Feature::Strings => "strings",
+            Feature::Arrays => "arrays",
+            Feature::DebugInfo => "debug info",
+            Feature::SeparateCompilation => "separate compilation",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Every backend by name, with the features it supports
+pub const BACKENDS: [(&str, &[Feature]); 3] = [
+    ("c", c::FEATURES),
+    ("interp", interp::FEATURES),
+    ("wasm", wasm::FEATURES),
+];
+
+// The features the IR uses, in the order of Feature::ALL
+pub fn features(nodes: &[IRNode]) -> Vec<Feature> {
+    Feature::ALL
+        .into_iter()
+        .filter(|feature| nodes.iter().any(|node| feature.used_by(node)))
+        .collect()
+}
+
+// Rejects the first of the features used that the backend doesn't
+// support
+pub fn check_support(backend: &'static str, used: &[Feature]) -> Result<(), CodeGenError> {
+    let supported = BACKENDS
+        .iter()
+        .find(|(name, _)| *name == backend)
+        .map_or(&[][..], |(_, features)| *features);
+    match used.iter().find(|feature| !supported.contains(feature)) {
+        Some(feature) => Err(CodeGenError::Unsupported {
+            backend,
+            feature: *feature,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::session::Session;
+
+    #[test]
+    fn check_features_per_backend() {
+        let src = "fun f(x: float64) -> float64
+    if x > 1.0 then
+        return x;
+    end
+    return 1.0;
+end
+
+program p
+    let y = f(2.0);
+    return 0;
+end";
+        let nodes = compile::analyze(src, &Session::default())
+            .unwrap()
+            .build_stack;
+        let used = features(&nodes);
+        assert_eq!(used, vec![Feature::Functions, Feature::If, Feature::Floats]);
+        assert!(check_support("c", &used).is_ok());
+        assert!(check_support("interp", &used).is_ok());
+        let err = check_support("wasm", &used).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "feature `functions` not supported by backend `wasm`"
+        );
+        assert!(check_support("interp", &[Feature::SeparateCompilation]).is_err());
+    }
+}
@@ -1,11 +1,20 @@
+use crate::abi::{self, PassMode, Scalar};
+use crate::backends::{self, Feature};
+use crate::builtins::{self, Builtin};
+use crate::cache::{self, Cache};
 use crate::codegen::{CodeGen, CodeGenContext, CodeGenError};
+use crate::diagnostics::line_col;
 use crate::ir::{self, FuncDef, IRNode};
+use crate::symbol::new_symbol;
 use crate::types::{self, Type};
 use anyhow::Result;
+use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
+use std::path::PathBuf;
 use std::process::Command;
+use std::sync::Arc;
 
 macro_rules! matches_variant {
     ($val:expr, $var:path) => {
@@ -22,31 +31,229 @@ pub fn translate_value(value: ir::Value) -> String {
         ir::Value::Int64(num) => format!("INT64_C({})", num),
         ir::Value::UInt32(num) => format!("UINT32_C({})", num),
         ir::Value::UInt64(num) => format!("UINT64_C({})", num),
+        // Debug formatting gives the shortest decimal that reads back as
+        // the same float, with a `.0` or an exponent so C parses it as one
         ir::Value::Float32(num) => {
-            let mut rep = format!("{}", &f32::to_string(&num));
-            if !rep.contains(".") {
-                rep.push_str(".0");
-            }
-            rep.push_str("F");
-            rep
+            special_float(num.to_bits().into(), 23, 8, "f").unwrap_or_else(|| format!("{:?}F", num))
         }
         ir::Value::Float64(num) => {
-            let mut rep = format!("{}", &f64::to_string(&num));
-            if !rep.contains(".") {
-                rep.push_str(".0");
-            }
-            rep
+            special_float(num.to_bits(), 52, 11, "").unwrap_or_else(|| format!("{:?}", num))
         }
-        ir::Value::Bool(b) => {
-            if b {
-                format!("1")
-            } else {
-                format!("0")
+        ir::Value::Bool(b) => b.to_string(),
+        ir::Value::String(s) => format!("\"{}\"", escape_string(&s)),
+        ir::Value::Id(ident) => translate_ident(&ident),
+        other => panic!("No value translation for: {:?}", other),
+    }
+}
+
+// Infinities and NaNs have no literal, gcc's builtins make them instead,
+// with `suffix` choosing the float32 ones. A NaN keeps its sign, payload
+// and whether it's quiet. None for any other float.
+fn special_float(
+    bits: u64,
+    mantissa_bits: u32,
+    exponent_bits: u32,
+    suffix: &str,
+) -> Option<String> {
+    let mantissa = bits & ((1 << mantissa_bits) - 1);
+    let exponent = (bits >> mantissa_bits) & ((1 << exponent_bits) - 1);
+    if exponent != (1 << exponent_bits) - 1 {
+        return None;
+    }
+    let sign = if (bits >> (mantissa_bits + exponent_bits)) & 1 == 1 {
+        "-"
+    } else {
+        ""
+    };
+    let quiet = 1 << (mantissa_bits - 1);
+    Some(match mantissa {
+        0 => format!("{}__builtin_inf{}()", sign, suffix),
+        m if m & quiet != 0 => format!("{}__builtin_nan{}(\"{:#x}\")", sign, suffix, m & !quiet),
+        m => format!("{}__builtin_nans{}(\"{:#x}\")", sign, suffix, m),
+    })
+}
+
+pub fn escape_string(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\000"),
+            other if other.is_ascii() && !other.is_ascii_control() => escaped.push(other),
+            // Rascal strings are UTF-8; spell out the bytes of anything
+            // non-ASCII so the C source doesn't rely on its own encoding,
+            // and of control characters, which a string can hold as
+            // written in the source
+            other => {
+                let mut buf = [0; 4];
+                for byte in other.encode_utf8(&mut buf).bytes() {
+                    escaped.push_str(&format!("\\{:03o}", byte));
+                }
             }
         }
-        ir::Value::String(s) => format!("{}", s),
-        ir::Value::Id(ident) => format!("{}", ident),
-        other => panic!("No value translation for: {:?}", other),
+    }
+    escaped
+}
+
+pub fn translate_scalar(scalar: Scalar) -> &'static str {
+    match scalar {
+        Scalar::I32 => "int32_t",
+        Scalar::I64 => "int64_t",
+        Scalar::U32 => "uint32_t",
+        Scalar::U64 => "uint64_t",
+        Scalar::F32 => "float",
+        Scalar::F64 => "double",
+        Scalar::Bool => "bool",
+        Scalar::Ptr => "void*",
+    }
+}
+
+// Identifiers may contain any XID character, which C99 only accepts
+// when written as universal character names
+pub fn translate_ident(ident: &str) -> String {
+    let mut translated = String::new();
+    for c in ident.chars() {
+        if c.is_ascii() {
+            translated.push(c);
+        } else if (c as u32) <= 0xFFFF {
+            translated.push_str(&format!("\\u{:04X}", c as u32));
+        } else {
+            translated.push_str(&format!("\\U{:08X}", c as u32));
+        }
+    }
+    translated
+}
+
+// The line, column and message of the first error in gcc's output,
+// which it reports as `out.c:3:14: error: message`
+pub fn gcc_error(stderr: &str) -> Option<(usize, usize, String)> {
+    stderr.lines().find_map(|line| {
+        let rest = line.strip_prefix(CGenContext::C_OUTPUT_FILENAME)?;
+        let mut fields = rest.strip_prefix(':')?.splitn(3, ':');
+        let line = fields.next()?.parse().ok()?;
+        let column = fields.next()?.parse().ok()?;
+        let error = fields.next()?.trim().strip_prefix("error:")?;
+        Some((line, column, error.trim().to_string()))
+    })
+}
+
+// With debug info, each function records itself on a stack of frames
+// when it's entered, and the line it's at before each statement. The
+// frame is popped by a cleanup when the function's scope is left, which
+// runs after a returned value has been computed. A trap, whether called
+// by a runtime check or raised by a signal, prints the frames innermost
+// first and aborts. Each unit has a stack of its own, so calls into
+// units compiled separately aren't traced.
+const TRACE_RUNTIME: &str = r##"#define _RASCAL_MAX_FRAMES 4096
+struct _rascal_frame { const char* function; uint32_t line; };
+static struct _rascal_frame _rascal_frames[_RASCAL_MAX_FRAMES];
+static uint32_t _rascal_depth = 0;
+static uint32_t _rascal_enter(const char* function) {
+    if (_rascal_depth < _RASCAL_MAX_FRAMES) {
+        _rascal_frames[_rascal_depth].function = function;
+        _rascal_frames[_rascal_depth].line = 0;
+    }
+    return _rascal_depth++;
+}
+static void _rascal_leave(uint32_t* depth) { _rascal_depth = *depth; }
+static void _rascal_line(uint32_t line) {
+    if (_rascal_depth - 1 < _RASCAL_MAX_FRAMES) _rascal_frames[_rascal_depth - 1].line = line;
+}
+_Noreturn static void _rascal_trap(const char* message) {
+    fprintf(stderr, "%s\n", message);
+    uint32_t recorded = _rascal_depth < _RASCAL_MAX_FRAMES ? _rascal_depth : _RASCAL_MAX_FRAMES;
+    if (recorded < _rascal_depth) fprintf(stderr, "(%u innermost calls not recorded)\n", _rascal_depth - recorded);
+    uint32_t shown = recorded < 64 ? recorded : 64;
+    for (uint32_t n = recorded; n > recorded - shown; n--) {
+        fprintf(stderr, "#%u %s at line %u\n", _rascal_depth - n, _rascal_frames[n - 1].function, _rascal_frames[n - 1].line);
+    }
+    if (shown < recorded) fprintf(stderr, "(%u outer calls not shown)\n", recorded - shown);
+    signal(SIGABRT, SIG_DFL);
+    abort();
+}
+// Checked builds guard integer division, whose divisor C leaves
+// undefined when it's zero
+#define _RASCAL_DIVISOR(T) static T _rascal_divisor_##T(T divisor) { \
+    if (divisor == 0) _rascal_trap("Division by zero"); \
+    return divisor; \
+}
+_RASCAL_DIVISOR(int32_t) _RASCAL_DIVISOR(int64_t) _RASCAL_DIVISOR(uint32_t) _RASCAL_DIVISOR(uint64_t)
+// and shifts, by as many bits as the type has or more, or by a negative
+// amount, which the cast makes too many
+#define _RASCAL_SHIFT(T) static T _rascal_shift_##T(T amount) { \
+    if ((uint64_t)amount >= sizeof(T) * 8) _rascal_trap("Shift amount out of range"); \
+    return amount; \
+}
+_RASCAL_SHIFT(int32_t) _RASCAL_SHIFT(int64_t) _RASCAL_SHIFT(uint32_t) _RASCAL_SHIFT(uint64_t)
+// and indexes past the end of an array, or before its start, which the
+// cast makes too many
+static uint64_t _rascal_index(uint64_t index, uint64_t length) {
+    if (index >= length) _rascal_trap("Index out of range");
+    return index;
+}
+static void _rascal_signal(int sig) {
+    _rascal_trap(sig == SIGFPE ? "Arithmetic error" : "Segmentation fault, possibly a stack overflow");
+}
+// The handlers run on a stack of their own, which still has room when
+// the program's has overflowed
+static void _rascal_start(void) {
+    static char stack[65536];
+    stack_t alt = { .ss_sp = stack, .ss_size = sizeof stack, .ss_flags = 0 };
+    sigaltstack(&alt, NULL);
+    struct sigaction action = { .sa_handler = _rascal_signal, .sa_flags = SA_ONSTACK };
+    sigemptyset(&action.sa_mask);
+    sigaction(SIGFPE, &action, NULL);
+    sigaction(SIGSEGV, &action, NULL);
+}
+"##;
+
+// Concatenation allocates the string it makes. Nothing frees strings,
+// so one made in a loop takes more memory each time around.
+const CONCAT_RUNTIME: &str = "static char* _rascal_concat(const char* a, const char* b){ size_t n = strlen(a), m = strlen(b); char* s = malloc(n + m + 1); if (!s) { abort(); } memcpy(s, a, n); memcpy(s + n, b, m + 1); return s; }";
+
+// Integers are raised by squaring, wrapping as multiplication does. A
+// negative exponent gives 1 / base ** -exponent, truncated as integer
+// division is, so it's 0 unless the base is 1 or -1, and dividing by a
+// base of 0 is what integer division by zero is: a trap in checked
+// builds, which pass their divisor guard as DIVISOR, and undefined in
+// others.
+const POW_RUNTIME: &str = r##"#define _RASCAL_POW(T, DIVISOR) static T _rascal_pow_##T(T base, T exponent) { \
+    if (exponent < 0) { T inverse = (T)1 / DIVISOR(base); return (exponent & 1) ? inverse : inverse * inverse; } \
+    uint64_t result = 1, b = (uint64_t)base; \
+    for (uint64_t e = (uint64_t)exponent; e; e >>= 1) { if (e & 1) result *= b; b *= b; } \
+    return (T)result; \
+}
+"##;
+const POW_TYPES: &str = "_RASCAL_POW(int32_t, ) _RASCAL_POW(int64_t, ) _RASCAL_POW(uint32_t, ) _RASCAL_POW(uint64_t, )\n";
+const CHECKED_POW_TYPES: &str = "_RASCAL_POW(int32_t, _rascal_divisor_int32_t) _RASCAL_POW(int64_t, _rascal_divisor_int64_t) _RASCAL_POW(uint32_t, _rascal_divisor_uint32_t) _RASCAL_POW(uint64_t, _rascal_divisor_uint64_t)\n";
+
+// A float out of an integer type's range converts to the nearest bound,
+// and NaN to 0, as in the interpreter, where a C cast is undefined
+const CONVERT_RUNTIME: &str = r##"#define _RASCAL_CONVERT(T, MIN, MAX) static T _rascal_convert_##T(double x) { \
+    if (x != x) { return 0; } \
+    if (x <= (double)MIN) { return MIN; } \
+    if (x >= (double)MAX) { return MAX; } \
+    return (T)x; \
+}
+_RASCAL_CONVERT(int32_t, INT32_MIN, INT32_MAX) _RASCAL_CONVERT(int64_t, INT64_MIN, INT64_MAX) _RASCAL_CONVERT(uint32_t, 0, UINT32_MAX) _RASCAL_CONVERT(uint64_t, 0, UINT64_MAX)
+"##;
+
+// A cast from a float to an integer, which goes through CONVERT_RUNTIME
+fn converts_float(sig: &ir::Signature) -> bool {
+    matches!(sig.params_t.first(), Some(Type::Float32 | Type::Float64)) && sig.return_t.is_integer()
+}
+
+fn is_nonzero(value: &ir::Value) -> bool {
+    match value {
+        ir::Value::Int32(n) => *n != 0,
+        ir::Value::Int64(n) => *n != 0,
+        ir::Value::UInt32(n) => *n != 0,
+        ir::Value::UInt64(n) => *n != 0,
+        _ => false,
     }
 }
 
@@ -54,16 +261,116 @@ pub fn is_expr_node(node: IRNode) -> bool {
     match node {
         IRNode::Term(_) => true,
         IRNode::Eval(_) => true,
+        IRNode::Load(_) => true,
         _ => false,
     }
 }
 
+// Takes an operator's operands off the expression stack, first to last
+fn operands(stack: &mut Vec<String>, count: usize) -> Result<Vec<String>, CodeGenError> {
+    if stack.len() < count {
+        return Err(CodeGenError::MalformedIR(format!(
+            "an operator takes {} operands, only {} were pushed",
+            count,
+            stack.len()
+        )));
+    }
+    Ok(stack.split_off(stack.len() - count))
+}
+
+fn binary_operator(func: &ir::Func) -> &'static str {
+    match func {
+        ir::Func::Add(_) => "+",
+        ir::Func::Sub(_) => "-",
+        ir::Func::Mult(_) => "*",
+        ir::Func::Div(_) => "/",
+        ir::Func::Mod(_) => "%",
+        ir::Func::BitAnd(_) => "&",
+        ir::Func::BitOr(_) => "|",
+        ir::Func::BitXor(_) => "^",
+        ir::Func::Shl(_) => "<<",
+        ir::Func::Shr(_) => ">>",
+        ir::Func::Lt(_) => "<",
+        ir::Func::Gt(_) => ">",
+        ir::Func::Leq(_) => "<=",
+        ir::Func::Geq(_) => ">=",
+        ir::Func::Eq(_) => "==",
+        ir::Func::Neq(_) => "!=",
+        ir::Func::Pow(_)
+        | ir::Func::Not(_)
+        | ir::Func::Neg(_)
+        | ir::Func::BitNot(_)
+        | ir::Func::Cast(_)
+        | ir::Func::Func(_) => {
+            unreachable!("not a binary operator")
+        }
+    }
+}
+
+// The parts of the generated C file, in the order they're written. Each
+// is generated on its own, and they're joined once all the IR has been
+// through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Includes,
+    // Definitions of the builtins the program uses
+    Runtime,
+    Types,
+    Prototypes,
+    Globals,
+    Functions,
+    Main,
+}
+
+impl Section {
+    const ALL: [Section; 7] = [
+        Section::Includes,
+        Section::Runtime,
+        Section::Types,
+        Section::Prototypes,
+        Section::Globals,
+        Section::Functions,
+        Section::Main,
+    ];
+
+    fn title(&self) -> &'static str {
+        match self {
+            Section::Includes => "includes",
+            Section::Runtime => "runtime",
+            Section::Types => "types",
+            Section::Prototypes => "prototypes",
+            Section::Globals => "globals",
+            Section::Functions => "functions",
+            Section::Main => "main",
+        }
+    }
+}
+
+pub const FEATURES: &[Feature] = &Feature::ALL;
+
 pub struct CGenContext {
-    build_stack: Vec<IRNode>,
+    // Shared with the contexts functions are generated in, see
+    // gen_functions
+    build_stack: Arc<Vec<IRNode>>,
     outfile: String,
     skip_validation: bool,
+    compile_only: bool,
+    // See CodeGenContext::debug_src
+    debug_src: Option<Arc<str>>,
+    fast_math: bool,
+    // See CodeGenContext::cache_dir
+    cache_dir: Option<PathBuf>,
+    cc: String,
+    // The code of each section, along with the Rascal function each
+    // piece of it was generated for, see CodeGenError::InvalidC
+    sections: Vec<Vec<(String, Option<String>)>>,
+    // The section code is being generated into
+    section: Section,
+    // The pieces of all the sections, in order, once they're joined
     code_buffer: Vec<String>,
-    global_idx: usize,
+    code_origins: Vec<Option<String>>,
+    // Functions whose definitions are being generated, innermost last
+    funcs: Vec<String>,
     type_counter: usize,
     type_map: HashMap<types::Type, String>,
 }
@@ -71,11 +378,19 @@ pub struct CGenContext {
 impl From<CodeGenContext> for CGenContext {
     fn from(ctx: CodeGenContext) -> Self {
         CGenContext {
-            build_stack: ctx.build_stack.into_iter().rev().collect(),
+            build_stack: Arc::new(ctx.build_stack.into_iter().rev().collect()),
             outfile: ctx.outfile,
             skip_validation: ctx.skip_validation,
+            compile_only: ctx.compile_only,
+            debug_src: ctx.debug_src.map(Arc::from),
+            fast_math: ctx.fast_math,
+            cache_dir: ctx.cache_dir,
+            cc: ctx.cc,
+            sections: vec![vec![]; Section::ALL.len()],
+            section: Section::Main,
             code_buffer: vec![],
-            global_idx: 0,
+            code_origins: vec![],
+            funcs: vec![],
             type_counter: 0,
             type_map: HashMap::new(),
         }
@@ -84,32 +399,79 @@ impl From<CodeGenContext> for CGenContext {
 
 impl CodeGen for CGenContext {
     fn gen(&mut self) -> Result<(), CodeGenError> {
-        for n in self.build_stack.clone() {
-            println!("- {:?}", n);
-        }
-        self.gen_includes();
-        self.save_global_idx();
-        let start = self.gen_globals();
-        self.gen_program(start);
+        let final_source = self.gen_source()?;
+        File::create(CGenContext::C_OUTPUT_FILENAME)
+            .and_then(|mut file| file.write_all(final_source.as_bytes()))
+            .map_err(|source| CodeGenError::Io {
+                action: format!("write {}", CGenContext::C_OUTPUT_FILENAME),
+                source,
+            })?;
 
-        let final_source = self.code_buffer.join(" ");
-        let mut file = File::create(CGenContext::C_OUTPUT_FILENAME)
-            .map_err(|err| CodeGenError::BinaryWrite(err.to_string()))?;
-        file.write_all(final_source.as_bytes())
-            .map_err(|err| CodeGenError::BinaryWrite(err.to_string()))?;
+        let mut flags = vec![];
+        if self.compile_only {
+            flags.push("-c");
+        }
+        if self.fast_math {
+            flags.push("-ffast-math");
+        }
+        // The C math library, which intrinsics and `**` on floats may call
+        // into, is only linked when asked for, after the code that needs
+        // it. The C says whether it's needed, so it's no part of the
+        // cache's key.
+        let math = self.build_stack.iter().any(|node| match node {
+            IRNode::Intrinsic(..) => true,
+            IRNode::Eval(ir::Func::Pow(sig)) => !sig.return_t.is_integer(),
+            _ => false,
+        });
+        let libs: &[&str] = if math && !self.compile_only {
+            &["-lm"]
+        } else {
+            &[]
+        };
+        // The same C built the same way by the same compiler builds the same
+        // file, which the cache may already have
+        let cached = self
+            .cache_dir
+            .as_deref()
+            .zip(cache::compiler_version(&self.cc))
+            .map(|(dir, version)| {
+                let key = cache::hash(&[
+                    final_source.as_bytes(),
+                    flags.join(" ").as_bytes(),
+                    &version,
+                ]);
+                (Cache::new(dir), key)
+            });
+        if let Some((cache, key)) = &cached {
+            if cache.fetch(key, &self.outfile) {
+                return Ok(());
+            }
+        }
 
-        let compile_cmd = Command::new("gcc")
+        let compile_cmd = Command::new(&self.cc)
+            .args(&flags)
             .arg(CGenContext::C_OUTPUT_FILENAME)
             .arg("-o")
             .arg(self.outfile.clone())
+            .args(libs)
             .output()
-            .map_err(|err| CodeGenError::CompilationFailed(err.to_string()))?;
+            .map_err(|err| CodeGenError::spawn(&self.cc, err))?;
 
         if !compile_cmd.status.success() {
-            return Err(CodeGenError::CompilationFailed(format!(
-                "C compilation failed: {}",
-                String::from_utf8(compile_cmd.stderr).unwrap()
-            )));
+            let stderr = String::from_utf8_lossy(&compile_cmd.stderr);
+            return Err(match gcc_error(&stderr) {
+                Some((line, column, error)) => CodeGenError::InvalidC {
+                    function: self.origin(&final_source, line, column),
+                    error,
+                },
+                None => CodeGenError::ToolFailed {
+                    tool: self.cc.clone(),
+                    stderr: stderr.into_owned(),
+                },
+            });
+        }
+        if let Some((cache, key)) = &cached {
+            cache.store(key, &self.outfile);
         }
 
         Ok(())
@@ -119,17 +481,84 @@ impl CodeGen for CGenContext {
 impl CGenContext {
     pub const C_OUTPUT_FILENAME: &'static str = "out.c";
 
+    // The whole C file for the IR, without writing or compiling it
+    pub fn gen_source(&mut self) -> Result<String, CodeGenError> {
+        let mut used = backends::features(&self.build_stack);
+        if self.compile_only {
+            used.push(Feature::SeparateCompilation);
+        }
+        backends::check_support("c", &used)?;
+        self.gen_includes()?;
+        self.gen_builtins();
+        self.gen_units()?;
+        Ok(self.source())
+    }
+
     fn add_code(&mut self, code: &str) {
-        self.code_buffer.push(code.into());
+        let origin = self.funcs.last().cloned();
+        self.sections[self.section as usize].push((code.into(), origin));
     }
 
-    fn add_global_code(&mut self, code: &str) {
-        self.code_buffer.insert(self.global_idx, code.into());
-        self.global_idx += 1;
+    fn add_code_to(&mut self, section: Section, code: &str) {
+        self.sections[section as usize].push((code.into(), None));
     }
 
-    fn save_global_idx(&mut self) {
-        self.global_idx = self.code_buffer.len();
+    // Joins the sections, each under a comment naming it
+    fn source(&mut self) -> String {
+        self.code_buffer.clear();
+        self.code_origins.clear();
+        for section in Section::ALL {
+            if self.sections[section as usize].is_empty() {
+                continue;
+            }
+            let gap = if self.code_buffer.is_empty() {
+                ""
+            } else {
+                "\n"
+            };
+            self.code_buffer
+                .push(format!("{}/* {} */\n", gap, section.title()));
+            self.code_origins.push(None);
+            for (code, origin) in self.sections[section as usize].iter() {
+                self.code_buffer.push(code.clone());
+                self.code_origins.push(origin.clone());
+            }
+        }
+        self.code_buffer.join(" ")
+    }
+
+    // Lines of C generated for each Rascal function, with the program
+    // block under None, counting a statement a line
+    pub fn c_lines(&self) -> HashMap<Option<String>, usize> {
+        let mut lines = HashMap::new();
+        for section in [Section::Functions, Section::Main] {
+            for (code, origin) in self.sections[section as usize].iter() {
+                if code.ends_with([';', '{', '}']) {
+                    *lines.entry(origin.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+        lines
+    }
+
+    // The Rascal function the code at a (1-based) line and column of the
+    // generated source came from
+    fn origin(&self, source: &str, line: usize, column: usize) -> Option<String> {
+        let line_start: usize = source
+            .split_inclusive('\n')
+            .take(line - 1)
+            .map(|l| l.len())
+            .sum();
+        let offset = line_start + column.saturating_sub(1);
+        // The pieces are joined by single spaces
+        let mut end = 0;
+        for (code, origin) in self.code_buffer.iter().zip(self.code_origins.iter()) {
+            end += code.len() + 1;
+            if offset < end {
+                return origin.clone();
+            }
+        }
+        None
     }
 
     fn get_new_type_id(&mut self) -> usize {
@@ -139,16 +568,8 @@ impl CGenContext {
     }
 
     fn translate_type(&mut self, type_t: Type) -> String {
-        match type_t.clone() {
-            Type::Int32 => "int32_t".into(),
-            Type::Int64 => "int64_t".into(),
-            Type::UInt32 => "uint32_t".into(),
-            Type::UInt64 => "uint64_t".into(),
-            Type::Float32 => "float".into(),
-            Type::Float64 => "double".into(),
-            Type::Bool => "int32_t".into(),
-            Type::String => "char*".into(),
-            Type::Function(func) => match self.type_map.get(&type_t.clone()) {
+        if let Type::Function(func) = &type_t {
+            return match self.type_map.get(&type_t) {
                 Some(val) => val.to_string(),
                 None => {
                     let typedef_name = format!("_func_type_{}", self.get_new_type_id());
@@ -160,83 +581,375 @@ impl CGenContext {
                         .collect();
                     let return_type: String = self.translate_type(*func.return_t.clone());
                     let joined_params = param_types.join(",");
-                    self.add_global_code(&format!(
-                        "typedef {} (*{})({});",
-                        return_type,
-                        typedef_name.clone(),
-                        joined_params
-                    ));
+                    self.add_code_to(
+                        Section::Types,
+                        &format!(
+                            "typedef {} (*{})({});",
+                            return_type,
+                            typedef_name.clone(),
+                            joined_params
+                        ),
+                    );
                     typedef_name
                 }
-            },
-            other => panic!("unknown type: {:?}", other),
+            };
+        }
+        match abi::classify(&type_t) {
+            Some(PassMode::Direct(scalar)) => translate_scalar(scalar).into(),
+            // Strings are the only values passed by pointer
+            Some(PassMode::Pointer) => "char*".into(),
+            Some(PassMode::Ignore) => "void".into(),
+            None => panic!("unknown type: {:?}", type_t),
         }
     }
 
     fn gen_includes(&mut self) -> Result<(), CodeGenError> {
-        self.add_code("#include \"stdint.h\"\n");
+        self.add_code_to(Section::Includes, "#include \"stdint.h\"\n");
+        self.add_code_to(Section::Includes, "#include \"stdbool.h\"\n");
+        if self.debug_src.is_some() {
+            for include in ["stdio.h", "stdlib.h", "signal.h"] {
+                self.add_code_to(Section::Includes, &format!("#include \"{}\"\n", include));
+            }
+            self.add_code_to(Section::Runtime, TRACE_RUNTIME);
+        }
         Ok(())
     }
 
-    fn gen_globals(&mut self) -> usize {
-        let mut idx = 0;
-        // A well formed program must start with a globals section
-        // which could be empty
-        while (*self.build_stack.get(idx).unwrap()).clone() != IRNode::GlobalSection {
-            idx += 1;
+    // A call refers to a builtin unless the program defines its own
+    // function of the same name
+    fn builtin(&self, ident: &str) -> Option<Builtin> {
+        let user_defined = self
+            .build_stack
+            .iter()
+            .any(|node| matches!(node, IRNode::FuncDef(def, _) if def.symbol.ident == ident));
+        if user_defined {
+            None
+        } else {
+            builtins::lookup(ident)
+        }
+    }
+
+    // The C function the function of the name stands for, if it's
+    // declared `@intrinsic`
+    fn intrinsic(&self, ident: &str) -> Option<&str> {
+        self.build_stack.iter().find_map(|node| match node {
+            IRNode::Intrinsic(def, c_ident) if def.symbol.ident == ident => Some(c_ident.as_str()),
+            _ => None,
+        })
+    }
+
+    // The C for a term, naming the C function of an intrinsic in place
+    // of the function that stands for it
+    fn term(&self, value: ir::Value) -> String {
+        match value {
+            ir::Value::Id(ident) => match self.intrinsic(&ident) {
+                Some(c_ident) => c_ident.to_string(),
+                None => translate_ident(&ident),
+            },
+            value => translate_value(value),
+        }
+    }
+
+    fn gen_builtins(&mut self) {
+        let mut used: Vec<Builtin> = vec![];
+        for node in self.build_stack.iter() {
+            if let IRNode::Eval(ir::Func::Func(sig)) = node {
+                if let Some(builtin) = self.builtin(&sig.symbol.ident) {
+                    if !used.iter().any(|b| b.ident == builtin.ident) {
+                        used.push(builtin);
+                    }
+                }
+            }
+        }
+        let mut includes: Vec<&str> = vec![];
+        let mut defs: Vec<&str> = vec![];
+        for builtin in used.iter() {
+            includes.extend(builtin.c_includes);
+            defs.push(builtin.c_def);
+        }
+        // Strings are compared by what they hold, and concatenated by
+        // the runtime
+        let string_ops: Vec<&ir::Func> = self
+            .build_stack
+            .iter()
+            .filter_map(|node| match node {
+                IRNode::Eval(func)
+                    if !matches!(func, ir::Func::Func(_))
+                        && func.signature().1.params_t.first() == Some(&Type::String) =>
+                {
+                    Some(func)
+                }
+                _ => None,
+            })
+            .collect();
+        let concatenates = string_ops
+            .iter()
+            .any(|func| matches!(func, ir::Func::Add(_)));
+        if !string_ops.is_empty() {
+            includes.push("string.h");
+        }
+        if concatenates {
+            includes.push("stdlib.h");
+            defs.push(CONCAT_RUNTIME);
         }
-        idx += 1;
-        let end_of_globals = self
+        let integer_pow = self.build_stack.iter().any(
+            |node| matches!(node, IRNode::Eval(ir::Func::Pow(sig)) if sig.return_t.is_integer()),
+        );
+        if integer_pow {
+            defs.push(POW_RUNTIME);
+            defs.push(match self.checked() {
+                true => CHECKED_POW_TYPES,
+                false => POW_TYPES,
+            });
+        }
+        let converts = self
             .build_stack
             .iter()
-            .enumerate()
-            .find(|(_, ir_node)| matches!(ir_node, IRNode::EndGlobalSection))
-            .unwrap()
-            .0;
-        self.gen_code(idx, end_of_globals) + 2
+            .any(|node| matches!(node, IRNode::Eval(ir::Func::Cast(sig)) if converts_float(sig)));
+        if converts {
+            defs.push(CONVERT_RUNTIME);
+        }
+        let mut included: Vec<&str> = vec![];
+        for include in includes {
+            if !included.contains(&include) {
+                self.add_code_to(Section::Includes, &format!("#include \"{}\"\n", include));
+                included.push(include);
+            }
+        }
+        for def in defs {
+            self.add_code_to(Section::Runtime, def);
+        }
+    }
+
+    // The index of the first node from `from` on that matches
+    fn find(
+        &self,
+        from: usize,
+        what: &str,
+        matches: impl Fn(&IRNode) -> bool,
+    ) -> Result<usize, CodeGenError> {
+        self.build_stack[from..]
+            .iter()
+            .position(matches)
+            .map(|n| from + n)
+            .ok_or_else(|| CodeGenError::MalformedIR(format!("no {} found", what)))
+    }
+
+    // The IR of a unit is its globals section, followed by the program
+    // block if it has one. Units without a program have no main, and are
+    // linked with one that does.
+    fn gen_units(&mut self) -> Result<(), CodeGenError> {
+        let start = self.find(0, "globals section", |node| {
+            matches!(node, IRNode::GlobalSection)
+        })?;
+        let end = self.find(start, "end of the globals section", |node| {
+            matches!(node, IRNode::EndGlobalSection)
+        })?;
+        self.gen_globals(start + 1, end)?;
+        match self.build_stack.get(end + 1) {
+            None => Ok(()),
+            Some(IRNode::Program) => {
+                self.gen_program(end + 2)?;
+                Ok(())
+            }
+            Some(node) => Err(CodeGenError::MalformedIR(format!(
+                "expected the program block after the globals, found {:?}",
+                node
+            ))),
+        }
+    }
+
+    // Functions at the top level are defined in their own section, and
+    // declared ahead of everything so they can be called from anywhere
+    fn gen_globals(&mut self, start: usize, end: usize) -> Result<(), CodeGenError> {
+        let mut functions = vec![];
+        let mut idx = start;
+        while idx < end {
+            match self.build_stack[idx].clone() {
+                IRNode::FuncDef(def, id) => {
+                    let end_func = self.find(
+                        idx,
+                        "end of a function",
+                        |node| matches!(node, IRNode::EndFuncDef(end_id) if *end_id == id),
+                    )?;
+                    self.section = Section::Prototypes;
+                    self.gen_signature(def);
+                    self.add_code(";");
+                    functions.push((idx, end_func + 1));
+                    idx = end_func + 1;
+                }
+                IRNode::ExternFuncDef(_) | IRNode::Intrinsic(..) => {
+                    self.section = Section::Prototypes;
+                    idx = self.gen_code(idx, idx + 1)?;
+                }
+                _ => {
+                    self.section = Section::Globals;
+                    idx = self.gen_code(idx, idx + 1)?;
+                }
+            }
+        }
+        self.gen_functions(&functions)
+    }
+
+    // Each function only adds to the functions section, so they're
+    // generated at the same time, each in a context of its own, and
+    // their code joined in the order they're defined. The typedefs of
+    // function types are the one thing they'd share, so they're all
+    // declared first.
+    fn gen_functions(&mut self, functions: &[(usize, usize)]) -> Result<(), CodeGenError> {
+        self.declare_types();
+        let generated: Vec<_> = functions
+            .par_iter()
+            .map(|&(start, end)| {
+                let mut worker = self.worker();
+                worker.gen_code(start, end)?;
+                Ok(worker.sections.swap_remove(Section::Functions as usize))
+            })
+            .collect::<Result<_, CodeGenError>>()?;
+        for code in generated {
+            self.sections[Section::Functions as usize].extend(code);
+        }
+        Ok(())
+    }
+
+    // Declares the C types of the function types anywhere in the IR, in
+    // the order they first appear
+    fn declare_types(&mut self) {
+        let build_stack = self.build_stack.clone();
+        for node in build_stack.iter() {
+            let types: Vec<&Type> = match node {
+                IRNode::Assign(assign) | IRNode::StaticAssign(assign) => vec![&assign.type_t],
+                IRNode::Reassign(reassign) => vec![&reassign.type_t],
+                IRNode::Term(term) => vec![&term.type_t],
+                IRNode::Eval(func) => {
+                    let sig = match func {
+                        ir::Func::Add(sig)
+                        | ir::Func::Sub(sig)
+                        | ir::Func::Mult(sig)
+                        | ir::Func::Div(sig)
+                        | ir::Func::Mod(sig)
+                        | ir::Func::Pow(sig)
+                        | ir::Func::BitAnd(sig)
+                        | ir::Func::BitOr(sig)
+                        | ir::Func::BitXor(sig)
+                        | ir::Func::Shl(sig)
+                        | ir::Func::Shr(sig)
+                        | ir::Func::Lt(sig)
+                        | ir::Func::Gt(sig)
+                        | ir::Func::Leq(sig)
+                        | ir::Func::Geq(sig)
+                        | ir::Func::Eq(sig)
+                        | ir::Func::Neq(sig)
+                        | ir::Func::Not(sig)
+                        | ir::Func::Neg(sig)
+                        | ir::Func::BitNot(sig)
+                        | ir::Func::Cast(sig)
+                        | ir::Func::Func(sig) => sig,
+                    };
+                    sig.params_t.iter().chain([&sig.return_t]).collect()
+                }
+                IRNode::FuncDef(def, _) | IRNode::ExternFuncDef(def) | IRNode::FuncDecl(def) => def
+                    .params_t
+                    .iter()
+                    .map(|(_, type_t)| type_t)
+                    .chain([&def.return_t])
+                    .collect(),
+                _ => vec![],
+            };
+            for type_t in types {
+                if matches!(type_t, Type::Function(_)) {
+                    self.translate_type(type_t.clone());
+                }
+            }
+        }
+    }
+
+    // A context to generate a function in, with nothing generated yet
+    fn worker(&self) -> CGenContext {
+        CGenContext {
+            build_stack: self.build_stack.clone(),
+            outfile: self.outfile.clone(),
+            skip_validation: self.skip_validation,
+            compile_only: self.compile_only,
+            debug_src: self.debug_src.clone(),
+            fast_math: self.fast_math,
+            cache_dir: self.cache_dir.clone(),
+            cc: self.cc.clone(),
+            sections: vec![vec![]; Section::ALL.len()],
+            section: Section::Functions,
+            code_buffer: vec![],
+            code_origins: vec![],
+            funcs: vec![],
+            type_counter: self.type_counter,
+            type_map: self.type_map.clone(),
+        }
     }
 
-    fn gen_program(&mut self, idx: usize) -> usize {
+    fn gen_program(&mut self, idx: usize) -> Result<usize, CodeGenError> {
+        self.section = Section::Main;
         self.add_code("int main(){");
-        let new_idx = self.gen_code(idx, self.build_stack.len());
+        if self.debug_src.is_some() {
+            self.add_code("_rascal_start();");
+            self.gen_enter("program");
+        }
+        let new_idx = self.gen_code(idx, self.build_stack.len())?;
         self.add_code("}");
-        new_idx
+        Ok(new_idx)
     }
 
-    fn gen_code(&mut self, idx: usize, end_idx: usize) -> usize {
+    fn gen_code(&mut self, idx: usize, end_idx: usize) -> Result<usize, CodeGenError> {
         let mut node_idx = idx;
         while node_idx < end_idx {
-            node_idx = match self.build_stack.get(node_idx).unwrap() {
-                IRNode::Term(_) => self.gen_term(node_idx).unwrap(),
-                IRNode::Eval(_) => self.gen_eval(node_idx).unwrap(),
-                IRNode::Label(_) => self.gen_label(node_idx).unwrap(),
-                IRNode::Assign(assign) => self.gen_assign(node_idx, assign.clone()).unwrap(),
-                IRNode::Reassign(reassign) => {
-                    self.gen_reassign(node_idx, reassign.clone()).unwrap()
-                }
+            node_idx = match &self.build_stack[node_idx] {
+                IRNode::Term(_) | IRNode::Load(_) => self.gen_term(node_idx)?,
+                IRNode::Eval(_) => self.gen_eval(node_idx)?,
+                IRNode::Label(_) => self.gen_label(node_idx)?,
+                IRNode::Loc(offset) => self.gen_loc(node_idx, *offset),
+                IRNode::Assign(assign) => self.gen_assign(node_idx, assign.clone())?,
+                IRNode::StaticAssign(assign) => self.gen_static_assign(node_idx, assign.clone())?,
+                IRNode::Reassign(reassign) => self.gen_reassign(node_idx, reassign.clone())?,
+                IRNode::Declare(assign) => self.gen_declare(node_idx, assign.clone())?,
+                IRNode::Store(access) => self.gen_store(node_idx, access.clone())?,
                 // If Statement
-                IRNode::If(_) => self.gen_if(node_idx).unwrap(),
-                IRNode::IfCase(_) => self.gen_if_case(node_idx).unwrap(),
-                IRNode::ElseIfCase(_) => self.gen_else_if_case(node_idx).unwrap(),
-                IRNode::ElseCase(_) => self.gen_else_case(node_idx).unwrap(),
-                IRNode::EndIf(_) => self.gen_end_if(node_idx).unwrap(),
+                IRNode::If(_) => self.gen_if(node_idx)?,
+                IRNode::IfCase(_) => self.gen_if_case(node_idx)?,
+                IRNode::ElseIfCase(_) => self.gen_else_if_case(node_idx)?,
+                IRNode::ElseCase(_) => self.gen_else_case(node_idx)?,
+                IRNode::EndIf(_) => self.gen_end_if(node_idx)?,
+                // Switch Statement
+                IRNode::Switch(_) => self.gen_switch(node_idx)?,
+                IRNode::SwitchCase(_, labels) => self.gen_switch_case(node_idx, labels.clone())?,
+                IRNode::DefaultCase(_) => self.gen_default_case(node_idx)?,
+                IRNode::EndSwitch(_) => self.gen_end_switch(node_idx)?,
+                // Repeat Loop
+                IRNode::Repeat(_) => self.gen_repeat(node_idx)?,
+                IRNode::Until(id) => self.gen_until(node_idx, id.clone())?,
+                // While Loop
+                IRNode::While(_) => self.gen_while(node_idx)?,
+                IRNode::EndWhile(id) => self.gen_end_while(node_idx, id.clone())?,
+                IRNode::Break(id) => self.gen_break(node_idx, id.clone())?,
+                IRNode::Continue(id) => self.gen_continue(node_idx, id.clone())?,
                 // Function Definitions
-                IRNode::FuncDef(def, _) => self.gen_func_def(node_idx, def.clone()).unwrap(),
-                IRNode::EndFuncDef(_) => self.gen_end_func_def(node_idx).unwrap(),
-                // Return
-                IRNode::Return => self.gen_return(node_idx).unwrap(),
-                IRNode::GlobalSection => {
-                    panic!("IRNode::GlobalSection should not be handled as code")
-                }
-                IRNode::EndGlobalSection => {
-                    panic!("IRNode::EndGlobalSection should not be handled as code")
+                IRNode::FuncDef(def, _) => self.gen_func_def(node_idx, def.clone())?,
+                IRNode::EndFuncDef(_) => self.gen_end_func_def(node_idx)?,
+                IRNode::ExternFuncDef(def) => self.gen_extern_func_def(node_idx, def.clone())?,
+                IRNode::FuncDecl(def) => self.gen_func_decl(node_idx, def.clone())?,
+                IRNode::Intrinsic(def, c_ident) => {
+                    self.gen_intrinsic(node_idx, def.clone(), c_ident.clone())
                 }
-                other => {
-                    panic!("Unimplemented IRNode: {:?}", other);
+                // Return
+                IRNode::Return => self.gen_return(node_idx)?,
+                IRNode::Discard => self.gen_discard(node_idx)?,
+                // Only gen_units starts the globals and the program block
+                node @ (IRNode::GlobalSection | IRNode::EndGlobalSection | IRNode::Program) => {
+                    return Err(CodeGenError::MalformedIR(format!(
+                        "unexpected {:?} in a block",
+                        node
+                    )))
                 }
             };
         }
-        node_idx
+        Ok(node_idx)
     }
 
     fn gen_term(&mut self, idx: usize) -> Result<usize, CodeGenError> {
@@ -257,23 +970,95 @@ impl CGenContext {
         {
             let assignment_type = &self.translate_type(assign.type_t);
             self.add_code(assignment_type);
-            self.add_code(&assign.symbol.ident.clone());
+            self.add_code(&translate_ident(&assign.symbol.ident));
             self.add_code("=");
-            self.gen_expr(idx - 1);
+            self.gen_expr(idx - 1)?;
             self.add_code(";");
         }
         Ok(idx + 1)
     }
 
+    fn gen_static_assign(&mut self, idx: usize, assign: ir::Assign) -> Result<usize, CodeGenError> {
+        self.add_code("static");
+        self.gen_assign(idx, assign)
+    }
+
     fn gen_reassign(&mut self, idx: usize, reassign: ir::Reassign) -> Result<usize, CodeGenError> {
-        self.add_code(&*reassign.symbol.ident.clone());
+        self.add_code(&translate_ident(&reassign.symbol.ident));
         self.add_code("=");
-        self.gen_expr(idx - 1);
+        self.gen_expr(idx - 1)?;
         self.add_code(";");
         Ok(idx + 1)
     }
 
+    // Arrays are zeroed when they're declared, as `let` never leaves a
+    // variable without a value
+    fn gen_declare(&mut self, idx: usize, assign: ir::Assign) -> Result<usize, CodeGenError> {
+        let Type::Array(element_t, length) = assign.type_t else {
+            return Err(CodeGenError::MalformedIR(format!(
+                "`{}` is declared as an array but is `{}`",
+                assign.symbol.ident, assign.type_t
+            )));
+        };
+        let element_t = self.translate_type(*element_t);
+        self.add_code(&element_t);
+        self.add_code(&format!(
+            "{}[{}]",
+            translate_ident(&assign.symbol.ident),
+            length
+        ));
+        self.add_code("= {0};");
+        Ok(idx + 1)
+    }
+
+    fn gen_store(&mut self, idx: usize, access: ir::Access) -> Result<usize, CodeGenError> {
+        let [index, value]: [String; 2] = match self.expr_operands(idx - 1)?.try_into() {
+            Ok(operands) => operands,
+            Err(operands) => {
+                return Err(CodeGenError::MalformedIR(format!(
+                    "a store takes an index and a value, {} were pushed",
+                    operands.len()
+                )))
+            }
+        };
+        let index = self.index(&access, index);
+        self.add_code(&format!(
+            "{}[{}] = {};",
+            translate_ident(&access.symbol.ident),
+            index,
+            value
+        ));
+        Ok(idx + 1)
+    }
+
+    // Checked builds trap on an index out of the array's range
+    fn index(&self, access: &ir::Access, index: String) -> String {
+        match &access.type_t {
+            Type::Array(_, length) if self.checked() => {
+                format!("_rascal_index({}, {})", index, length)
+            }
+            _ => index,
+        }
+    }
+
     fn gen_expr(&mut self, idx: usize) -> Result<(), CodeGenError> {
+        let stack = self.expr_operands(idx)?;
+        match stack.as_slice() {
+            [expr] => {
+                let expr = expr.clone();
+                self.add_code(&expr);
+                Ok(())
+            }
+            _ => Err(CodeGenError::MalformedIR(format!(
+                "an expression left {} values",
+                stack.len()
+            ))),
+        }
+    }
+
+    // The C of each value the expression nodes ending at `idx` leave,
+    // first to last
+    fn expr_operands(&mut self, idx: usize) -> Result<Vec<String>, CodeGenError> {
         // Collect
         let expr: Vec<IRNode> = self
             .build_stack
@@ -284,84 +1069,127 @@ impl CGenContext {
             .cloned()
             .collect();
 
-        // Use a stack to build the expression
+        // Use a stack to build the expression. Operands are pushed in the
+        // order they're evaluated, so an operator's last operand is on top.
         let mut stack: Vec<String> = vec![];
+        // Alongside each operand, whether it's a literal other than zero
+        let mut nonzero: Vec<bool> = vec![];
         for node in expr.into_iter().rev() {
             match node {
-                IRNode::Term(term) => stack.push(translate_value(term.value)),
+                IRNode::Term(term) => {
+                    nonzero.push(is_nonzero(&term.value));
+                    stack.push(self.term(term.value))
+                }
+                IRNode::Load(access) => {
+                    let index = operands(&mut stack, 1)?.remove(0);
+                    nonzero.pop();
+                    nonzero.push(false);
+                    let index = self.index(&access, index);
+                    stack.push(format!(
+                        "{}[{}]",
+                        translate_ident(&access.symbol.ident),
+                        index
+                    ))
+                }
                 IRNode::Eval(eval) => {
+                    let arity = match &eval {
+                        ir::Func::Not(_)
+                        | ir::Func::Neg(_)
+                        | ir::Func::BitNot(_)
+                        | ir::Func::Cast(_) => 1,
+                        ir::Func::Func(sig) => sig.params_t.len(),
+                        _ => 2,
+                    };
+                    let divisor_nonzero = nonzero.last() == Some(&true);
+                    nonzero.truncate(nonzero.len().saturating_sub(arity));
+                    nonzero.push(false);
                     let mut sub_expr: Vec<String> = vec!["(".into()];
                     let evaluated = match eval {
-                        ir::Func::Add(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} + {}", lhs, rhs)
-                        }
-                        ir::Func::Sub(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} - {}", lhs, rhs)
-                        }
-                        ir::Func::Mult(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} * {}", lhs, rhs)
-                        }
-                        ir::Func::Div(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} / {}", lhs, rhs)
+                        ir::Func::Not(_) => format!("!{}", operands(&mut stack, 1)?[0]),
+                        ir::Func::Neg(_) => format!("-{}", operands(&mut stack, 1)?[0]),
+                        ir::Func::BitNot(_) => format!("~{}", operands(&mut stack, 1)?[0]),
+                        ir::Func::Cast(sig) if converts_float(&sig) => {
+                            let u = operands(&mut stack, 1)?.remove(0);
+                            format!(
+                                "_rascal_convert_{}({})",
+                                self.translate_type(sig.return_t),
+                                u
+                            )
                         }
-                        ir::Func::Lt(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} < {}", lhs, rhs)
+                        ir::Func::Cast(sig) => {
+                            let u = operands(&mut stack, 1)?.remove(0);
+                            format!("({}) {}", self.translate_type(sig.return_t), u)
                         }
-                        ir::Func::Gt(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} > {}", lhs, rhs)
-                        }
-                        ir::Func::Leq(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} <= {}", lhs, rhs)
+                        ir::Func::Func(sig) => {
+                            let mut call: String = match self.builtin(&sig.symbol.ident) {
+                                Some(builtin) => builtin.c_ident.to_string(),
+                                None => self.term(ir::Value::Id(sig.symbol.ident.clone())),
+                            };
+                            let args = operands(&mut stack, sig.params_t.len())?;
+                            call.push_str(&format!("({})", args.join(", ")));
+                            call
                         }
-                        ir::Func::Geq(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} >= {}", lhs, rhs)
+                        ir::Func::Add(ref sig) if sig.return_t == Type::String => {
+                            let [lhs, rhs]: [String; 2] =
+                                operands(&mut stack, 2)?.try_into().unwrap();
+                            format!("_rascal_concat({}, {})", lhs, rhs)
                         }
-                        ir::Func::Eq(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} == {}", lhs, rhs)
+                        ir::Func::Lt(ref sig)
+                        | ir::Func::Gt(ref sig)
+                        | ir::Func::Leq(ref sig)
+                        | ir::Func::Geq(ref sig)
+                        | ir::Func::Eq(ref sig)
+                        | ir::Func::Neq(ref sig)
+                            if sig.params_t.first() == Some(&Type::String) =>
+                        {
+                            let [lhs, rhs]: [String; 2] =
+                                operands(&mut stack, 2)?.try_into().unwrap();
+                            format!("strcmp({}, {}) {} 0", lhs, rhs, binary_operator(&eval))
                         }
-                        ir::Func::Neq(_) => {
-                            let rhs = stack.pop().unwrap();
-                            let lhs = stack.pop().unwrap();
-                            format!("{} != {}", lhs, rhs)
+                        // Dividing by a literal can't trap
+                        ir::Func::Div(ref sig) | ir::Func::Mod(ref sig)
+                            if self.checked()
+                                && !divisor_nonzero
+                                && sig.params_t.first().is_some_and(Type::is_integer) =>
+                        {
+                            let [lhs, rhs]: [String; 2] =
+                                operands(&mut stack, 2)?.try_into().unwrap();
+                            let divisor_t = self.translate_type(sig.params_t[0].clone());
+                            format!(
+                                "{} {} _rascal_divisor_{}({})",
+                                lhs,
+                                binary_operator(&eval),
+                                divisor_t,
+                                rhs
+                            )
                         }
-                        ir::Func::Not(_) => {
-                            let u = stack.pop().unwrap();
-                            format!("!{}", u)
+                        // gcc's builtins for floats, so no math.h to clash with user names
+                        ir::Func::Pow(ref sig) => {
+                            let [lhs, rhs]: [String; 2] =
+                                operands(&mut stack, 2)?.try_into().unwrap();
+                            let power = match &sig.return_t {
+                                Type::Float64 => "__builtin_pow".to_string(),
+                                Type::Float32 => "__builtin_powf".to_string(),
+                                t => format!("_rascal_pow_{}", self.translate_type(t.clone())),
+                            };
+                            format!("{}({}, {})", power, lhs, rhs)
                         }
-                        ir::Func::Neg(_) => {
-                            let u = stack.pop().unwrap();
-                            format!("-{}", u)
+                        ir::Func::Shl(ref sig) | ir::Func::Shr(ref sig) if self.checked() => {
+                            let [lhs, rhs]: [String; 2] =
+                                operands(&mut stack, 2)?.try_into().unwrap();
+                            let amount_t = self.translate_type(sig.params_t[1].clone());
+                            format!(
+                                "{} {} _rascal_shift_{}({})",
+                                lhs,
+                                binary_operator(&eval),
+                                amount_t,
+                                rhs
+                            )
                         }
-                        ir::Func::Func(sig) => {
-                            let mut call: String = sig.symbol.ident.clone();
-                            call.push_str("(");
-                            let num_params = sig.params_t.len();
-                            for i in 0..num_params {
-                                call.push_str(&stack.pop().unwrap());
-                                if i != num_params - 1 {
-                                    call.push_str(", ");
-                                }
-                            }
-                            call.push_str(")");
-                            call
+                        binary => {
+                            let [lhs, rhs]: [String; 2] =
+                                operands(&mut stack, 2)?.try_into().unwrap();
+                            format!("{} {} {}", lhs, binary_operator(&binary), rhs)
                         }
                     };
                     sub_expr.push(evaluated);
@@ -371,8 +1199,7 @@ impl CGenContext {
                 _ => panic!("This shouldn't ever happen!"),
             };
         }
-        self.add_code(&stack.pop().unwrap());
-        Ok(())
+        Ok(stack)
     }
 
     fn gen_if(&mut self, idx: usize) -> Result<usize, CodeGenError> {
@@ -382,7 +1209,7 @@ impl CGenContext {
     fn gen_if_case(&mut self, idx: usize) -> Result<usize, CodeGenError> {
         self.add_code("if");
         self.add_code("(");
-        self.gen_expr(idx - 1);
+        self.gen_expr(idx - 1)?;
         self.add_code(")");
         self.add_code("{");
         Ok(idx + 1)
@@ -392,7 +1219,7 @@ impl CGenContext {
         self.add_code("}");
         self.add_code("else if");
         self.add_code("(");
-        self.gen_expr(idx - 1);
+        self.gen_expr(idx - 1)?;
         self.add_code(")");
         self.add_code("{");
         Ok(idx + 1)
@@ -410,34 +1237,589 @@ impl CGenContext {
         Ok(idx + 1)
     }
 
+    fn gen_switch(&mut self, idx: usize) -> Result<usize, CodeGenError> {
+        self.add_code("switch");
+        self.add_code("(");
+        self.gen_expr(idx - 1)?;
+        self.add_code(")");
+        self.add_code("{");
+        Ok(idx + 1)
+    }
+
+    // Each case's block is braced and ends in a break, so cases never
+    // fall through into the next one
+    fn end_switch_case(&mut self, idx: usize) {
+        if !matches_variant!(self.build_stack.get(idx - 1).unwrap(), IRNode::Switch) {
+            self.add_code("break;");
+            self.add_code("}");
+        }
+    }
+
+    fn gen_switch_case(
+        &mut self,
+        idx: usize,
+        labels: Vec<ir::Value>,
+    ) -> Result<usize, CodeGenError> {
+        self.end_switch_case(idx);
+        for label in labels {
+            self.add_code("case");
+            self.add_code(&translate_value(label));
+            self.add_code(":");
+        }
+        self.add_code("{");
+        Ok(idx + 1)
+    }
+
+    fn gen_default_case(&mut self, idx: usize) -> Result<usize, CodeGenError> {
+        self.end_switch_case(idx);
+        self.add_code("default:");
+        self.add_code("{");
+        Ok(idx + 1)
+    }
+
+    fn gen_end_switch(&mut self, idx: usize) -> Result<usize, CodeGenError> {
+        self.end_switch_case(idx);
+        self.add_code("}");
+        Ok(idx + 1)
+    }
+
+    fn gen_repeat(&mut self, idx: usize) -> Result<usize, CodeGenError> {
+        self.add_code("do");
+        self.add_code("{");
+        Ok(idx + 1)
+    }
+
+    fn gen_until(&mut self, idx: usize, id: String) -> Result<usize, CodeGenError> {
+        self.gen_loop_next(&id);
+        self.add_code("}");
+        self.add_code("while");
+        self.add_code("(!(");
+        self.gen_expr(idx - 1)?;
+        self.add_code("));");
+        self.gen_loop_end(&id);
+        Ok(idx + 1)
+    }
+
+    fn gen_while(&mut self, idx: usize) -> Result<usize, CodeGenError> {
+        self.add_code("while");
+        self.add_code("(");
+        self.gen_expr(idx - 1)?;
+        self.add_code(")");
+        self.add_code("{");
+        Ok(idx + 1)
+    }
+
+    fn gen_end_while(&mut self, idx: usize, id: String) -> Result<usize, CodeGenError> {
+        self.gen_loop_next(&id);
+        self.add_code("}");
+        self.gen_loop_end(&id);
+        Ok(idx + 1)
+    }
+
+    // Where continues of the loop jump to, the end of its body, if there
+    // are any
+    fn gen_loop_next(&mut self, id: &str) {
+        let continued = self
+            .build_stack
+            .iter()
+            .any(|node| matches!(node, IRNode::Continue(target) if target == id));
+        if continued {
+            self.add_code(&format!("{}_next:;", id));
+        }
+    }
+
+    // Where breaks out of the loop jump to, if there are any
+    fn gen_loop_end(&mut self, id: &str) {
+        let broken = self
+            .build_stack
+            .iter()
+            .any(|node| matches!(node, IRNode::Break(target) if target == id));
+        if broken {
+            self.add_code(&format!("{}_end:;", id));
+        }
+    }
+
+    // A C break would only leave an enclosing switch, and can't reach
+    // outer loops, so breaks jump past the end of their loop instead
+    fn gen_break(&mut self, idx: usize, id: String) -> Result<usize, CodeGenError> {
+        self.add_code(&format!("goto {}_end;", id));
+        Ok(idx + 1)
+    }
+
+    // Continues jump for the same reason, to the end of the loop's body
+    fn gen_continue(&mut self, idx: usize, id: String) -> Result<usize, CodeGenError> {
+        self.add_code(&format!("goto {}_next;", id));
+        Ok(idx + 1)
+    }
+
     fn gen_func_def(&mut self, idx: usize, def: FuncDef) -> Result<usize, CodeGenError> {
+        self.funcs.push(def.symbol.ident.clone());
+        let ident = def.symbol.ident.clone();
+        self.gen_signature(def);
+        self.add_code("{");
+        if self.debug_src.is_some() {
+            self.gen_enter(&ident);
+        }
+        Ok(idx + 1)
+    }
+
+    // Checked builds trap on what C leaves undefined, which needs the
+    // runtime that comes with debug info to report it
+    fn checked(&self) -> bool {
+        self.debug_src.is_some()
+    }
+
+    // Pushes a frame for the function, popped as its scope is left
+    fn gen_enter(&mut self, function: &str) {
+        self.add_code(&format!(
+            "__attribute__((cleanup(_rascal_leave))) uint32_t _rascal_frame = _rascal_enter({});",
+            translate_value(ir::Value::String(function.into()))
+        ));
+    }
+
+    // Statements only run in functions and the program, initializers of
+    // globals have nowhere to record their line
+    fn gen_loc(&mut self, idx: usize, offset: usize) -> usize {
+        if let Some(src) = &self.debug_src {
+            if self.section != Section::Globals {
+                let line = line_col(src, offset).0;
+                self.add_code(&format!("_rascal_line({});", line));
+            }
+        }
+        idx + 1
+    }
+
+    // A prototype, which is all that's needed to call a function
+    // compiled in another unit
+    fn gen_extern_func_def(&mut self, idx: usize, def: FuncDef) -> Result<usize, CodeGenError> {
+        self.gen_signature(def);
+        self.add_code(";");
+        Ok(idx + 1)
+    }
+
+    // Declares the C function itself, rather than including its header
+    // and every other name in it, and calls go straight to it
+    fn gen_intrinsic(&mut self, idx: usize, def: FuncDef, c_ident: String) -> usize {
+        self.gen_signature(FuncDef {
+            symbol: new_symbol(c_ident),
+            ..def
+        });
+        self.add_code(";");
+        idx + 1
+    }
+
+    // Functions nested in others are a GNU extension, which needs them
+    // declared `auto` to be used ahead of their definition
+    fn gen_func_decl(&mut self, idx: usize, def: FuncDef) -> Result<usize, CodeGenError> {
+        self.add_code("auto");
+        self.gen_signature(def);
+        self.add_code(";");
+        Ok(idx + 1)
+    }
+
+    fn gen_signature(&mut self, def: FuncDef) {
+        let params_t: Vec<Type> = def.params_t.iter().map(|(_, t)| t.clone()).collect();
+        if abi::fn_abi(&params_t, &def.return_t).is_some_and(|abi| abi.noreturn) {
+            self.add_code("_Noreturn");
+        }
         let return_type = &self.translate_type(def.return_t);
         self.add_code(return_type);
-        self.add_code(&def.symbol.ident);
+        self.add_code(&translate_ident(&def.symbol.ident));
         self.add_code("(");
         let num_params = def.params_t.clone().len();
         for (n, param) in def.params_t.into_iter().enumerate() {
             let param_type = &self.translate_type(param.1);
             self.add_code(param_type);
-            self.add_code(&param.0);
+            self.add_code(&translate_ident(&param.0));
             if n != num_params - 1 {
                 self.add_code(",");
             }
         }
         self.add_code(")");
-        self.add_code("{");
-        Ok(idx + 1)
     }
 
     fn gen_end_func_def(&mut self, idx: usize) -> Result<usize, CodeGenError> {
         self.add_code("}");
+        self.funcs.pop();
         Ok(idx + 1)
     }
 
     fn gen_return(&mut self, idx: usize) -> Result<usize, CodeGenError> {
         self.add_code("return");
-        self.gen_expr(idx - 1);
+        self.gen_expr(idx - 1)?;
+        self.add_code(";");
+        Ok(idx + 1)
+    }
+
+    fn gen_discard(&mut self, idx: usize) -> Result<usize, CodeGenError> {
+        self.gen_expr(idx - 1)?;
         self.add_code(";");
         Ok(idx + 1)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen;
+    use crate::compile;
+    use crate::session::Session;
+
+    // Functions are generated at the same time, but their C comes out
+    // the same on every run, in the order they're defined
+    #[test]
+    fn gen_functions_in_order() {
+        let mut src = String::new();
+        for n in 0..40 {
+            src.push_str(&format!(
+                "fun f{}(g: (int32) -> int32) -> int32 return g({}); end\n",
+                n, n
+            ));
+        }
+        src.push_str("fun id(x: int32) -> int32 return x; end\nprogram p return f39(id); end");
+        let c = || {
+            compile::compile_to_string(&src, &Session::default())
+                .unwrap()
+                .c
+        };
+        let first = c();
+        assert_eq!(c(), first);
+        let at = |n: usize| first.find(&format!("int32_t f{} ( _func_type_0 g ) {{", n));
+        let positions: Vec<_> = (0..40).map(|n| at(n).unwrap()).collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        // Every function shares the one typedef
+        assert_eq!(first.matches("typedef").count(), 1);
+    }
+
+    // Builtins are written by hand in C, against the same ABI
+    #[test]
+    fn builtins_follow_abi() {
+        let mut ctx = CGenContext::from(codegen::new(vec![], "a.out".into(), false));
+        for builtin in builtins::builtins().into_iter().chain(builtins::runtime()) {
+            let abi = abi::fn_abi(&builtin.params_t, &builtin.return_t).unwrap();
+            let name = format!(
+                "{} {}(",
+                ctx.translate_type(builtin.return_t.clone()),
+                builtin.c_ident
+            );
+            let params = &builtin.c_def[builtin.c_def.find(&name).unwrap() + name.len()..];
+            let params: Vec<&str> = params[..params.find(')').unwrap()]
+                .split(',')
+                .filter(|param| param.trim() != "void")
+                .collect();
+            assert_eq!(params.len(), builtin.params_t.len(), "{}", builtin.ident);
+            for (param, param_t) in params.iter().zip(builtin.params_t.iter()) {
+                let c_type = ctx.translate_type(param_t.clone());
+                assert!(param.trim().starts_with(&c_type), "{}", builtin.ident);
+            }
+            assert_eq!(abi.noreturn, builtin.c_def.starts_with("_Noreturn"));
+        }
+    }
+
+    #[test]
+    fn translate_multibyte_strings() {
+        assert_eq!(
+            translate_value(ir::Value::String("é \"世\"\n".into())),
+            r#""\303\251 \"\344\270\226\"\n""#
+        );
+        assert_eq!(
+            translate_value(ir::Value::String("a\u{1}\u{7f}\u{1b}[0m".into())),
+            r#""a\001\177\033[0m""#
+        );
+    }
+
+    // Floats reach C with the same bits, specials included
+    #[test]
+    fn translate_floats_exactly() {
+        let f64s = [0.1, -0.0, 1e300, 5e-324, f64::MAX, 1.0 / 3.0, 100.0];
+        for num in f64s {
+            let rep = translate_value(ir::Value::Float64(num));
+            assert_eq!(
+                rep.parse::<f64>().unwrap().to_bits(),
+                num.to_bits(),
+                "{}",
+                rep
+            );
+        }
+        let f32s = [0.1f32, 16_777_217.0, f32::MIN_POSITIVE, 3.4e38];
+        for num in f32s {
+            let rep = translate_value(ir::Value::Float32(num));
+            let digits = rep.strip_suffix('F').unwrap();
+            assert_eq!(
+                digits.parse::<f32>().unwrap().to_bits(),
+                num.to_bits(),
+                "{}",
+                rep
+            );
+        }
+        assert_eq!(translate_value(ir::Value::Float64(100.0)), "100.0");
+        assert_eq!(translate_value(ir::Value::Float64(1e300)), "1e300");
+        assert_eq!(
+            translate_value(ir::Value::Float64(f64::NEG_INFINITY)),
+            "-__builtin_inf()"
+        );
+        assert_eq!(
+            translate_value(ir::Value::Float32(f32::INFINITY)),
+            "__builtin_inff()"
+        );
+        assert_eq!(
+            translate_value(ir::Value::Float64(f64::NAN)),
+            "__builtin_nan(\"0x0\")"
+        );
+        assert_eq!(
+            translate_value(ir::Value::Float32(f32::from_bits(0xFF80_0001))),
+            "-__builtin_nansf(\"0x1\")"
+        );
+    }
+
+    #[test]
+    fn translate_unicode_identifiers() {
+        assert_eq!(translate_ident("plain_id"), "plain_id");
+        assert_eq!(translate_ident("größe"), r"gr\u00F6\u00DFe");
+        assert_eq!(translate_ident("𝑥"), r"\U0001D465");
+    }
+
+    #[test]
+    fn locate_gcc_errors() {
+        let stderr = "out.c: In function 'f':
+out.c:3:10: warning: unused variable 'y'
+out.c:3:22: error: 'x' undeclared (first use in this function)
+";
+        let (line, column, error) = gcc_error(stderr).unwrap();
+        assert_eq!((line, column), (3, 22));
+        assert_eq!(error, "'x' undeclared (first use in this function)");
+
+        let mut ctx = CGenContext::from(codegen::new(vec![], "a.out".into(), false));
+        ctx.add_code("#include \"stdint.h\"\n");
+        ctx.funcs.push("f".into());
+        ctx.add_code("int32_t f(){");
+        ctx.add_code("return x;");
+        ctx.add_code("}");
+        ctx.funcs.pop();
+        let source = ctx.source();
+        assert_eq!(ctx.origin(&source, 3, 15), Some("f".into()));
+        assert_eq!(ctx.origin(&source, 2, 3), None);
+    }
+
+    #[test]
+    fn gen_sections_in_order() {
+        let int = |n| {
+            IRNode::Term(ir::Term {
+                type_t: Type::Int32,
+                value: ir::Value::Int32(n),
+            })
+        };
+        let build_stack = vec![
+            IRNode::GlobalSection,
+            int(1),
+            IRNode::Assign(ir::Assign {
+                type_t: Type::Int32,
+                symbol: crate::symbol::new_symbol("g".into()),
+            }),
+            IRNode::FuncDef(
+                ir::new_func_def("f", vec![], Type::Int32),
+                "_func_def_0".into(),
+            ),
+            int(2),
+            IRNode::Return,
+            IRNode::EndFuncDef("_func_def_0".into()),
+            IRNode::EndGlobalSection,
+            IRNode::Program,
+            int(0),
+            IRNode::Return,
+        ];
+        let mut ctx = CGenContext::from(codegen::new(build_stack.clone(), "a.out".into(), false));
+        ctx.gen_includes().unwrap();
+        ctx.gen_units().unwrap();
+        let source = ctx.source();
+        let at = |s: &str| source.find(s).unwrap();
+        assert!(at("/* includes */") < at("/* prototypes */"));
+        assert!(at("int32_t f ( ) ;") < at("/* globals */"));
+        assert!(at("int32_t g = INT32_C(1)") < at("/* functions */"));
+        assert!(at("int32_t f ( ) {") < at("/* main */"));
+        assert!(at("int main(){") > at("/* main */"));
+
+        let mut ctx = CGenContext::from(codegen::new(
+            build_stack[1..].to_vec(),
+            "a.out".into(),
+            false,
+        ));
+        assert!(matches!(ctx.gen_units(), Err(CodeGenError::MalformedIR(_))));
+    }
+
+    #[test]
+    fn gen_call_statements_in_argument_order() {
+        let int = |n| {
+            IRNode::Term(ir::Term {
+                type_t: Type::Int32,
+                value: ir::Value::Int32(n),
+            })
+        };
+        let call = |ident: &str, params_t: Vec<Type>| {
+            IRNode::Eval(ir::Func::Func(ir::new_sig(ident, params_t, Type::Int32)))
+        };
+        let build_stack = vec![
+            int(1),
+            call("zero", vec![]),
+            call("sub", vec![Type::Int32, Type::Int32]),
+            IRNode::Discard,
+        ];
+        let len = build_stack.len();
+        let mut ctx = CGenContext::from(codegen::new(build_stack, "a.out".into(), false));
+        ctx.gen_code(0, len).unwrap();
+        let code: Vec<&str> = ctx.sections[Section::Main as usize]
+            .iter()
+            .map(|(code, _)| code.as_str())
+            .collect();
+        assert_eq!(code.join(" "), "( sub(INT32_C(1), ( zero() )) ) ;");
+    }
+
+    #[test]
+    fn gen_operators_in_operand_order() {
+        let int = |n| {
+            IRNode::Term(ir::Term {
+                type_t: Type::Int32,
+                value: ir::Value::Int32(n),
+            })
+        };
+        let op = |func: fn(ir::Signature) -> ir::Func| {
+            IRNode::Eval(func(ir::new_sig(
+                "op",
+                vec![Type::Int32, Type::Int32],
+                Type::Int32,
+            )))
+        };
+        // 1 > (7 - 2) / 5
+        let build_stack = vec![
+            int(1),
+            int(7),
+            int(2),
+            op(ir::Func::Sub),
+            int(5),
+            op(ir::Func::Div),
+            op(ir::Func::Gt),
+            IRNode::Discard,
+        ];
+        let len = build_stack.len();
+        let mut ctx = CGenContext::from(codegen::new(build_stack, "a.out".into(), false));
+        ctx.gen_code(0, len).unwrap();
+        let code: Vec<&str> = ctx.sections[Section::Main as usize]
+            .iter()
+            .map(|(code, _)| code.as_str())
+            .collect();
+        assert_eq!(
+            code.join(" "),
+            "( INT32_C(1) > ( ( INT32_C(7) - INT32_C(2) ) / INT32_C(5) ) ) ;"
+        );
+
+        let build_stack = vec![int(7), op(ir::Func::Sub)];
+        let mut ctx = CGenContext::from(codegen::new(build_stack, "a.out".into(), false));
+        assert!(matches!(ctx.gen_expr(1), Err(CodeGenError::MalformedIR(_))));
+        // An operand short in a function or the program is an error for
+        // the whole file, not a panic
+        let short = || [int(7), op(ir::Func::Sub), IRNode::Return];
+        let def = ir::new_func_def("f", vec![], Type::Int32);
+        let in_program = [
+            IRNode::GlobalSection,
+            IRNode::EndGlobalSection,
+            IRNode::Program,
+        ];
+        let in_function = [IRNode::GlobalSection, IRNode::FuncDef(def, "f".into())];
+        let function_end = [IRNode::EndFuncDef("f".into()), IRNode::EndGlobalSection];
+        for build_stack in [
+            in_program.into_iter().chain(short()).collect(),
+            in_function
+                .into_iter()
+                .chain(short())
+                .chain(function_end)
+                .collect(),
+        ] {
+            let mut ctx = CGenContext::from(codegen::new(build_stack, "a.out".into(), false));
+            assert!(matches!(
+                ctx.gen_source(),
+                Err(CodeGenError::MalformedIR(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn gen_frame_tracking_and_checks_with_debug_info() {
+        let src = "fun f(n: int32) -> int32
+    return 10 / n + n / 2;
+end
+
+program p
+    return f(0);
+end
+";
+        let root = crate::parser::parse(src).unwrap();
+        let state = compile::analyze_root(root, &Session::default(), true).unwrap();
+        let mut ctx = codegen::new(state.build_stack.clone(), "a.out".into(), false);
+        ctx.debug_src = Some(src.into());
+        let source = CGenContext::from(ctx).gen_source().unwrap();
+        let at = |s: &str| source.find(s).unwrap();
+        assert!(at("_rascal_enter(\"f\");") < at("_rascal_line(2);"));
+        assert!(source.contains("INT32_C(10) / _rascal_divisor_int32_t(n)"));
+        assert!(source.contains("n / INT32_C(2)"));
+        assert!(at("_rascal_start();") < at("_rascal_enter(\"program\");"));
+        assert!(at("_rascal_enter(\"program\");") < at("_rascal_line(6);"));
+
+        // Without the source, the markers are left out
+        let ctx = codegen::new(state.build_stack, "a.out".into(), false);
+        let source = CGenContext::from(ctx).gen_source().unwrap();
+        assert!(!source.contains("_rascal"));
+    }
+
+    // The IR of a program, a node a line, and its C, a statement a line,
+    // or the error that stopped the build
+    fn snapshots(src: &str) -> Result<(String, String), String> {
+        let state = compile::analyze(src, &Session::default()).map_err(|err| err.to_string())?;
+        let ir: Vec<String> = state
+            .build_stack
+            .iter()
+            .map(|node| format!("{:?}", node))
+            .collect();
+
+        let mut ctx = CGenContext::from(codegen::new(state.build_stack, "a.out".into(), false));
+        ctx.gen_source().map_err(|err| err.to_string())?;
+        let mut c = String::new();
+        let mut depth = 0;
+        let mut line_start = true;
+        for code in ctx.code_buffer.iter() {
+            if code == "}" {
+                depth -= 1;
+            }
+            if line_start {
+                c.push_str(&"    ".repeat(depth));
+            } else if !c.ends_with('\n') {
+                c.push(' ');
+            }
+            c.push_str(code.trim_start_matches(' '));
+            line_start = code.ends_with(['\n', ';', '{']) || code == "}";
+            if code.ends_with('{') {
+                depth += 1;
+            }
+            if line_start && !c.ends_with('\n') {
+                c.push('\n');
+            }
+        }
+        Ok((ir.join("\n"), c))
+    }
+
+    // Changes to the IR or C generated for the samples show up as diffs
+    // of the snapshots beside this file, to be reviewed and accepted with
+    // `cargo insta review`
+    #[test]
+    fn snapshot_samples() {
+        insta::glob!("../../samples", "*.ras", |path| {
+            let src = std::fs::read_to_string(path).unwrap();
+            match snapshots(&src) {
+                Ok((ir, c)) => {
+                    insta::assert_snapshot!("ir", ir);
+                    insta::assert_snapshot!("c", c);
+                }
+                Err(err) => insta::assert_snapshot!("error", err),
+            }
+        });
+    }
+}
@@ -0,0 +1,1306 @@
+use crate::backends::{self, Feature};
+use crate::codegen::CodeGenError;
+use crate::intrinsics;
+use crate::ir::{self, FuncDef, IRNode, Value};
+use crate::lower;
+use crate::types::Type;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+// Runs the IR in process rather than compiling it. The postfix IR is
+// first rebuilt into a tree of statements, each holding the range of
+// nodes that make up its expressions, which are evaluated on a stack as
+// they're written. Names are looked up through nested environments the
+// way C scopes them, so nested functions see the variables around their
+// definition.
+
+// Something that stops a program while it runs
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Trap {
+    #[error("Division by zero")]
+    DivisionByZero,
+    // By as many bits as the type has or more, or by a negative amount
+    #[error("Shift amount out of range")]
+    ShiftOutOfRange,
+    // Past the end of an array, or before its start
+    #[error("Index out of range")]
+    IndexOutOfRange,
+    // The program called `exit`
+    #[error("The program exited with {0}")]
+    Exit(i32),
+    #[error("No function {0} to call")]
+    Unbound(String),
+    #[error("Host function {function} failed: {message}")]
+    Host { function: String, message: String },
+    // Operands of the wrong type, which checked IR never has
+    #[error("Invalid operation: {0}")]
+    Invalid(String),
+    #[error("The program exceeded its {0}")]
+    Limit(Limit),
+    // A hook asked for the program to stop, as when quitting a debugger
+    #[error("The program was stopped")]
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Memory,
+    Timeout,
+    Depth,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Steps => write!(f, "step limit"),
+            Limit::Memory => write!(f, "memory limit"),
+            Limit::Timeout => write!(f, "time limit"),
+            Limit::Depth => write!(f, "recursion limit"),
+        }
+    }
+}
+
+// Bounds on what running untrusted code can take, each counted afresh
+// every time the interpreter is entered. None is unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    // Statements executed and expression nodes evaluated
+    pub max_steps: Option<u64>,
+    // Bytes held by variables, roughly: each variable counts the size of
+    // a value and the bytes of its string, while the scope defining it
+    // lives
+    pub max_memory: Option<usize>,
+    pub timeout: Option<Duration>,
+    // Calls to functions defined in Rascal that haven't returned. Without
+    // a limit, deep enough recursion overflows the host's stack.
+    pub max_depth: Option<usize>,
+}
+
+// Functions provided by the program embedding the interpreter, called
+// through `@extern` declarations. Nil functions return None.
+pub type HostFn = Rc<dyn Fn(&[Value]) -> Result<Option<Value>, Trap>>;
+
+// Where `clock`, and the timing of `bench` blocks, read the time from. A
+// virtual clock is the number of milliseconds in the cell, which only
+// changes when the embedder sets it, so that runs given the same times
+// do the same thing.
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    Virtual(Rc<Cell<u64>>),
+}
+
+// A call that hasn't returned, or the program block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    // Where the statement running in it starts in the source, known in
+    // IR lowered with debug info
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Local {
+    pub ident: String,
+    pub type_t: Type,
+    pub value: Value,
+}
+
+// Runs before each statement of IR lowered with debug info, which is
+// paused until it returns. Returning a trap stops the program with it.
+pub trait Hook {
+    fn before(&self, pause: &Pause) -> Result<(), Trap>;
+}
+
+pub struct Pause<'a> {
+    // Where the statement about to run starts in the source
+    pub offset: usize,
+    // Innermost last
+    pub frames: &'a [Frame],
+    env: &'a Env,
+}
+
+impl Pause<'_> {
+    // The variables the statement can see, innermost first, leaving out
+    // the globals and those shadowed. They go by their names in the
+    // source, not the ones lowering gives locals that shadow others.
+    pub fn locals(&self) -> Vec<Local> {
+        let mut locals: Vec<Local> = vec![];
+        let mut env = self.env;
+        while let Some(parent) = &env.parent {
+            let slots = env.slots.borrow();
+            let mut scope: Vec<(Option<usize>, Local)> = slots
+                .iter()
+                .filter_map(|(ident, slot)| match slot {
+                    Slot::Var(var, type_t) => {
+                        let (ident, id) = lower::source_ident(ident);
+                        let local = Local {
+                            ident: ident.to_string(),
+                            type_t: type_t.clone(),
+                            value: var.borrow().clone(),
+                        };
+                        Some((id, local))
+                    }
+                    _ => None,
+                })
+                .collect();
+            // A name declared again in the same block, the latest first
+            scope.sort_by(|(a_id, a), (b_id, b)| a.ident.cmp(&b.ident).then(b_id.cmp(a_id)));
+            for (_, local) in scope {
+                if !locals.iter().any(|other| other.ident == local.ident) {
+                    locals.push(local);
+                }
+            }
+            env = parent;
+        }
+        locals
+    }
+}
+
+// The nodes of an expression, in postfix order
+type Expr = Range<usize>;
+
+#[derive(Debug)]
+enum Stmt {
+    Assign(String, Type, Expr),
+    // Keyed by the position of its node, to find the value kept from the
+    // previous call
+    Static(usize, String, Type, Expr),
+    Reassign(String, Expr),
+    // An array of the type, with its elements zeroed
+    Declare(String, Type),
+    // The expression leaves the index and then the value
+    Store(String, Expr),
+    // Cases in order, the condition of an `else` being None
+    If(Vec<(Option<Expr>, Vec<Stmt>)>),
+    // Cases in order, the labels of the `default` being None
+    Switch(Expr, Vec<(Option<Vec<Value>>, Vec<Stmt>)>),
+    Repeat(String, Vec<Stmt>, Expr),
+    While(String, Expr, Vec<Stmt>),
+    Break(String),
+    Continue(String),
+    Discard(Expr),
+    Return(Expr),
+    FuncDef(Rc<Func>),
+    Extern(FuncDef),
+    // A function and the C function it stands for
+    Intrinsic(String, String),
+    Loc(usize),
+}
+
+#[derive(Debug)]
+pub struct Func {
+    pub def: FuncDef,
+    body: Vec<Stmt>,
+}
+
+enum Flow {
+    Normal,
+    Break(String),
+    Continue(String),
+    Return(Option<Value>),
+}
+
+#[derive(Clone)]
+enum Slot {
+    Var(Rc<RefCell<Value>>, Type),
+    // The elements of an array
+    Array(Rc<RefCell<Vec<Value>>>),
+    // A function and the environment it was defined in
+    Func(Rc<Func>, Rc<Env>),
+    Extern(FuncDef),
+    // The C function an intrinsic stands for
+    Intrinsic(String),
+}
+
+struct Env {
+    slots: RefCell<HashMap<String, Slot>>,
+    parent: Option<Rc<Env>>,
+    // The bytes in use by all scopes, and those of its variables, given
+    // back when the scope goes
+    memory: Rc<Cell<usize>>,
+    charged: Cell<usize>,
+}
+
+impl Env {
+    fn root() -> Rc<Env> {
+        Rc::new(Env {
+            slots: RefCell::new(HashMap::new()),
+            parent: None,
+            memory: Rc::new(Cell::new(0)),
+            charged: Cell::new(0),
+        })
+    }
+
+    fn child(parent: &Rc<Env>) -> Rc<Env> {
+        Rc::new(Env {
+            slots: RefCell::new(HashMap::new()),
+            parent: Some(parent.clone()),
+            memory: parent.memory.clone(),
+            charged: Cell::new(0),
+        })
+    }
+
+    fn define(&self, ident: &str, slot: Slot) {
+        let size = match &slot {
+            Slot::Var(var, _) => size_of(&var.borrow()),
+            Slot::Array(elements) => elements.borrow().iter().map(size_of).sum(),
+            _ => 0,
+        };
+        self.charged.set(self.charged.get() + size);
+        self.memory.set(self.memory.get() + size);
+        self.slots.borrow_mut().insert(ident.to_string(), slot);
+    }
+
+    fn lookup(&self, ident: &str) -> Option<Slot> {
+        match self.slots.borrow().get(ident) {
+            Some(slot) => Some(slot.clone()),
+            None => self.parent.as_ref().and_then(|parent| parent.lookup(ident)),
+        }
+    }
+}
+
+impl Drop for Env {
+    fn drop(&mut self) {
+        self.memory.set(self.memory.get() - self.charged.get());
+    }
+}
+
+fn size_of(value: &Value) -> usize {
+    std::mem::size_of::<Value>()
+        + match value {
+            Value::String(s) | Value::Id(s) => s.len(),
+            _ => 0,
+        }
+}
+
+// Rebuilds the statements from the IR
+struct Parser<'a> {
+    nodes: &'a [IRNode],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    // The expression starting at the current position, and the node
+    // after it, None at the end of the IR
+    fn next(&mut self) -> (Expr, Option<usize>) {
+        let start = self.pos;
+        let mut end = start;
+        while end < self.nodes.len()
+            && matches!(
+                self.nodes[end],
+                IRNode::Term(_) | IRNode::Eval(_) | IRNode::Load(_)
+            )
+        {
+            end += 1;
+        }
+        (start..end, (end < self.nodes.len()).then_some(end))
+    }
+
+    // Statements up to the node for which `ends` holds, returning them
+    // with the expression just before that node, and the node's position
+    fn block(
+        &mut self,
+        ends: &dyn Fn(Option<&IRNode>) -> bool,
+    ) -> Result<(Vec<Stmt>, Expr, Option<usize>), CodeGenError> {
+        let mut stmts = vec![];
+        loop {
+            let (expr, at) = self.next();
+            let node = at.map(|at| &self.nodes[at]);
+            if ends(node) {
+                self.pos = at.map_or(self.nodes.len(), |at| at + 1);
+                return Ok((stmts, expr, at));
+            }
+            let (Some(at), Some(node)) = (at, node) else {
+                return Err(CodeGenError::MalformedIR("a block isn't closed".into()));
+            };
+            self.pos = at + 1;
+            let consumed = matches!(
+                node,
+                IRNode::Assign(_)
+                    | IRNode::StaticAssign(_)
+                    | IRNode::Reassign(_)
+                    | IRNode::Store(_)
+                    | IRNode::IfCase(_)
+                    | IRNode::Switch(_)
+                    | IRNode::While(_)
+                    | IRNode::Discard
+                    | IRNode::Return
+            );
+            if !consumed && !expr.is_empty() {
+                return Err(CodeGenError::MalformedIR(format!(
+                    "{:?} follows an expression",
+                    node
+                )));
+            }
+            match node {
+                IRNode::Assign(assign) => {
+                    let ident = assign.symbol.ident.clone();
+                    stmts.push(Stmt::Assign(ident, assign.type_t.clone(), expr))
+                }
+                IRNode::StaticAssign(assign) => {
+                    let ident = assign.symbol.ident.clone();
+                    stmts.push(Stmt::Static(at, ident, assign.type_t.clone(), expr))
+                }
+                IRNode::Reassign(reassign) => {
+                    stmts.push(Stmt::Reassign(reassign.symbol.ident.clone(), expr))
+                }
+                IRNode::Declare(assign) => {
+                    let ident = assign.symbol.ident.clone();
+                    stmts.push(Stmt::Declare(ident, assign.type_t.clone()))
+                }
+                IRNode::Store(access) => stmts.push(Stmt::Store(access.symbol.ident.clone(), expr)),
+                IRNode::IfCase(id) => stmts.push(self.if_stmt(expr, id)?),
+                IRNode::Switch(id) => stmts.push(self.switch(expr, id)?),
+                IRNode::Repeat(id) => {
+                    let (block, condition, _) =
+                        self.block(&|node| matches!(node, Some(IRNode::Until(end)) if end == id))?;
+                    stmts.push(Stmt::Repeat(id.clone(), block, condition));
+                }
+                IRNode::While(id) => {
+                    let (block, _, _) = self.closed_block(
+                        &|node| matches!(node, Some(IRNode::EndWhile(end)) if end == id),
+                    )?;
+                    stmts.push(Stmt::While(id.clone(), expr, block));
+                }
+                IRNode::Break(id) => stmts.push(Stmt::Break(id.clone())),
+                IRNode::Continue(id) => stmts.push(Stmt::Continue(id.clone())),
+                IRNode::Discard => stmts.push(Stmt::Discard(expr)),
+                IRNode::Return => stmts.push(Stmt::Return(expr)),
+                IRNode::FuncDef(def, id) => {
+                    let (body, _, _) = self.closed_block(
+                        &|node| matches!(node, Some(IRNode::EndFuncDef(end)) if end == id),
+                    )?;
+                    stmts.push(Stmt::FuncDef(Rc::new(Func {
+                        def: def.clone(),
+                        body,
+                    })));
+                }
+                IRNode::ExternFuncDef(def) => stmts.push(Stmt::Extern(def.clone())),
+                IRNode::Intrinsic(def, ident) => {
+                    stmts.push(Stmt::Intrinsic(def.symbol.ident.clone(), ident.clone()))
+                }
+                IRNode::Loc(offset) => stmts.push(Stmt::Loc(*offset)),
+                // Functions are bound when their block is entered, and If
+                // only marks where the statements of its first case begin
+                IRNode::FuncDecl(_) | IRNode::If(_) | IRNode::Label(_) => {}
+                _ => {
+                    return Err(CodeGenError::MalformedIR(format!(
+                        "{:?} is out of place",
+                        node
+                    )))
+                }
+            }
+        }
+    }
+
+    // A block that no expression ends
+    fn closed_block(
+        &mut self,
+        ends: &dyn Fn(Option<&IRNode>) -> bool,
+    ) -> Result<(Vec<Stmt>, Expr, Option<usize>), CodeGenError> {
+        let block = self.block(ends)?;
+        if !block.1.is_empty() {
+            return Err(CodeGenError::MalformedIR(
+                "an expression ends a block".into(),
+            ));
+        }
+        Ok(block)
+    }
+
+    // The statements storing temporaries for the first condition come
+    // between If and IfCase, so the statement starts at IfCase
+    fn if_stmt(&mut self, condition: Expr, id: &str) -> Result<Stmt, CodeGenError> {
+        let mut condition = Some(condition);
+        let mut cases = vec![];
+        loop {
+            let (block, next_condition, at) = self.block(&|node| {
+                matches!(
+                    node,
+                    Some(IRNode::ElseIfCase(end) | IRNode::ElseCase(end) | IRNode::EndIf(end))
+                        if end == id
+                )
+            })?;
+            cases.push((condition, block));
+            match &self.nodes[at.unwrap()] {
+                IRNode::ElseIfCase(_) => condition = Some(next_condition),
+                _ if !next_condition.is_empty() => {
+                    return Err(CodeGenError::MalformedIR(
+                        "an expression ends a block".into(),
+                    ))
+                }
+                IRNode::ElseCase(_) => condition = None,
+                _ => return Ok(Stmt::If(cases)),
+            }
+        }
+    }
+
+    fn switch(&mut self, scrutinee: Expr, id: &str) -> Result<Stmt, CodeGenError> {
+        let is_case = |node: Option<&IRNode>| {
+            matches!(
+                node,
+                Some(IRNode::SwitchCase(end, _) | IRNode::DefaultCase(end) | IRNode::EndSwitch(end))
+                    if end == id
+            )
+        };
+        let (_, _, mut at) = self.closed_block(&is_case)?;
+        let mut cases = vec![];
+        loop {
+            let labels = match &self.nodes[at.unwrap()] {
+                IRNode::SwitchCase(_, labels) => Some(labels.clone()),
+                IRNode::DefaultCase(_) => None,
+                _ => return Ok(Stmt::Switch(scrutinee, cases)),
+            };
+            let (block, _, end) = self.closed_block(&is_case)?;
+            cases.push((labels, block));
+            at = end;
+        }
+    }
+}
+
+// Everything but objects, programs run straight from their IR
+pub const FEATURES: &[Feature] = &[
+    Feature::Functions,
+    Feature::ExternFunctions,
+    Feature::StaticVariables,
+    Feature::If,
+    Feature::Switch,
+    Feature::Repeat,
+    Feature::While,
+    Feature::Floats,
+    Feature::Strings,
+    Feature::Arrays,
+    Feature::DebugInfo,
+];
+
+pub struct Interpreter {
+    nodes: Vec<IRNode>,
+    globals: Vec<Stmt>,
+    program: Option<Vec<Stmt>>,
+    env: Rc<Env>,
+    statics: RefCell<HashMap<usize, Rc<RefCell<Value>>>>,
+    hosts: HashMap<String, HostFn>,
+    limits: Limits,
+    steps: Cell<u64>,
+    depth: Cell<usize>,
+    frames: RefCell<Vec<Frame>>,
+    // The frames when the last trap was raised, before they unwound
+    trace: RefCell<Vec<Frame>>,
+    hook: Option<Rc<dyn Hook>>,
+    started: Cell<Option<Instant>>,
+    // The state of the generator behind `random`
+    seed: Cell<u64>,
+    clock: Clock,
+    created: Instant,
+}
+
+impl Interpreter {
+    pub fn new(nodes: Vec<IRNode>, hosts: HashMap<String, HostFn>) -> Result<Self, CodeGenError> {
+        backends::check_support("interp", &backends::features(&nodes))?;
+        if nodes.first() != Some(&IRNode::GlobalSection) {
+            return Err(CodeGenError::MalformedIR(
+                "the globals don't come first".into(),
+            ));
+        }
+        let mut parser = Parser {
+            nodes: &nodes,
+            pos: 1,
+        };
+        let (globals, _, _) =
+            parser.closed_block(&|node| matches!(node, Some(IRNode::EndGlobalSection)))?;
+        let program = match parser.next() {
+            (_, Some(at)) if nodes[at] == IRNode::Program => {
+                parser.pos = at + 1;
+                Some(parser.closed_block(&|node| node.is_none())?.0)
+            }
+            (expr, None) if expr.is_empty() => None,
+            _ => {
+                return Err(CodeGenError::MalformedIR(
+                    "the program block doesn't follow the globals".into(),
+                ))
+            }
+        };
+        Ok(Interpreter {
+            nodes,
+            globals,
+            program,
+            env: Env::root(),
+            statics: RefCell::new(HashMap::new()),
+            hosts,
+            limits: Limits::default(),
+            steps: Cell::new(0),
+            depth: Cell::new(0),
+            frames: RefCell::new(vec![]),
+            trace: RefCell::new(vec![]),
+            hook: None,
+            started: Cell::new(None),
+            seed: Cell::new(now() ^ u64::from(std::process::id())),
+            clock: Clock::System,
+            created: Instant::now(),
+        })
+    }
+
+    // Makes `random` return the same numbers on every run
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.seed.set(seed);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // splitmix64, as in the C runtime
+    fn random(&self) -> u64 {
+        let seed = self.seed.get().wrapping_add(0x9E3779B97F4A7C15);
+        self.seed.set(seed);
+        let z = (seed ^ (seed >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn clock(&self) -> u64 {
+        match &self.clock {
+            Clock::System => now(),
+            Clock::Virtual(time) => time.get(),
+        }
+    }
+
+    // Nanoseconds since the interpreter was made, on a monotonic clock,
+    // or the virtual clock's milliseconds in nanoseconds
+    fn bench_now(&self) -> u64 {
+        match &self.clock {
+            Clock::System => self.created.elapsed().as_nanos() as u64,
+            Clock::Virtual(time) => time.get().saturating_mul(1_000_000),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_hook(mut self, hook: Rc<dyn Hook>) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    // The calls that were running when the last trap was raised,
+    // innermost last
+    pub fn backtrace(&self) -> Vec<Frame> {
+        self.trace.borrow().clone()
+    }
+
+    // Starts counting towards the limits again, in the given frame
+    fn enter(&self, frame: Option<&str>) {
+        self.steps.set(0);
+        self.depth.set(0);
+        *self.frames.borrow_mut() = frame
+            .map(|function| Frame {
+                function: function.to_string(),
+                offset: None,
+            })
+            .into_iter()
+            .collect();
+        self.trace.borrow_mut().clear();
+        self.started
+            .set(self.limits.timeout.map(|_| Instant::now()));
+    }
+
+    // Counts a step, and checks the limits that grow with every step
+    fn step(&self, env: &Env) -> Result<(), Trap> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if self.limits.max_steps.is_some_and(|max| steps > max) {
+            return Err(Trap::Limit(Limit::Steps));
+        }
+        if self
+            .limits
+            .max_memory
+            .is_some_and(|max| env.memory.get() > max)
+        {
+            return Err(Trap::Limit(Limit::Memory));
+        }
+        // Reading the clock on every step would slow everything down
+        if steps.is_multiple_of(1024) {
+            if let (Some(timeout), Some(started)) = (self.limits.timeout, self.started.get()) {
+                if started.elapsed() > timeout {
+                    return Err(Trap::Limit(Limit::Timeout));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Initializes the globals, which has to be done before anything else
+    pub fn init(&self) -> Result<(), Trap> {
+        self.enter(None);
+        self.exec_block(&self.globals, &self.env)
+            .map_err(|trap| self.unwound(trap))?;
+        Ok(())
+    }
+
+    pub fn has_program(&self) -> bool {
+        self.program.is_some()
+    }
+
+    // Runs the program block, returning its exit code
+    pub fn run(&self) -> Result<i32, Trap> {
+        let Some(program) = &self.program else {
+            return Ok(0);
+        };
+        self.enter(Some("program"));
+        match self
+            .exec_block(program, &Env::child(&self.env))
+            .map_err(|trap| self.unwound(trap))
+        {
+            // Converted as C converts the result of main to int
+            Ok(Flow::Return(Some(value))) => match as_i128(&value) {
+                Some(code) => Ok(code as i32),
+                None => Err(Trap::Invalid(format!("the program returned {:?}", value))),
+            },
+            Ok(_) => Ok(0),
+            Err(Trap::Exit(code)) => Ok(code),
+            Err(trap) => Err(trap),
+        }
+    }
+
+    // A function defined at the top level
+    pub fn func(&self, ident: &str) -> Option<Rc<Func>> {
+        self.globals.iter().find_map(|stmt| match stmt {
+            Stmt::FuncDef(func) if func.def.symbol.ident == ident => Some(func.clone()),
+            _ => None,
+        })
+    }
+
+    // The functions declared `@extern`, which the host has to provide
+    pub fn externs(&self) -> Vec<FuncDef> {
+        self.globals
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Extern(def) => Some(def.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn call(&self, ident: &str, args: Vec<Value>) -> Result<Option<Value>, Trap> {
+        self.enter(None);
+        self.call_slot(ident, self.env.lookup(ident), args)
+            .map_err(|trap| self.unwound(trap))
+    }
+
+    fn call_slot(
+        &self,
+        ident: &str,
+        slot: Option<Slot>,
+        args: Vec<Value>,
+    ) -> Result<Option<Value>, Trap> {
+        match slot {
+            Some(Slot::Func(func, env)) => {
+                let frame = Env::child(&env);
+                for ((param, type_t), arg) in func.def.params_t.iter().zip(args) {
+                    frame.define(param, Slot::Var(Rc::new(RefCell::new(arg)), type_t.clone()));
+                }
+                let depth = self.depth.get() + 1;
+                if self.limits.max_depth.is_some_and(|max| depth > max) {
+                    return Err(Trap::Limit(Limit::Depth));
+                }
+                self.depth.set(depth);
+                self.frames.borrow_mut().push(Frame {
+                    function: ident.to_string(),
+                    offset: None,
+                });
+                let flow = self.exec_block(&func.body, &frame);
+                if flow.is_err() && self.trace.borrow().is_empty() {
+                    *self.trace.borrow_mut() = self.frames.borrow().clone();
+                }
+                self.frames.borrow_mut().pop();
+                self.depth.set(depth - 1);
+                match flow? {
+                    Flow::Return(value) => Ok(value),
+                    _ => Ok(None),
+                }
+            }
+            Some(Slot::Extern(def)) => match self.hosts.get(&def.symbol.ident) {
+                Some(host) => host(&args),
+                None => Err(Trap::Unbound(def.symbol.ident)),
+            },
+            Some(Slot::Intrinsic(ident)) => intrinsic(&ident, &args),
+            // A variable holding a function
+            Some(Slot::Var(var, _)) => match &*var.borrow() {
+                Value::Id(target) => self.call_slot(target, self.env.lookup(target), args),
+                value => Err(Trap::Invalid(format!("{:?} isn't a function", value))),
+            },
+            // Checked IR never calls an array
+            Some(Slot::Array(..)) | None => match (ident, args.as_slice()) {
+                ("random", []) => Ok(Some(Value::UInt64(self.random()))),
+                ("clock", []) => Ok(Some(Value::UInt64(self.clock()))),
+                ("bench.now", []) => Ok(Some(Value::UInt64(self.bench_now()))),
+                _ => builtin(ident, &args),
+            },
+        }
+    }
+
+    // Keeps the frames of the program block or of the globals, which
+    // aren't calls, when a trap unwinds them
+    fn unwound(&self, trap: Trap) -> Trap {
+        if self.trace.borrow().is_empty() {
+            *self.trace.borrow_mut() = self.frames.borrow().clone();
+        }
+        trap
+    }
+
+    fn exec_block(&self, stmts: &[Stmt], env: &Rc<Env>) -> Result<Flow, Trap> {
+        // Functions can be called before their definition in the block
+        for stmt in stmts {
+            match stmt {
+                Stmt::FuncDef(func) => env.define(
+                    &func.def.symbol.ident,
+                    Slot::Func(func.clone(), env.clone()),
+                ),
+                Stmt::Extern(def) => env.define(&def.symbol.ident, Slot::Extern(def.clone())),
+                Stmt::Intrinsic(ident, c_ident) => {
+                    env.define(ident, Slot::Intrinsic(c_ident.clone()))
+                }
+                _ => {}
+            }
+        }
+        for stmt in stmts {
+            let flow = self.exec(stmt, env)?;
+            if !matches!(flow, Flow::Normal) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&self, stmt: &Stmt, env: &Rc<Env>) -> Result<Flow, Trap> {
+        self.step(env)?;
+        match stmt {
+            Stmt::Assign(ident, type_t, expr) => {
+                let value = self.value(expr, env)?;
+                env.define(
+                    ident,
+                    Slot::Var(Rc::new(RefCell::new(value)), type_t.clone()),
+                );
+            }
+            Stmt::Static(key, ident, type_t, expr) => {
+                let existing = self.statics.borrow().get(key).cloned();
+                let var = match existing {
+                    Some(var) => var,
+                    None => {
+                        let var = Rc::new(RefCell::new(self.value(expr, env)?));
+                        self.statics.borrow_mut().insert(*key, var.clone());
+                        var
+                    }
+                };
+                env.define(ident, Slot::Var(var, type_t.clone()));
+            }
+            Stmt::Reassign(ident, expr) => {
+                let value = self.value(expr, env)?;
+                match env.lookup(ident) {
+                    Some(Slot::Var(var, _)) => *var.borrow_mut() = value,
+                    _ => return Err(Trap::Invalid(format!("{} isn't a variable", ident))),
+                }
+            }
+            Stmt::Declare(..) | Stmt::Store(..) => self.exec_array(stmt, env)?,
+            Stmt::If(cases) => {
+                for (condition, block) in cases {
+                    let taken = match condition {
+                        Some(condition) => truthy(&self.value(condition, env)?)?,
+                        None => true,
+                    };
+                    if taken {
+                        return self.exec_block(block, &Env::child(env));
+                    }
+                }
+            }
+            Stmt::Switch(scrutinee, cases) => {
+                let value = self.value(scrutinee, env)?;
+                let matched = cases
+                    .iter()
+                    .find(|(labels, _)| match labels {
+                        Some(labels) => labels.iter().any(|label| same_number(label, &value)),
+                        None => false,
+                    })
+                    .or_else(|| cases.iter().find(|(labels, _)| labels.is_none()));
+                if let Some((_, block)) = matched {
+                    return self.exec_block(block, &Env::child(env));
+                }
+            }
+            Stmt::Repeat(id, block, condition) => loop {
+                match self.exec_block(block, &Env::child(env))? {
+                    Flow::Break(target) if target == *id => break,
+                    Flow::Continue(target) if target == *id => {}
+                    Flow::Normal => {}
+                    flow => return Ok(flow),
+                }
+                if truthy(&self.value(condition, env)?)? {
+                    break;
+                }
+            },
+            Stmt::While(id, condition, block) => {
+                while truthy(&self.value(condition, env)?)? {
+                    match self.exec_block(block, &Env::child(env))? {
+                        Flow::Break(target) if target == *id => break,
+                        Flow::Continue(target) if target == *id => {}
+                        Flow::Normal => {}
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Stmt::Break(id) => return Ok(Flow::Break(id.clone())),
+            Stmt::Continue(id) => return Ok(Flow::Continue(id.clone())),
+            Stmt::Discard(expr) => {
+                self.eval(expr, env)?;
+            }
+            Stmt::Return(expr) => return Ok(Flow::Return(self.eval(expr, env)?.pop())),
+            Stmt::Loc(offset) => {
+                if let Some(frame) = self.frames.borrow_mut().last_mut() {
+                    frame.offset = Some(*offset);
+                }
+                if let Some(hook) = &self.hook {
+                    hook.before(&Pause {
+                        offset: *offset,
+                        frames: &self.frames.borrow(),
+                        env,
+                    })?;
+                }
+            }
+            Stmt::FuncDef(_) | Stmt::Extern(_) | Stmt::Intrinsic(..) => {}
+        }
+        Ok(Flow::Normal)
+    }
+
+    // Kept out of `exec`, whose frame every call nested in an expression
+    // adds to the host's stack
+    fn exec_array(&self, stmt: &Stmt, env: &Rc<Env>) -> Result<(), Trap> {
+        match stmt {
+            Stmt::Declare(ident, Type::Array(element_t, length)) => {
+                let elements = vec![zero(element_t)?; *length];
+                env.define(ident, Slot::Array(Rc::new(RefCell::new(elements))));
+            }
+            Stmt::Store(ident, expr) => {
+                let mut operands = self.eval(expr, env)?;
+                let (Some(value), Some(index)) = (operands.pop(), operands.pop()) else {
+                    return Err(Trap::Invalid("a store is missing operands".into()));
+                };
+                let elements = array(env, ident)?;
+                *element(&mut elements.borrow_mut(), &index)? = value;
+            }
+            stmt => return Err(Trap::Invalid(format!("{:?} as an array", stmt))),
+        }
+        Ok(())
+    }
+
+    fn value(&self, expr: &Expr, env: &Rc<Env>) -> Result<Value, Trap> {
+        self.eval(expr, env)?
+            .pop()
+            .ok_or_else(|| Trap::Invalid("an expression has no value".into()))
+    }
+
+    // Evaluates the nodes of an expression in order, returning the
+    // values left on the stack: one, none for a call to a function
+    // returning Nil, or both the index and the value of a store
+    fn eval(&self, expr: &Expr, env: &Rc<Env>) -> Result<Vec<Value>, Trap> {
+        let mut stack: Vec<Value> = vec![];
+        let underflow = || Trap::Invalid("an operator is missing operands".into());
+        for node in &self.nodes[expr.clone()] {
+            self.step(env)?;
+            match node {
+                IRNode::Term(term) => stack.push(match &term.value {
+                    Value::Id(ident) => match env.lookup(ident) {
+                        Some(Slot::Var(var, _)) => var.borrow().clone(),
+                        Some(_) => Value::Id(ident.clone()),
+                        None => return Err(Trap::Unbound(ident.clone())),
+                    },
+                    value => value.clone(),
+                }),
+                IRNode::Load(access) => load(&mut stack, env, &access.symbol.ident)?,
+                IRNode::Eval(ir::Func::Func(sig)) => {
+                    let count = sig.params_t.len();
+                    if stack.len() < count {
+                        return Err(underflow());
+                    }
+                    let args = stack.split_off(stack.len() - count);
+                    let ident = &sig.symbol.ident;
+                    if let Some(value) = self.call_slot(ident, env.lookup(ident), args)? {
+                        stack.push(value);
+                    }
+                }
+                IRNode::Eval(ir::Func::Not(_)) => match stack.pop().ok_or_else(underflow)? {
+                    Value::Bool(b) => stack.push(Value::Bool(!b)),
+                    value => return Err(Trap::Invalid(format!("!{:?}", value))),
+                },
+                IRNode::Eval(ir::Func::Neg(_)) => {
+                    let value = stack.pop().ok_or_else(underflow)?;
+                    stack.push(negate(value)?);
+                }
+                IRNode::Eval(ir::Func::BitNot(_)) => match stack.pop().ok_or_else(underflow)? {
+                    Value::Int32(n) => stack.push(Value::Int32(!n)),
+                    Value::Int64(n) => stack.push(Value::Int64(!n)),
+                    Value::UInt32(n) => stack.push(Value::UInt32(!n)),
+                    Value::UInt64(n) => stack.push(Value::UInt64(!n)),
+                    value => return Err(Trap::Invalid(format!("~{:?}", value))),
+                },
+                IRNode::Eval(ir::Func::Cast(sig)) => {
+                    let value = stack.pop().ok_or_else(underflow)?;
+                    stack.push(cast(value, &sig.return_t)?);
+                }
+                IRNode::Eval(func) => {
+                    let rhs = stack.pop().ok_or_else(underflow)?;
+                    let lhs = stack.pop().ok_or_else(underflow)?;
+                    stack.push(binary(func, lhs, rhs)?);
+                }
+                _ => return Err(Trap::Invalid(format!("{:?} in an expression", node))),
+            }
+        }
+        Ok(stack)
+    }
+}
+
+// Replaces the index on top of the stack with the element at it. Kept
+// out of `eval`, whose frame every nested call adds to the host's stack.
+fn load(stack: &mut Vec<Value>, env: &Env, ident: &str) -> Result<(), Trap> {
+    let index = stack
+        .pop()
+        .ok_or_else(|| Trap::Invalid("a load is missing its index".into()))?;
+    let elements = array(env, ident)?;
+    let value = element(&mut elements.borrow_mut(), &index)?.clone();
+    stack.push(value);
+    Ok(())
+}
+
+fn array(env: &Env, ident: &str) -> Result<Rc<RefCell<Vec<Value>>>, Trap> {
+    match env.lookup(ident) {
+        Some(Slot::Array(elements)) => Ok(elements),
+        _ => Err(Trap::Invalid(format!("{} isn't an array", ident))),
+    }
+}
+
+fn element<'a>(elements: &'a mut [Value], index: &Value) -> Result<&'a mut Value, Trap> {
+    let index = as_i128(index).ok_or_else(|| Trap::Invalid(format!("{:?} as an index", index)))?;
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| elements.get_mut(index))
+        .ok_or(Trap::IndexOutOfRange)
+}
+
+// What the elements of a new array start out as
+fn zero(type_t: &Type) -> Result<Value, Trap> {
+    Ok(match type_t {
+        Type::Int32 => Value::Int32(0),
+        Type::Int64 => Value::Int64(0),
+        Type::UInt32 => Value::UInt32(0),
+        Type::UInt64 => Value::UInt64(0),
+        Type::Float32 => Value::Float32(0.0),
+        Type::Float64 => Value::Float64(0.0),
+        Type::Bool => Value::Bool(false),
+        _ => return Err(Trap::Invalid(format!("an array of {}", type_t))),
+    })
+}
+
+fn truthy(value: &Value) -> Result<bool, Trap> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        // Numbers are conditions in earlier editions
+        value => match as_f64(value) {
+            Some(n) => Ok(n != 0.0),
+            None => Err(Trap::Invalid(format!("{:?} as a condition", value))),
+        },
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    Some(match value {
+        Value::Int32(n) => *n as f64,
+        Value::Int64(n) => *n as f64,
+        Value::UInt32(n) => *n as f64,
+        Value::UInt64(n) => *n as f64,
+        Value::Float32(n) => *n as f64,
+        Value::Float64(n) => *n,
+        _ => return None,
+    })
+}
+
+fn as_i128(value: &Value) -> Option<i128> {
+    Some(match value {
+        Value::Int32(n) => *n as i128,
+        Value::Int64(n) => *n as i128,
+        Value::UInt32(n) => *n as i128,
+        Value::UInt64(n) => *n as i128,
+        _ => return None,
+    })
+}
+
+// Switch labels are written as plain integer literals
+fn same_number(label: &Value, value: &Value) -> bool {
+    match (as_i128(label), as_i128(value)) {
+        (Some(label), Some(value)) => label == value,
+        _ => label == value,
+    }
+}
+
+fn cast(value: Value, type_t: &Type) -> Result<Value, Trap> {
+    value
+        .convert(type_t)
+        .ok_or_else(|| Trap::Invalid(format!("{:?} to {}", value, type_t)))
+}
+
+fn negate(value: Value) -> Result<Value, Trap> {
+    Ok(match value {
+        Value::Int32(n) => Value::Int32(n.wrapping_neg()),
+        Value::Int64(n) => Value::Int64(n.wrapping_neg()),
+        Value::UInt32(n) => Value::UInt32(n.wrapping_neg()),
+        Value::UInt64(n) => Value::UInt64(n.wrapping_neg()),
+        Value::Float32(n) => Value::Float32(-n),
+        Value::Float64(n) => Value::Float64(-n),
+        value => return Err(Trap::Invalid(format!("-{:?}", value))),
+    })
+}
+
+// Integer arithmetic wraps, and dividing by zero or shifting out of
+// range traps
+macro_rules! arithmetic {
+    ($func:expr, $lhs:expr, $rhs:expr, $variant:path) => {{
+        let (l, r) = ($lhs, $rhs);
+        match $func {
+            ir::Func::Add(_) => $variant(l.wrapping_add(r)),
+            ir::Func::Sub(_) => $variant(l.wrapping_sub(r)),
+            ir::Func::Mult(_) => $variant(l.wrapping_mul(r)),
+            ir::Func::Div(_) if r == 0 => return Err(Trap::DivisionByZero),
+            ir::Func::Div(_) => $variant(l.wrapping_div(r)),
+            ir::Func::Mod(_) if r == 0 => return Err(Trap::DivisionByZero),
+            ir::Func::Mod(_) => $variant(l.wrapping_rem(r)),
+            ir::Func::Pow(_) => $variant(power(l as i128, r as i128)? as _),
+            ir::Func::BitAnd(_) => $variant(l & r),
+            ir::Func::BitOr(_) => $variant(l | r),
+            ir::Func::BitXor(_) => $variant(l ^ r),
+            ir::Func::Shl(_) => $variant(
+                u32::try_from(r)
+                    .ok()
+                    .and_then(|r| l.checked_shl(r))
+                    .ok_or(Trap::ShiftOutOfRange)?,
+            ),
+            ir::Func::Shr(_) => $variant(
+                u32::try_from(r)
+                    .ok()
+                    .and_then(|r| l.checked_shr(r))
+                    .ok_or(Trap::ShiftOutOfRange)?,
+            ),
+            func => compare(func, l.partial_cmp(&r))?,
+        }
+    }};
+}
+
+// Raises by squaring, wrapping as multiplication does once truncated to
+// the operands' type. A negative exponent gives 1 / base ** -exponent,
+// truncated as integer division is, so 0 unless the base is 1 or -1.
+fn power(base: i128, exponent: i128) -> Result<i128, Trap> {
+    if exponent < 0 {
+        let inverse = 1i128.checked_div(base).ok_or(Trap::DivisionByZero)?;
+        return Ok(if exponent & 1 == 1 {
+            inverse
+        } else {
+            inverse * inverse
+        });
+    }
+    let (mut result, mut base, mut exponent) = (1i128, base, exponent);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+    Ok(result)
+}
+
+macro_rules! float_arithmetic {
+    ($func:expr, $lhs:expr, $rhs:expr, $variant:path) => {{
+        let (l, r) = ($lhs, $rhs);
+        match $func {
+            ir::Func::Add(_) => $variant(l + r),
+            ir::Func::Sub(_) => $variant(l - r),
+            ir::Func::Mult(_) => $variant(l * r),
+            ir::Func::Div(_) => $variant(l / r),
+            ir::Func::Mod(_) => $variant(l % r),
+            ir::Func::Pow(_) => $variant(l.powf(r)),
+            func => compare(func, l.partial_cmp(&r))?,
+        }
+    }};
+}
+
+fn binary(func: &ir::Func, lhs: Value, rhs: Value) -> Result<Value, Trap> {
+    Ok(match (lhs, rhs) {
+        (Value::Int32(l), Value::Int32(r)) => arithmetic!(func, l, r, Value::Int32),
+        (Value::Int64(l), Value::Int64(r)) => arithmetic!(func, l, r, Value::Int64),
+        (Value::UInt32(l), Value::UInt32(r)) => arithmetic!(func, l, r, Value::UInt32),
+        (Value::UInt64(l), Value::UInt64(r)) => arithmetic!(func, l, r, Value::UInt64),
+        (Value::Float32(l), Value::Float32(r)) => float_arithmetic!(func, l, r, Value::Float32),
+        (Value::Float64(l), Value::Float64(r)) => float_arithmetic!(func, l, r, Value::Float64),
+        (Value::Bool(l), Value::Bool(r)) => compare(func, Some(l.cmp(&r)))?,
+        (Value::String(l), Value::String(r)) => match func {
+            ir::Func::Add(_) => Value::String(l + &r),
+            func => compare(func, Some(l.cmp(&r)))?,
+        },
+        (lhs, rhs) => {
+            return Err(Trap::Invalid(format!(
+                "{:?} of {:?} and {:?}",
+                func, lhs, rhs
+            )))
+        }
+    })
+}
+
+fn compare(func: &ir::Func, ordering: Option<std::cmp::Ordering>) -> Result<Value, Trap> {
+    use std::cmp::Ordering::*;
+    Ok(Value::Bool(match (func, ordering) {
+        (ir::Func::Eq(_), ordering) => ordering == Some(Equal),
+        (ir::Func::Neq(_), ordering) => ordering != Some(Equal),
+        (ir::Func::Lt(_), ordering) => ordering == Some(Less),
+        (ir::Func::Gt(_), ordering) => ordering == Some(Greater),
+        (ir::Func::Leq(_), ordering) => matches!(ordering, Some(Less | Equal)),
+        (ir::Func::Geq(_), ordering) => matches!(ordering, Some(Greater | Equal)),
+        (func, _) => return Err(Trap::Invalid(format!("{:?} isn't a comparison", func))),
+    }))
+}
+
+// Milliseconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64)
+}
+
+fn builtin(ident: &str, args: &[Value]) -> Result<Option<Value>, Trap> {
+    match (ident, args) {
+        ("byte_len", [Value::String(s)]) => Ok(Some(Value::UInt64(s.len() as u64))),
+        ("char_len", [Value::String(s)]) => Ok(Some(Value::UInt64(s.chars().count() as u64))),
+        ("exit", [Value::Int32(code)]) => Err(Trap::Exit(*code)),
+        ("is_nan", [Value::Float64(x)]) => Ok(Some(Value::Bool(x.is_nan()))),
+        ("is_inf", [Value::Float64(x)]) => Ok(Some(Value::Bool(x.is_infinite()))),
+        ("bench.report", [Value::String(label), Value::UInt64(n), Value::UInt64(ns)]) => {
+            let plural = if *n == 1 { "" } else { "s" };
+            let (ms, us) = (ns / 1_000_000, ns / 1_000 % 1_000);
+            eprintln!(
+                "bench {}: {} iteration{} in {}.{:03} ms",
+                label, n, plural, ms, us
+            );
+            Ok(None)
+        }
+        _ => Err(Trap::Unbound(ident.to_string())),
+    }
+}
+
+// Intrinsics are evaluated in float64, and their float32 results
+// rounded from that
+fn intrinsic(ident: &str, args: &[Value]) -> Result<Option<Value>, Trap> {
+    let intrinsic = intrinsics::lookup(ident).ok_or_else(|| Trap::Unbound(ident.to_string()))?;
+    let args: Vec<f64> = args
+        .iter()
+        .map(|arg| match arg {
+            Value::Float64(x) => Ok(*x),
+            Value::Float32(x) => Ok(*x as f64),
+            arg => Err(Trap::Invalid(format!("{:?} isn't a float", arg))),
+        })
+        .collect::<Result<_, _>>()?;
+    let result = (intrinsic.eval)(&args);
+    Ok(Some(match intrinsic.return_t {
+        Type::Float32 => Value::Float32(result as f32),
+        _ => Value::Float64(result),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::session::Session;
+
+    fn run(src: &str) -> Result<i32, Trap> {
+        let state = compile::analyze(src, &Session::default()).unwrap();
+        let interpreter = Interpreter::new(state.build_stack, HashMap::new()).unwrap();
+        interpreter.init()?;
+        interpreter.run()
+    }
+
+    // The samples exit with the same codes as their compiled binaries
+    #[test]
+    fn interpret_samples() {
+        for (sample, code) in [
+            ("fib", 13),
+            ("if", 20),
+            ("simple", 24),
+            ("short_circuit", 42),
+            ("operand_order", 42),
+            ("unicode", 1),
+            ("while", 243),
+            ("mod", 21),
+            ("bitwise", 28),
+            ("concat", 7),
+            ("bench", 45),
+            ("arrays", 15),
+            ("intrinsics", 7),
+            ("pow", 31),
+            ("signum", 7),
+            ("operand_order", 42),
+        ] {
+            let src = std::fs::read_to_string(format!("samples/{}.ras", sample)).unwrap();
+            assert_eq!(run(&src), Ok(code), "{}", sample);
+        }
+        assert_eq!(
+            run("program p let n = 0; return 1 / n; end"),
+            Err(Trap::DivisionByZero)
+        );
+        assert_eq!(
+            run("program p let n = 32; return 1 << n; end"),
+            Err(Trap::ShiftOutOfRange)
+        );
+        assert_eq!(
+            run("program p let xs: array[int32, 2]; let i = 2; return xs[i]; end"),
+            Err(Trap::IndexOutOfRange)
+        );
+        assert_eq!(run("program p exit(3); return 0; end"), Ok(3));
+    }
+
+    // Bench blocks are timed on the virtual clock when there is one
+    #[test]
+    fn interpret_bench_on_virtual_clock() {
+        let state = compile::analyze("program p return 0; end", &Session::default()).unwrap();
+        let time = Rc::new(Cell::new(3));
+        let interpreter = Interpreter::new(state.build_stack, HashMap::new())
+            .unwrap()
+            .with_clock(Clock::Virtual(time.clone()));
+        assert_eq!(interpreter.bench_now(), 3_000_000);
+        time.set(5);
+        assert_eq!(interpreter.bench_now(), 5_000_000);
+    }
+
+    // NaN is unordered, so it compares unequal to everything, itself
+    // included, as in the C the backend generates
+    #[test]
+    fn interpret_ieee_floats() {
+        let src = "program p
+            let zero = 0.0;
+            let nan = zero / zero;
+            let inf = 1.0 / zero;
+            if !is_nan(nan) or is_nan(inf) then return 1; end
+            if !is_inf(inf) or is_inf(nan) then return 2; end
+            if nan == nan or !(nan != nan) then return 3; end
+            if nan < 1.0 or nan >= 1.0 then return 4; end
+            if !(inf > 1.0) then return 5; end
+            return 0;
+        end";
+        assert_eq!(run(src), Ok(0));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::ast::{self};
+use crate::backends::{self, Feature};
 use crate::codegen::{CodeGen, CodeGenContext, CodeGenError};
 use crate::ir::IRNode;
 use anyhow::Result;
@@ -18,10 +19,14 @@ macro_rules! matches_variant {
     };
 }
 
+// Only an empty program so far
+pub const FEATURES: &[Feature] = &[];
+
 pub struct WasmGenContext {
     build_stack: Vec<IRNode>,
     outfile: String,
     skip_validation: bool,
+    compile_only: bool,
 }
 
 impl From<CodeGenContext> for WasmGenContext {
@@ -30,12 +35,18 @@ impl From<CodeGenContext> for WasmGenContext {
             build_stack: ctx.build_stack,
             outfile: ctx.outfile,
             skip_validation: ctx.skip_validation,
+            compile_only: ctx.compile_only,
         }
     }
 }
 
 impl CodeGen for WasmGenContext {
     fn gen(&mut self) -> Result<(), CodeGenError> {
+        let mut used = backends::features(&self.build_stack);
+        if self.compile_only {
+            used.push(Feature::SeparateCompilation);
+        }
+        backends::check_support("wasm", &used)?;
         let mut module = Module::new();
 
         // Encode the type section.
@@ -64,10 +75,12 @@ impl CodeGen for WasmGenContext {
             wasmparser::validate(&wasm_output)
                 .map_err(|err| CodeGenError::ValidateFailed(err.message().into()))?;
         }
-        let mut file = File::create(self.outfile.clone())
-            .map_err(|err| CodeGenError::BinaryWrite(err.to_string()))?;
-        file.write_all(&wasm_output)
-            .map_err(|err| CodeGenError::BinaryWrite(err.to_string()))?;
+        File::create(&self.outfile)
+            .and_then(|mut file| file.write_all(&wasm_output))
+            .map_err(|source| CodeGenError::Io {
+                action: format!("write {}", self.outfile),
+                source,
+            })?;
 
         Ok(())
     }
@@ -0,0 +1,144 @@
+use crate::ast::Node;
+use crate::semantic::{sinsert, SymbolStack};
+use crate::symbol::{new_symbol, new_var};
+use crate::types::{FunctionType, Type};
+
+// Functions provided by the compiler rather than by Rascal source.
+// Each one is declared in the global scope before type checking and
+// is backed by a small C definition emitted on demand by the C backend.
+#[derive(Debug, Clone)]
+pub struct Builtin {
+    pub ident: &'static str,
+    pub params_t: Vec<Type>,
+    pub return_t: Type,
+    pub c_ident: &'static str,
+    pub c_includes: &'static [&'static str],
+    pub c_def: &'static str,
+}
+
+impl Builtin {
+    pub fn func_type(&self) -> Type {
+        Type::Function(FunctionType {
+            params_t: self.params_t.clone(),
+            return_t: Box::new(self.return_t.clone()),
+        })
+    }
+}
+
+pub fn builtins() -> Vec<Builtin> {
+    vec![
+        // Strings are UTF-8, so the number of bytes and the number of
+        // characters (code points) in a string can differ. Both count up
+        // to the end of the string, which is its first NUL, as in C: the
+        // length of "ab\0c" is 2.
+        Builtin {
+            ident: "byte_len",
+            params_t: vec![Type::String],
+            return_t: Type::UInt64,
+            c_ident: "_rascal_byte_len",
+            c_includes: &["string.h"],
+            c_def: "static uint64_t _rascal_byte_len(char* s){ return (uint64_t)strlen(s); }",
+        },
+        Builtin {
+            ident: "char_len",
+            params_t: vec![Type::String],
+            return_t: Type::UInt64,
+            c_ident: "_rascal_char_len",
+            c_includes: &[],
+            c_def: "static uint64_t _rascal_char_len(char* s){ uint64_t n = 0; for (; *s; s++) { if (((unsigned char)*s & 0xC0) != 0x80) { n++; } } return n; }",
+        },
+        Builtin {
+            ident: "exit",
+            params_t: vec![Type::Int32],
+            return_t: Type::Never,
+            c_ident: "_rascal_exit",
+            c_includes: &["stdlib.h"],
+            c_def: "_Noreturn static void _rascal_exit(int32_t code){ exit(code); }",
+        },
+        // Pseudo-random numbers from splitmix64, seeded from the time the
+        // program starts. The interpreter can be given the seed instead.
+        Builtin {
+            ident: "random",
+            params_t: vec![],
+            return_t: Type::UInt64,
+            c_ident: "_rascal_random",
+            c_includes: &["time.h"],
+            c_def: "static uint64_t _rascal_random(void){ static uint64_t s = 0; static bool seeded = false; if (!seeded) { s = (uint64_t)time(NULL); seeded = true; } uint64_t z = (s += 0x9E3779B97F4A7C15u); z = (z ^ (z >> 30)) * 0xBF58476D1CE4E5B9u; z = (z ^ (z >> 27)) * 0x94D049BB133111EBu; return z ^ (z >> 31); }",
+        },
+        // Floats follow IEEE 754, even with --fast-math these look at the
+        // bits rather than trusting the compiler's assumptions about them.
+        Builtin {
+            ident: "is_nan",
+            params_t: vec![Type::Float64],
+            return_t: Type::Bool,
+            c_ident: "_rascal_is_nan",
+            c_includes: &[],
+            c_def: "static bool _rascal_is_nan(double x){ union { double f; uint64_t u; } v = { x }; return (v.u & 0x7FF0000000000000u) == 0x7FF0000000000000u && (v.u & 0x000FFFFFFFFFFFFFu) != 0; }",
+        },
+        Builtin {
+            ident: "is_inf",
+            params_t: vec![Type::Float64],
+            return_t: Type::Bool,
+            c_ident: "_rascal_is_inf",
+            c_includes: &[],
+            c_def: "static bool _rascal_is_inf(double x){ union { double f; uint64_t u; } v = { x }; return (v.u & 0x7FFFFFFFFFFFFFFFu) == 0x7FF0000000000000u; }",
+        },
+        // Milliseconds since the Unix epoch. The interpreter can be given
+        // a virtual clock instead.
+        Builtin {
+            ident: "clock",
+            params_t: vec![],
+            return_t: Type::UInt64,
+            c_ident: "_rascal_clock",
+            c_includes: &["time.h"],
+            c_def: "static uint64_t _rascal_clock(void){ struct timespec t; timespec_get(&t, TIME_UTC); return (uint64_t)t.tv_sec * 1000 + (uint64_t)t.tv_nsec / 1000000; }",
+        },
+    ]
+}
+
+// Helpers that code the compiler generates calls, which aren't in any
+// scope. A `.` can't be part of an identifier, so their names can't
+// clash with a function of the program's own.
+pub fn runtime() -> Vec<Builtin> {
+    vec![
+        // Nanoseconds on a monotonic clock, from an unspecified start, for
+        // `bench` blocks to time their runs with. A virtual clock in the
+        // interpreter drives it too.
+        Builtin {
+            ident: "bench.now",
+            params_t: vec![],
+            return_t: Type::UInt64,
+            c_ident: "_rascal_bench_now",
+            c_includes: &["time.h"],
+            c_def: "static uint64_t _rascal_bench_now(void){ struct timespec t; clock_gettime(CLOCK_MONOTONIC, &t); return (uint64_t)t.tv_sec * 1000000000u + (uint64_t)t.tv_nsec; }",
+        },
+        // What a `bench` block reports once its runs are over, on stderr
+        // so that it stays out of the program's own output
+        Builtin {
+            ident: "bench.report",
+            params_t: vec![Type::String, Type::UInt64, Type::UInt64],
+            return_t: Type::Nil,
+            c_ident: "_rascal_bench_report",
+            c_includes: &["stdio.h"],
+            c_def: "static void _rascal_bench_report(char* label, uint64_t iterations, uint64_t ns){ fprintf(stderr, \"bench %s: %llu iteration%s in %llu.%03llu ms\\n\", label, (unsigned long long)iterations, iterations == 1 ? \"\" : \"s\", (unsigned long long)(ns / 1000000), (unsigned long long)(ns / 1000 % 1000)); }",
+        },
+    ]
+}
+
+pub fn lookup(ident: &str) -> Option<Builtin> {
+    builtins()
+        .into_iter()
+        .chain(runtime())
+        .find(|b| b.ident == ident)
+}
+
+// Assume this to be into the top of the stack
+pub fn declare(stack: &mut SymbolStack) {
+    for builtin in builtins() {
+        sinsert(
+            stack,
+            new_symbol(builtin.ident.to_string()),
+            new_var(builtin.func_type(), Node::Null),
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+// What the C compiler has built, so that building C that was built
+// before, with the same flags and by the same compiler, copies the
+// earlier build rather than compiling it again. Each build is a file
+// named by the hash of all of that.
+
+// $RASCALC_CACHE_DIR, or rascalc in the user's cache directory
+pub fn dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("RASCALC_CACHE_DIR") {
+        return Some(dir.into());
+    }
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        return Some(Path::new(&dir).join("rascalc"));
+    }
+    env::var_os("HOME").map(|home| Path::new(&home).join(".cache").join("rascalc"))
+}
+
+// FNV-1a, 128 bits wide, whose hashes unlike std's stay the same from
+// one build of the compiler to the next
+pub fn hash(parts: &[&[u8]]) -> String {
+    let mut hash: u128 = 0x6c62272e07bb014262b821756295c58d;
+    for part in parts {
+        // The lengths keep ["ab", "c"] apart from ["a", "bc"]
+        for byte in part.len().to_le_bytes().iter().chain(part.iter()) {
+            hash ^= *byte as u128;
+            hash = hash.wrapping_mul(0x0000000001000000000000000000013b);
+        }
+    }
+    format!("{:032x}", hash)
+}
+
+// What `cc --version` prints, None without a compiler to ask
+pub fn compiler_version(cc: &str) -> Option<Vec<u8>> {
+    Command::new(cc)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| output.stdout)
+}
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: &Path) -> Cache {
+        Cache {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    // Copies the build for the key to `outfile`, returning whether there
+    // was one
+    pub fn fetch(&self, key: &str, outfile: &str) -> bool {
+        fs::copy(self.dir.join(key), outfile).is_ok()
+    }
+
+    // Keeps a copy of `built` for the key. Failing to only means the next
+    // build isn't sped up, so it isn't an error.
+    pub fn store(&self, key: &str, built: &str) {
+        // Copied beside the entry and renamed into place, so a build
+        // running at the same time never fetches half of one
+        let partial = self.dir.join(format!("{}.{}", key, process::id()));
+        let stored = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::copy(built, &partial))
+            .and_then(|_| fs::rename(&partial, self.dir.join(key)));
+        if stored.is_err() {
+            let _ = fs::remove_file(partial);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_fetch_by_hash() {
+        // Keys name files in caches already on disk, so they can't change
+        assert_eq!(hash(&[]), "6c62272e07bb014262b821756295c58d");
+        assert_eq!(hash(&[b"int main(){}"]), hash(&[b"int main(){}"]));
+        assert_ne!(hash(&[b"ab", b"c"]), hash(&[b"a", b"bc"]));
+
+        let dir = env::temp_dir().join(format!("rascalc-cache-test-{}", process::id()));
+        let cache = Cache::new(&dir.join("cache"));
+        let built = dir.join("built");
+        let fetched = dir.join("fetched");
+        let (built, fetched) = (built.to_str().unwrap(), fetched.to_str().unwrap());
+        let key = hash(&[b"int main(){}", b"-c"]);
+        assert!(!cache.fetch(&key, fetched));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(built, "object").unwrap();
+        cache.store(&key, built);
+        assert!(cache.fetch(&key, fetched));
+        assert_eq!(fs::read_to_string(fetched).unwrap(), "object");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,19 +1,125 @@
+use crate::backends::{Feature, BACKENDS};
+use crate::diagnostics::{Diagnostic, ISSUES_URL};
 use crate::ir::IRNode;
-use crate::symbol::{Symbol};
+use crate::symbol::Symbol;
 use anyhow::Result;
 use std::collections::HashMap;
-use std::io::Write;
+use std::io;
+use std::path::PathBuf;
 
 use thiserror::Error;
 
+// How a backend can fail. Each has a diagnostic, so the driver reports
+// them all the same way.
 #[derive(Error, Debug)]
 pub enum CodeGenError {
+    // Something the program or the build asks for that the backend can't
+    // generate code for
+    #[error("feature `{feature}` not supported by backend `{backend}`")]
+    Unsupported {
+        backend: &'static str,
+        feature: Feature,
+    },
+    // An external program the backend runs, like the C compiler, isn't
+    // installed
+    #[error("{tool} not found")]
+    ToolchainMissing { tool: String },
+    // An external program ran and failed, with what it printed
+    #[error("{tool} failed: {stderr}")]
+    ToolFailed { tool: String, stderr: String },
+    // Reading or writing a file, or starting an external program
+    #[error("Couldn't {action}: {source}")]
+    Io {
+        action: String,
+        #[source]
+        source: io::Error,
+    },
+    // gcc rejected the C generated for the named Rascal function, or for
+    // the top level of the program when there is no name
+    #[error("The generated C was rejected: {error}")]
+    InvalidC {
+        error: String,
+        function: Option<String>,
+    },
     #[error("The resulting WASM failed validation: {0}")]
     ValidateFailed(String),
-    #[error("Couldn't write output binary: {0}")]
-    BinaryWrite(String),
-    #[error("Couldn't compile binary: {0}")]
-    CompilationFailed(String),
+    // The IR doesn't have the shape a backend expects
+    #[error("The IR is malformed: {0}")]
+    MalformedIR(String),
+}
+
+impl CodeGenError {
+    // Running `tool` failing, which unless it's missing is like any other
+    // io error
+    pub fn spawn(tool: &str, err: io::Error) -> CodeGenError {
+        match err.kind() {
+            io::ErrorKind::NotFound => CodeGenError::ToolchainMissing {
+                tool: tool.to_string(),
+            },
+            _ => CodeGenError::Io {
+                action: format!("run {}", tool),
+                source: err,
+            },
+        }
+    }
+
+    // The backend only knows the IR, so the diagnostic doesn't point into
+    // the source. Errors in code the backend generated are bugs in the
+    // compiler, and say so.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let internal = |message: String| {
+            Diagnostic::error(format!("internal compiler error: {}", message)).with_help(format!(
+                "this is a bug in rascalc, please file an issue at {}",
+                ISSUES_URL
+            ))
+        };
+        match self {
+            CodeGenError::Unsupported { feature, .. } => {
+                let supporting: Vec<_> = BACKENDS
+                    .iter()
+                    .filter(|(_, features)| features.contains(feature))
+                    .map(|(name, _)| format!("`{}`", name))
+                    .collect();
+                let diagnostic = Diagnostic::error(self.to_string());
+                match supporting.as_slice() {
+                    [] => diagnostic,
+                    supporting => diagnostic.with_help(format!(
+                        "the backends supporting it are {}",
+                        supporting.join(", ")
+                    )),
+                }
+            }
+            CodeGenError::ToolchainMissing { tool } => {
+                Diagnostic::error(format!("{} not found", tool))
+                    .with_help("install it, or pass the C compiler to use with --cc")
+            }
+            CodeGenError::ToolFailed { tool, stderr } => {
+                let diagnostic = Diagnostic::error(format!("{} failed", tool));
+                match stderr.trim() {
+                    "" => diagnostic,
+                    stderr => diagnostic.with_note(stderr),
+                }
+            }
+            CodeGenError::Io { action, source } => {
+                Diagnostic::error(format!("couldn't {}: {}", action, source))
+            }
+            CodeGenError::InvalidC { error, function } => internal(format!(
+                "gcc rejected the C generated for this {}: {}",
+                if function.is_some() {
+                    "function"
+                } else {
+                    "program"
+                },
+                error
+            )),
+            CodeGenError::ValidateFailed(error) => {
+                internal(format!("the generated WASM is invalid: {}", error))
+            }
+            CodeGenError::MalformedIR(error) => {
+                Diagnostic::error(format!("the IR is malformed: {}", error))
+            }
+        }
+    }
 }
 
 macro_rules! matches_variant {
@@ -59,6 +165,20 @@ pub struct CodeGenContext {
     pub build_stack: Vec<IRNode>,
     pub outfile: String,
     pub skip_validation: bool,
+    // Produce an object to be linked later rather than an executable
+    pub compile_only: bool,
+    // The source the IR was lowered from with debug info. The generated
+    // code then keeps track of the calls it's in and their lines, to print
+    // them if the program traps.
+    pub debug_src: Option<String>,
+    // Let the backend's compiler assume floats are never NaN or infinite
+    // and reassociate their arithmetic, which gives up IEEE semantics
+    pub fast_math: bool,
+    // Where to keep what the backend's compiler builds, to reuse it when
+    // the same code is built again, or None to always compile
+    pub cache_dir: Option<PathBuf>,
+    // The C compiler to build with, and link with too
+    pub cc: String,
 }
 
 pub fn new(build_stack: Vec<IRNode>, outfile: String, skip_validation: bool) -> CodeGenContext {
@@ -66,9 +186,48 @@ pub fn new(build_stack: Vec<IRNode>, outfile: String, skip_validation: bool) ->
         build_stack: build_stack.into_iter().rev().collect(),
         outfile,
         skip_validation,
+        compile_only: false,
+        debug_src: None,
+        fast_math: false,
+        cache_dir: None,
+        cc: "gcc".into(),
     }
 }
 
 pub trait CodeGen {
     fn gen(&mut self) -> Result<(), CodeGenError>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_as_diagnostics() {
+        let missing = CodeGenError::spawn("cc", io::ErrorKind::NotFound.into());
+        assert_eq!(missing.to_string(), "cc not found");
+        assert_eq!(
+            missing.diagnostic().help,
+            vec!["install it, or pass the C compiler to use with --cc"]
+        );
+        let denied = CodeGenError::spawn("cc", io::ErrorKind::PermissionDenied.into());
+        assert!(denied.diagnostic().message.starts_with("couldn't run cc: "));
+
+        let failed = CodeGenError::ToolFailed {
+            tool: "gcc".into(),
+            stderr: "ld: cannot find -lm\n".into(),
+        };
+        let diagnostic = failed.diagnostic();
+        assert_eq!(diagnostic.message, "gcc failed");
+        assert_eq!(diagnostic.notes, vec!["ld: cannot find -lm"]);
+        // What the backend generated wrongly is the compiler's fault
+        let invalid = CodeGenError::InvalidC {
+            error: "expected ';'".into(),
+            function: Some("f".into()),
+        };
+        assert!(invalid.diagnostic().message.starts_with(
+            "internal compiler error: gcc rejected the C generated for this function"
+        ));
+        assert_eq!(invalid.diagnostic().help.len(), 1);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::ast::Root;
+use crate::backends::c::CGenContext;
+use crate::codegen::{self, CodeGenError};
+use crate::diagnostics::Diagnostic;
+use crate::infer::{self, TypeError};
+use crate::ir::IRNode;
+use crate::parser;
+use crate::semantic::{BuildIRError, ProgramState};
+use crate::session::Session;
+use thiserror::Error;
+
+// Compiling a program in memory, for build tools and tests that want the
+// generated code itself rather than a binary. Nothing is written to disk
+// and gcc isn't run, so unlike the driver's, the C returned hasn't been
+// checked by a C compiler.
+
+#[derive(Error, Debug)]
+pub enum CompileError {
+    #[error("Couldn't parse the program: {0}")]
+    Parse(String),
+    #[error(transparent)]
+    Type(#[from] TypeError),
+    #[error(transparent)]
+    Build(#[from] BuildIRError),
+    #[error(transparent)]
+    CodeGen(#[from] CodeGenError),
+}
+
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub ir: Vec<IRNode>,
+    pub c: String,
+    // Problems that don't stop the build, which callers asking for
+    // `strict` should treat as errors
+    pub warnings: Vec<Diagnostic>,
+}
+
+// Checks a program and lowers it to IR
+pub fn analyze(src: &str, session: &Session) -> Result<ProgramState, CompileError> {
+    let root = parser::parse(src).map_err(|err| CompileError::Parse(err.to_string()))?;
+    analyze_root(root, session, false)
+}
+
+// Checks a program that's already been parsed, for callers adding to it
+// first. With `debug_info`, the IR marks where each statement starts.
+pub fn analyze_root(
+    mut root: Box<Root>,
+    session: &Session,
+    debug_info: bool,
+) -> Result<ProgramState, CompileError> {
+    parser::check_nesting(&root, session.nesting_limit())
+        .map_err(|err| CompileError::Parse(err.to_string()))?;
+    infer::TypingState::new().augment(&mut root)?;
+    let mut infer_state = infer::InferState::new();
+    infer_state.strict_numerics = session.strict_numerics();
+    infer_state.edition = session.edition();
+    infer_state.constrain(&mut root)?;
+    infer_state.resolve()?;
+    infer::SubState::new(infer_state.get_type_mapping()).substitute(&mut root)?;
+    let mut state = ProgramState::new(root);
+    state.debug_info = debug_info;
+    state.build_ir()?;
+    Ok(state)
+}
+
+pub fn compile_to_string(src: &str, session: &Session) -> Result<Compiled, CompileError> {
+    let state = analyze(src, session)?;
+    let ctx = codegen::new(state.build_stack.clone(), "a.out".into(), false);
+    let c = CGenContext::from(ctx).gen_source()?;
+    Ok(Compiled {
+        ir: state.build_stack,
+        c,
+        warnings: state.warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Edition;
+
+    #[test]
+    fn compile_without_files() {
+        let compiled = compile_to_string(
+            "fun twice(n: int32) -> int32 return n * 2; end
+             program p return twice(21); end",
+            &Session::default(),
+        )
+        .unwrap();
+        assert!(compiled.ir.contains(&IRNode::Program));
+        assert!(compiled.c.contains("int32_t twice ( int32_t n ) {"));
+        assert!(compiled.c.contains("int main(){"));
+        assert!(compiled.warnings.is_empty());
+        assert!(matches!(
+            compile_to_string("program p return x; end", &Session::default()),
+            Err(CompileError::Type(_))
+        ));
+    }
+
+    #[test]
+    fn default_to_the_compatible_edition() {
+        let src = "program p let n = 2; if n then return 1; end return 0; end";
+        assert!(compile_to_string(src, &Session::default()).is_ok());
+        let strict = Session {
+            strict: true,
+            ..Session::default()
+        };
+        let edition_2025 = Session {
+            edition: Edition::E2025,
+            ..Session::default()
+        };
+        for session in [strict, edition_2025] {
+            assert!(matches!(
+                compile_to_string(src, &session),
+                Err(CompileError::Type(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn compile_pathological_inputs() {
+        let session = Session::default();
+        // Nothing needs to end in a newline, not even a comment
+        for src in [
+            "program p return 0; end",
+            "program p return 0; end // done",
+            "",
+        ] {
+            assert!(compile_to_string(src, &session).is_ok(), "{:?}", src);
+        }
+        let name = "a".repeat(5_000);
+        // Nested far deeper than anything could recurse through
+        let n = 200_000;
+        for src in [
+            format!("program p return 1{}; end", " + 1".repeat(n)),
+            format!("program p return {}1{}; end", "(".repeat(n), ")".repeat(n)),
+            format!("program p return {}1; end", "-".repeat(n)),
+            format!("program p return {}1{}; end", "f(".repeat(n), ")".repeat(n)),
+            format!(
+                "program p {}return 1;{} return 0; end",
+                "while true do ".repeat(n),
+                " end".repeat(n)
+            ),
+            format!("program p return {}1; end", "(".repeat(n)),
+            format!("program p return 1{} end", " + 1".repeat(n)),
+            format!("program p return 1{}", " + 1".repeat(n)),
+            format!("let x = 1{}; program p return x; end", " * 1".repeat(n)),
+        ] {
+            assert!(
+                compile_to_string(&src, &session).is_err(),
+                "{:?}",
+                &src[..40]
+            );
+        }
+        for src in [
+            format!("program p let {} = 1; return {}; end", name, name),
+            format!("program p return {}; end", "9".repeat(500)),
+            format!("program p let x = {}.5; return 0; end", "9".repeat(400)),
+            format!("program p let x = 1{}; return 0; end", "z".repeat(5_000)),
+            "program p return 0; end ///".to_string(),
+            "program p let s = \"open".to_string(),
+        ] {
+            assert!(
+                matches!(
+                    compile_to_string(&src, &session),
+                    Err(CompileError::Parse(_))
+                ),
+                "{:?}",
+                &src[..40]
+            );
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::cmp::Ordering;
+
+use crate::hir::{BinOp, Expr, ExprKind, LogicalOp, UnOp};
+use crate::ir::Value;
+
+// Evaluates expressions whose value is known at compile time: literals
+// and the operators applied to them. Anything reading a variable or
+// calling a function isn't constant, and neither is an operation that
+// would overflow, divide an integer by zero or shift it out of range,
+// so those give `None`.
+pub fn eval(expr: &Expr) -> Option<Value> {
+    match &expr.kind {
+        ExprKind::Literal(Value::Id(_)) => None,
+        ExprKind::Literal(value) => Some(value.clone()),
+        ExprKind::Cast(inner) => eval(inner)?.convert(&expr.type_t),
+        ExprKind::Unary(UnOp::Not, operand) => match eval(operand)? {
+            Value::Bool(b) => Some(Value::Bool(!b)),
+            _ => None,
+        },
+        ExprKind::Unary(UnOp::Neg, operand) => match eval(operand)? {
+            Value::Int32(n) => n.checked_neg().map(Value::Int32),
+            Value::Int64(n) => n.checked_neg().map(Value::Int64),
+            Value::Float32(n) => Some(Value::Float32(-n)),
+            Value::Float64(n) => Some(Value::Float64(-n)),
+            _ => None,
+        },
+        ExprKind::Unary(UnOp::BitNot, operand) => match eval(operand)? {
+            Value::Int32(n) => Some(Value::Int32(!n)),
+            Value::Int64(n) => Some(Value::Int64(!n)),
+            Value::UInt32(n) => Some(Value::UInt32(!n)),
+            Value::UInt64(n) => Some(Value::UInt64(!n)),
+            _ => None,
+        },
+        ExprKind::Binary(op, lhs, rhs) => binary(*op, eval(lhs)?, eval(rhs)?),
+        ExprKind::Logical(op, lhs, rhs) => match (op, eval(lhs)?) {
+            (LogicalOp::And, Value::Bool(false)) => Some(Value::Bool(false)),
+            (LogicalOp::Or, Value::Bool(true)) => Some(Value::Bool(true)),
+            (_, Value::Bool(_)) => eval(rhs),
+            _ => None,
+        },
+        ExprKind::Var(_) | ExprKind::Index(..) | ExprKind::Call(..) | ExprKind::Lambda(_) => None,
+    }
+}
+
+fn compare(op: BinOp, ordering: Ordering) -> Option<bool> {
+    match op {
+        BinOp::Eq => Some(ordering == Ordering::Equal),
+        BinOp::Neq => Some(ordering != Ordering::Equal),
+        BinOp::Leq => Some(ordering != Ordering::Greater),
+        BinOp::Geq => Some(ordering != Ordering::Less),
+        BinOp::Lt => Some(ordering == Ordering::Less),
+        BinOp::Gt => Some(ordering == Ordering::Greater),
+        _ => None,
+    }
+}
+
+macro_rules! integer_op {
+    ($op:expr, $a:expr, $b:expr, $variant:path) => {
+        match $op {
+            BinOp::Add => $a.checked_add($b).map($variant),
+            BinOp::Sub => $a.checked_sub($b).map($variant),
+            BinOp::Mult => $a.checked_mul($b).map($variant),
+            BinOp::Div => $a.checked_div($b).map($variant),
+            BinOp::Mod => $a.checked_rem($b).map($variant),
+            BinOp::Pow => u32::try_from($b)
+                .ok()
+                .and_then(|b| $a.checked_pow(b))
+                .map($variant),
+            BinOp::BitAnd => Some($variant($a & $b)),
+            BinOp::BitOr => Some($variant($a | $b)),
+            BinOp::BitXor => Some($variant($a ^ $b)),
+            BinOp::Shl => u32::try_from($b)
+                .ok()
+                .and_then(|b| $a.checked_shl(b))
+                .map($variant),
+            BinOp::Shr => u32::try_from($b)
+                .ok()
+                .and_then(|b| $a.checked_shr(b))
+                .map($variant),
+            op => compare(op, $a.cmp(&$b)).map(Value::Bool),
+        }
+    };
+}
+
+macro_rules! float_op {
+    ($op:expr, $a:expr, $b:expr, $variant:path) => {
+        match $op {
+            BinOp::Add => Some($variant($a + $b)),
+            BinOp::Sub => Some($variant($a - $b)),
+            BinOp::Mult => Some($variant($a * $b)),
+            BinOp::Div => Some($variant($a / $b)),
+            BinOp::Pow => Some($variant($a.powf($b))),
+            BinOp::Mod | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => {
+                None
+            }
+            op => compare(op, $a.partial_cmp(&$b)?).map(Value::Bool),
+        }
+    };
+}
+
+fn binary(op: BinOp, lhs: Value, rhs: Value) -> Option<Value> {
+    match (lhs, rhs) {
+        (Value::Int32(a), Value::Int32(b)) => integer_op!(op, a, b, Value::Int32),
+        (Value::Int64(a), Value::Int64(b)) => integer_op!(op, a, b, Value::Int64),
+        (Value::UInt32(a), Value::UInt32(b)) => integer_op!(op, a, b, Value::UInt32),
+        (Value::UInt64(a), Value::UInt64(b)) => integer_op!(op, a, b, Value::UInt64),
+        (Value::Float32(a), Value::Float32(b)) => float_op!(op, a, b, Value::Float32),
+        (Value::Float64(a), Value::Float64(b)) => float_op!(op, a, b, Value::Float64),
+        (Value::Bool(a), Value::Bool(b)) => compare(op, a.cmp(&b)).map(Value::Bool),
+        (Value::String(a), Value::String(b)) if op == BinOp::Add => Some(Value::String(a + &b)),
+        (Value::String(a), Value::String(b)) => compare(op, a.cmp(&b)).map(Value::Bool),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Type;
+
+    fn literal(value: Value) -> Box<Expr> {
+        let type_t = match value {
+            Value::Int32(_) => Type::Int32,
+            Value::UInt64(_) => Type::UInt64,
+            _ => Type::Bool,
+        };
+        Box::new(Expr {
+            type_t,
+            kind: ExprKind::Literal(value),
+        })
+    }
+
+    fn binary_expr(op: BinOp, lhs: Box<Expr>, rhs: Box<Expr>, type_t: Type) -> Box<Expr> {
+        Box::new(Expr {
+            type_t,
+            kind: ExprKind::Binary(op, lhs, rhs),
+        })
+    }
+
+    #[test]
+    fn eval_constant_expressions() {
+        let sum = binary_expr(
+            BinOp::Add,
+            literal(Value::UInt64(8)),
+            literal(Value::UInt64(4)),
+            Type::UInt64,
+        );
+        let check = binary_expr(BinOp::Eq, sum, literal(Value::UInt64(12)), Type::Bool);
+        assert_eq!(eval(&check), Some(Value::Bool(true)));
+
+        let overflow = binary_expr(
+            BinOp::Mult,
+            literal(Value::Int32(i32::MAX)),
+            literal(Value::Int32(2)),
+            Type::Int32,
+        );
+        assert_eq!(eval(&overflow), None);
+        let widened = Expr {
+            type_t: Type::Int64,
+            kind: ExprKind::Cast(literal(Value::Int32(-3))),
+        };
+        assert_eq!(eval(&widened), Some(Value::Int64(-3)));
+        assert_eq!(eval(&literal(Value::Id("x".into()))), None);
+    }
+}
@@ -0,0 +1,342 @@
+use crate::backends::interp::{Frame, Hook, Interpreter, Pause, Trap};
+use crate::compile::{self, CompileError};
+use crate::diagnostics::line_col;
+use crate::ir::Value;
+use crate::parser;
+use crate::session::Session;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+// A line debugger over the interpreter. The program is lowered with
+// debug info, so the interpreter stops before each statement to ask
+// whether to pause there, and while paused, commands are read a line at
+// a time from the input.
+
+const HELP: &str = "\
+break N, b N      pause before the statements on line N
+delete N, d N     remove the breakpoint on line N
+step, s           run to the next statement, entering calls
+next, n           run to the next statement in this function or its callers
+continue, c       run to the next breakpoint
+locals, l         show the variables in scope
+print X, p X      show the variable X
+backtrace, bt     show the calls that haven't returned
+quit, q           stop the program";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Step,
+    // Pauses once no more than this many frames are left
+    Next(usize),
+    Continue,
+}
+
+struct State<R, W> {
+    input: R,
+    output: W,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    // The line and frame count of the last statement, so a breakpoint
+    // pauses once on arriving at its line rather than at every statement
+    // on it
+    last: Option<(usize, usize)>,
+}
+
+pub struct Debugger<R, W> {
+    src: String,
+    state: RefCell<State<R, W>>,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    // Pauses before the first statement
+    pub fn new(src: &str, input: R, output: W) -> Self {
+        Debugger {
+            src: src.to_string(),
+            state: RefCell::new(State {
+                input,
+                output,
+                breakpoints: BTreeSet::new(),
+                mode: Mode::Step,
+                last: None,
+            }),
+        }
+    }
+
+    fn source_line(&self, line: usize) -> &str {
+        self.src.lines().nth(line - 1).unwrap_or("").trim()
+    }
+
+    // Writes where the program stopped and why, innermost call first
+    fn report(&self, result: &Result<i32, Trap>, interpreter: &Interpreter) {
+        let mut state = self.state.borrow_mut();
+        let _ = match result {
+            Ok(code) => writeln!(state.output, "The program exited with {}", code),
+            Err(Trap::Stopped) => Ok(()),
+            Err(trap) => {
+                let _ = writeln!(state.output, "{}", trap);
+                self.backtrace(&mut state.output, &interpreter.backtrace())
+            }
+        };
+    }
+
+    fn backtrace(&self, output: &mut W, frames: &[Frame]) -> std::io::Result<()> {
+        for (n, frame) in frames.iter().rev().enumerate() {
+            match frame.offset {
+                Some(offset) => writeln!(
+                    output,
+                    "#{} {} at line {}",
+                    n,
+                    frame.function,
+                    line_col(&self.src, offset).0
+                )?,
+                None => writeln!(output, "#{} {}", n, frame.function)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Reads commands until one resumes the program
+    fn prompt(&self, state: &mut State<R, W>, pause: &Pause, line: usize) -> Result<(), Trap> {
+        let _ = writeln!(state.output, "{}: {}", line, self.source_line(line));
+        loop {
+            let _ = write!(state.output, "(rdb) ");
+            let _ = state.output.flush();
+            let mut command = String::new();
+            // The end of the input quits
+            if state.input.read_line(&mut command).map_or(0, |n| n) == 0 {
+                return Err(Trap::Stopped);
+            }
+            let words: Vec<&str> = command.split_whitespace().collect();
+            let line_arg = || words.get(1).and_then(|word| word.parse::<usize>().ok());
+            let _ = match words.as_slice() {
+                [] => Ok(()),
+                ["step" | "s"] => {
+                    state.mode = Mode::Step;
+                    return Ok(());
+                }
+                ["next" | "n"] => {
+                    state.mode = Mode::Next(pause.frames.len());
+                    return Ok(());
+                }
+                ["continue" | "c"] => {
+                    state.mode = Mode::Continue;
+                    return Ok(());
+                }
+                ["quit" | "q"] => return Err(Trap::Stopped),
+                ["break" | "b", _] => match line_arg() {
+                    Some(line) => {
+                        state.breakpoints.insert(line);
+                        writeln!(state.output, "Breakpoint on line {}", line)
+                    }
+                    None => writeln!(state.output, "Expected a line number"),
+                },
+                ["delete" | "d", _] => match line_arg() {
+                    Some(line) if state.breakpoints.remove(&line) => {
+                        writeln!(state.output, "Removed the breakpoint on line {}", line)
+                    }
+                    _ => writeln!(state.output, "No breakpoint on that line"),
+                },
+                ["locals" | "l"] => {
+                    let mut result = Ok(());
+                    for local in pause.locals() {
+                        result = writeln!(
+                            state.output,
+                            "{}: {} = {}",
+                            local.ident,
+                            local.type_t,
+                            show(&local.value)
+                        );
+                    }
+                    result
+                }
+                ["print" | "p", ident] => match pause
+                    .locals()
+                    .into_iter()
+                    .find(|local| local.ident == *ident)
+                {
+                    Some(local) => writeln!(
+                        state.output,
+                        "{}: {} = {}",
+                        local.ident,
+                        local.type_t,
+                        show(&local.value)
+                    ),
+                    None => writeln!(state.output, "No variable {} in scope", ident),
+                },
+                ["backtrace" | "bt"] => self.backtrace(&mut state.output, pause.frames),
+                ["help" | "h"] => writeln!(state.output, "{}", HELP),
+                _ => writeln!(state.output, "Unknown command, try help"),
+            };
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Hook for Debugger<R, W> {
+    fn before(&self, pause: &Pause) -> Result<(), Trap> {
+        let mut state = self.state.borrow_mut();
+        let line = line_col(&self.src, pause.offset).0;
+        let depth = pause.frames.len();
+        let arrived = state.last != Some((line, depth));
+        state.last = Some((line, depth));
+        let pausing = match state.mode {
+            Mode::Step => true,
+            Mode::Next(frames) => depth <= frames,
+            Mode::Continue => false,
+        } || (arrived && state.breakpoints.contains(&line));
+        if pausing {
+            self.prompt(&mut state, pause, line)?;
+        }
+        Ok(())
+    }
+}
+
+// Values as they'd be written in Rascal
+fn show(value: &Value) -> String {
+    match value {
+        Value::Int32(n) => n.to_string(),
+        Value::Int64(n) => n.to_string(),
+        Value::UInt32(n) => n.to_string(),
+        Value::UInt64(n) => n.to_string(),
+        Value::Float32(n) => format!("{:?}", n),
+        Value::Float64(n) => format!("{:?}", n),
+        Value::Bool(b) => b.to_string(),
+        Value::String(s) => format!("{:?}", s),
+        Value::Id(ident) => ident.clone(),
+    }
+}
+
+// Debugs the program in `src`, reading commands from `input` and writing
+// to `output`. Returns the program's exit code, or the trap that stopped
+// it.
+pub fn debug<R, W>(
+    src: &str,
+    session: &Session,
+    input: R,
+    output: W,
+) -> Result<Result<i32, Trap>, CompileError>
+where
+    R: BufRead + 'static,
+    W: Write + 'static,
+{
+    let root = parser::parse(src).map_err(|err| CompileError::Parse(err.to_string()))?;
+    let state = compile::analyze_root(root, session, true)?;
+    let debugger = Rc::new(Debugger::new(src, input, output));
+    let interpreter =
+        Interpreter::new(state.build_stack, HashMap::new())?.with_hook(debugger.clone());
+    let result = interpreter.init().and_then(|_| interpreter.run());
+    debugger.report(&result, &interpreter);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Output the test can read after the debugger is done with it
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const SRC: &str = "fun fact(n: int32) -> int32
+    if n <= 1 then
+        return 1;
+    end
+    return n * fact(n - 1);
+end
+
+program p
+    let x = 3;
+    let y = fact(x);
+    return y;
+end
+";
+
+    fn session(commands: &str) -> (Result<i32, Trap>, String) {
+        let output = Shared::default();
+        let result = debug(
+            SRC,
+            &Session::default(),
+            Cursor::new(commands.to_string()),
+            output.clone(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+        (result, output)
+    }
+
+    #[test]
+    fn debug_with_breakpoints_and_stepping() {
+        let (result, output) = session("s\ns\nb 5\nc\np n\nbt\nd 5\nn\nl\nc\n");
+        assert_eq!(result, Ok(6));
+        let expected = "\
+9: let x = 3;
+(rdb) 10: let y = fact(x);
+(rdb) 2: if n <= 1 then
+(rdb) Breakpoint on line 5
+(rdb) 5: return n * fact(n - 1);
+(rdb) n: int32 = 3
+(rdb) #0 fact at line 5
+#1 program at line 10
+(rdb) Removed the breakpoint on line 5
+(rdb) 11: return y;
+(rdb) x: int32 = 3
+y: int32 = 6
+(rdb) The program exited with 6
+";
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn debug_quit_and_traps() {
+        let (result, output) = session("s\ns\nq\n");
+        assert_eq!(result, Err(Trap::Stopped));
+        assert!(output.ends_with("(rdb) 2: if n <= 1 then\n(rdb) "));
+        // Running out of commands quits too
+        assert_eq!(session("").0, Err(Trap::Stopped));
+
+        let output = Shared::default();
+        let result = debug(
+            "fun f(n: int32) -> int32 return 1 / n; end
+             program p return f(0); end",
+            &Session::default(),
+            Cursor::new("c\n"),
+            output.clone(),
+        )
+        .unwrap();
+        assert_eq!(result, Err(Trap::DivisionByZero));
+        let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert!(output.ends_with("Division by zero\n#0 f at line 1\n#1 program at line 2\n"));
+    }
+
+    #[test]
+    fn debug_shadowed_names() {
+        let output = Shared::default();
+        let src = "program p
+    let x = 1;
+    let x = x + 1;
+    return x;
+end";
+        let result = debug(
+            src,
+            &Session::default(),
+            Cursor::new("b 4\nc\nl\np x\nc\n"),
+            output.clone(),
+        )
+        .unwrap();
+        assert_eq!(result, Ok(2));
+        let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert!(output.contains("(rdb) x: int32 = 2\n(rdb) x: int32 = 2\n"));
+    }
+}
@@ -0,0 +1,383 @@
+use crate::ast::Span;
+use serde::{Deserialize, Serialize};
+
+// Where bugs in the compiler are reported
+pub const ISSUES_URL: &str = "https://github.com/drewrip/rascal/issues";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// A message about the program being compiled, optionally pointing at
+// the source it concerns and at other source that explains it, followed
+// by any number of note and help lines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    // Said about the source at `span`
+    pub label: Option<String>,
+    // Other source involved, like the definition of a function called
+    // wrongly at `span`
+    pub secondary: Vec<Label>,
+    // Facts that explain the problem
+    pub notes: Vec<String>,
+    // What to do about it
+    pub help: Vec<String>,
+    // Edits that fix the problem without needing a person to check them,
+    // applied by `--fix`
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+// Replaces the source at the span with the text
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span: None,
+            label: None,
+            secondary: vec![],
+            notes: vec![],
+            help: vec![],
+            suggestions: vec![],
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message)
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Diagnostic {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Diagnostic {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help.push(help.into());
+        self
+    }
+
+    pub fn with_suggestion(mut self, span: Span, replacement: impl Into<String>) -> Diagnostic {
+        self.suggestions.push(Suggestion {
+            span,
+            replacement: replacement.into(),
+        });
+        self
+    }
+
+    // Renders the diagnostic against the source it refers to, with the
+    // primary span underlined by carets and secondary ones by dashes:
+    //
+    //   error: `f` takes 2 arguments but 1 was given
+    //    --> calls.ras:6:12
+    //     |
+    //   6 |     return f(1);
+    //     |            ^^^^ expected 2 arguments
+    //     |
+    //   1 | fun f(a: int32, b: int32) -> int32
+    //     |     - `f` is defined here
+    //     = help: pass a value for each parameter
+    pub fn render(&self, filename: &str, src: &str) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut out = format!("{}: {}\n", severity, self.message);
+        // Wide enough for the greatest line number shown
+        let gutter = " ".repeat(
+            self.span
+                .iter()
+                .chain(self.secondary.iter().map(|label| &label.span))
+                .map(|span| line_col(src, span.start).0.to_string().len())
+                .max()
+                .unwrap_or(0),
+        );
+        if let Some(span) = self.span {
+            let (line, col) = line_col(src, span.start);
+            out.push_str(&format!("{}--> {}:{}:{}\n", gutter, filename, line, col));
+            out.push_str(&format!("{} |\n", gutter));
+            snippet(&mut out, src, &gutter, span, '^', self.label.as_deref());
+        }
+        for label in self.secondary.iter() {
+            out.push_str(&format!("{} |\n", gutter));
+            snippet(
+                &mut out,
+                src,
+                &gutter,
+                label.span,
+                '-',
+                Some(&label.message),
+            );
+        }
+        for note in self.notes.iter() {
+            out.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+        for help in self.help.iter() {
+            out.push_str(&format!("{} = help: {}\n", gutter, help));
+        }
+        out
+    }
+}
+
+// The version of the JSON that `--error-format json` writes. It's
+// bumped when a field is removed or renamed or changes meaning. Fields
+// can be added without a bump, so readers should ignore the ones they
+// don't know.
+pub const REPORT_VERSION: u32 = 1;
+
+// The diagnostics found in a file, as `--error-format json` writes them,
+// a report a line. Spans are byte offsets into the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub version: u32,
+    // None for problems that aren't with any one file, like a missing
+    // input
+    pub file: Option<String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+    pub fn new(file: Option<&str>, diagnostics: Vec<Diagnostic>) -> Report {
+        Report {
+            version: REPORT_VERSION,
+            file: file.map(str::to_string),
+            diagnostics,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("reports only hold strings and numbers")
+    }
+}
+
+// The line the span starts on, with the span underlined up to the end of
+// that line and followed by the label
+fn snippet(out: &mut String, src: &str, gutter: &str, span: Span, mark: char, label: Option<&str>) {
+    let (line, col) = line_col(src, span.start);
+    let text = src.lines().nth(line - 1).unwrap_or("");
+    let width = text
+        .chars()
+        .skip(col - 1)
+        .take(src[span.start..span.end].chars().count())
+        .take_while(|c| *c != '\n')
+        .count()
+        .max(1);
+    let line_no = format!("{:>1$}", line, gutter.len());
+    out.push_str(&format!("{} | {}\n", line_no, text));
+    let underline = format!("{}{}", " ".repeat(col - 1), mark.to_string().repeat(width));
+    match label {
+        Some(label) => out.push_str(&format!("{} | {} {}\n", gutter, underline, label)),
+        None => out.push_str(&format!("{} | {}\n", gutter, underline)),
+    }
+}
+
+// The 1-based line and column (counted in characters) of a byte offset
+pub fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+// Applies the suggestions of the diagnostics to the source, returning
+// the fixed source and the number applied. A suggestion overlapping one
+// earlier in the source is left out; running the fix again picks it up.
+// Removing all there is on a line, but for its indentation, removes the
+// line.
+pub fn apply_suggestions(src: &str, diagnostics: &[Diagnostic]) -> (String, usize) {
+    let mut suggestions: Vec<_> = diagnostics
+        .iter()
+        .flat_map(|diagnostic| diagnostic.suggestions.iter())
+        .collect();
+    suggestions.sort_by_key(|suggestion| (suggestion.span.start, suggestion.span.end));
+    let mut fixed = String::new();
+    let mut pos = 0;
+    let mut applied = 0;
+    for suggestion in suggestions {
+        if suggestion.span.start < pos || suggestion.span.end > src.len() {
+            continue;
+        }
+        let lines = whole_lines(src, suggestion.span).filter(|lines| lines.start >= pos);
+        let span = match lines {
+            Some(lines) if suggestion.replacement.is_empty() => lines,
+            _ => suggestion.span,
+        };
+        fixed.push_str(&src[pos..span.start]);
+        fixed.push_str(&suggestion.replacement);
+        pos = span.end;
+        applied += 1;
+    }
+    fixed.push_str(&src[pos..]);
+    (fixed, applied)
+}
+
+// The lines the span covers, line break included, if there's nothing
+// but whitespace on them outside it
+fn whole_lines(src: &str, span: Span) -> Option<Span> {
+    let start = src[..span.start].trim_end_matches([' ', '\t']).len();
+    let after = &src[span.end..];
+    let end = src.len() - after.trim_start_matches([' ', '\t']).len();
+    if start > 0 && !src[..start].ends_with('\n') {
+        return None;
+    }
+    let rest = &src[end..];
+    let line_break = if rest.starts_with("\r\n") {
+        2
+    } else if rest.starts_with('\n') {
+        1
+    } else if rest.is_empty() {
+        0
+    } else {
+        return None;
+    };
+    Some(Span::new(start, end + line_break))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_with_span_and_help() {
+        let src = "program p\n  if größe then\n  end\nend";
+        let start = src.find("größe").unwrap();
+        let diagnostic = Diagnostic::error("condition must be a bool, found int32")
+            .with_span(Span::new(start, start + "größe".len()))
+            .with_help("compare it explicitly: `größe != 0`");
+        assert_eq!(
+            diagnostic.render("p.ras", src),
+            "error: condition must be a bool, found int32
+ --> p.ras:2:6
+  |
+2 |   if größe then
+  |      ^^^^^
+  = help: compare it explicitly: `größe != 0`
+"
+        );
+        assert_eq!(
+            Diagnostic::warning("unused").render("p.ras", src),
+            "warning: unused\n"
+        );
+    }
+
+    #[test]
+    fn render_secondary_labels_and_notes() {
+        let src = "fun f(a: int32, b: int32) -> int32\n    return a;\nend\n\n\n\n\n\n\nprogram p\n    return f(1);\nend";
+        let at = |s: &str| Span::new(src.find(s).unwrap(), src.find(s).unwrap() + s.len());
+        let diagnostic = Diagnostic::error("`f` takes 2 arguments but 1 was given")
+            .with_span(at("f(1)"))
+            .with_label("expected 2 arguments")
+            .with_secondary(at("f(a"), "`f` is defined here")
+            .with_note("`b` has no default")
+            .with_help("pass a value for `b`");
+        assert_eq!(
+            diagnostic.render("p.ras", src),
+            "error: `f` takes 2 arguments but 1 was given
+  --> p.ras:11:12
+   |
+11 |     return f(1);
+   |            ^^^^ expected 2 arguments
+   |
+ 1 | fun f(a: int32, b: int32) -> int32
+   |     --- `f` is defined here
+   = note: `b` has no default
+   = help: pass a value for `b`
+"
+        );
+        // Labels and notes are kept in the JSON encoding too
+        let json = serde_json::to_string(&diagnostic).unwrap();
+        assert!(json.contains(r#""label":"expected 2 arguments""#));
+        assert!(json.contains(r#""message":"`f` is defined here""#));
+        assert!(json.contains(r#""notes":["`b` has no default"]"#));
+    }
+
+    // Tools parse these reports, so any change to what this test expects
+    // needs REPORT_VERSION bumped, unless it only adds fields
+    #[test]
+    fn report_json_is_stable() {
+        let report = Report::new(
+            Some("p.ras"),
+            vec![
+                Diagnostic::error("mismatched types")
+                    .with_span(Span::new(10, 13))
+                    .with_label("expected int32")
+                    .with_secondary(Span::new(0, 3), "declared here")
+                    .with_note("int32 and float64 don't mix")
+                    .with_help("write it as int32")
+                    .with_suggestion(Span::new(10, 13), "1"),
+                Diagnostic::warning("unused variable `x`"),
+            ],
+        );
+        let json = r#"{"version":1,"file":"p.ras","diagnostics":[{"severity":"error","message":"mismatched types","span":{"start":10,"end":13},"label":"expected int32","secondary":[{"span":{"start":0,"end":3},"message":"declared here"}],"notes":["int32 and float64 don't mix"],"help":["write it as int32"],"suggestions":[{"span":{"start":10,"end":13},"replacement":"1"}]},{"severity":"warning","message":"unused variable `x`","span":null,"label":null,"secondary":[],"notes":[],"help":[],"suggestions":[]}]}"#;
+        assert_eq!(report.to_json(), json);
+        assert_eq!(serde_json::from_str::<Report>(json).unwrap(), report);
+        // Fields from a later compiler are ignored
+        let newer = json.replace(r#""file":"#, r#""elapsed_ms":3,"file":"#);
+        assert_eq!(serde_json::from_str::<Report>(&newer).unwrap(), report);
+    }
+
+    #[test]
+    fn apply_non_overlapping_suggestions() {
+        let src = "let x = 1; if n then";
+        let at = |s: &str| Span::new(src.find(s).unwrap(), src.find(s).unwrap() + s.len());
+        let diagnostics = [
+            Diagnostic::warning("unused variable `x`").with_suggestion(at("x"), "_x"),
+            Diagnostic::error("condition must be a bool, found int32")
+                .with_suggestion(at("n"), "n != 0")
+                .with_suggestion(at("n then"), "n do"),
+        ];
+        assert_eq!(
+            apply_suggestions(src, &diagnostics),
+            ("let _x = 1; if n != 0 then".to_string(), 2)
+        );
+        // A statement removed from its own line takes the line with it
+        let src = "program p\n  let x = 1;\n  return 0; let y = 2;\nend";
+        let at = |s: &str| Span::new(src.find(s).unwrap(), src.find(s).unwrap() + s.len());
+        let diagnostics = [
+            Diagnostic::warning("unused variable `x`").with_suggestion(at("let x = 1;"), ""),
+            Diagnostic::warning("unused variable `y`").with_suggestion(at(" let y = 2;"), ""),
+        ];
+        assert_eq!(
+            apply_suggestions(src, &diagnostics),
+            ("program p\n  return 0;\nend".to_string(), 2)
+        );
+    }
+}
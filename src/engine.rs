@@ -0,0 +1,483 @@
+use crate::ast::{self, Attribute, Param, Span, Stmt};
+use crate::backends::interp::{Clock, HostFn, Interpreter, Limits, Trap};
+use crate::compile::{self, CompileError};
+use crate::ir::{FuncDef, IRNode, Value};
+use crate::marshal::HostFunction;
+use crate::parser;
+use crate::session::Session;
+use crate::types::{FunctionType, Type};
+use std::collections::HashMap;
+use std::rc::Rc;
+use thiserror::Error;
+
+// Rascal as a scripting language for other programs. Scripts are checked
+// as they are for compiling, then run by the interpreter. The host
+// provides the functions a script declares `@extern`, and can call the
+// functions it defines at the top level. Values crossing between them
+// are checked against the script's declarations, since nothing else
+// checks what the host passes.
+
+#[derive(Error, Debug)]
+pub enum EngineError {
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+    #[error("No host function was registered for the extern function {0}")]
+    MissingHost(String),
+    #[error("The script doesn't define a function {0}")]
+    NoFunction(String),
+    #[error("{function} takes {expected} arguments but {found} were given")]
+    Arity {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Argument {index} of {function} should be {expected}, found {found:?}")]
+    Argument {
+        function: String,
+        index: usize,
+        expected: Type,
+        found: Value,
+    },
+    #[error("{function} is declared {declared} but registered as {registered}")]
+    Signature {
+        function: String,
+        declared: Type,
+        registered: Type,
+    },
+    #[error(transparent)]
+    Trap(#[from] Trap),
+}
+
+#[derive(Default)]
+pub struct Engine {
+    session: Session,
+    hosts: HashMap<String, Host>,
+    limits: Limits,
+    seed: Option<u64>,
+    clock: Clock,
+}
+
+struct Host {
+    func: HostFn,
+    // Known for functions registered with their Rust types, which
+    // scripts don't have to declare
+    signature: Option<FunctionType>,
+}
+
+impl Engine {
+    pub fn new(session: Session) -> Self {
+        Engine {
+            session,
+            hosts: HashMap::new(),
+            limits: Limits::default(),
+            seed: None,
+            clock: Clock::System,
+        }
+    }
+
+    // Bounds what the scripts loaded after this can take each time
+    // they're run or called, so that untrusted ones can be run. A script
+    // exceeding them stops with Trap::Limit.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // Makes `random` in the scripts loaded after this start from `seed`,
+    // rather than from the time, so reloading a script and making the
+    // same calls gets the same numbers
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    // Where `clock` in the scripts loaded after this reads the time from
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    // Provides the `@extern` function named `ident` to the scripts loaded
+    // after this. Nil functions return None.
+    pub fn register<F>(&mut self, ident: &str, host: F)
+    where
+        F: Fn(&[Value]) -> Result<Option<Value>, Trap> + 'static,
+    {
+        let host = Host {
+            func: Rc::new(host),
+            signature: None,
+        };
+        self.hosts.insert(ident.to_string(), host);
+    }
+
+    // Provides a Rust function as a function scripts can call, with the
+    // Rascal types of its parameters and result. Scripts can call it
+    // without declaring it, and calls are type checked against it.
+    pub fn register_fn<Args, F: HostFunction<Args>>(&mut self, ident: &str, func: F) {
+        let host = Host {
+            func: func.into_host(ident),
+            signature: Some(F::signature()),
+        };
+        self.hosts.insert(ident.to_string(), host);
+    }
+
+    // Checks a script and initializes its globals. Every extern function
+    // it declares must have been registered.
+    pub fn load(&self, src: &str) -> Result<Script, EngineError> {
+        let mut root = parser::parse(src).map_err(|err| CompileError::Parse(err.to_string()))?;
+        let mut declared = HashMap::new();
+        for stmt in root.preblock.iter().chain(root.postblock.iter()) {
+            if let Stmt::FuncDef(func) = stmt.as_ref() {
+                if func.is_extern() {
+                    declared.insert(func.ident.clone(), func_type(func));
+                }
+            }
+        }
+        let mut externs = vec![];
+        for (ident, host) in self.hosts.iter() {
+            let Some(signature) = &host.signature else {
+                continue;
+            };
+            match declared.get(ident) {
+                Some(declared) if declared != signature => {
+                    return Err(EngineError::Signature {
+                        function: ident.clone(),
+                        declared: Type::Function(declared.clone()),
+                        registered: Type::Function(signature.clone()),
+                    })
+                }
+                Some(_) => {}
+                None => externs.push(Box::new(Stmt::FuncDef(declaration(ident, signature)))),
+            }
+        }
+        root.preblock.splice(0..0, externs);
+        let state = compile::analyze_root(root, &self.session, false)?;
+        let mut hosts = HashMap::new();
+        for node in &state.build_stack {
+            let IRNode::ExternFuncDef(def) = node else {
+                continue;
+            };
+            let ident = def.symbol.ident.clone();
+            let Some(host) = self.hosts.get(&ident) else {
+                return Err(EngineError::MissingHost(ident));
+            };
+            hosts.insert(ident, checked(def.clone(), host.func.clone()));
+        }
+        let interpreter = Interpreter::new(state.build_stack, hosts)
+            .map_err(CompileError::from)?
+            .with_limits(self.limits.clone())
+            .with_clock(self.clock.clone());
+        let interpreter = match self.seed {
+            Some(seed) => interpreter.with_seed(seed),
+            None => interpreter,
+        };
+        interpreter.init()?;
+        Ok(Script { interpreter })
+    }
+}
+
+// A loaded script, whose globals keep their values between calls
+pub struct Script {
+    interpreter: Interpreter,
+}
+
+impl Script {
+    pub fn call(&self, ident: &str, args: &[Value]) -> Result<Option<Value>, EngineError> {
+        let func = self
+            .interpreter
+            .func(ident)
+            .ok_or_else(|| EngineError::NoFunction(ident.to_string()))?;
+        let params = &func.def.params_t;
+        if params.len() != args.len() {
+            return Err(EngineError::Arity {
+                function: ident.to_string(),
+                expected: params.len(),
+                found: args.len(),
+            });
+        }
+        for (index, ((_, type_t), arg)) in params.iter().zip(args).enumerate() {
+            if type_of(arg).as_ref() != Some(type_t) {
+                return Err(EngineError::Argument {
+                    function: ident.to_string(),
+                    index,
+                    expected: type_t.clone(),
+                    found: arg.clone(),
+                });
+            }
+        }
+        Ok(self.interpreter.call(ident, args.to_vec())?)
+    }
+
+    pub fn has_program(&self) -> bool {
+        self.interpreter.has_program()
+    }
+
+    // Runs the program block, returning its exit code
+    pub fn run(&self) -> Result<i32, EngineError> {
+        Ok(self.interpreter.run()?)
+    }
+}
+
+// The type of a value passed by the host. Functions can't be passed.
+fn type_of(value: &Value) -> Option<Type> {
+    Some(match value {
+        Value::Int32(_) => Type::Int32,
+        Value::Int64(_) => Type::Int64,
+        Value::UInt32(_) => Type::UInt32,
+        Value::UInt64(_) => Type::UInt64,
+        Value::Float32(_) => Type::Float32,
+        Value::Float64(_) => Type::Float64,
+        Value::Bool(_) => Type::Bool,
+        Value::String(_) => Type::String,
+        Value::Id(_) => return None,
+    })
+}
+
+fn func_type(func: &ast::Func) -> FunctionType {
+    FunctionType {
+        params_t: func
+            .params
+            .iter()
+            .map(|param| param.type_t.clone())
+            .collect(),
+        return_t: Box::new(func.return_t.clone()),
+    }
+}
+
+// The `@extern` declaration of a function registered with its types
+fn declaration(ident: &str, signature: &FunctionType) -> ast::Func {
+    ast::Func {
+        return_t: (*signature.return_t).clone(),
+        params: signature
+            .params_t
+            .iter()
+            .enumerate()
+            .map(|(n, type_t)| {
+                Box::new(Param {
+                    type_t: type_t.clone(),
+                    ident: format!("arg{}", n),
+                    span: Span::default(),
+                })
+            })
+            .collect(),
+        ident: ident.to_string(),
+        block: vec![],
+        docs: vec![],
+        attrs: vec![Attribute {
+            name: "extern".into(),
+            args: vec![],
+            span: Span::default(),
+        }],
+        span: Span::default(),
+    }
+}
+
+// Traps when the host returns something other than what the script
+// declared
+fn checked(def: FuncDef, host: HostFn) -> HostFn {
+    Rc::new(move |args| {
+        let value = host(args)?;
+        let expected = match def.return_t {
+            Type::Nil | Type::Never => None,
+            ref type_t => Some(type_t),
+        };
+        if value.as_ref().and_then(type_of).as_ref() != expected {
+            return Err(Trap::Host {
+                function: def.symbol.ident.clone(),
+                message: format!("returned {:?} instead of {}", value, def.return_t),
+            });
+        }
+        Ok(value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::interp::Limit;
+    use crate::marshal::Marshal;
+
+    const SCRIPT: &str = "@extern fun scale(n: int32) -> int32 end
+         let calls = 0;
+         fun apply(n: int32) -> int32
+             calls = calls + 1;
+             return scale(n) + calls;
+         end";
+
+    #[test]
+    fn engine_calls_into_and_out_of_scripts() {
+        let mut engine = Engine::default();
+        assert!(matches!(
+            engine.load(SCRIPT),
+            Err(EngineError::MissingHost(ident)) if ident == "scale"
+        ));
+        engine.register("scale", |args| match args {
+            [Value::Int32(n)] => Ok(Some(Value::Int32(n * 10))),
+            _ => unreachable!(),
+        });
+        let script = engine.load(SCRIPT).unwrap();
+        assert!(!script.has_program());
+        assert_eq!(
+            script.call("apply", &[Value::Int32(4)]).unwrap(),
+            Some(Value::Int32(41))
+        );
+        assert_eq!(
+            script.call("apply", &[Value::Int32(4)]).unwrap(),
+            Some(Value::Int32(42))
+        );
+        assert!(matches!(
+            script.call("apply", &[Value::Int64(4)]),
+            Err(EngineError::Argument { index: 0, .. })
+        ));
+        assert!(matches!(
+            script.call("apply", &[]),
+            Err(EngineError::Arity {
+                expected: 1,
+                found: 0,
+                ..
+            })
+        ));
+        assert!(matches!(
+            script.call("missing", &[]),
+            Err(EngineError::NoFunction(_))
+        ));
+
+        engine.register("scale", |_| Ok(Some(Value::Bool(true))));
+        let script = engine.load(SCRIPT).unwrap();
+        assert!(matches!(
+            script.call("apply", &[Value::Int32(1)]),
+            Err(EngineError::Trap(Trap::Host { .. }))
+        ));
+    }
+
+    #[test]
+    fn engine_marshals_typed_host_functions() {
+        let mut engine = Engine::default();
+        engine.register_fn("twice", |n: i64| n * 2);
+        engine.register_fn("shout", |s: String| -> Result<String, String> {
+            match s.is_empty() {
+                true => Err("nothing to shout".into()),
+                false => Ok(s.to_uppercase()),
+            }
+        });
+        let script = engine
+            .load(
+                "fun quad(n: int64) -> int64 return twice(twice(n)); end
+                 fun loud(s: string) -> string return shout(s); end",
+            )
+            .unwrap();
+        assert_eq!(
+            script.call("quad", &[Value::Int64(5)]).unwrap(),
+            Some(Value::Int64(20))
+        );
+        assert_eq!(
+            script
+                .call("loud", &["hi".to_string().into_value()])
+                .unwrap(),
+            Some(Value::String("HI".into()))
+        );
+        assert!(matches!(
+            script.call("loud", &[Value::String("".into())]),
+            Err(EngineError::Trap(Trap::Host { message, .. })) if message == "nothing to shout"
+        ));
+        // Calls are checked against the registered types
+        assert!(matches!(
+            engine.load("fun f() -> bool return twice(true); end"),
+            Err(EngineError::Compile(CompileError::Type(_)))
+        ));
+        assert!(matches!(
+            engine.load("@extern fun twice(n: int32) -> int32 end"),
+            Err(EngineError::Signature { function, .. }) if function == "twice"
+        ));
+        assert!(engine
+            .load("@extern fun twice(n: int64) -> int64 end")
+            .is_ok());
+    }
+
+    #[test]
+    fn engine_limits_scripts() {
+        let script = "fun spin(n: int32) -> int32
+                 let i = 0;
+                 repeat i = i + 1; until i == n;
+                 return i;
+             end
+             fun deep(n: int32) -> int32
+                 if n == 0 then return 0; end
+                 return deep(n - 1) + 1;
+             end
+             fun forever() -> int32
+                 let i = 0;
+                 repeat i = i + 1; until false;
+                 return i;
+             end";
+        let limited = |limits: Limits| {
+            let mut engine = Engine::default();
+            engine.set_limits(limits);
+            engine.load(script).unwrap()
+        };
+        let trapped = |result: Result<Option<Value>, EngineError>, limit: Limit| matches!(result, Err(EngineError::Trap(Trap::Limit(l))) if l == limit);
+
+        let script = limited(Limits {
+            max_steps: Some(1000),
+            ..Limits::default()
+        });
+        assert!(script.call("spin", &[Value::Int32(10)]).is_ok());
+        assert!(trapped(
+            script.call("spin", &[Value::Int32(1000)]),
+            Limit::Steps
+        ));
+        // Each call gets the whole limit
+        assert!(script.call("spin", &[Value::Int32(10)]).is_ok());
+
+        let script = limited(Limits {
+            max_depth: Some(50),
+            ..Limits::default()
+        });
+        assert_eq!(
+            script.call("deep", &[Value::Int32(49)]).unwrap(),
+            Some(Value::Int32(49))
+        );
+        assert!(trapped(
+            script.call("deep", &[Value::Int32(50)]),
+            Limit::Depth
+        ));
+
+        let script = limited(Limits {
+            max_memory: Some(4096),
+            ..Limits::default()
+        });
+        assert!(script.call("deep", &[Value::Int32(10)]).is_ok());
+        assert!(trapped(
+            script.call("deep", &[Value::Int32(1000)]),
+            Limit::Memory
+        ));
+
+        let script = limited(Limits {
+            timeout: Some(std::time::Duration::from_millis(10)),
+            ..Limits::default()
+        });
+        assert!(trapped(script.call("forever", &[]), Limit::Timeout));
+    }
+
+    #[test]
+    fn engine_replays_random_and_clock() {
+        let script = "fun roll() -> uint64 return random(); end
+             fun elapsed(since: uint64) -> uint64 return clock() - since; end";
+        let time = Rc::new(std::cell::Cell::new(1000));
+        let mut engine = Engine::default();
+        engine.set_seed(7);
+        engine.set_clock(Clock::Virtual(time.clone()));
+        let rolls = |script: &Script| -> Vec<Option<Value>> {
+            (0..4).map(|_| script.call("roll", &[]).unwrap()).collect()
+        };
+        let first = rolls(&engine.load(script).unwrap());
+        assert_eq!(first, rolls(&engine.load(script).unwrap()));
+        assert_ne!(first[0], first[1]);
+        engine.set_seed(8);
+        assert_ne!(first, rolls(&engine.load(script).unwrap()));
+
+        let script = engine.load(script).unwrap();
+        time.set(1250);
+        assert_eq!(
+            script.call("elapsed", &[Value::UInt64(1000)]).unwrap(),
+            Some(Value::UInt64(250))
+        );
+    }
+}
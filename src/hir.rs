@@ -0,0 +1,383 @@
+use crate::ast::{Docs, Span};
+use crate::diagnostics::line_col;
+use crate::ir;
+use crate::types::Type;
+use serde::{Deserialize, Serialize};
+
+// The typed AST produced by semantic analysis. Unlike the parse tree,
+// every expression carries its resolved type and every reference to a
+// name has been resolved to the SymbolId of its definition.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SymbolId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ScopeId(pub usize);
+
+// A block names can be declared in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scope {
+    // What the block belongs to, like `fun f` or `repeat`
+    pub name: String,
+    // `None` for the global scope
+    pub parent: Option<ScopeId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SymbolKind {
+    Builtin,
+    Program,
+    Function,
+    Global,
+    Param,
+    Local,
+    // A local declared `static`, which keeps its value between calls
+    Static,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolInfo {
+    pub ident: String,
+    pub type_t: Type,
+    pub kind: SymbolKind,
+    // The scope it's declared in
+    pub scope: ScopeId,
+    pub docs: Docs,
+    // Span of the name at the definition, `None` for builtins
+    pub span: Option<Span>,
+    // Spans of every use of the name
+    pub references: Vec<Span>,
+    // Set for functions marked `@deprecated`, to the reason given or
+    // to an empty string
+    pub deprecated: Option<String>,
+    // For variables, the span of the `let` defining them, from its doc
+    // comments to its semicolon
+    pub statement: Option<Span>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Symbols {
+    pub symbols: Vec<SymbolInfo>,
+    pub scopes: Vec<Scope>,
+}
+
+impl Symbols {
+    pub const SYMBOLS_OUTPUT_FILENAME: &'static str = "out.symbols";
+    pub const SYMTAB_OUTPUT_FILENAME: &'static str = "out.symtab";
+
+    pub fn add(
+        &mut self,
+        ident: String,
+        type_t: Type,
+        kind: SymbolKind,
+        scope: ScopeId,
+        span: Option<Span>,
+    ) -> SymbolId {
+        self.symbols.push(SymbolInfo {
+            ident,
+            type_t,
+            kind,
+            scope,
+            docs: vec![],
+            span,
+            references: vec![],
+            deprecated: None,
+            statement: None,
+        });
+        SymbolId(self.symbols.len() - 1)
+    }
+
+    pub fn add_scope(&mut self, name: impl Into<String>, parent: Option<ScopeId>) -> ScopeId {
+        self.scopes.push(Scope {
+            name: name.into(),
+            parent,
+        });
+        ScopeId(self.scopes.len() - 1)
+    }
+
+    // Every scope with the names declared in it, nested scopes indented
+    // under the ones enclosing them. Builtins, the same for every
+    // program, and scopes without any names in or under them are left
+    // out.
+    //
+    //   global
+    //     f: (int32) -> int32, function at 1:5
+    //     fun f
+    //       n: int32, param at 1:7
+    pub fn render_scopes(&self, src: &str) -> String {
+        let mut out = String::new();
+        for (n, scope) in self.scopes.iter().enumerate() {
+            if scope.parent.is_none() {
+                self.render_scope(ScopeId(n), 0, src, &mut out);
+            }
+        }
+        out
+    }
+
+    fn render_scope(&self, scope: ScopeId, depth: usize, src: &str, out: &mut String) {
+        if !self.declares_any(scope) {
+            return;
+        }
+        out.push_str(&format!(
+            "{:1$}{2}\n",
+            "",
+            depth * 2,
+            self.scopes[scope.0].name
+        ));
+        for symbol in self.symbols.iter() {
+            if symbol.scope != scope || symbol.kind == SymbolKind::Builtin {
+                continue;
+            }
+            out.push_str(&format!(
+                "{:1$}{2}: {3}, {4}",
+                "",
+                depth * 2 + 2,
+                symbol.ident,
+                symbol.type_t,
+                format!("{:?}", symbol.kind).to_lowercase()
+            ));
+            if let Some(span) = symbol.span {
+                let (line, col) = line_col(src, span.start);
+                out.push_str(&format!(" at {}:{}", line, col));
+            }
+            out.push('\n');
+        }
+        for n in 0..self.scopes.len() {
+            if self.scopes[n].parent == Some(scope) {
+                self.render_scope(ScopeId(n), depth + 1, src, out);
+            }
+        }
+    }
+
+    fn declares_any(&self, scope: ScopeId) -> bool {
+        self.symbols
+            .iter()
+            .any(|symbol| symbol.scope == scope && symbol.kind != SymbolKind::Builtin)
+            || (0..self.scopes.len())
+                .any(|n| self.scopes[n].parent == Some(scope) && self.declares_any(ScopeId(n)))
+    }
+
+    pub fn add_reference(&mut self, id: SymbolId, span: Span) {
+        self.symbols[id.0].references.push(span);
+    }
+
+    pub fn get(&self, id: SymbolId) -> &SymbolInfo {
+        &self.symbols[id.0]
+    }
+
+    pub fn ident(&self, id: SymbolId) -> &str {
+        &self.get(id).ident
+    }
+
+    // Whether `outer` is `inner` or one of the scopes around it
+    pub fn encloses(&self, outer: ScopeId, inner: ScopeId) -> bool {
+        let mut scope = Some(inner);
+        while let Some(id) = scope {
+            if id == outer {
+                return true;
+            }
+            scope = self.scopes[id.0].parent;
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Module {
+    pub docs: Docs,
+    pub symbols: Symbols,
+    pub globals: Block,
+    // `None` for a unit without a program block
+    pub program: Option<Program>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Program {
+    pub symbol: SymbolId,
+    pub block: Block,
+}
+
+pub type Block = Vec<Stmt>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Stmt {
+    Let(SymbolId, Expr),
+    // Compound assignments (`+=` etc.) are desugared to plain assignment.
+    // The span is that of the target.
+    Assign(SymbolId, Expr, Span),
+    // Every value is evaluated before any of the targets is assigned
+    MultiAssign(Vec<(SymbolId, Expr)>),
+    // An array whose elements all start out as zero
+    Declare(SymbolId),
+    // Assigns the element at the index, the first expression, of the
+    // array. A compound assignment keeps its operator, as desugaring it
+    // would evaluate the index twice. The span is that of the array's
+    // name.
+    Store(SymbolId, Expr, Option<BinOp>, Expr, Span),
+    If(Vec<IfCase>),
+    Switch(Expr, Vec<SwitchCase>),
+    // The block runs until the condition after it holds
+    Repeat(Block, Expr),
+    // The block runs while the condition before it holds. The count is
+    // how many copies of the block `@unroll` asks for in each iteration,
+    // 1 when it isn't unrolled.
+    While(Expr, Block, usize),
+    // Leaves the enclosing loop this many loops out, 0 being the innermost
+    Break(usize),
+    Expr(Expr),
+    FuncDef(Func),
+    Return(Expr),
+    // Where the statement after it starts in the source. Only emitted for
+    // debugging, see ProgramState::debug_info.
+    Loc(Span),
+}
+
+// Whether control can never reach the end of the block: every path
+// through it returns or calls a function that never returns
+pub fn diverges(block: &[Stmt]) -> bool {
+    block.iter().any(|stmt| match stmt {
+        Stmt::Let(_, expr) | Stmt::Assign(_, expr, _) | Stmt::Expr(expr) => expr.diverges(),
+        Stmt::MultiAssign(pairs) => pairs.iter().any(|(_, expr)| expr.diverges()),
+        Stmt::Store(_, index, _, value, _) => index.diverges() || value.diverges(),
+        Stmt::Return(_) => true,
+        Stmt::If(cases) => {
+            let exhaustive = cases.last().is_some_and(|case| case.condition.is_none());
+            let first = cases.first().and_then(|case| case.condition.as_ref());
+            (exhaustive && cases.iter().all(|case| diverges(&case.block)))
+                || first.is_some_and(|condition| condition.diverges())
+        }
+        Stmt::Switch(scrutinee, cases) => {
+            let exhaustive = cases.iter().any(|case| case.labels.is_none());
+            (exhaustive && cases.iter().all(|case| diverges(&case.block))) || scrutinee.diverges()
+        }
+        // The block always runs at least once
+        Stmt::Repeat(block, condition) => {
+            (diverges(block) && !breaks(block, 0)) || condition.diverges()
+        }
+        // The block may never run
+        Stmt::While(condition, ..) => condition.diverges(),
+        Stmt::Break(_) => true,
+        Stmt::Declare(_) | Stmt::FuncDef(_) | Stmt::Loc(_) => false,
+    })
+}
+
+// Whether the block contains a break out of the loop `depth` loops out
+// from it, not counting breaks out of loops nested within the block
+pub fn breaks(block: &[Stmt], depth: usize) -> bool {
+    block.iter().any(|stmt| match stmt {
+        Stmt::Break(n) => *n == depth,
+        Stmt::If(cases) => cases.iter().any(|case| breaks(&case.block, depth)),
+        Stmt::Switch(_, cases) => cases.iter().any(|case| breaks(&case.block, depth)),
+        Stmt::Repeat(block, _) | Stmt::While(_, block, _) => breaks(block, depth + 1),
+        _ => false,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IfCase {
+    // `None` for the trailing `else` case
+    pub condition: Option<Expr>,
+    pub block: Block,
+    // Span of the condition, or of the `else` keyword
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchCase {
+    // `None` for the `default` case
+    pub labels: Option<Vec<ir::Value>>,
+    pub block: Block,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Func {
+    pub symbol: SymbolId,
+    pub params: Vec<SymbolId>,
+    pub return_t: Type,
+    pub block: Block,
+    // Declared `@extern`, with the definition in another unit, or
+    // `@intrinsic`, with none of its own
+    pub external: bool,
+    // The C function it stands for, if declared `@intrinsic`
+    pub intrinsic: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lambda {
+    pub params: Vec<SymbolId>,
+    pub return_t: Type,
+    pub block: Block,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Expr {
+    pub type_t: Type,
+    pub kind: ExprKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ExprKind {
+    Literal(ir::Value),
+    Var(SymbolId),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Call(SymbolId, Vec<Expr>),
+    // The element of the array at the index
+    Index(SymbolId, Box<Expr>),
+    Lambda(Lambda),
+    // Numeric conversion to the expression's type, either a widening,
+    // see Type::widens_to, or written out, see Value::convert
+    Cast(Box<Expr>),
+    // The right operand is only evaluated when the left doesn't decide
+    // the result
+    Logical(LogicalOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // Whether evaluating the expression always calls a function that
+    // never returns. Lambda bodies aren't evaluated by their definition.
+    pub fn diverges(&self) -> bool {
+        self.type_t == Type::Never
+            || match &self.kind {
+                ExprKind::Binary(_, l, r) => l.diverges() || r.diverges(),
+                ExprKind::Logical(_, l, _) => l.diverges(),
+                ExprKind::Unary(_, u) | ExprKind::Cast(u) | ExprKind::Index(_, u) => u.diverges(),
+                ExprKind::Call(_, args) => args.iter().any(|arg| arg.diverges()),
+                ExprKind::Literal(_) | ExprKind::Var(_) | ExprKind::Lambda(_) => false,
+            }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mult,
+    Div,
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Neq,
+    Leq,
+    Geq,
+    Lt,
+    Gt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum UnOp {
+    Not,
+    Neg,
+    BitNot,
+}
@@ -1,6 +1,9 @@
-use crate::ast::{Block, Expr, Node, Param, Root, Stmt, Term, TypedExpr, TypedTerm};
+use crate::ast::{Block, Expr, Node, Num, Param, Root, Span, Stmt, Term, TypedExpr, TypedTerm};
+use crate::builtins;
+use crate::diagnostics::Diagnostic;
 use crate::semantic::{new_empty_symbol_table, sinsert, slookup, SymbolStack, SymbolTable};
-use crate::symbol::{new_symbol, new_var};
+use crate::session::Edition;
+use crate::symbol::{new_symbol, new_var, Symbol};
 use crate::traverse::Traverse;
 use crate::types::{FunctionType, Type};
 
@@ -17,11 +20,212 @@ pub enum TypeError {
     DeriveNumType(String),
     #[error("Couldn't perform substitution: {0}")]
     SubstitutionError(String),
+    #[error("condition must be a bool, found {found}")]
+    ConditionNotBool { found: Type, span: Span },
+    #[error("the literal {literal} doesn't fit in {type_t}")]
+    LiteralOutOfRange {
+        literal: String,
+        type_t: Type,
+        span: Span,
+    },
+    #[error("expected {expected}, found an unsuffixed {found} literal")]
+    LiteralMismatch {
+        found: Type,
+        expected: Type,
+        literal: Num,
+        span: Span,
+    },
+    #[error("can't negate {found}, only signed numbers")]
+    NotNegatable { found: Type, span: Span },
+    #[error("returns {found}, but the function returns {expected}")]
+    ReturnMismatch {
+        found: Type,
+        expected: Type,
+        span: Span,
+    },
+    #[error("`{op}` needs {expected}, found {found}")]
+    BadOperand {
+        op: &'static str,
+        expected: &'static str,
+        found: Type,
+        span: Span,
+    },
+    #[error("`{ident}` isn't an array, so it can't be indexed")]
+    NotArray { ident: String, span: Span },
+    #[error("expected {expected}, found {found}")]
+    Mismatch {
+        found: Type,
+        expected: Type,
+        span: Span,
+    },
+}
+
+impl TypeError {
+    pub fn diagnostic(&self, src: &str) -> Diagnostic {
+        match self {
+            TypeError::ConditionNotBool { found, span } => {
+                let diagnostic = Diagnostic::error(self.to_string()).with_span(*span);
+                let cond = &src[span.start..span.end];
+                let (help, fixed) = match found {
+                    _ if found.is_numeric() => (
+                        "compare it explicitly",
+                        format!("{} != {}", cond, zero_literal(found)),
+                    ),
+                    Type::String => (
+                        "test whether it's empty",
+                        format!("byte_len({}) != 0u64", cond),
+                    ),
+                    _ => return diagnostic,
+                };
+                diagnostic
+                    .with_help(format!("{}: `{}`", help, fixed))
+                    .with_suggestion(*span, fixed)
+            }
+            TypeError::LiteralOutOfRange { type_t, span, .. } => {
+                let diagnostic = Diagnostic::error(self.to_string()).with_span(*span);
+                match type_t.integer_range() {
+                    Some((min, max)) => {
+                        diagnostic.with_help(format!("{} holds {} to {}", type_t, min, max))
+                    }
+                    None => diagnostic,
+                }
+            }
+            TypeError::LiteralMismatch {
+                expected,
+                literal,
+                span,
+                ..
+            } => {
+                let diagnostic = Diagnostic::error(self.to_string()).with_span(*span);
+                match suffixed(&src[span.start..span.end], literal, expected) {
+                    Some(fixed) => diagnostic
+                        .with_help(format!("write it as {}: `{}`", expected, fixed))
+                        .with_suggestion(*span, fixed),
+                    None => diagnostic,
+                }
+            }
+            TypeError::NotNegatable { found, span } => {
+                let diagnostic = Diagnostic::error(self.to_string()).with_span(*span);
+                match found {
+                    Type::UInt32 | Type::UInt64 => {
+                        diagnostic.with_note(format!("{} has no negative values", found))
+                    }
+                    _ => diagnostic,
+                }
+            }
+            TypeError::BadOperand { span, .. } | TypeError::NotArray { span, .. } => {
+                Diagnostic::error(self.to_string()).with_span(*span)
+            }
+            TypeError::ReturnMismatch { expected, span, .. } => Diagnostic::error(self.to_string())
+                .with_span(*span)
+                .with_label(format!("expected {}", expected)),
+            TypeError::Mismatch {
+                found,
+                expected,
+                span,
+            } => {
+                let diagnostic = Diagnostic::error(self.to_string()).with_span(*span);
+                if !found.is_numeric() || !expected.is_numeric() {
+                    return diagnostic;
+                }
+                let converted = format!("{}({})", expected, &src[span.start..span.end]);
+                let help = format!("convert it explicitly: `{}`", converted);
+                // Only a conversion that loses nothing is offered to fix
+                if found.widens_to(expected) {
+                    diagnostic
+                        .with_note(format!(
+                            "{} widens to {} only where a value is assigned, passed or returned, \
+                             never between operands, and not with --strict-numerics",
+                            found, expected
+                        ))
+                        .with_help(help)
+                        .with_suggestion(*span, converted)
+                } else {
+                    diagnostic
+                        .with_note(format!("numbers only widen implicitly {}", widenings()))
+                        .with_help(help)
+                }
+            }
+            _ => Diagnostic::error(self.to_string()),
+        }
+    }
+}
+
+// The implicit widenings, see Type::widens_to, as "from int32 to int64
+// or float64, ..."
+fn widenings() -> String {
+    let numeric = [
+        Type::Int32,
+        Type::Int64,
+        Type::UInt32,
+        Type::UInt64,
+        Type::Float32,
+        Type::Float64,
+    ];
+    let listed: Vec<String> = numeric
+        .iter()
+        .filter_map(|from| {
+            let to: Vec<String> = numeric
+                .iter()
+                .filter(|to| from.widens_to(to))
+                .map(|to| to.to_string())
+                .collect();
+            (!to.is_empty()).then(|| format!("from {} to {}", from, to.join(" or ")))
+        })
+        .collect();
+    match listed.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{}, and {}", rest.join(", "), last),
+        _ => listed.join(""),
+    }
+}
+
+// Zero written with the suffix for a numeric type
+fn zero_literal(type_t: &Type) -> &'static str {
+    match type_t {
+        Type::Int64 => "0i64",
+        Type::UInt32 => "0u32",
+        Type::UInt64 => "0u64",
+        Type::Float32 => "0.0f32",
+        Type::Float64 => "0.0",
+        _ => "0",
+    }
+}
+
+// An unsuffixed literal, as written, with what makes it a literal of
+// `type_t` when it converts without losing anything, see Num::coerce
+fn suffixed(written: &str, literal: &Num, type_t: &Type) -> Option<String> {
+    let point = match literal.integer() {
+        Some(_) if !type_t.is_integer() => ".0",
+        _ => "",
+    };
+    let suffix = match literal.coerce(type_t)? {
+        Num::Int64(_) => "i64",
+        Num::UInt32(_) => "u32",
+        Num::UInt64(_) => "u64",
+        Num::Float32(_) => "f32",
+        Num::Int32(_) | Num::Float64(_) => "",
+    };
+    Some(format!("{}{}{}", written, point, suffix))
 }
 
 #[derive(Debug, Clone)]
 pub enum Constraint {
     Eq(Type, Type),
+    // The type of the value at the span is the second type, or widens
+    // to it when the flag is set
+    Flows(Type, Type, Span, bool),
+    // The type of the condition at the span is bool
+    Condition(Type, Span),
+    // The type of the unsuffixed literal at the span is the type
+    Literal(Num, Type, Span),
+    // The type of the negated expression at the span is a signed number
+    Negated(Type, Span),
+    // The type of the operator's operands at the span is one it
+    // applies to, see operand_types
+    Operand(&'static str, Type, Span),
+    // The type returned at the span is the function's return type, or
+    // widens to it when the flag is set, as for Widen
+    Returned(Type, Type, Span, bool),
 }
 
 #[derive(Debug, Clone)]
@@ -145,36 +349,153 @@ fn subst(sub: Vec<Subst>, t: Type) -> Type {
 fn subst_into_constr(sub: Vec<Subst>, constr: Constraint) -> Constraint {
     match constr {
         Constraint::Eq(t1, t2) => Constraint::Eq(subst(sub.clone(), t1), subst(sub.clone(), t2)),
+        Constraint::Flows(t1, t2, span, widen) => {
+            Constraint::Flows(subst(sub.clone(), t1), subst(sub, t2), span, widen)
+        }
+        Constraint::Condition(t, span) => Constraint::Condition(subst(sub, t), span),
+        Constraint::Literal(num, t, span) => Constraint::Literal(num, subst(sub, t), span),
+        Constraint::Negated(t, span) => Constraint::Negated(subst(sub, t), span),
+        Constraint::Operand(op, t, span) => Constraint::Operand(op, subst(sub, t), span),
+        Constraint::Returned(t1, t2, span, widen) => {
+            Constraint::Returned(subst(sub.clone(), t1), subst(sub, t2), span, widen)
+        }
     }
 }
 
-fn solve_helper(
-    constraints: Vec<Constraint>,
-    sub: &mut Vec<Subst>,
-) -> Result<Vec<Subst>, TypeError> {
-    match constraints.first() {
-        Some(cst) => match cst.clone() {
-            Constraint::Eq(t1, t2) => {
-                let new_subs = mgu(t1, t2)?;
-                sub.extend(new_subs);
-                solve_helper(
-                    constraints
-                        .iter()
-                        .skip(1)
-                        .map(|c| subst_into_constr(sub.clone(), c.clone()))
-                        .collect(),
-                    sub,
-                )
-            }
-        },
-        None => Ok(vec![]),
+// Solves the constraints in order, substituting what each one solves
+// into those after it. Programs make thousands of constraints, so this
+// is a loop rather than a recursion through them.
+fn solve_helper(mut constraints: Vec<Constraint>, sub: &mut Vec<Subst>) -> Result<(), TypeError> {
+    for next in 0..constraints.len() {
+        let new_subs = match &constraints[next] {
+            Constraint::Eq(t1, t2) => mgu(t1.clone(), t2.clone())?,
+            Constraint::Flows(t1, t2, _, true) if t1.widens_to(t2) => vec![],
+            Constraint::Flows(t1, t2, span, _) => {
+                mgu(t1.clone(), t2.clone()).map_err(|_| TypeError::Mismatch {
+                    found: t1.clone(),
+                    expected: t2.clone(),
+                    span: *span,
+                })?
+            }
+            Constraint::Returned(t1, t2, _, true) if t1.widens_to(t2) => vec![],
+            Constraint::Returned(t1, t2, span, _) => {
+                mgu(t1.clone(), t2.clone()).map_err(|_| TypeError::ReturnMismatch {
+                    found: t1.clone(),
+                    expected: t2.clone(),
+                    span: *span,
+                })?
+            }
+            Constraint::Condition(t, span) => {
+                mgu(t.clone(), Type::Bool).map_err(|_| TypeError::ConditionNotBool {
+                    found: t.clone(),
+                    span: *span,
+                })?
+            }
+            Constraint::Literal(num, t, span) => {
+                let found = Type::try_from(num.clone()).unwrap_or(Type::Unknown);
+                mgu(found.clone(), t.clone()).map_err(|_| TypeError::LiteralMismatch {
+                    found,
+                    expected: t.clone(),
+                    literal: num.clone(),
+                    span: *span,
+                })?
+            }
+            // Checked once everything else is solved, see solve
+            Constraint::Negated(..) | Constraint::Operand(..) => vec![],
+        };
+        sub.extend(new_subs);
+        for c in constraints[next + 1..].iter_mut() {
+            *c = subst_into_constr(sub.clone(), c.clone());
+        }
     }
+    Ok(())
 }
 
 pub fn solve(constraints: Vec<Constraint>) -> Result<Vec<Subst>, TypeError> {
+    // Widening needs to know both types, so it's checked after all of
+    // the equalities are solved. A widening constraint whose types are
+    // still unknown at that point falls back to equality.
+    // Negation and the integer operators check a type rather than
+    // solving for one, so they're checked once every type is known
+    let (operators, constraints): (Vec<Constraint>, Vec<Constraint>) = constraints
+        .into_iter()
+        .partition(|c| matches!(c, Constraint::Negated(..) | Constraint::Operand(..)));
+    let (widen, eq): (Vec<Constraint>, Vec<Constraint>) = constraints.into_iter().partition(|c| {
+        matches!(
+            c,
+            Constraint::Flows(.., true) | Constraint::Returned(.., true)
+        )
+    });
     let mut substitutions = vec![];
-    solve_helper(constraints, &mut substitutions)?;
-    Ok(substitutions)
+    solve_helper(eq, &mut substitutions)?;
+    let substitutions = resolve_substitutions(substitutions);
+    let mut widened = substitutions.clone();
+    solve_helper(
+        widen
+            .into_iter()
+            .map(|c| subst_into_constr(substitutions.clone(), c))
+            .collect(),
+        &mut widened,
+    )?;
+    let widened = resolve_substitutions(widened);
+    for c in operators {
+        match subst_into_constr(widened.clone(), c) {
+            Constraint::Negated(t, span) if !unknown(&t) => {
+                let signed = matches!(t, Type::Int32 | Type::Int64 | Type::Float32 | Type::Float64);
+                if !signed {
+                    return Err(TypeError::NotNegatable { found: t, span });
+                }
+            }
+            Constraint::Operand(op, t, span) if !unknown(&t) => {
+                let (expected, applies) = operand_types(op);
+                if !applies(&t) {
+                    return Err(TypeError::BadOperand {
+                        op,
+                        expected,
+                        found: t,
+                        span,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(widened)
+}
+
+// What an operator's operands can be, described and as a test. `+`
+// concatenates strings as well as adding numbers.
+fn operand_types(op: &str) -> (&'static str, fn(&Type) -> bool) {
+    match op {
+        "+" => ("numbers or strings", |t| {
+            t.is_numeric() || *t == Type::String
+        }),
+        "-" | "*" | "/" | "**" => ("numbers", Type::is_numeric),
+        _ if op.ends_with("()") => ("numbers", Type::is_numeric),
+        _ => ("integers", Type::is_integer),
+    }
+}
+
+fn unknown(t: &Type) -> bool {
+    matches!(t, Type::TypeVar(_) | Type::Unknown)
+}
+
+// A substitution can name a type variable bound by a later one, so
+// substitute into each one until only unbound type variables remain
+fn resolve_substitutions(substitutions: Vec<Subst>) -> Vec<Subst> {
+    substitutions
+        .iter()
+        .map(|Subst(var, t)| {
+            let mut t = t.clone();
+            loop {
+                let next = subst(substitutions.clone(), t.clone());
+                if next == t {
+                    break Subst(var.clone(), t);
+                }
+                t = next;
+            }
+        })
+        .collect()
 }
 
 // Implement inference with:
@@ -192,6 +513,11 @@ pub struct InferState {
     pub constraints: Vec<Constraint>,
     symbols: SymbolStack,
     type_mapping: HashMap<Type, Type>,
+    // Return types of the enclosing functions, innermost last
+    returns: Vec<Type>,
+    // Require values to match declared types exactly, see Type::widens_to
+    pub strict_numerics: bool,
+    pub edition: Edition,
 }
 
 pub struct SubState {
@@ -215,7 +541,17 @@ impl Traverse for TypingState {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
             }
-            Expr::Div(ref mut lhs, ref mut rhs) => {
+            Expr::Div(ref mut lhs, ref mut rhs)
+            | Expr::Mod(ref mut lhs, ref mut rhs)
+            | Expr::Pow(ref mut lhs, ref mut rhs) => {
+                self.visit_expr(lhs)?;
+                self.visit_expr(rhs)?;
+            }
+            Expr::BitAnd(ref mut lhs, ref mut rhs)
+            | Expr::BitOr(ref mut lhs, ref mut rhs)
+            | Expr::BitXor(ref mut lhs, ref mut rhs)
+            | Expr::Shl(ref mut lhs, ref mut rhs)
+            | Expr::Shr(ref mut lhs, ref mut rhs) => {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
             }
@@ -243,10 +579,14 @@ impl Traverse for TypingState {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
             }
+            Expr::And(ref mut lhs, ref mut rhs) | Expr::Or(ref mut lhs, ref mut rhs) => {
+                self.visit_expr(lhs)?;
+                self.visit_expr(rhs)?;
+            }
             Expr::Not(ref mut u) => {
                 self.visit_expr(u)?;
             }
-            Expr::Neg(ref mut u) => {
+            Expr::Neg(ref mut u) | Expr::BitNot(ref mut u) => {
                 self.visit_expr(u)?;
             }
             Expr::Term(ref mut t) => {
@@ -257,6 +597,13 @@ impl Traverse for TypingState {
                     self.visit_expr(&mut arg)?;
                 }
             }
+            Expr::Index(_, _, ref mut index) => {
+                self.visit_expr(index)?;
+            }
+            Expr::SizeOf(_) => {}
+            Expr::TypeName(ref mut e) | Expr::Convert(_, ref mut e) => {
+                self.visit_expr(e)?;
+            }
             Expr::LambdaFunc(ref mut lf) => {
                 lf.params = lf
                     .params
@@ -268,6 +615,7 @@ impl Traverse for TypingState {
                                 Type::Unknown => self.get_new_type_var(),
                                 _ => p.type_t.clone(),
                             },
+                            span: p.span,
                         })
                     })
                     .collect();
@@ -293,7 +641,7 @@ impl Traverse for TypingState {
                     _ => term.type_t.clone(),
                 };
             }
-            Term::Num(ref num) => {
+            Term::Num(ref num, _) => {
                 term.type_t = match Type::try_from(num.clone()) {
                     Ok(t) => Ok(t),
                     Err(_) => Err(TypeError::DeriveNumType("Couldn't derive Num type".into())),
@@ -318,27 +666,67 @@ impl Traverse for TypingState {
 
     fn visit_stmt(&mut self, stmt: &mut Stmt) -> Result<(), Self::Error> {
         match stmt {
+            Stmt::Switch(scrutinee, cases, _) => {
+                self.visit_expr(scrutinee)?;
+                for case in cases {
+                    for label in case.labels.iter_mut() {
+                        self.visit_expr(label)?;
+                    }
+                    self.visit_block(&mut case.block)?;
+                }
+            }
+            Stmt::Repeat(block, condition, _) => {
+                self.visit_block(block)?;
+                self.visit_expr(condition)?;
+            }
+            Stmt::While(condition, block, _) => {
+                self.visit_expr(condition)?;
+                self.visit_block(block)?;
+            }
+            Stmt::Bench(_, count, block, _) => {
+                if let Some(count) = count {
+                    self.visit_expr(count)?;
+                }
+                self.visit_block(block)?;
+            }
+            Stmt::Break(..) => {}
+            Stmt::StaticAssert(condition, ..) => {
+                self.visit_expr(condition)?;
+            }
+            Stmt::Defer(stmt) | Stmt::Static(stmt) | Stmt::Attributed(_, stmt) => {
+                self.visit_stmt(stmt)?;
+            }
             Stmt::If(if_cases) => {
                 for if_case in if_cases {
                     self.visit_expr(&mut if_case.condition)?;
                     self.visit_block(&mut if_case.block)?;
                 }
             }
-            Stmt::Assign(_, var, expr) => {
+            Stmt::Assign(_, var, expr, ..) => {
                 self.visit_expr(expr)?;
                 var.type_t = match var.type_t.clone() {
                     Type::Unknown => self.get_new_type_var(),
                     other => other.clone(),
                 };
             }
-            Stmt::Reassign(_, var, _, expr) => {
+            Stmt::Reassign(_, var, _, expr, _) => {
                 self.visit_expr(expr)?;
                 var.type_t = match var.type_t.clone() {
                     Type::Unknown => self.get_new_type_var(),
                     other => other.clone(),
                 };
             }
-            Stmt::Call(_, args) => {
+            Stmt::Declare(..) => {}
+            Stmt::Store(_, index, _, value, _) => {
+                self.visit_expr(index)?;
+                self.visit_expr(value)?;
+            }
+            Stmt::MultiAssign(_, values) => {
+                for value in values {
+                    self.visit_expr(value)?;
+                }
+            }
+            Stmt::Call(_, args, _) => {
                 for arg in args {
                     self.visit_expr(arg)?;
                 }
@@ -355,6 +743,7 @@ impl Traverse for TypingState {
                                 Type::Unknown => self.get_new_type_var(),
                                 _ => p.type_t,
                             },
+                            span: p.span,
                         })
                     })
                     .collect();
@@ -380,11 +769,17 @@ impl Traverse for InferState {
             preblock,
             program,
             postblock,
+            ..
         } = root;
         self.spush();
+        builtins::declare(&mut self.symbols);
+        self.declare_funcs(preblock);
+        self.declare_funcs(postblock);
         self.visit_preblock(preblock)?;
         self.visit_postblock(postblock)?;
-        self.visit_program(program)?;
+        if let Some(program) = program {
+            self.visit_program(program)?;
+        }
         self.spop();
         Ok(())
     }
@@ -405,6 +800,7 @@ impl Traverse for InferState {
 
     fn visit_block(&mut self, block: &mut Block) -> Result<(), Self::Error> {
         self.spush();
+        self.declare_funcs(block);
         for stmt in block {
             self.visit_stmt(stmt)?;
         }
@@ -415,82 +811,87 @@ impl Traverse for InferState {
     fn visit_expr(&mut self, expr: &mut TypedExpr) -> Result<(), Self::Error> {
         match expr.expr {
             Expr::Add(ref mut lhs, ref mut rhs) => {
-                self.visit_expr(lhs)?;
-                self.visit_expr(rhs)?;
-                self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), expr.type_t.clone()));
-                self.constraints
-                    .push(Constraint::Eq(rhs.type_t.clone(), expr.type_t.clone()));
+                self.typed_operands("+", lhs, rhs, expr.type_t.clone(), expr.span)?;
             }
             Expr::Sub(ref mut lhs, ref mut rhs) => {
-                self.visit_expr(lhs)?;
-                self.visit_expr(rhs)?;
-                self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), expr.type_t.clone()));
-                self.constraints
-                    .push(Constraint::Eq(rhs.type_t.clone(), expr.type_t.clone()));
+                self.typed_operands("-", lhs, rhs, expr.type_t.clone(), expr.span)?;
             }
             Expr::Mult(ref mut lhs, ref mut rhs) => {
-                self.visit_expr(lhs)?;
-                self.visit_expr(rhs)?;
-                self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), expr.type_t.clone()));
-                self.constraints
-                    .push(Constraint::Eq(rhs.type_t.clone(), expr.type_t.clone()));
+                self.typed_operands("*", lhs, rhs, expr.type_t.clone(), expr.span)?;
             }
             Expr::Div(ref mut lhs, ref mut rhs) => {
-                self.visit_expr(lhs)?;
-                self.visit_expr(rhs)?;
-                self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), expr.type_t.clone()));
-                self.constraints
-                    .push(Constraint::Eq(rhs.type_t.clone(), expr.type_t.clone()));
+                self.typed_operands("/", lhs, rhs, expr.type_t.clone(), expr.span)?;
+            }
+            Expr::Mod(ref mut lhs, ref mut rhs) => {
+                self.typed_operands("%", lhs, rhs, expr.type_t.clone(), expr.span)?;
+            }
+            Expr::Pow(ref mut lhs, ref mut rhs) => {
+                self.typed_operands("**", lhs, rhs, expr.type_t.clone(), expr.span)?;
+            }
+            Expr::BitAnd(ref mut lhs, ref mut rhs) => {
+                self.typed_operands("&", lhs, rhs, expr.type_t.clone(), expr.span)?;
+            }
+            Expr::BitOr(ref mut lhs, ref mut rhs) => {
+                self.typed_operands("|", lhs, rhs, expr.type_t.clone(), expr.span)?;
+            }
+            Expr::BitXor(ref mut lhs, ref mut rhs) => {
+                self.typed_operands("^", lhs, rhs, expr.type_t.clone(), expr.span)?;
+            }
+            Expr::Shl(ref mut lhs, ref mut rhs) => {
+                self.typed_operands("<<", lhs, rhs, expr.type_t.clone(), expr.span)?;
+            }
+            Expr::Shr(ref mut lhs, ref mut rhs) => {
+                self.typed_operands(">>", lhs, rhs, expr.type_t.clone(), expr.span)?;
             }
             Expr::Eq(ref mut lhs, ref mut rhs) => {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
-                self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), rhs.type_t.clone()));
+                self.compared(lhs, rhs);
                 self.constraints
                     .push(Constraint::Eq(expr.type_t.clone(), Type::Bool));
             }
             Expr::Neq(ref mut lhs, ref mut rhs) => {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
-                self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), rhs.type_t.clone()));
+                self.compared(lhs, rhs);
                 self.constraints
                     .push(Constraint::Eq(expr.type_t.clone(), Type::Bool));
             }
             Expr::Leq(ref mut lhs, ref mut rhs) => {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
-                self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), rhs.type_t.clone()));
+                self.compared(lhs, rhs);
                 self.constraints
                     .push(Constraint::Eq(expr.type_t.clone(), Type::Bool));
             }
             Expr::Geq(ref mut lhs, ref mut rhs) => {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
-                self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), rhs.type_t.clone()));
+                self.compared(lhs, rhs);
                 self.constraints
                     .push(Constraint::Eq(expr.type_t.clone(), Type::Bool));
             }
             Expr::LessThan(ref mut lhs, ref mut rhs) => {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
-                self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), rhs.type_t.clone()));
+                self.compared(lhs, rhs);
                 self.constraints
                     .push(Constraint::Eq(expr.type_t.clone(), Type::Bool));
             }
             Expr::GreaterThan(ref mut lhs, ref mut rhs) => {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
+                self.compared(lhs, rhs);
+                self.constraints
+                    .push(Constraint::Eq(expr.type_t.clone(), Type::Bool));
+            }
+            Expr::And(ref mut lhs, ref mut rhs) | Expr::Or(ref mut lhs, ref mut rhs) => {
+                self.visit_expr(lhs)?;
+                self.visit_expr(rhs)?;
+                self.constraints
+                    .push(Constraint::Condition(lhs.type_t.clone(), lhs.span));
                 self.constraints
-                    .push(Constraint::Eq(lhs.type_t.clone(), rhs.type_t.clone()));
+                    .push(Constraint::Condition(rhs.type_t.clone(), rhs.span));
                 self.constraints
                     .push(Constraint::Eq(expr.type_t.clone(), Type::Bool));
             }
@@ -502,11 +903,28 @@ impl Traverse for InferState {
                     .push(Constraint::Eq(Type::Bool, expr.type_t.clone()));
             }
             Expr::Neg(ref mut u) => {
+                // An unsigned literal has no negative to be
+                if let Expr::Term(ref t) = u.expr {
+                    if let Term::Num(ref num @ (Num::UInt32(_) | Num::UInt64(_)), _) = t.term {
+                        return Err(TypeError::LiteralOutOfRange {
+                            literal: format!("-{}", num.integer().unwrap_or_default()),
+                            type_t: Type::try_from(num.clone()).unwrap_or(Type::Unknown),
+                            span: expr.span,
+                        });
+                    }
+                }
                 self.visit_expr(u)?;
                 self.constraints
                     .push(Constraint::Eq(u.type_t.clone(), expr.type_t.clone()));
                 self.constraints
-                    .push(Constraint::Eq(Type::Bool, expr.type_t.clone()));
+                    .push(Constraint::Negated(u.type_t.clone(), u.span));
+            }
+            Expr::BitNot(ref mut u) => {
+                self.visit_expr(u)?;
+                self.constraints
+                    .push(Constraint::Eq(u.type_t.clone(), expr.type_t.clone()));
+                self.constraints
+                    .push(Constraint::Operand("~", u.type_t.clone(), u.span));
             }
             Expr::Term(ref mut t) => {
                 self.visit_term(t)?;
@@ -525,22 +943,55 @@ impl Traverse for InferState {
                     ))),
                 }?;
                 for (arg, target_param_type) in args.into_iter().zip(target_func_type.params_t) {
+                    coerce_literal(arg, &target_param_type)?;
                     self.visit_expr(arg)?;
-                    self.constraints
-                        .push(Constraint::Eq(arg.type_t.clone(), target_param_type));
+                    self.flows_into(arg.type_t.clone(), target_param_type, arg.span);
                 }
+                self.constraints.push(Constraint::Eq(
+                    expr.type_t.clone(),
+                    *target_func_type.return_t,
+                ));
+            }
+            Expr::Index(ref symbol, span, ref mut index) => {
+                let element_t = self.element_type(symbol, span)?;
+                self.visit_expr(index)?;
+                self.constraints
+                    .push(Constraint::Operand("[]", index.type_t.clone(), index.span));
+                self.constraints
+                    .push(Constraint::Eq(expr.type_t.clone(), element_t));
+            }
+            Expr::SizeOf(_) => {
+                self.constraints
+                    .push(Constraint::Eq(expr.type_t.clone(), Type::UInt64));
+            }
+            Expr::TypeName(ref mut e) => {
+                self.visit_expr(e)?;
+                self.constraints
+                    .push(Constraint::Eq(expr.type_t.clone(), Type::String));
+            }
+            Expr::Convert(ref type_t, ref mut e) => {
+                self.visit_expr(e)?;
+                self.constraints.push(Constraint::Operand(
+                    conversion(type_t),
+                    e.type_t.clone(),
+                    e.span,
+                ));
+                self.constraints
+                    .push(Constraint::Eq(expr.type_t.clone(), type_t.clone()));
             }
             Expr::LambdaFunc(ref mut lf) => {
                 self.spush();
                 for param in lf.params.clone() {
-                    let Param { type_t, ident } = *param;
+                    let Param { type_t, ident, .. } = *param;
                     sinsert(
                         &mut self.symbols,
                         new_symbol(ident),
                         new_var(type_t, Node::Null),
                     );
                 }
+                self.returns.push(lf.return_t.clone());
                 self.visit_block(&mut lf.block)?;
+                self.returns.pop();
                 self.spop();
             }
         }
@@ -559,7 +1010,7 @@ impl Traverse for InferState {
                     found_term.type_t.clone(),
                 ));
             }
-            Term::Num(_) => {}
+            Term::Num(..) => {}
             Term::Bool(_) => {}
             Term::String(_) => {}
             Term::Expr(ref mut expr) => {
@@ -573,27 +1024,112 @@ impl Traverse for InferState {
 
     fn visit_stmt(&mut self, stmt: &mut Stmt) -> Result<(), Self::Error> {
         match stmt {
+            Stmt::Switch(scrutinee, cases, _) => {
+                self.visit_expr(scrutinee)?;
+                for case in cases {
+                    for label in case.labels.iter_mut() {
+                        self.visit_expr(label)?;
+                        self.constraints.push(Constraint::Eq(
+                            label.type_t.clone(),
+                            scrutinee.type_t.clone(),
+                        ));
+                    }
+                    self.visit_block(&mut case.block)?;
+                }
+            }
+            Stmt::Repeat(block, condition, _) => {
+                self.visit_block(block)?;
+                self.visit_expr(condition)?;
+                self.constraints.push(Constraint::Condition(
+                    condition.type_t.clone(),
+                    condition.span,
+                ));
+            }
+            Stmt::While(condition, block, _) => {
+                self.visit_expr(condition)?;
+                self.constraints.push(Constraint::Condition(
+                    condition.type_t.clone(),
+                    condition.span,
+                ));
+                self.visit_block(block)?;
+            }
+            Stmt::Bench(_, count, block, _) => {
+                if let Some(count) = count {
+                    coerce_literal(count, &Type::UInt64)?;
+                    self.visit_expr(count)?;
+                    self.flows_into(count.type_t.clone(), Type::UInt64, count.span);
+                }
+                self.visit_block(block)?;
+            }
+            Stmt::Break(..) => {}
+            Stmt::StaticAssert(condition, ..) => {
+                type_constant_literals(condition)?;
+                self.visit_expr(condition)?;
+                self.constraints.push(Constraint::Condition(
+                    condition.type_t.clone(),
+                    condition.span,
+                ));
+            }
+            Stmt::Defer(stmt) | Stmt::Static(stmt) | Stmt::Attributed(_, stmt) => {
+                self.visit_stmt(stmt)?;
+            }
             Stmt::If(if_cases) => {
                 for if_case in if_cases {
                     self.visit_expr(&mut if_case.condition)?;
-                    self.constraints
-                        .push(Constraint::Eq(if_case.condition.type_t.clone(), Type::Bool));
+                    self.constraints.push(Constraint::Condition(
+                        if_case.condition.type_t.clone(),
+                        if_case.condition.span,
+                    ));
                     self.visit_block(&mut if_case.block)?;
                 }
             }
-            Stmt::Assign(symbol, var, expr) => {
+            Stmt::Assign(symbol, var, expr, ..) => {
+                coerce_literal(expr, &var.type_t)?;
                 self.visit_expr(expr)?;
                 sinsert(&mut self.symbols, symbol.clone(), *var.clone());
-                self.constraints
-                    .push(Constraint::Eq(var.type_t.clone(), expr.type_t.clone()));
+                self.flows_into(expr.type_t.clone(), var.type_t.clone(), expr.span);
             }
-            Stmt::Reassign(symbol, var, _, expr) => {
+            Stmt::Reassign(symbol, var, assign_op, expr, span) => {
+                let target = slookup(&self.symbols, symbol.clone()).ok_or(
+                    TypeError::IdentNotFound(format!("Ident {:?} not found", symbol.clone())),
+                )?;
+                let target_type = target.type_t.clone();
+                coerce_literal(expr, &target_type)?;
                 self.visit_expr(expr)?;
+                self.constraints
+                    .push(Constraint::Eq(var.type_t.clone(), target_type.clone()));
+                if let Some(op) = assign_op.operator() {
+                    self.constraints
+                        .push(Constraint::Operand(op, target_type.clone(), *span));
+                }
+                self.flows_into(expr.type_t.clone(), target_type, expr.span);
+            }
+            Stmt::Declare(symbol, var, ..) => {
                 sinsert(&mut self.symbols, symbol.clone(), *var.clone());
+            }
+            Stmt::Store(symbol, index, assign_op, value, span) => {
+                let element_t = self.element_type(symbol, *span)?;
+                coerce_literal(value, &element_t)?;
+                self.visit_expr(index)?;
+                self.visit_expr(value)?;
                 self.constraints
-                    .push(Constraint::Eq(var.type_t.clone(), expr.type_t.clone()));
+                    .push(Constraint::Operand("[]", index.type_t.clone(), index.span));
+                if let Some(op) = assign_op.operator() {
+                    self.constraints
+                        .push(Constraint::Operand(op, element_t.clone(), *span));
+                }
+                self.flows_into(value.type_t.clone(), element_t, value.span);
+            }
+            Stmt::MultiAssign(targets, values) => {
+                for ((symbol, _), value) in targets.iter().zip(values) {
+                    self.visit_expr(value)?;
+                    let target = slookup(&self.symbols, symbol.clone()).ok_or(
+                        TypeError::IdentNotFound(format!("Ident {:?} not found", symbol.clone())),
+                    )?;
+                    self.flows_into(value.type_t.clone(), target.type_t.clone(), value.span);
+                }
             }
-            Stmt::Call(symbol, args) => {
+            Stmt::Call(symbol, args, _) => {
                 let target_func = slookup(&self.symbols, symbol.clone()).ok_or(
                     TypeError::IdentNotFound(format!("Function {:?} not found", symbol.clone())),
                 )?;
@@ -605,39 +1141,42 @@ impl Traverse for InferState {
                     ))),
                 }?;
                 for (arg, target_param_type) in args.into_iter().zip(target_func_type.params_t) {
+                    coerce_literal(arg, &target_param_type)?;
                     self.visit_expr(arg)?;
-                    self.constraints
-                        .push(Constraint::Eq(arg.type_t.clone(), target_param_type));
+                    self.flows_into(arg.type_t.clone(), target_param_type, arg.span);
                 }
             }
             Stmt::FuncDef(func) => {
-                sinsert(
-                    &mut self.symbols,
-                    new_symbol(func.ident.clone()),
-                    new_var(
-                        Type::Function(FunctionType {
-                            params_t: func.params.iter().map(|p| p.type_t.clone()).collect(),
-                            return_t: Box::new(func.return_t.clone()),
-                        }),
-                        Node::Null,
-                    ),
-                );
                 self.spush();
                 for param in func.params.clone() {
-                    let Param { type_t, ident } = *param;
+                    let Param { type_t, ident, .. } = *param;
                     sinsert(
                         &mut self.symbols,
                         new_symbol(ident),
                         new_var(type_t, Node::Null),
                     );
                 }
+                self.returns.push(func.return_t.clone());
                 self.visit_block(&mut func.block)?;
+                self.returns.pop();
                 self.spop();
             }
             Stmt::Return(expr) => {
-                // TODO: the type of `expr` needs to be Eq to
-                // the return value of the surrounding function
-                self.visit_expr(expr)?;
+                // Returns from the program block aren't checked
+                match self.returns.last().cloned() {
+                    Some(return_t) => {
+                        coerce_literal(expr, &return_t)?;
+                        self.visit_expr(expr)?;
+                        let widen = self.can_widen_to(&return_t);
+                        self.constraints.push(Constraint::Returned(
+                            expr.type_t.clone(),
+                            return_t,
+                            expr.span,
+                            widen,
+                        ));
+                    }
+                    None => self.visit_expr(expr)?,
+                }
             }
         };
         Ok(())
@@ -661,7 +1200,17 @@ impl Traverse for SubState {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
             }
-            Expr::Div(ref mut lhs, ref mut rhs) => {
+            Expr::Div(ref mut lhs, ref mut rhs)
+            | Expr::Mod(ref mut lhs, ref mut rhs)
+            | Expr::Pow(ref mut lhs, ref mut rhs) => {
+                self.visit_expr(lhs)?;
+                self.visit_expr(rhs)?;
+            }
+            Expr::BitAnd(ref mut lhs, ref mut rhs)
+            | Expr::BitOr(ref mut lhs, ref mut rhs)
+            | Expr::BitXor(ref mut lhs, ref mut rhs)
+            | Expr::Shl(ref mut lhs, ref mut rhs)
+            | Expr::Shr(ref mut lhs, ref mut rhs) => {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
             }
@@ -689,10 +1238,14 @@ impl Traverse for SubState {
                 self.visit_expr(lhs)?;
                 self.visit_expr(rhs)?;
             }
+            Expr::And(ref mut lhs, ref mut rhs) | Expr::Or(ref mut lhs, ref mut rhs) => {
+                self.visit_expr(lhs)?;
+                self.visit_expr(rhs)?;
+            }
             Expr::Not(ref mut u) => {
                 self.visit_expr(u)?;
             }
-            Expr::Neg(ref mut u) => {
+            Expr::Neg(ref mut u) | Expr::BitNot(ref mut u) => {
                 self.visit_expr(u)?;
             }
             Expr::Term(ref mut t) => {
@@ -703,6 +1256,13 @@ impl Traverse for SubState {
                     self.visit_expr(&mut arg)?;
                 }
             }
+            Expr::Index(_, _, ref mut index) => {
+                self.visit_expr(index)?;
+            }
+            Expr::SizeOf(_) => {}
+            Expr::TypeName(ref mut e) | Expr::Convert(_, ref mut e) => {
+                self.visit_expr(e)?;
+            }
             Expr::LambdaFunc(ref mut lf) => {
                 lf.params = lf
                     .params
@@ -711,6 +1271,7 @@ impl Traverse for SubState {
                         Box::new(Param {
                             ident: p.ident.clone(),
                             type_t: self.resolve_type(p.type_t.clone()),
+                            span: p.span,
                         })
                     })
                     .collect();
@@ -727,7 +1288,7 @@ impl Traverse for SubState {
             Term::Id(_) => {
                 term.type_t = self.resolve_type(term.type_t.clone());
             }
-            Term::Num(ref num) => {
+            Term::Num(ref num, _) => {
                 term.type_t = match Type::try_from(num.clone()) {
                     Ok(t) => Ok(t),
                     Err(_) => Err(TypeError::DeriveNumType("Couldn't derive Num type".into())),
@@ -749,21 +1310,61 @@ impl Traverse for SubState {
 
     fn visit_stmt(&mut self, stmt: &mut Stmt) -> Result<(), Self::Error> {
         match stmt {
+            Stmt::Switch(scrutinee, cases, _) => {
+                self.visit_expr(scrutinee)?;
+                for case in cases {
+                    for label in case.labels.iter_mut() {
+                        self.visit_expr(label)?;
+                    }
+                    self.visit_block(&mut case.block)?;
+                }
+            }
+            Stmt::Repeat(block, condition, _) => {
+                self.visit_block(block)?;
+                self.visit_expr(condition)?;
+            }
+            Stmt::While(condition, block, _) => {
+                self.visit_expr(condition)?;
+                self.visit_block(block)?;
+            }
+            Stmt::Bench(_, count, block, _) => {
+                if let Some(count) = count {
+                    self.visit_expr(count)?;
+                }
+                self.visit_block(block)?;
+            }
+            Stmt::Break(..) => {}
+            Stmt::StaticAssert(condition, ..) => {
+                self.visit_expr(condition)?;
+            }
+            Stmt::Defer(stmt) | Stmt::Static(stmt) | Stmt::Attributed(_, stmt) => {
+                self.visit_stmt(stmt)?;
+            }
             Stmt::If(if_cases) => {
                 for if_case in if_cases {
                     self.visit_expr(&mut if_case.condition)?;
                     self.visit_block(&mut if_case.block)?;
                 }
             }
-            Stmt::Assign(_, var, expr) => {
+            Stmt::Assign(_, var, expr, ..) => {
                 self.visit_expr(expr)?;
                 var.type_t = self.resolve_type(var.type_t.clone());
             }
-            Stmt::Reassign(_, var, _, expr) => {
+            Stmt::Reassign(_, var, _, expr, _) => {
                 self.visit_expr(expr)?;
                 var.type_t = self.resolve_type(var.type_t.clone());
             }
-            Stmt::Call(_, args) => {
+            Stmt::Declare(..) => {}
+            Stmt::Store(_, index, _, value, _) => {
+                self.visit_expr(index)?;
+                self.visit_expr(value)?;
+            }
+            Stmt::MultiAssign(_, values) => {
+                for value in values {
+                    self.visit_expr(value)?;
+                }
+            }
+            Stmt::Call(_, args, _) => {
                 for arg in args {
                     self.visit_expr(arg)?;
                 }
@@ -777,6 +1378,7 @@ impl Traverse for SubState {
                         Box::new(Param {
                             ident: p.ident,
                             type_t: self.resolve_type(p.type_t),
+                            span: p.span,
                         })
                     })
                     .collect();
@@ -809,19 +1411,247 @@ impl TypingState {
     }
 }
 
+// Unsuffixed number literals assigned, passed as arguments or returned
+// from a function take the type expected there, see Num::coerce. An
+// integer literal that's out of range for the integer type expected is an
+// error, rather than left for C to truncate.
+fn coerce_literal(expr: &mut TypedExpr, expected: &Type) -> Result<(), TypeError> {
+    let out_of_range = |n: i128| TypeError::LiteralOutOfRange {
+        literal: n.to_string(),
+        type_t: expected.clone(),
+        span: expr.span,
+    };
+    match expr.expr {
+        Expr::Term(ref mut t) => match t.term {
+            Term::Num(ref mut num, false) => match (num.coerce(expected), num.integer()) {
+                (Some(coerced), _) => {
+                    *num = coerced;
+                    t.type_t = expected.clone();
+                }
+                (None, Some(n)) if expected.is_integer() => return Err(out_of_range(n)),
+                (None, _) => {}
+            },
+            Term::Expr(ref mut inner) => coerce_literal(inner, expected)?,
+            _ => {}
+        },
+        // A negated literal becomes a negative one, which fits when the
+        // negative does, as -2147483648 does in int32
+        Expr::Neg(ref u) if expected.is_integer() => {
+            if let Expr::Term(ref t) = u.expr {
+                if let Term::Num(ref num, false) = t.term {
+                    if let Some(n) = num.integer() {
+                        let num = Num::fit(-n, expected).ok_or_else(|| out_of_range(-n))?;
+                        expr.expr = Expr::Term(Box::new(TypedTerm {
+                            type_t: expected.clone(),
+                            term: Term::Num(num, false),
+                        }));
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// The operands of the operators in a static assertion are constant, so
+// an unsuffixed literal among them takes the type of the operand beside
+// it, as in `size_of(int64) == 8`, when that type is plain without
+// solving for it, see constant_type
+fn type_constant_literals(expr: &mut TypedExpr) -> Result<(), TypeError> {
+    let (lhs, rhs) = match expr.expr {
+        Expr::Add(ref mut l, ref mut r)
+        | Expr::Sub(ref mut l, ref mut r)
+        | Expr::Mult(ref mut l, ref mut r)
+        | Expr::Div(ref mut l, ref mut r)
+        | Expr::Mod(ref mut l, ref mut r)
+        | Expr::Pow(ref mut l, ref mut r)
+        | Expr::BitAnd(ref mut l, ref mut r)
+        | Expr::BitOr(ref mut l, ref mut r)
+        | Expr::BitXor(ref mut l, ref mut r)
+        | Expr::Shl(ref mut l, ref mut r)
+        | Expr::Shr(ref mut l, ref mut r)
+        | Expr::Eq(ref mut l, ref mut r)
+        | Expr::Neq(ref mut l, ref mut r)
+        | Expr::Leq(ref mut l, ref mut r)
+        | Expr::Geq(ref mut l, ref mut r)
+        | Expr::LessThan(ref mut l, ref mut r)
+        | Expr::GreaterThan(ref mut l, ref mut r)
+        | Expr::And(ref mut l, ref mut r)
+        | Expr::Or(ref mut l, ref mut r) => (l, r),
+        Expr::Not(ref mut u) | Expr::Neg(ref mut u) | Expr::BitNot(ref mut u) => {
+            return type_constant_literals(u)
+        }
+        Expr::Term(ref mut t) => match t.term {
+            Term::Expr(ref mut inner) => return type_constant_literals(inner),
+            _ => return Ok(()),
+        },
+        _ => return Ok(()),
+    };
+    type_constant_literals(lhs)?;
+    type_constant_literals(rhs)?;
+    if let Some(type_t) = constant_type(lhs).or_else(|| constant_type(rhs)) {
+        coerce_literal(lhs, &type_t)?;
+        coerce_literal(rhs, &type_t)?;
+    }
+    Ok(())
+}
+
+// The type of a constant expression, when it's plain from the
+// expression itself
+fn constant_type(expr: &TypedExpr) -> Option<Type> {
+    match expr.expr {
+        Expr::Term(ref t) => match t.term {
+            Term::Num(ref num, true) => Type::try_from(num.clone()).ok(),
+            Term::Expr(ref inner) => constant_type(inner),
+            _ => None,
+        },
+        Expr::SizeOf(_) => Some(Type::UInt64),
+        Expr::Convert(ref type_t, _) => Some(type_t.clone()),
+        Expr::Neg(ref u) | Expr::BitNot(ref u) => constant_type(u),
+        Expr::Add(ref l, ref r)
+        | Expr::Sub(ref l, ref r)
+        | Expr::Mult(ref l, ref r)
+        | Expr::Div(ref l, ref r)
+        | Expr::Mod(ref l, ref r)
+        | Expr::Pow(ref l, ref r)
+        | Expr::BitAnd(ref l, ref r)
+        | Expr::BitOr(ref l, ref r)
+        | Expr::BitXor(ref l, ref r)
+        | Expr::Shl(ref l, ref r)
+        | Expr::Shr(ref l, ref r) => constant_type(l).or_else(|| constant_type(r)),
+        _ => None,
+    }
+}
+
+fn unsuffixed(expr: &TypedExpr) -> Option<&Num> {
+    match expr.expr {
+        Expr::Term(ref t) => match t.term {
+            Term::Num(ref num, false) => Some(num),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn operand_constraint(operand: &TypedExpr, type_t: Type) -> Constraint {
+    match unsuffixed(operand) {
+        Some(num) => Constraint::Literal(num.clone(), type_t, operand.span),
+        None => Constraint::Flows(operand.type_t.clone(), type_t, operand.span, false),
+    }
+}
+
+// A conversion named as an operator, for its operand's constraint
+fn conversion(type_t: &Type) -> &'static str {
+    match type_t {
+        Type::Int32 => "int32()",
+        Type::Int64 => "int64()",
+        Type::UInt32 => "uint32()",
+        Type::UInt64 => "uint64()",
+        Type::Float32 => "float32()",
+        _ => "float64()",
+    }
+}
+
 impl InferState {
     pub fn new() -> Self {
         InferState {
             constraints: vec![],
             symbols: vec![],
             type_mapping: HashMap::new(),
+            returns: vec![],
+            strict_numerics: false,
+            edition: Edition::default(),
         }
     }
 
+    // Constrains a value of type `from`, at the span, flowing into a
+    // declaration of type `to`. Only declared types can be widened to,
+    // so values flowing into an inferred type have to match it.
+    fn flows_into(&mut self, from: Type, to: Type, span: Span) {
+        let widen = self.can_widen_to(&to);
+        self.constraints
+            .push(Constraint::Flows(from, to, span, widen));
+    }
+
+    fn can_widen_to(&self, to: &Type) -> bool {
+        !self.strict_numerics && !matches!(to, Type::TypeVar(_))
+    }
+
+    // Constrains the operands of an arithmetic operator to the type of
+    // its result. An unsuffixed literal's constraint goes last, so a
+    // mismatch is blamed on the literal rather than on the operand that
+    // decided the type.
+    fn operands(&mut self, lhs: &TypedExpr, rhs: &TypedExpr, type_t: Type) {
+        let mut constraints = [lhs, rhs].map(|operand| operand_constraint(operand, type_t.clone()));
+        constraints.sort_by_key(|c| matches!(c, Constraint::Literal(..)));
+        self.constraints.extend(constraints);
+    }
+
+    // As for arithmetic, and the result, so the operands too, is of a
+    // type the operator applies to
+    fn typed_operands(
+        &mut self,
+        op: &'static str,
+        lhs: &mut TypedExpr,
+        rhs: &mut TypedExpr,
+        type_t: Type,
+        span: Span,
+    ) -> Result<(), TypeError> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)?;
+        self.operands(lhs, rhs, type_t.clone());
+        self.constraints.push(Constraint::Operand(op, type_t, span));
+        Ok(())
+    }
+
+    // The type of the elements of the array named, at the span
+    fn element_type(&self, symbol: &Symbol, span: Span) -> Result<Type, TypeError> {
+        let array = slookup(&self.symbols, symbol.clone()).ok_or(TypeError::IdentNotFound(
+            format!("Ident {:?} not found", symbol.clone()),
+        ))?;
+        match &array.type_t {
+            Type::Array(element_t, _) => Ok(*element_t.clone()),
+            _ => Err(TypeError::NotArray {
+                ident: symbol.ident.clone(),
+                span,
+            }),
+        }
+    }
+
+    // Constrains the operands of a comparison to the same type, blaming
+    // a mismatch on an unsuffixed literal, as for arithmetic
+    fn compared(&mut self, lhs: &TypedExpr, rhs: &TypedExpr) {
+        self.constraints.push(match unsuffixed(lhs) {
+            Some(_) if unsuffixed(rhs).is_none() => operand_constraint(lhs, rhs.type_t.clone()),
+            _ => operand_constraint(rhs, lhs.type_t.clone()),
+        });
+    }
+
     pub fn get_type_mapping(&self) -> HashMap<Type, Type> {
         self.type_mapping.clone()
     }
 
+    // A function can be called from anywhere in the block it's defined
+    // in, including before its definition
+    fn declare_funcs(&mut self, block: &Block) {
+        for stmt in block {
+            if let Stmt::FuncDef(func) = stmt.as_ref() {
+                sinsert(
+                    &mut self.symbols,
+                    new_symbol(func.ident.clone()),
+                    new_var(
+                        Type::Function(FunctionType {
+                            params_t: func.params.iter().map(|p| p.type_t.clone()).collect(),
+                            return_t: Box::new(func.return_t.clone()),
+                        }),
+                        Node::Null,
+                    ),
+                );
+            }
+        }
+    }
+
     pub fn spush(&mut self) -> Result<(), TypeError> {
         self.symbols.push(new_empty_symbol_table());
         Ok(())
@@ -837,7 +1667,22 @@ impl InferState {
 
     pub fn resolve(&mut self) -> Result<(), TypeError> {
         // Solve the constraint system for the TypeVars
-        let subs = solve(self.constraints.clone())?;
+        let mut constraints = self.constraints.clone();
+        if !self.edition.bool_conditions() {
+            // Conditions that turn out to be numbers are allowed
+            let subs = solve(
+                constraints
+                    .iter()
+                    .filter(|c| !matches!(c, Constraint::Condition(..)))
+                    .cloned()
+                    .collect(),
+            )?;
+            constraints.retain(|c| match c {
+                Constraint::Condition(t, _) => !subst(subs.clone(), t.clone()).is_numeric(),
+                _ => true,
+            });
+        }
+        let subs = solve(constraints)?;
         for sub in subs {
             match (sub.0, sub.1) {
                 (Type::TypeVar(t), other) => {
@@ -872,7 +1717,7 @@ impl SubState {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ast::{Expr, Node, Num, Term};
+    use crate::ast::{Expr, Node, Span, Term};
     use crate::semantic::SymbolTable;
     use crate::symbol::{new_symbol, new_var};
     use crate::types::{FunctionType, Type};
@@ -891,13 +1736,15 @@ mod tests {
                     type_t: Type::Unknown,
                     term: Term::Id("x".into()),
                 })),
+                span: Span::default(),
             }),
             Box::new(TypedExpr {
                 type_t: Type::Unknown,
                 expr: Expr::Term(Box::new(TypedTerm {
                     type_t: Type::Unknown,
-                    term: Term::Num(Num::Int32(5)),
+                    term: Term::Num(Num::Int32(5), false),
                 })),
+                span: Span::default(),
             }),
         );
         let res = infer(ctx, expr);
@@ -951,4 +1798,326 @@ mod tests {
         println!("solved: {:?}", subs);
         assert!(subs.is_ok());
     }
+
+    fn check_src(src: &str) -> Result<(), TypeError> {
+        check_src_with(src, false)
+    }
+
+    fn check_src_with(src: &str, strict_numerics: bool) -> Result<(), TypeError> {
+        check_src_in(src, strict_numerics, Edition::default())
+    }
+
+    fn check_src_in(src: &str, strict_numerics: bool, edition: Edition) -> Result<(), TypeError> {
+        let mut root = crate::rascal::RootParser::new()
+            .parse(crate::parser::tokens(src))
+            .unwrap();
+        TypingState::new().augment(&mut root)?;
+        let mut infer_state = InferState::new();
+        infer_state.strict_numerics = strict_numerics;
+        infer_state.edition = edition;
+        infer_state.constrain(&mut root)?;
+        infer_state.resolve()
+    }
+
+    #[test]
+    fn coerce_unsuffixed_literals() {
+        assert_eq!(
+            Num::Int32(1).coerce(&Type::Float64),
+            Some(Num::Float64(1.0))
+        );
+        assert_eq!(Num::Int32(-1).coerce(&Type::UInt32), None);
+        assert_eq!(Num::Int32(16_777_217).coerce(&Type::Float32), None);
+        assert_eq!(
+            Num::Float64(0.5).coerce(&Type::Float32),
+            Some(Num::Float32(0.5))
+        );
+        assert_eq!(Num::Float64(0.1).coerce(&Type::Float32), None);
+        assert_eq!(Num::Float64(1.0).coerce(&Type::Int32), None);
+
+        let src = |arg: &str, ret: &str| {
+            format!(
+                "fun f(x: float64, n: uint64) -> float64 return {}; end
+                 program p let y = f({}, 2); return 0; end",
+                ret, arg
+            )
+        };
+        assert!(check_src(&src("1", "0")).is_ok());
+        assert!(check_src(&src("(1)", "x")).is_ok());
+        assert!(check_src(&src("1i64", "0")).is_err());
+        assert!(check_src(&src("1", "0i64")).is_err());
+    }
+
+    #[test]
+    fn widen_numeric_types() {
+        let src = |from: &str, to: &str| {
+            format!(
+                "fun f(x: {to}) -> {to} return x; end
+                 program p let a: {from} = 1{from_suffix}; let b: {to} = a; b = f(a); return 0; end",
+                from = from,
+                to = to,
+                from_suffix = match from {
+                    "int32" => "",
+                    "uint32" => "u32",
+                    "float32" => ".0f32",
+                    _ => unreachable!(),
+                },
+            )
+        };
+        for (from, to) in [
+            ("int32", "int64"),
+            ("int32", "float64"),
+            ("uint32", "uint64"),
+            ("uint32", "float64"),
+            ("float32", "float64"),
+        ] {
+            assert!(check_src(&src(from, to)).is_ok(), "{} -> {}", from, to);
+            assert!(check_src_with(&src(from, to), true).is_err());
+        }
+        assert!(check_src(&src("int32", "float32")).is_err());
+        assert!(check_src(&src("uint32", "int32")).is_err());
+        // Operands of an operator aren't widened
+        assert!(check_src("program p let a: int64 = 1i64 + 2; return 0; end").is_err());
+    }
+
+    #[test]
+    fn reject_literals_out_of_range() {
+        let out_of_range = |src: &str| match check_src(src) {
+            Err(TypeError::LiteralOutOfRange {
+                literal, type_t, ..
+            }) => Some(format!("{} {}", literal, type_t)),
+            _ => None,
+        };
+        let src = "program p let x: int32 = 4294967296; return 0; end";
+        assert_eq!(out_of_range(src), Some("4294967296 int32".into()));
+        assert_eq!(
+            check_src(src).unwrap_err().diagnostic(src).help,
+            vec!["int32 holds -2147483648 to 2147483647"]
+        );
+        let src = "program p let x: uint32 = -1; return 0; end";
+        assert_eq!(out_of_range(src), Some("-1 uint32".into()));
+        let src = "program p let x = -1u64; return 0; end";
+        assert_eq!(out_of_range(src), Some("-1 uint64".into()));
+        let src = "fun f(n: uint32) -> int32 n = 5000000000; return -2147483649; end
+                   program p return 0; end";
+        assert_eq!(out_of_range(src), Some("5000000000 uint32".into()));
+
+        assert!(check_src("program p let x: int32 = -2147483648; return 0; end").is_ok());
+        assert!(check_src("program p let x: uint64 = 18446744073709551615; return 0; end").is_ok());
+        assert!(check_src("program p let x = 4294967296; let y: int64 = x; return 0; end").is_ok());
+    }
+
+    #[test]
+    fn suggest_suffixes_for_mismatched_literals() {
+        let fixes = |src: &str| {
+            let diagnostic = check_src(src).unwrap_err().diagnostic(src);
+            let suggestion = &diagnostic.suggestions[0];
+            (
+                diagnostic.message,
+                src[suggestion.span.start..suggestion.span.end].to_string(),
+                suggestion.replacement.clone(),
+            )
+        };
+        let src = "fun f(x: float32) -> float32 return 0.5 * x; end program p return 0; end";
+        assert_eq!(
+            fixes(src),
+            (
+                "expected float32, found an unsuffixed float64 literal".into(),
+                "0.5".into(),
+                "0.5f32".into()
+            )
+        );
+        let src = "fun f(x: float32) -> bool return x - 2 < x; end program p return 0; end";
+        assert_eq!(fixes(src).2, "2.0f32");
+        let src = "program p let n = 2i64; if 2 > n then return 1; end return 0; end";
+        assert_eq!(fixes(src).2, "2i64");
+        // No suffix makes 0.1 a float32 without rounding it
+        let src = "fun f(x: float32) -> float32 return x * 0.1; end program p return 0; end";
+        assert!(check_src(src).unwrap_err().diagnostic(src).help.is_empty());
+    }
+
+    #[test]
+    fn reject_non_bool_conditions() {
+        let src = "program p let n = 2u32; if (n) then return 1; end return 0; end";
+        let check = |src: &str| check_src_in(src, false, Edition::E2025);
+        let err = check(src).unwrap_err();
+        assert!(matches!(
+            err,
+            TypeError::ConditionNotBool {
+                found: Type::UInt32,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.diagnostic(src).help,
+            vec!["compare it explicitly: `(n) != 0u32`"]
+        );
+        assert!(check("program p if 1 < 2 then return 1; end return 0; end").is_ok());
+        assert!(matches!(
+            check("program p let s = \"x\"; repeat s = \"y\"; until s; end"),
+            Err(TypeError::ConditionNotBool {
+                found: Type::String,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn blame_mismatched_returns() {
+        let src = "fun f(x: int32) -> int32
+    if x > 0 then
+        return x > 1;
+    end
+    return x;
+end
+program p return f(1); end";
+        let err = check_src(src).unwrap_err();
+        assert!(matches!(
+            err,
+            TypeError::ReturnMismatch {
+                found: Type::Bool,
+                expected: Type::Int32,
+                ..
+            }
+        ));
+        let span = err.diagnostic(src).span.unwrap();
+        assert_eq!(&src[span.start..span.end], "x > 1");
+        // Returns widen to the declared type, except with strict numerics
+        let src = "fun f(x: int32) -> int64 return x; end program p return 0; end";
+        assert!(check_src(src).is_ok());
+        assert!(matches!(
+            check_src_with(src, true),
+            Err(TypeError::ReturnMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn explain_numeric_mismatches() {
+        let explain = |src: &str| {
+            let diagnostic = check_src(src).unwrap_err().diagnostic(src);
+            let span = diagnostic.span.unwrap();
+            (
+                diagnostic.message,
+                src[span.start..span.end].to_string(),
+                diagnostic.notes,
+                diagnostic.help,
+                diagnostic.suggestions.len(),
+            )
+        };
+        let lattice = "numbers only widen implicitly from int32 to int64 or float64, \
+                       from uint32 to uint64 or float64, and from float32 to float64";
+        for (declared, value, expected, found) in [
+            ("int32", "5i64", "int32", "int64"),
+            ("int64", "5u32", "int64", "uint32"),
+            ("float64", "5i64", "float64", "int64"),
+        ] {
+            let src = format!(
+                "program p let a = {}; let b: {} = a; return 0; end",
+                value, declared
+            );
+            assert_eq!(
+                explain(&src),
+                (
+                    format!("expected {}, found {}", expected, found),
+                    "a".into(),
+                    vec![lattice.to_string()],
+                    vec![format!("convert it explicitly: `{}(a)`", expected)],
+                    0
+                )
+            );
+        }
+        // Operands of mixed widths, only the lossless conversion is
+        // offered as a fix
+        let src = "program p let a = 1i64; let b = 2; let c = a * b; return 0; end";
+        assert_eq!(
+            explain(src),
+            (
+                "expected int64, found int32".into(),
+                "b".into(),
+                vec![
+                    "int32 widens to int64 only where a value is assigned, passed or \
+                      returned, never between operands, and not with --strict-numerics"
+                        .into()
+                ],
+                vec!["convert it explicitly: `int64(b)`".into()],
+                1
+            )
+        );
+        let src = "program p let a = 1i64; let b = 2; let c = a * int64(b); \
+                   let d: int32 = int32(c) + int32(2.5); return d; end";
+        assert!(check_src(src).is_ok());
+        assert!(matches!(
+            check_src("program p let a = int32(\"2\"); return 0; end"),
+            Err(TypeError::BadOperand { op: "int32()", .. })
+        ));
+    }
+
+    #[test]
+    fn negate_only_signed_numbers() {
+        let src = "program p let x = -5; let y = -2.5f32; let n = 2u32; let m = -n; return -x; end";
+        let err = check_src(src).unwrap_err();
+        assert!(matches!(
+            err,
+            TypeError::NotNegatable {
+                found: Type::UInt32,
+                ..
+            }
+        ));
+        let span = err.diagnostic(src).span.unwrap();
+        assert_eq!(&src[span.start..span.end], "n");
+        assert!(check_src("program p let x = -5; let y = -2.5f32; return -x; end").is_ok());
+        assert!(matches!(
+            check_src("program p let b = -(1 < 2); return 0; end"),
+            Err(TypeError::NotNegatable {
+                found: Type::Bool,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn check_operand_types() {
+        assert!(check_src("program p let n = 7u32 % 2u32; return 7 % -2; end").is_ok());
+        let src = "program p let f = 2.5; let r = f % 2.0; return 0; end";
+        let err = check_src(src).unwrap_err();
+        assert!(matches!(
+            err,
+            TypeError::BadOperand {
+                op: "%",
+                found: Type::Float64,
+                ..
+            }
+        ));
+        let span = err.diagnostic(src).span.unwrap();
+        assert_eq!(&src[span.start..span.end], "f % 2.0");
+        let src = "program p let s = \"a\" + \"b\"; s += \"c\"; return 0; end";
+        assert!(check_src(src).is_ok());
+        let src = "program p let m = ~7u64 & 12u64 | 1u64 << 3u64; return 5 ^ 3 >> 1; end";
+        assert!(check_src(src).is_ok());
+        for (src, op) in [
+            ("program p let b = true & false; return 0; end", "&"),
+            ("program p let f = 1.5 << 2.0; return 0; end", "<<"),
+            ("program p let f = ~2.5; return 0; end", "~"),
+            ("program p let s = \"a\" - \"b\"; return 0; end", "-"),
+            ("program p let b = true; b += false; return 0; end", "+"),
+        ] {
+            assert!(
+                matches!(check_src(src), Err(TypeError::BadOperand { op: found, .. }) if found == op),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn accept_numeric_conditions_before_2025() {
+        let check = |src: &str, edition: Edition| check_src_in(src, false, edition);
+        let src = "program p let n = 2u32; if n then return 1; end return 0; end";
+        assert!(check(src, Edition::E2024).is_ok());
+        assert!(check(src, Edition::E2025).is_err());
+        let src = "program p let s = \"x\"; if s then return 1; end return 0; end";
+        assert!(matches!(
+            check(src, Edition::E2024),
+            Err(TypeError::ConditionNotBool { .. })
+        ));
+    }
 }
@@ -0,0 +1,62 @@
+use crate::types::{FunctionType, Type};
+
+// C functions a Rascal function can stand for directly. A function
+// declared `@intrinsic("sqrt")`, with no body, is the C function of that
+// name: calls to it are calls to the C function, without a wrapper in
+// between, so it has to be declared with the C function's signature.
+// These are all from the C math library, on floats, and the interpreter
+// evaluates them in float64.
+#[derive(Debug, Clone)]
+pub struct Intrinsic {
+    pub ident: &'static str,
+    pub params_t: Vec<Type>,
+    pub return_t: Type,
+    pub eval: fn(&[f64]) -> f64,
+}
+
+impl Intrinsic {
+    pub fn func_type(&self) -> Type {
+        Type::Function(FunctionType {
+            params_t: self.params_t.clone(),
+            return_t: Box::new(self.return_t.clone()),
+        })
+    }
+}
+
+fn math(ident: &'static str, type_t: Type, arity: usize, eval: fn(&[f64]) -> f64) -> Intrinsic {
+    Intrinsic {
+        ident,
+        params_t: vec![type_t.clone(); arity],
+        return_t: type_t,
+        eval,
+    }
+}
+
+pub fn intrinsics() -> Vec<Intrinsic> {
+    vec![
+        math("sqrt", Type::Float64, 1, |x| x[0].sqrt()),
+        math("fabs", Type::Float64, 1, |x| x[0].abs()),
+        math("floor", Type::Float64, 1, |x| x[0].floor()),
+        math("ceil", Type::Float64, 1, |x| x[0].ceil()),
+        math("trunc", Type::Float64, 1, |x| x[0].trunc()),
+        math("round", Type::Float64, 1, |x| x[0].round()),
+        math("sin", Type::Float64, 1, |x| x[0].sin()),
+        math("cos", Type::Float64, 1, |x| x[0].cos()),
+        math("tan", Type::Float64, 1, |x| x[0].tan()),
+        math("exp", Type::Float64, 1, |x| x[0].exp()),
+        math("log", Type::Float64, 1, |x| x[0].ln()),
+        math("pow", Type::Float64, 2, |x| x[0].powf(x[1])),
+        math("atan2", Type::Float64, 2, |x| x[0].atan2(x[1])),
+        math("fmin", Type::Float64, 2, |x| x[0].min(x[1])),
+        math("fmax", Type::Float64, 2, |x| x[0].max(x[1])),
+        // Only those whose float64 result rounds to the float32 one
+        math("sqrtf", Type::Float32, 1, |x| x[0].sqrt()),
+        math("fabsf", Type::Float32, 1, |x| x[0].abs()),
+        math("floorf", Type::Float32, 1, |x| x[0].floor()),
+        math("ceilf", Type::Float32, 1, |x| x[0].ceil()),
+    ]
+}
+
+pub fn lookup(ident: &str) -> Option<Intrinsic> {
+    intrinsics().into_iter().find(|i| i.ident == ident)
+}
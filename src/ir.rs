@@ -6,25 +6,65 @@ use serde::{Deserialize, Serialize};
 pub enum IRNode {
     // Mutate State
     Assign(Assign),
+    // An Assign run once, whose variable keeps its value between calls
+    StaticAssign(Assign),
     Reassign(Reassign),
+    // An array with its elements zeroed
+    Declare(Assign),
+    // Assigns the element of the array at the index, which precedes the
+    // value assigned
+    Store(Access),
     // If statements
     If(String),
     IfCase(String),
     ElseIfCase(String),
     ElseCase(String),
     EndIf(String),
+    // Switch statements, the value switched on precedes Switch
+    Switch(String),
+    SwitchCase(String, Vec<Value>),
+    DefaultCase(String),
+    EndSwitch(String),
+    // Post-condition loops, the condition precedes Until
+    Repeat(String),
+    Until(String),
+    // Pre-condition loops, the condition precedes While
+    While(String),
+    EndWhile(String),
+    // Leaves the loop with the given id
+    Break(String),
+    // Goes on to the next iteration of the loop with the given id
+    Continue(String),
     // Expression nodes
     Term(Term),
     Eval(Func),
+    // The element of the array at the index before it
+    Load(Access),
+    // Evaluates the expression before it only for its effects, as in a
+    // bare call statement
+    Discard,
     // Function Definition
     FuncDef(FuncDef, String),
     EndFuncDef(String),
+    // A function defined in another unit
+    ExternFuncDef(FuncDef),
+    // A function that is the C function named, declared `@intrinsic`
+    Intrinsic(FuncDef, String),
+    // Declares a function defined further on in the same block, so it
+    // can be called before its definition
+    FuncDecl(FuncDef),
     // Globals
     GlobalSection,
     EndGlobalSection,
+    // Starts the program block, which follows the globals. Units without
+    // one end with the globals.
+    Program,
     // Extra
     Return,
     Label(Label),
+    // The byte offset in the source where the statement that follows
+    // starts, only in IR lowered for debugging
+    Loc(usize),
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -42,6 +82,13 @@ pub struct Reassign {
     pub symbol: Symbol,
 }
 
+// An array and its type, for reading or writing one of its elements
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Access {
+    pub type_t: Type,
+    pub symbol: Symbol,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IfCase {
     pub end_block_label: Label,
@@ -74,6 +121,14 @@ pub enum Func {
     Sub(Signature),
     Mult(Signature),
     Div(Signature),
+    Pow(Signature),
+    // Integers only
+    Mod(Signature),
+    BitAnd(Signature),
+    BitOr(Signature),
+    BitXor(Signature),
+    Shl(Signature),
+    Shr(Signature),
     Lt(Signature),
     Gt(Signature),
     Leq(Signature),
@@ -83,6 +138,10 @@ pub enum Func {
     // Primitive unary
     Not(Signature),
     Neg(Signature),
+    // Integers only
+    BitNot(Signature),
+    // Numeric widening from the single parameter type to the return type
+    Cast(Signature),
     // User Defined functions
     Func(Signature),
 }
@@ -116,3 +175,348 @@ pub fn new_func_def(ident: &str, params_t: Vec<(String, Type)>, return_t: Type)
         return_t,
     }
 }
+
+// How `render` colors what it writes, as ANSI escapes
+const BOLD: &str = "1";
+const CONTROL: &str = "35";
+const TYPE: &str = "36";
+const DIM: &str = "2";
+
+fn paint(text: &str, code: &str, color: bool) -> String {
+    if color && !text.trim().is_empty() {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+macro_rules! converted {
+    ($n:expr, $to:expr) => {
+        match $to {
+            Type::Int32 => Value::Int32($n as i32),
+            Type::Int64 => Value::Int64($n as i64),
+            Type::UInt32 => Value::UInt32($n as u32),
+            Type::UInt64 => Value::UInt64($n as u64),
+            Type::Float32 => Value::Float32($n as f32),
+            Type::Float64 => Value::Float64($n as f64),
+            _ => return None,
+        }
+    };
+}
+
+impl Value {
+    // The number converted to a numeric type. Integers wrap to the
+    // width of the type, and floats are truncated toward zero,
+    // saturating at the bounds of an integer type, with NaN as zero.
+    pub fn convert(&self, to: &Type) -> Option<Value> {
+        Some(match self {
+            Value::Int32(n) => converted!(*n, to),
+            Value::Int64(n) => converted!(*n, to),
+            Value::UInt32(n) => converted!(*n, to),
+            Value::UInt64(n) => converted!(*n, to),
+            Value::Float32(n) => converted!(*n, to),
+            Value::Float64(n) => converted!(*n, to),
+            _ => return None,
+        })
+    }
+
+    // The value as written in the source, its type is shown beside it
+    fn show(&self) -> String {
+        match self {
+            Value::Int32(n) => n.to_string(),
+            Value::Int64(n) => n.to_string(),
+            Value::UInt32(n) => n.to_string(),
+            Value::UInt64(n) => n.to_string(),
+            Value::Float32(n) => format!("{:?}", n),
+            Value::Float64(n) => format!("{:?}", n),
+            Value::Bool(b) => b.to_string(),
+            Value::String(s) => format!("{:?}", s),
+            Value::Id(ident) => ident.clone(),
+        }
+    }
+}
+
+impl Func {
+    pub fn signature(&self) -> (&str, &Signature) {
+        match self {
+            Func::Add(sig) => ("+", sig),
+            Func::Sub(sig) => ("-", sig),
+            Func::Mult(sig) => ("*", sig),
+            Func::Div(sig) => ("/", sig),
+            Func::Pow(sig) => ("**", sig),
+            Func::Mod(sig) => ("%", sig),
+            Func::BitAnd(sig) => ("&", sig),
+            Func::BitOr(sig) => ("|", sig),
+            Func::BitXor(sig) => ("^", sig),
+            Func::Shl(sig) => ("<<", sig),
+            Func::Shr(sig) => (">>", sig),
+            Func::Lt(sig) => ("<", sig),
+            Func::Gt(sig) => (">", sig),
+            Func::Leq(sig) => ("<=", sig),
+            Func::Geq(sig) => (">=", sig),
+            Func::Eq(sig) => ("==", sig),
+            Func::Neq(sig) => ("!=", sig),
+            Func::Not(sig) => ("!", sig),
+            Func::Neg(sig) => ("-", sig),
+            Func::BitNot(sig) => ("~", sig),
+            Func::Cast(sig) => ("cast", sig),
+            Func::Func(sig) => (sig.symbol.ident.as_str(), sig),
+        }
+    }
+}
+
+fn show_func_def(func: &FuncDef) -> String {
+    let params: Vec<String> = func
+        .params_t
+        .iter()
+        .map(|(ident, type_t)| format!("{}: {}", ident, type_t))
+        .collect();
+    format!("{}({})", func.symbol.ident, params.join(", "))
+}
+
+// The IR as text to read when debugging lowering: a node a line, grouped
+// into the globals, each function and the program, indented by how
+// deeply the node is nested in them, with the kind, type and operands of
+// the nodes in aligned columns. `color` adds ANSI colors for a terminal.
+pub fn render(nodes: &[IRNode], color: bool) -> String {
+    // A node's depth, kind, type and operands, or None for a blank line
+    // between groups
+    let mut rows: Vec<Option<(usize, &str, String, String)>> = vec![];
+    let mut depth: usize = 0;
+    for node in nodes {
+        let (kind, type_t, operands) = match node {
+            IRNode::Assign(assign) => (
+                "assign",
+                assign.type_t.to_string(),
+                assign.symbol.ident.clone(),
+            ),
+            IRNode::StaticAssign(assign) => (
+                "static_assign",
+                assign.type_t.to_string(),
+                assign.symbol.ident.clone(),
+            ),
+            IRNode::Reassign(reassign) => (
+                "reassign",
+                reassign.type_t.to_string(),
+                reassign.symbol.ident.clone(),
+            ),
+            IRNode::Declare(assign) => (
+                "declare",
+                assign.type_t.to_string(),
+                assign.symbol.ident.clone(),
+            ),
+            IRNode::Store(access) => (
+                "store",
+                access.type_t.to_string(),
+                access.symbol.ident.clone(),
+            ),
+            IRNode::If(id) => ("if", String::new(), id.clone()),
+            IRNode::IfCase(id) => ("if_case", String::new(), id.clone()),
+            IRNode::ElseIfCase(id) => ("else_if_case", String::new(), id.clone()),
+            IRNode::ElseCase(id) => ("else_case", String::new(), id.clone()),
+            IRNode::EndIf(id) => ("end_if", String::new(), id.clone()),
+            IRNode::Switch(id) => ("switch", String::new(), id.clone()),
+            IRNode::SwitchCase(id, values) => {
+                let values: Vec<String> = values.iter().map(Value::show).collect();
+                (
+                    "switch_case",
+                    String::new(),
+                    format!("{} {}", id, values.join(", ")),
+                )
+            }
+            IRNode::DefaultCase(id) => ("default_case", String::new(), id.clone()),
+            IRNode::EndSwitch(id) => ("end_switch", String::new(), id.clone()),
+            IRNode::Repeat(id) => ("repeat", String::new(), id.clone()),
+            IRNode::Until(id) => ("until", String::new(), id.clone()),
+            IRNode::While(id) => ("while", String::new(), id.clone()),
+            IRNode::EndWhile(id) => ("end_while", String::new(), id.clone()),
+            IRNode::Break(id) => ("break", String::new(), id.clone()),
+            IRNode::Continue(id) => ("continue", String::new(), id.clone()),
+            IRNode::Term(term) => ("term", term.type_t.to_string(), term.value.show()),
+            IRNode::Load(access) => (
+                "load",
+                access.type_t.to_string(),
+                access.symbol.ident.clone(),
+            ),
+            IRNode::Eval(func) => {
+                let (name, sig) = func.signature();
+                let params: Vec<String> = sig.params_t.iter().map(Type::to_string).collect();
+                (
+                    "eval",
+                    sig.return_t.to_string(),
+                    format!("{}({})", name, params.join(", ")),
+                )
+            }
+            IRNode::Discard => ("discard", String::new(), String::new()),
+            IRNode::FuncDef(func, id) => (
+                "func_def",
+                func.return_t.to_string(),
+                format!("{} {}", show_func_def(func), id),
+            ),
+            IRNode::EndFuncDef(id) => ("end_func_def", String::new(), id.clone()),
+            IRNode::ExternFuncDef(func) => (
+                "extern_func_def",
+                func.return_t.to_string(),
+                show_func_def(func),
+            ),
+            IRNode::Intrinsic(func, ident) => (
+                "intrinsic",
+                func.return_t.to_string(),
+                format!("{} {}", show_func_def(func), ident),
+            ),
+            IRNode::FuncDecl(func) => ("func_decl", func.return_t.to_string(), show_func_def(func)),
+            IRNode::GlobalSection => ("global_section", String::new(), String::new()),
+            IRNode::EndGlobalSection => ("end_global_section", String::new(), String::new()),
+            IRNode::Program => ("program", String::new(), String::new()),
+            IRNode::Return => ("return", String::new(), String::new()),
+            IRNode::Label(label) => ("label", String::new(), label.0.clone()),
+            IRNode::Loc(offset) => ("loc", String::new(), offset.to_string()),
+        };
+        // Blocks end a level out, and their cases sit a level out, at
+        // the depth of what they belong to
+        let at = match node {
+            IRNode::EndIf(_)
+            | IRNode::EndSwitch(_)
+            | IRNode::Until(_)
+            | IRNode::EndWhile(_)
+            | IRNode::EndFuncDef(_)
+            | IRNode::EndGlobalSection => {
+                depth = depth.saturating_sub(1);
+                depth
+            }
+            IRNode::IfCase(_)
+            | IRNode::ElseIfCase(_)
+            | IRNode::ElseCase(_)
+            | IRNode::SwitchCase(..)
+            | IRNode::DefaultCase(_) => depth.saturating_sub(1),
+            _ => depth,
+        };
+        // Each function of the unit and its program are a group of their
+        // own
+        let starts_group = matches!(node, IRNode::Program)
+            || (matches!(node, IRNode::FuncDef(..)) && at == 1)
+            || (matches!(rows.last(), Some(Some((1, "end_func_def", ..))))
+                && !matches!(node, IRNode::EndGlobalSection));
+        let after_header = matches!(
+            rows.last(),
+            None | Some(None | Some((_, "global_section", ..)))
+        );
+        if starts_group && !after_header {
+            rows.push(None);
+        }
+        rows.push(Some((at, kind, type_t, operands)));
+        if matches!(
+            node,
+            IRNode::If(_)
+                | IRNode::Switch(_)
+                | IRNode::Repeat(_)
+                | IRNode::While(_)
+                | IRNode::FuncDef(..)
+                | IRNode::GlobalSection
+                | IRNode::Program
+        ) {
+            depth += 1;
+        }
+    }
+
+    let kind_width = rows
+        .iter()
+        .flatten()
+        .map(|(depth, kind, ..)| depth * 2 + kind.len())
+        .max()
+        .unwrap_or(0);
+    let type_width = rows
+        .iter()
+        .flatten()
+        .map(|(_, _, type_t, _)| type_t.len())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for row in rows {
+        let Some((depth, kind, type_t, operands)) = row else {
+            out.push('\n');
+            continue;
+        };
+        let code = match kind {
+            "func_def" | "end_func_def" | "global_section" | "end_global_section" | "program" => {
+                BOLD
+            }
+            "term" | "eval" | "assign" | "static_assign" | "reassign" | "discard" => "",
+            "loc" | "label" => DIM,
+            _ => CONTROL,
+        };
+        let indent = "  ".repeat(depth);
+        let mut line = indent.clone();
+        if operands.is_empty() && type_t.is_empty() {
+            line.push_str(&paint(kind, code, color && !code.is_empty()));
+        } else {
+            let padded = format!("{:<1$}", kind, kind_width - indent.len());
+            line.push_str(&paint(&padded, code, color && !code.is_empty()));
+            line.push_str("  ");
+            if operands.is_empty() {
+                line.push_str(&paint(&type_t, TYPE, color));
+            } else {
+                line.push_str(&paint(&format!("{:<1$}", type_t, type_width), TYPE, color));
+                line.push_str("  ");
+                line.push_str(&operands);
+            }
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::session::Session;
+
+    #[test]
+    fn render_grouped_and_aligned() {
+        let src = "fun f(n: int32) -> bool
+    repeat n = n - 1; until n < 0;
+    return n == 1;
+end
+program p
+    if f(2) then return 1; end
+    return 0;
+end";
+        let state = compile::analyze(src, &Session::default()).unwrap();
+        let expected = "\
+global_section
+  func_def          bool   f(n: int32) _func_def_0
+    repeat                 _repeat_stmt_1
+      term          int32  n
+      term          int32  1
+      eval          int32  -(int32, int32)
+      reassign      int32  n
+      term          int32  n
+      term          int32  0
+      eval          bool   <(int32, int32)
+    until                  _repeat_stmt_1
+    term            int32  n
+    term            int32  1
+    eval            bool   ==(int32, int32)
+    return
+  end_func_def             _func_def_0
+end_global_section
+
+program
+  if                       _if_stmt_2
+    term            int32  2
+    eval            bool   f(int32)
+  if_case                  _if_stmt_2
+    term            int32  1
+    return
+  end_if                   _if_stmt_2
+  term              int32  0
+  return
+";
+        assert_eq!(render(&state.build_stack, false), expected);
+        let colored = render(&state.build_stack, true);
+        assert!(colored.contains("\x1b[35mrepeat"));
+        assert!(colored.contains("\x1b[36mint32\x1b[0m  n"));
+    }
+}
@@ -0,0 +1,477 @@
+use crate::builtins;
+use crate::ir::{Access, Func, IRNode, Signature, Term, Value};
+use crate::types::Type;
+use std::collections::HashMap;
+use thiserror::Error;
+
+// Errors in the IR itself rather than in the program it came from. The
+// IR built from a checked program is always well typed, so any of these
+// means a pass that produced it has a bug. Nodes are counted from 0.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum IRTypeError {
+    #[error("IR node {node}: expected `{expected}`, found `{found}`")]
+    Mismatch {
+        node: usize,
+        expected: Type,
+        found: Type,
+    },
+    #[error("IR node {node}: expected a condition, found `{found}`")]
+    Condition { node: usize, found: Type },
+    #[error("IR node {0}: missing an operand")]
+    MissingOperand(usize),
+    #[error("IR node {0}: a value before it is never used")]
+    UnusedValue(usize),
+    #[error("IR node {0}: doesn't match the block it's in")]
+    Unbalanced(usize),
+    #[error("IR node {0}: `{1}` can't be called with these operands")]
+    BadOperands(usize, String),
+    #[error("The IR ends inside a block")]
+    Unterminated,
+}
+
+enum Block {
+    // Whether a case has been opened, along with its scope
+    If(bool),
+    Switch(bool),
+    Repeat,
+    While,
+    Func(Type),
+}
+
+// Checks that the IR is consistent with the types it's annotated with,
+// by running it with types in place of values: terms push their type,
+// Evals pop their operands and push their result, and statements pop
+// what they consume. Run after every pass that produces IR so that a
+// miscompiling pass fails here rather than in a backend.
+pub fn check(nodes: &[IRNode]) -> Result<(), IRTypeError> {
+    let mut checker = Checker {
+        // Functions can be called before they're defined
+        funcs: nodes
+            .iter()
+            .filter_map(|node| match node {
+                IRNode::FuncDef(def, _)
+                | IRNode::ExternFuncDef(def)
+                | IRNode::Intrinsic(def, _) => Some((
+                    def.symbol.ident.clone(),
+                    (
+                        def.params_t.iter().map(|(_, t)| t.clone()).collect(),
+                        def.return_t.clone(),
+                    ),
+                )),
+                _ => None,
+            })
+            .collect(),
+        values: vec![],
+        scopes: vec![HashMap::new()],
+        blocks: vec![],
+    };
+    for (n, node) in nodes.iter().enumerate() {
+        checker.node(n, node)?;
+    }
+    if !checker.blocks.is_empty() {
+        return Err(IRTypeError::Unterminated);
+    }
+    checker.settle(nodes.len())
+}
+
+struct Checker {
+    funcs: HashMap<String, (Vec<Type>, Type)>,
+    // Types of the values computed and not yet consumed
+    values: Vec<Type>,
+    // Types of the variables declared in each enclosing scope
+    scopes: Vec<HashMap<String, Type>>,
+    blocks: Vec<Block>,
+}
+
+impl Checker {
+    fn pop(&mut self, n: usize) -> Result<Type, IRTypeError> {
+        self.values.pop().ok_or(IRTypeError::MissingOperand(n))
+    }
+
+    // A value that never returns can stand in for any other
+    fn expect(n: usize, expected: &Type, found: Type) -> Result<(), IRTypeError> {
+        if found == *expected || found == Type::Never {
+            Ok(())
+        } else {
+            Err(IRTypeError::Mismatch {
+                node: n,
+                expected: expected.clone(),
+                found,
+            })
+        }
+    }
+
+    // Backends test numbers against zero, so they're accepted here in
+    // any edition; which edition allows them is checked on the source
+    fn condition(&mut self, n: usize) -> Result<(), IRTypeError> {
+        let found = self.pop(n)?;
+        if found == Type::Bool || found == Type::Never || found.is_numeric() {
+            Ok(())
+        } else {
+            Err(IRTypeError::Condition { node: n, found })
+        }
+    }
+
+    // Statements start with nothing computed
+    fn settle(&self, n: usize) -> Result<(), IRTypeError> {
+        if self.values.is_empty() {
+            Ok(())
+        } else {
+            Err(IRTypeError::UnusedValue(n))
+        }
+    }
+
+    fn declare(&mut self, ident: &str, type_t: Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(ident.to_string(), type_t);
+        }
+    }
+
+    // The C backend scopes the conditions of `else if` and `until`
+    // differently from the IR, so a variable only has to match one of
+    // the declarations in sight
+    fn variable(&self, n: usize, ident: &str, type_t: &Type) -> Result<(), IRTypeError> {
+        let declared: Vec<&Type> = self
+            .scopes
+            .iter()
+            .filter_map(|scope| scope.get(ident))
+            .collect();
+        match declared.last() {
+            Some(last) if !declared.contains(&type_t) => Err(IRTypeError::Mismatch {
+                node: n,
+                expected: (*last).clone(),
+                found: type_t.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // The type of the elements of the array accessed
+    fn element(&self, n: usize, access: &Access) -> Result<Type, IRTypeError> {
+        self.variable(n, &access.symbol.ident, &access.type_t)?;
+        match &access.type_t {
+            Type::Array(element_t, _) => Ok(*element_t.clone()),
+            _ => Err(IRTypeError::BadOperands(n, access.symbol.ident.clone())),
+        }
+    }
+
+    // Any integer can index an array
+    fn index(&mut self, n: usize) -> Result<(), IRTypeError> {
+        let found = self.pop(n)?;
+        if found.is_integer() || found == Type::Never {
+            Ok(())
+        } else {
+            Err(IRTypeError::Mismatch {
+                node: n,
+                expected: Type::Int64,
+                found,
+            })
+        }
+    }
+
+    fn open_case(&mut self, n: usize, switch: bool) -> Result<(), IRTypeError> {
+        match self.blocks.last_mut() {
+            Some(Block::If(cased)) if !switch => {
+                if std::mem::replace(cased, true) {
+                    self.scopes.pop();
+                }
+            }
+            Some(Block::Switch(cased)) if switch => {
+                if std::mem::replace(cased, true) {
+                    self.scopes.pop();
+                }
+            }
+            _ => return Err(IRTypeError::Unbalanced(n)),
+        }
+        self.scopes.push(HashMap::new());
+        Ok(())
+    }
+
+    fn node(&mut self, n: usize, node: &IRNode) -> Result<(), IRTypeError> {
+        match node {
+            IRNode::Term(Term { type_t, value }) => {
+                if let Value::Id(ident) = value {
+                    self.variable(n, ident, type_t)?;
+                }
+                self.values.push(type_t.clone());
+                return Ok(());
+            }
+            IRNode::Eval(func) => return self.eval(n, func),
+            IRNode::Load(access) => {
+                let element_t = self.element(n, access)?;
+                self.index(n)?;
+                self.values.push(element_t);
+                return Ok(());
+            }
+            IRNode::Assign(assign) | IRNode::StaticAssign(assign) => {
+                let found = self.pop(n)?;
+                Self::expect(n, &assign.type_t, found)?;
+                self.declare(&assign.symbol.ident, assign.type_t.clone());
+            }
+            IRNode::Reassign(reassign) => {
+                let found = self.pop(n)?;
+                Self::expect(n, &reassign.type_t, found)?;
+                self.variable(n, &reassign.symbol.ident, &reassign.type_t)?;
+            }
+            IRNode::Declare(assign) => {
+                self.declare(&assign.symbol.ident, assign.type_t.clone());
+            }
+            IRNode::Store(access) => {
+                let element_t = self.element(n, access)?;
+                let found = self.pop(n)?;
+                Self::expect(n, &element_t, found)?;
+                self.index(n)?;
+            }
+            IRNode::If(_) => {
+                self.settle(n)?;
+                self.blocks.push(Block::If(false));
+            }
+            IRNode::IfCase(_) | IRNode::ElseIfCase(_) => {
+                self.condition(n)?;
+                self.settle(n)?;
+                self.open_case(n, false)?;
+            }
+            IRNode::ElseCase(_) => {
+                self.settle(n)?;
+                self.open_case(n, false)?;
+            }
+            IRNode::Switch(_) => {
+                let found = self.pop(n)?;
+                if !found.is_integer() && found != Type::Never {
+                    return Err(IRTypeError::Mismatch {
+                        node: n,
+                        expected: Type::Int32,
+                        found,
+                    });
+                }
+                self.settle(n)?;
+                self.blocks.push(Block::Switch(false));
+            }
+            IRNode::SwitchCase(..) | IRNode::DefaultCase(_) => {
+                self.settle(n)?;
+                self.open_case(n, true)?;
+            }
+            IRNode::Repeat(_) => {
+                self.settle(n)?;
+                self.blocks.push(Block::Repeat);
+                self.scopes.push(HashMap::new());
+            }
+            IRNode::While(_) => {
+                self.condition(n)?;
+                self.settle(n)?;
+                self.blocks.push(Block::While);
+                self.scopes.push(HashMap::new());
+            }
+            IRNode::FuncDef(def, _) => {
+                self.settle(n)?;
+                self.blocks.push(Block::Func(def.return_t.clone()));
+                self.scopes.push(
+                    def.params_t
+                        .iter()
+                        .map(|(ident, t)| (ident.clone(), t.clone()))
+                        .collect(),
+                );
+            }
+            IRNode::EndIf(_) | IRNode::EndSwitch(_) => {
+                self.settle(n)?;
+                match (self.blocks.pop(), node) {
+                    (Some(Block::If(cased)), IRNode::EndIf(_))
+                    | (Some(Block::Switch(cased)), IRNode::EndSwitch(_)) => {
+                        if cased {
+                            self.scopes.pop();
+                        }
+                    }
+                    _ => return Err(IRTypeError::Unbalanced(n)),
+                }
+            }
+            IRNode::Until(_) => {
+                self.condition(n)?;
+                self.settle(n)?;
+                let Some(Block::Repeat) = self.blocks.pop() else {
+                    return Err(IRTypeError::Unbalanced(n));
+                };
+                self.scopes.pop();
+            }
+            IRNode::EndWhile(_) => {
+                self.settle(n)?;
+                let Some(Block::While) = self.blocks.pop() else {
+                    return Err(IRTypeError::Unbalanced(n));
+                };
+                self.scopes.pop();
+            }
+            IRNode::EndFuncDef(_) => {
+                self.settle(n)?;
+                let Some(Block::Func(_)) = self.blocks.pop() else {
+                    return Err(IRTypeError::Unbalanced(n));
+                };
+                self.scopes.pop();
+            }
+            IRNode::Return => {
+                let found = self.pop(n)?;
+                let return_t = self.blocks.iter().rev().find_map(|block| match block {
+                    Block::Func(return_t) => Some(return_t),
+                    _ => None,
+                });
+                // The program's return value is its exit code, which
+                // backends convert
+                if let Some(return_t) = return_t {
+                    Self::expect(n, return_t, found)?;
+                }
+            }
+            IRNode::Discard => {
+                self.pop(n)?;
+            }
+            IRNode::Break(_)
+            | IRNode::Continue(_)
+            | IRNode::ExternFuncDef(_)
+            | IRNode::Intrinsic(..)
+            | IRNode::FuncDecl(_)
+            | IRNode::GlobalSection
+            | IRNode::EndGlobalSection
+            | IRNode::Program
+            | IRNode::Label(_)
+            | IRNode::Loc(_) => {}
+        }
+        self.settle(n)
+    }
+
+    fn eval(&mut self, n: usize, func: &Func) -> Result<(), IRTypeError> {
+        let (name, sig) = match func {
+            Func::Add(sig) => ("Add", sig),
+            Func::Sub(sig) => ("Sub", sig),
+            Func::Mult(sig) => ("Mult", sig),
+            Func::Div(sig) => ("Div", sig),
+            Func::Pow(sig) => ("Pow", sig),
+            Func::Mod(sig) => ("Mod", sig),
+            Func::BitAnd(sig) => ("BitAnd", sig),
+            Func::BitOr(sig) => ("BitOr", sig),
+            Func::BitXor(sig) => ("BitXor", sig),
+            Func::Shl(sig) => ("Shl", sig),
+            Func::Shr(sig) => ("Shr", sig),
+            Func::Lt(sig) => ("Lt", sig),
+            Func::Gt(sig) => ("Gt", sig),
+            Func::Leq(sig) => ("Leq", sig),
+            Func::Geq(sig) => ("Geq", sig),
+            Func::Eq(sig) => ("Eq", sig),
+            Func::Neq(sig) => ("Neq", sig),
+            Func::Not(sig) => ("Not", sig),
+            Func::Neg(sig) => ("Neg", sig),
+            Func::BitNot(sig) => ("BitNot", sig),
+            Func::Cast(sig) => ("Cast", sig),
+            Func::Func(sig) => (sig.symbol.ident.as_str(), sig),
+        };
+        if !self.well_formed(func, sig) {
+            return Err(IRTypeError::BadOperands(n, name.to_string()));
+        }
+        for param_t in sig.params_t.iter().rev() {
+            let found = self.pop(n)?;
+            Self::expect(n, param_t, found)?;
+        }
+        self.values.push(sig.return_t.clone());
+        Ok(())
+    }
+
+    // Whether the signature is one the operation can have
+    fn well_formed(&self, func: &Func, sig: &Signature) -> bool {
+        let params = &sig.params_t;
+        let ret = &sig.return_t;
+        match func {
+            Func::Add(_) => {
+                params.len() == 2
+                    && params[0] == params[1]
+                    && params[0] == *ret
+                    && (ret.is_numeric() || *ret == Type::String)
+            }
+            Func::Sub(_) | Func::Mult(_) | Func::Div(_) | Func::Pow(_) => {
+                params.len() == 2 && params[0] == params[1] && params[0] == *ret && ret.is_numeric()
+            }
+            Func::Mod(_)
+            | Func::BitAnd(_)
+            | Func::BitOr(_)
+            | Func::BitXor(_)
+            | Func::Shl(_)
+            | Func::Shr(_) => {
+                params.len() == 2 && params[0] == params[1] && params[0] == *ret && ret.is_integer()
+            }
+            Func::Lt(_)
+            | Func::Gt(_)
+            | Func::Leq(_)
+            | Func::Geq(_)
+            | Func::Eq(_)
+            | Func::Neq(_) => params.len() == 2 && params[0] == params[1] && *ret == Type::Bool,
+            Func::Not(_) => params.len() == 1 && *ret == Type::Bool,
+            Func::Neg(_) => params.len() == 1 && params[0] == *ret && ret.is_numeric(),
+            Func::BitNot(_) => params.len() == 1 && params[0] == *ret && ret.is_integer(),
+            Func::Cast(_) => params.len() == 1 && params[0].is_numeric() && ret.is_numeric(),
+            Func::Func(sig) => {
+                let ident = sig.symbol.ident.as_str();
+                let declared = match (self.funcs.get(ident), builtins::lookup(ident)) {
+                    (Some((params_t, return_t)), _) => Some((params_t.clone(), return_t.clone())),
+                    (None, Some(builtin)) => Some((builtin.params_t, builtin.return_t)),
+                    (None, None) => None,
+                };
+                declared.is_none_or(|(params_t, return_t)| *params == params_t && *ret == return_t)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{new_func_def, new_sig, Assign};
+    use crate::symbol::new_symbol;
+
+    fn term(type_t: Type, value: Value) -> IRNode {
+        IRNode::Term(Term { type_t, value })
+    }
+
+    #[test]
+    fn check_ir_types() {
+        let func = new_func_def("inc", vec![("n".into(), Type::Int64)], Type::Int64);
+        let body = vec![
+            IRNode::FuncDef(func, "_func_def_0".into()),
+            term(Type::Int64, Value::Id("n".into())),
+            term(Type::Int64, Value::Int64(1)),
+            IRNode::Eval(Func::Add(new_sig(
+                "Add",
+                vec![Type::Int64, Type::Int64],
+                Type::Int64,
+            ))),
+            IRNode::Return,
+            IRNode::EndFuncDef("_func_def_0".into()),
+            term(Type::Int32, Value::Int32(1)),
+            IRNode::Eval(Func::Cast(new_sig("Cast", vec![Type::Int32], Type::Int64))),
+            IRNode::Eval(Func::Func(new_sig("inc", vec![Type::Int64], Type::Int64))),
+            IRNode::Assign(Assign {
+                type_t: Type::Int64,
+                symbol: new_symbol("x".into()),
+            }),
+        ];
+        assert_eq!(check(&body), Ok(()));
+
+        // A call skipping the widening of its argument
+        let mut uncast = body.clone();
+        uncast.remove(7);
+        assert_eq!(
+            check(&uncast),
+            Err(IRTypeError::Mismatch {
+                node: 7,
+                expected: Type::Int64,
+                found: Type::Int32
+            })
+        );
+        // A term disagreeing with the declaration of its variable
+        let mut retyped = body.clone();
+        retyped[1] = term(Type::Int32, Value::Id("n".into()));
+        assert!(matches!(
+            check(&retyped),
+            Err(IRTypeError::Mismatch { node: 1, .. })
+        ));
+        // A value computed and then dropped
+        let mut dropped = body.clone();
+        dropped.insert(5, term(Type::Bool, Value::Bool(true)));
+        assert_eq!(check(&dropped), Err(IRTypeError::UnusedValue(6)));
+        assert_eq!(check(&body[..3]), Err(IRTypeError::Unterminated));
+    }
+}
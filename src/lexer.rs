@@ -0,0 +1,661 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::ast::Num;
+use crate::types::Type;
+
+pub type Spanned<Tok, Loc, Error> = Result<(Loc, Tok, Loc), Error>;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum LexicalError {
+    #[error("Unexpected character {1:?} at {0}")]
+    UnexpectedChar(usize, char),
+    #[error("Unterminated string literal starting at {0}")]
+    UnterminatedString(usize),
+    #[error("Unknown escape sequence \\{1} at {0}")]
+    InvalidEscape(usize, char),
+    #[error("Invalid numeric literal {1:?} at {0}")]
+    InvalidNumber(usize, String),
+    // The start and end of the literal, and the widest type it was
+    // tried in
+    #[error("Number at {0} is too large for {2}")]
+    NumberTooLarge(usize, usize, Type),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Tok {
+    // Keywords
+    Let,
+    If,
+    Then,
+    Else,
+    End,
+    Program,
+    Fun,
+    Return,
+    Begin,
+    True,
+    False,
+    SizeOf,
+    TypeName,
+    Switch,
+    Case,
+    Default,
+    Repeat,
+    Until,
+    While,
+    Do,
+    Break,
+    Defer,
+    Static,
+    StaticAssert,
+    Bench,
+    And,
+    Or,
+    // Type keywords
+    Int64,
+    Int32,
+    UInt64,
+    UInt32,
+    Float64,
+    Float32,
+    Bool,
+    String,
+    Never,
+    Array,
+    Nil,
+    // Doc comments (/// and //!)
+    DocComment(String),
+    InnerDocComment(String),
+    // Identifiers and literals
+    Id(String),
+    // The flag is set when the number was written with a type suffix
+    Num(Num, bool),
+    Str(String),
+    // Assignment operators
+    Assign,
+    AddAssign,
+    SubAssign,
+    MultAssign,
+    DivAssign,
+    // Comparison operators
+    Eq,
+    Neq,
+    Leq,
+    Geq,
+    Lt,
+    Gt,
+    // Arithmetic and unary operators
+    Plus,
+    Minus,
+    Star,
+    StarStar,
+    Slash,
+    Percent,
+    Bang,
+    // Bitwise operators
+    Amp,
+    Pipe,
+    Caret,
+    Tilde,
+    Shl,
+    Shr,
+    // Punctuation
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Colon,
+    Semicolon,
+    Arrow,
+    At,
+}
+
+pub fn keyword(ident: &str) -> Option<Tok> {
+    match ident {
+        "let" => Some(Tok::Let),
+        "if" => Some(Tok::If),
+        "then" => Some(Tok::Then),
+        "else" => Some(Tok::Else),
+        "end" => Some(Tok::End),
+        "program" => Some(Tok::Program),
+        "fun" => Some(Tok::Fun),
+        "return" => Some(Tok::Return),
+        "begin" => Some(Tok::Begin),
+        "true" => Some(Tok::True),
+        "false" => Some(Tok::False),
+        "size_of" => Some(Tok::SizeOf),
+        "type_name" => Some(Tok::TypeName),
+        "switch" => Some(Tok::Switch),
+        "case" => Some(Tok::Case),
+        "default" => Some(Tok::Default),
+        "repeat" => Some(Tok::Repeat),
+        "until" => Some(Tok::Until),
+        "while" => Some(Tok::While),
+        "do" => Some(Tok::Do),
+        "break" => Some(Tok::Break),
+        "defer" => Some(Tok::Defer),
+        "static" => Some(Tok::Static),
+        "static_assert" => Some(Tok::StaticAssert),
+        "bench" => Some(Tok::Bench),
+        "and" => Some(Tok::And),
+        "or" => Some(Tok::Or),
+        "int64" => Some(Tok::Int64),
+        "int32" => Some(Tok::Int32),
+        "uint64" => Some(Tok::UInt64),
+        "uint32" => Some(Tok::UInt32),
+        "float64" => Some(Tok::Float64),
+        "float32" => Some(Tok::Float32),
+        "bool" => Some(Tok::Bool),
+        "string" => Some(Tok::String),
+        "never" => Some(Tok::Never),
+        "array" => Some(Tok::Array),
+        "Nil" => Some(Tok::Nil),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rep = match self {
+            Tok::Let => "let",
+            Tok::If => "if",
+            Tok::Then => "then",
+            Tok::Else => "else",
+            Tok::End => "end",
+            Tok::Program => "program",
+            Tok::Fun => "fun",
+            Tok::Return => "return",
+            Tok::Begin => "begin",
+            Tok::True => "true",
+            Tok::False => "false",
+            Tok::SizeOf => "size_of",
+            Tok::TypeName => "type_name",
+            Tok::Switch => "switch",
+            Tok::Case => "case",
+            Tok::Default => "default",
+            Tok::Repeat => "repeat",
+            Tok::Until => "until",
+            Tok::While => "while",
+            Tok::Do => "do",
+            Tok::Break => "break",
+            Tok::Defer => "defer",
+            Tok::Static => "static",
+            Tok::StaticAssert => "static_assert",
+            Tok::Bench => "bench",
+            Tok::And => "and",
+            Tok::Or => "or",
+            Tok::Int64 => "int64",
+            Tok::Int32 => "int32",
+            Tok::UInt64 => "uint64",
+            Tok::UInt32 => "uint32",
+            Tok::Float64 => "float64",
+            Tok::Float32 => "float32",
+            Tok::Bool => "bool",
+            Tok::String => "string",
+            Tok::Never => "never",
+            Tok::Array => "array",
+            Tok::Nil => "Nil",
+            Tok::DocComment(_) => "///",
+            Tok::InnerDocComment(_) => "//!",
+            Tok::Id(ident) => return write!(f, "identifier `{}`", ident),
+            Tok::Num(num, _) => return write!(f, "number {:?}", num),
+            Tok::Str(s) => return write!(f, "string {:?}", s),
+            Tok::Assign => "=",
+            Tok::AddAssign => "+=",
+            Tok::SubAssign => "-=",
+            Tok::MultAssign => "*=",
+            Tok::DivAssign => "/=",
+            Tok::Eq => "==",
+            Tok::Neq => "!=",
+            Tok::Leq => "<=",
+            Tok::Geq => ">=",
+            Tok::Lt => "<",
+            Tok::Gt => ">",
+            Tok::Plus => "+",
+            Tok::Minus => "-",
+            Tok::Star => "*",
+            Tok::StarStar => "**",
+            Tok::Slash => "/",
+            Tok::Percent => "%",
+            Tok::Bang => "!",
+            Tok::Amp => "&",
+            Tok::Pipe => "|",
+            Tok::Caret => "^",
+            Tok::Tilde => "~",
+            Tok::Shl => "<<",
+            Tok::Shr => ">>",
+            Tok::LParen => "(",
+            Tok::RParen => ")",
+            Tok::LBracket => "[",
+            Tok::RBracket => "]",
+            Tok::Comma => ",",
+            Tok::Colon => ":",
+            Tok::Semicolon => ";",
+            Tok::Arrow => "->",
+            Tok::At => "@",
+        };
+        write!(f, "`{}`", rep)
+    }
+}
+
+// Identifiers follow UAX #31: a letter (XID_Start) or underscore,
+// followed by any number of XID_Continue characters.
+pub fn is_ident_start(c: char) -> bool {
+    c == '_' || unicode_ident::is_xid_start(c)
+}
+
+pub fn is_ident_continue(c: char) -> bool {
+    unicode_ident::is_xid_continue(c)
+}
+
+// Invalid literals are quoted in messages, but not at any length
+fn abbreviated(text: &str) -> String {
+    match text.char_indices().nth(32) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+pub struct Lexer<'input> {
+    input: &'input str,
+    pos: usize,
+}
+
+impl<'input> Lexer<'input> {
+    pub const TOKENS_OUTPUT_FILENAME: &'static str = "out.tokens";
+
+    pub fn new(input: &'input str) -> Self {
+        Lexer { input, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.input[self.pos..].chars().nth(1)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn bump_while(&mut self, pred: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&pred) {
+            self.bump();
+        }
+    }
+
+    fn skip_trivia(&mut self) {
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) if !self.at_doc_comment() => self.bump_while(|c| c != '\n'),
+                _ => break,
+            }
+        }
+    }
+
+    // `///` and `//!` start doc comments, but `////...` is an ordinary comment
+    fn at_doc_comment(&self) -> bool {
+        let rest = &self.input[self.pos..];
+        (rest.starts_with("///") && !rest.starts_with("////")) || rest.starts_with("//!")
+    }
+
+    fn lex_doc_comment(&mut self, start: usize) -> Spanned<Tok, usize, LexicalError> {
+        let inner = self.input[start..].starts_with("//!");
+        self.bump_while(|c| c != '\n');
+        let text = self.input[start + 3..self.pos].trim_end_matches('\r');
+        let text = text.strip_prefix(' ').unwrap_or(text).to_string();
+        let tok = if inner {
+            Tok::InnerDocComment(text)
+        } else {
+            Tok::DocComment(text)
+        };
+        Ok((start, tok, self.pos))
+    }
+
+    fn lex_ident(&mut self, start: usize) -> Spanned<Tok, usize, LexicalError> {
+        self.bump_while(is_ident_continue);
+        let ident = &self.input[start..self.pos];
+        let tok = keyword(ident).unwrap_or_else(|| Tok::Id(ident.to_string()));
+        Ok((start, tok, self.pos))
+    }
+
+    fn lex_number(&mut self, start: usize) -> Spanned<Tok, usize, LexicalError> {
+        self.bump_while(|c| c.is_ascii_digit());
+        let mut is_float = false;
+        if self.peek() == Some('.') && self.peek_second().is_some_and(|c| c.is_ascii_digit()) {
+            is_float = true;
+            self.bump();
+            self.bump_while(|c| c.is_ascii_digit());
+        }
+        let digits_end = self.pos;
+        self.bump_while(|c| c.is_alphanumeric() || c == '_');
+        let digits = &self.input[start..digits_end];
+        let suffix = &self.input[digits_end..self.pos];
+        let invalid =
+            || LexicalError::InvalidNumber(start, abbreviated(&self.input[start..self.pos]));
+        // Only digits are left to parse, so they can only fail by being
+        // too many, as floats do by parsing to infinity
+        let too_large = |type_t| LexicalError::NumberTooLarge(start, self.pos, type_t);
+        let num = match (suffix, is_float) {
+            // Unsuffixed integers are int32 unless they don't fit, and
+            // then the first of int64 and uint64 that they do
+            ("", false) => i128::from_str(digits)
+                .ok()
+                .and_then(|n| {
+                    [Type::Int32, Type::Int64, Type::UInt64]
+                        .iter()
+                        .find_map(|t| Num::fit(n, t))
+                })
+                .ok_or_else(|| too_large(Type::UInt64))?,
+            ("i32", false) => {
+                Num::Int32(i32::from_str(digits).map_err(|_| too_large(Type::Int32))?)
+            }
+            ("i64", false) => {
+                Num::Int64(i64::from_str(digits).map_err(|_| too_large(Type::Int64))?)
+            }
+            ("u32", false) => {
+                Num::UInt32(u32::from_str(digits).map_err(|_| too_large(Type::UInt32))?)
+            }
+            ("u64", false) => {
+                Num::UInt64(u64::from_str(digits).map_err(|_| too_large(Type::UInt64))?)
+            }
+            ("f32", true) => Num::Float32(
+                f32::from_str(digits)
+                    .ok()
+                    .filter(|n| n.is_finite())
+                    .ok_or_else(|| too_large(Type::Float32))?,
+            ),
+            ("" | "f64", true) => Num::Float64(
+                f64::from_str(digits)
+                    .ok()
+                    .filter(|n| n.is_finite())
+                    .ok_or_else(|| too_large(Type::Float64))?,
+            ),
+            _ => return Err(invalid()),
+        };
+        Ok((start, Tok::Num(num, !suffix.is_empty()), self.pos))
+    }
+
+    fn lex_string(&mut self, start: usize) -> Spanned<Tok, usize, LexicalError> {
+        let mut content = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => break,
+                Some('\\') => {
+                    let escape_pos = self.pos - 1;
+                    let escaped = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        Some(other) => return Err(LexicalError::InvalidEscape(escape_pos, other)),
+                        None => return Err(LexicalError::UnterminatedString(start)),
+                    };
+                    content.push(escaped);
+                }
+                Some(c) => content.push(c),
+                None => return Err(LexicalError::UnterminatedString(start)),
+            }
+        }
+        Ok((start, Tok::Str(content), self.pos))
+    }
+
+    // Raw strings are written r"..." and skip escape processing. Any
+    // number of #'s may follow the r, and the same number must follow
+    // the closing quote, so r#"say "hi""# can contain quotes.
+    fn lex_raw_string(&mut self, start: usize) -> Spanned<Tok, usize, LexicalError> {
+        let mut hashes = 0;
+        while self.peek() == Some('#') {
+            self.bump();
+            hashes += 1;
+        }
+        if self.bump() != Some('"') {
+            return Err(LexicalError::UnterminatedString(start));
+        }
+        let content_start = self.pos;
+        let terminator = format!("\"{}", "#".repeat(hashes));
+        match self.input[content_start..].find(&terminator) {
+            Some(len) => {
+                self.pos = content_start + len + terminator.len();
+                let content = self.input[content_start..content_start + len].to_string();
+                Ok((start, Tok::Str(content), self.pos))
+            }
+            None => {
+                self.pos = self.input.len();
+                Err(LexicalError::UnterminatedString(start))
+            }
+        }
+    }
+
+    fn lex_operator(&mut self, start: usize, c: char) -> Spanned<Tok, usize, LexicalError> {
+        let next = self.peek();
+        let (tok, double) = match (c, next) {
+            ('=', Some('=')) => (Tok::Eq, true),
+            ('!', Some('=')) => (Tok::Neq, true),
+            ('<', Some('=')) => (Tok::Leq, true),
+            ('>', Some('=')) => (Tok::Geq, true),
+            ('+', Some('=')) => (Tok::AddAssign, true),
+            ('-', Some('=')) => (Tok::SubAssign, true),
+            ('*', Some('=')) => (Tok::MultAssign, true),
+            ('*', Some('*')) => (Tok::StarStar, true),
+            ('/', Some('=')) => (Tok::DivAssign, true),
+            ('-', Some('>')) => (Tok::Arrow, true),
+            ('<', Some('<')) => (Tok::Shl, true),
+            ('>', Some('>')) => (Tok::Shr, true),
+            ('=', _) => (Tok::Assign, false),
+            ('!', _) => (Tok::Bang, false),
+            ('<', _) => (Tok::Lt, false),
+            ('>', _) => (Tok::Gt, false),
+            ('+', _) => (Tok::Plus, false),
+            ('-', _) => (Tok::Minus, false),
+            ('*', _) => (Tok::Star, false),
+            ('/', _) => (Tok::Slash, false),
+            ('%', _) => (Tok::Percent, false),
+            ('&', _) => (Tok::Amp, false),
+            ('|', _) => (Tok::Pipe, false),
+            ('^', _) => (Tok::Caret, false),
+            ('~', _) => (Tok::Tilde, false),
+            ('(', _) => (Tok::LParen, false),
+            (')', _) => (Tok::RParen, false),
+            ('[', _) => (Tok::LBracket, false),
+            (']', _) => (Tok::RBracket, false),
+            (',', _) => (Tok::Comma, false),
+            (':', _) => (Tok::Colon, false),
+            (';', _) => (Tok::Semicolon, false),
+            ('@', _) => (Tok::At, false),
+            (other, _) => return Err(LexicalError::UnexpectedChar(start, other)),
+        };
+        if double {
+            self.bump();
+        }
+        Ok((start, tok, self.pos))
+    }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+    type Item = Spanned<Tok, usize, LexicalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_trivia();
+        let start = self.pos;
+        if self.at_doc_comment() {
+            return Some(self.lex_doc_comment(start));
+        }
+        let c = self.bump()?;
+        let token = if c == 'r' && matches!(self.peek(), Some('"' | '#')) {
+            self.lex_raw_string(start)
+        } else if is_ident_start(c) {
+            self.lex_ident(start)
+        } else if c.is_ascii_digit() {
+            self.lex_number(start)
+        } else if c == '"' {
+            self.lex_string(start)
+        } else {
+            self.lex_operator(start, c)
+        };
+        Some(token)
+    }
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<(usize, Tok, usize)>, LexicalError> {
+    Lexer::new(input).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toks(input: &str) -> Vec<Tok> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|(_, tok, _)| tok)
+            .collect()
+    }
+
+    #[test]
+    fn lex_assignment_with_spans() {
+        let spanned = tokenize("let x = 10;").unwrap();
+        assert_eq!(
+            spanned,
+            vec![
+                (0, Tok::Let, 3),
+                (4, Tok::Id("x".into()), 5),
+                (6, Tok::Assign, 7),
+                (8, Tok::Num(Num::Int32(10), false), 10),
+                (10, Tok::Semicolon, 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_numeric_suffixes() {
+        assert_eq!(
+            toks("1 2i64 3u32 4u64 1.5 2.5f32"),
+            vec![
+                Tok::Num(Num::Int32(1), false),
+                Tok::Num(Num::Int64(2), true),
+                Tok::Num(Num::UInt32(3), true),
+                Tok::Num(Num::UInt64(4), true),
+                Tok::Num(Num::Float64(1.5), false),
+                Tok::Num(Num::Float32(2.5), true),
+            ]
+        );
+        assert!(tokenize("12abc").is_err());
+        // Unsuffixed integers too big for int32 take a wider type
+        assert_eq!(
+            toks("4294967296 18446744073709551615"),
+            vec![
+                Tok::Num(Num::Int64(4_294_967_296), false),
+                Tok::Num(Num::UInt64(u64::MAX), false),
+            ]
+        );
+        assert!(tokenize("18446744073709551616").is_err());
+    }
+
+    #[test]
+    fn lex_oversized_literals() {
+        let digits = "9".repeat(300);
+        assert_eq!(
+            tokenize(&digits),
+            Err(LexicalError::NumberTooLarge(0, 300, Type::UInt64))
+        );
+        assert_eq!(
+            tokenize("x = 4294967296u32"),
+            Err(LexicalError::NumberTooLarge(4, 17, Type::UInt32))
+        );
+        // Floats too large parse to infinity rather than failing
+        assert_eq!(
+            tokenize(&format!("{}.5f32", &digits[..40])),
+            Err(LexicalError::NumberTooLarge(0, 45, Type::Float32))
+        );
+        assert_eq!(
+            tokenize(&format!("{}.0", digits.repeat(2))),
+            Err(LexicalError::NumberTooLarge(0, 602, Type::Float64))
+        );
+        assert_eq!(
+            toks(&format!("0.{}", "1".repeat(300))),
+            vec![Tok::Num(Num::Float64(0.1111111111111111), false)]
+        );
+        assert_eq!(
+            tokenize(&format!("1{}", "z".repeat(100))),
+            Err(LexicalError::InvalidNumber(
+                0,
+                format!("1{}...", "z".repeat(31))
+            ))
+        );
+    }
+
+    #[test]
+    fn lex_skips_comments() {
+        assert_eq!(
+            toks("x // the rest is ignored\n-> y"),
+            vec![Tok::Id("x".into()), Tok::Arrow, Tok::Id("y".into())]
+        );
+    }
+
+    #[test]
+    fn lex_doc_comments() {
+        assert_eq!(
+            toks("//! module docs\n/// item docs\r\n////not docs\n///\nfun"),
+            vec![
+                Tok::InnerDocComment("module docs".into()),
+                Tok::DocComment("item docs".into()),
+                Tok::DocComment("".into()),
+                Tok::Fun,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_string_escapes() {
+        assert_eq!(toks(r#""a\n\"b\"""#), vec![Tok::Str("a\n\"b\"".into())]);
+        assert_eq!(tokenize("\"open"), Err(LexicalError::UnterminatedString(0)));
+    }
+
+    #[test]
+    fn lex_unicode_identifiers() {
+        assert_eq!(
+            tokenize("let π = \"héllo, 世界\";").unwrap(),
+            vec![
+                (0, Tok::Let, 3),
+                (4, Tok::Id("π".into()), 6),
+                (7, Tok::Assign, 8),
+                (9, Tok::Str("héllo, 世界".into()), 25),
+                (25, Tok::Semicolon, 26),
+            ]
+        );
+        assert_eq!(toks("größe_1"), vec![Tok::Id("größe_1".into())]);
+        assert!(tokenize("€").is_err());
+    }
+
+    #[test]
+    fn lex_raw_strings() {
+        assert_eq!(
+            toks(r##"r"C:\path\n" r#"say "hi""# rest"##),
+            vec![
+                Tok::Str(r"C:\path\n".into()),
+                Tok::Str(r#"say "hi""#.into()),
+                Tok::Id("rest".into()),
+            ]
+        );
+        assert_eq!(
+            tokenize(r#"r#"open""#),
+            Err(LexicalError::UnterminatedString(0))
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use lalrpop_util::lalrpop_mod;
+
+pub mod abi;
+#[cfg(test)]
+pub mod arbitrary;
+pub mod ast;
+pub mod backends;
+pub mod builtins;
+pub mod cache;
+pub mod codegen;
+pub mod compile;
+pub mod consteval;
+pub mod debugger;
+pub mod diagnostics;
+pub mod engine;
+pub mod hir;
+pub mod infer;
+pub mod intrinsics;
+pub mod ir;
+pub mod ircheck;
+pub mod lexer;
+pub mod link;
+pub mod lint;
+pub mod lower;
+pub mod marshal;
+pub mod metrics;
+pub mod optimize;
+pub mod parser;
+pub mod selftest;
+pub mod semantic;
+pub mod session;
+pub mod symbol;
+pub mod traverse;
+pub mod types;
+
+lalrpop_mod!(pub rascal);
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{Expr, Stmt, Term, TypedExpr};
+    use crate::parser::{tokens, ParseError, SyntaxError};
+    use crate::rascal;
+
+    fn parses(src: &str) -> bool {
+        rascal::RootParser::new().parse(tokens(src)).is_ok()
+    }
+
+    #[test]
+    fn parse_trailing_commas() {
+        assert!(parses(
+            "fun add(a: int32, b: int32,) -> int32 return a + b; end
+             program p
+                 let f: (int32, int32,) -> int32 = add;
+                 let g = fun (x, y,) -> (x);
+                 return add(1, 2,);
+             end"
+        ));
+        assert!(!parses("program p return add(,); end"));
+    }
+
+    #[test]
+    fn parse_optional_final_semicolon() {
+        let root = rascal::RootParser::new()
+            .parse(tokens(
+                "program p
+                     let x = 1;
+                     if x == 1 then
+                         x = 2
+                     else then
+                         x = 3
+                     end
+                     return x
+                 end",
+            ))
+            .unwrap();
+        let stmts = &root.program.unwrap().1;
+        assert_eq!(stmts.len(), 3);
+        assert!(matches!(*stmts[2], Stmt::Return(ref e) if matches!(e.expr, Expr::Term(_))));
+        assert!(!parses("program p let x = 1 return x; end"));
+    }
+
+    #[test]
+    fn parse_doc_comments() {
+        let root = rascal::RootParser::new()
+            .parse(tokens(
+                "//! Computes things.
+                 /// The answer.
+                 let answer = 42;
+
+                 /// Adds two numbers.
+                 ///
+                 /// Overflow wraps.
+                 fun add(a: int32, b: int32) -> int32 return a + b end
+
+                 program p return add(answer, 1) end",
+            ))
+            .unwrap();
+        assert_eq!(root.docs, vec!["Computes things."]);
+        assert!(
+            matches!(*root.preblock[0], Stmt::Assign(_, _, _, ref d, ..) if d == &["The answer."])
+        );
+        assert!(matches!(
+            *root.preblock[1],
+            Stmt::FuncDef(ref f) if f.docs == ["Adds two numbers.", "", "Overflow wraps."]
+        ));
+        assert!(!parses("program p /// dangling\n end"));
+    }
+
+    // Render an expression as an s-expression so tests can assert its shape
+    fn shape(expr: &TypedExpr) -> String {
+        let bin =
+            |op: &str, l: &TypedExpr, r: &TypedExpr| format!("({} {} {})", op, shape(l), shape(r));
+        match &expr.expr {
+            Expr::Term(t) => match &t.term {
+                Term::Id(id) => id.clone(),
+                Term::Num(n, _) => format!("{:?}", n),
+                Term::Bool(b) => b.to_string(),
+                Term::String(s) => format!("{:?}", s),
+                Term::Expr(e) => shape(e),
+            },
+            Expr::Add(l, r) => bin("+", l, r),
+            Expr::Sub(l, r) => bin("-", l, r),
+            Expr::Mult(l, r) => bin("*", l, r),
+            Expr::Div(l, r) => bin("/", l, r),
+            Expr::Mod(l, r) => bin("%", l, r),
+            Expr::Pow(l, r) => bin("**", l, r),
+            Expr::BitAnd(l, r) => bin("&", l, r),
+            Expr::BitOr(l, r) => bin("|", l, r),
+            Expr::BitXor(l, r) => bin("^", l, r),
+            Expr::Shl(l, r) => bin("<<", l, r),
+            Expr::Shr(l, r) => bin(">>", l, r),
+            Expr::Eq(l, r) => bin("==", l, r),
+            Expr::Neq(l, r) => bin("!=", l, r),
+            Expr::Leq(l, r) => bin("<=", l, r),
+            Expr::Geq(l, r) => bin(">=", l, r),
+            Expr::LessThan(l, r) => bin("<", l, r),
+            Expr::GreaterThan(l, r) => bin(">", l, r),
+            Expr::And(l, r) => bin("and", l, r),
+            Expr::Or(l, r) => bin("or", l, r),
+            Expr::Not(u) => format!("(! {})", shape(u)),
+            Expr::Neg(u) => format!("(- {})", shape(u)),
+            Expr::BitNot(u) => format!("(~ {})", shape(u)),
+            Expr::Call(s, args) => format!(
+                "({} {})",
+                s.ident,
+                args.iter().map(|a| shape(a)).collect::<Vec<_>>().join(" ")
+            ),
+            Expr::Index(s, _, i) => format!("([] {} {})", s.ident, shape(i)),
+            Expr::LambdaFunc(_) => "lambda".into(),
+            Expr::SizeOf(t) => format!("(size_of {})", t),
+            Expr::TypeName(e) => format!("(type_name {})", shape(e)),
+            Expr::Convert(t, e) => format!("({} {})", t, shape(e)),
+        }
+    }
+
+    fn parse_shape(src: &str) -> String {
+        shape(&rascal::ExprParser::new().parse(tokens(src)).unwrap())
+    }
+
+    #[test]
+    fn parse_precedence_shapes() {
+        assert_eq!(parse_shape("a + b * c"), "(+ a (* b c))");
+        assert_eq!(parse_shape("a - b - c"), "(- (- a b) c)");
+        assert_eq!(parse_shape("a / b * c"), "(* (/ a b) c)");
+        assert_eq!(parse_shape("a + b == c * d"), "(== (+ a b) (* c d))");
+        assert_eq!(
+            parse_shape("n - 1 < f(n) + 2"),
+            "(< (- n Int32(1)) (+ (f n) Int32(2)))"
+        );
+        assert_eq!(parse_shape("-a * b"), "(* (- a) b)");
+        assert_eq!(parse_shape("a - -b"), "(- a (- b))");
+        assert_eq!(parse_shape("!a == b"), "(== (! a) b)");
+        assert_eq!(parse_shape("(a < b) == c"), "(== (< a b) c)");
+        assert_eq!(parse_shape("a | b ^ c & d"), "(| a (^ b (& c d)))");
+        assert_eq!(parse_shape("a << b + c >> d"), "(>> (<< a (+ b c)) d)");
+        assert_eq!(parse_shape("a & m == b"), "(== (& a m) b)");
+        assert_eq!(parse_shape("~a & b"), "(& (~ a) b)");
+        assert_eq!(parse_shape("-a ** b"), "(- (** a b))");
+        assert_eq!(parse_shape("a ** b ** c"), "(** a (** b c))");
+        assert_eq!(parse_shape("a * b ** c"), "(* a (** b c))");
+        assert_eq!(parse_shape("a ** (-b)"), "(** a (- b))");
+        assert_eq!(
+            parse_shape("-xs[i + 1] * 2"),
+            "(* (- ([] xs (+ i Int32(1)))) Int32(2))"
+        );
+    }
+
+    #[test]
+    fn parse_rejects_chained_comparisons() {
+        let err = rascal::ExprParser::new()
+            .parse(tokens("a < b < c"))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::User {
+                error: SyntaxError::ChainedComparison(6)
+            }
+        );
+        assert!(rascal::ExprParser::new()
+            .parse(tokens("a == b != c"))
+            .is_err());
+    }
+
+    #[test]
+    fn parse_multi_assign() {
+        let parse = |src: &str| rascal::StmtParser::new().parse(tokens(src));
+        let stmt = parse("a, b = b, a + 1;").unwrap();
+        match stmt.as_ref() {
+            Stmt::MultiAssign(targets, values) => {
+                let idents: Vec<&str> = targets.iter().map(|(s, _)| s.ident.as_str()).collect();
+                assert_eq!(idents, ["a", "b"]);
+                let values: Vec<String> = values.iter().map(|v| shape(v)).collect();
+                assert_eq!(values, ["b", "(+ a Int32(1))"]);
+            }
+            other => panic!("expected a multiple assignment, found {:?}", other),
+        }
+        assert_eq!(
+            parse("a, b = 1;").unwrap_err(),
+            ParseError::User {
+                error: SyntaxError::AssignmentArity(5, 2, 1)
+            }
+        );
+        assert_eq!(
+            parse("a, b, a = 1, 2, 3;").unwrap_err(),
+            ParseError::User {
+                error: SyntaxError::DuplicateTarget(6, "a".into())
+            }
+        );
+    }
+}
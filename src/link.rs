@@ -0,0 +1,122 @@
+use crate::ast::{Attribute, Block, Root, Span, Stmt};
+use crate::codegen::CodeGenError;
+use std::path::Path;
+use std::process::Command;
+
+// Programs can be split across several units, each compiled on its own
+// to an object and then linked. A unit sees the functions of the others
+// through their interfaces: `@extern` declarations, which only need the
+// other units to be parsed, not checked or compiled.
+
+// The functions a unit defines at the top level, as declarations that
+// can be added to the other units it's compiled with
+pub fn interface(root: &Root) -> Block {
+    root.preblock
+        .iter()
+        .chain(root.postblock.iter())
+        .filter_map(|stmt| match stmt.as_ref() {
+            Stmt::FuncDef(func) if !func.is_extern() => {
+                let mut decl = func.clone();
+                // Spans point into the unit being compiled, not this one
+                decl.span = Span::default();
+                for param in decl.params.iter_mut() {
+                    param.span = Span::default();
+                }
+                decl.block = vec![];
+                // An intrinsic stays one, the unit defines nothing for it
+                let (name, args) = match func.intrinsic() {
+                    Some(ident) => ("intrinsic", vec![ident.to_string()]),
+                    None => ("extern", vec![]),
+                };
+                decl.attrs = vec![Attribute {
+                    name: name.into(),
+                    args,
+                    span: Span::default(),
+                }];
+                Some(Box::new(Stmt::FuncDef(decl)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Where `-c` puts the object for a unit: beside it, named after it
+pub fn object_path(infile: &str) -> String {
+    Path::new(infile)
+        .with_extension("o")
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Checks that the C compiler, which links too, can be run at all, so a
+// build without one fails before any work rather than at the end of it
+pub fn find_compiler(cc: &str) -> Result<(), CodeGenError> {
+    Command::new(cc)
+        .arg("--version")
+        .output()
+        .map(|_| ())
+        .map_err(|err| CodeGenError::spawn(cc, err))
+}
+
+// Links objects into an executable with the C compiler `cc`, returning
+// the linker's errors if it fails
+pub fn link(cc: &str, objects: &[String], outfile: &str) -> Result<(), CodeGenError> {
+    let output = Command::new(cc)
+        .args(objects)
+        .arg("-o")
+        .arg(outfile)
+        // Intrinsics in any of the units may call into the math library
+        .arg("-lm")
+        .output()
+        .map_err(|err| CodeGenError::spawn(cc, err))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(CodeGenError::ToolFailed {
+            tool: cc.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn interface_declares_top_level_functions() {
+        let root = parser::parse(
+            "@extern fun g() -> int32 end
+             fun f(n: int32) -> int32
+                 fun inner() -> int32 return 1; end
+                 return n;
+             end
+             @intrinsic(\"sqrt\") fun root(x: float64) -> float64 end
+             let x = 1;",
+        )
+        .unwrap();
+        assert!(root.program.is_none());
+        let decls = interface(&root);
+        assert_eq!(decls.len(), 2);
+        let Stmt::FuncDef(f) = decls[0].as_ref() else {
+            panic!("expected a function");
+        };
+        assert_eq!(f.ident, "f");
+        assert!(f.is_extern() && f.block.is_empty());
+        let Stmt::FuncDef(root) = decls[1].as_ref() else {
+            panic!("expected a function");
+        };
+        assert_eq!(root.intrinsic(), Some("sqrt"));
+        assert!(!root.is_extern());
+        assert_eq!(object_path("src/main.ras"), "src/main.o");
+    }
+
+    #[test]
+    fn missing_compiler_is_not_found() {
+        assert!(matches!(
+            find_compiler("rascalc-no-such-cc"),
+            Err(CodeGenError::ToolchainMissing { .. })
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::consteval;
+use crate::diagnostics::Diagnostic;
+use crate::hir::{BinOp, Block, Expr, ExprKind, Module, Stmt, SymbolId, SymbolKind, Symbols};
+use crate::ir::Value;
+
+// Checks run over the HIR once it has been built, for code that is
+// valid but almost certainly not what was meant. Their findings are
+// reported as warnings and never stop the build.
+pub fn lint(module: &Module) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+    lint_block(&module.symbols, &module.globals, &mut warnings);
+    if let Some(program) = &module.program {
+        lint_block(&module.symbols, &program.block, &mut warnings);
+    }
+    let mut removable = vec![];
+    let blocks = module.program.iter().map(|program| &program.block);
+    for block in blocks.chain([&module.globals]) {
+        pure_lets(block, &mut removable);
+    }
+    unused_variables(&module.symbols, &removable, &mut warnings);
+    warnings
+}
+
+// Locals that are never mentioned after their `let`. Names starting
+// with an underscore are taken to be unused on purpose. The `let` of
+// one in `removable` can be taken out without changing what the
+// program does.
+fn unused_variables(symbols: &Symbols, removable: &[SymbolId], warnings: &mut Vec<Diagnostic>) {
+    for (n, symbol) in symbols.symbols.iter().enumerate() {
+        let Some(span) = symbol.span else {
+            continue;
+        };
+        if matches!(symbol.kind, SymbolKind::Local | SymbolKind::Static)
+            && symbol.references.is_empty()
+            && !symbol.ident.starts_with('_')
+        {
+            let ident = format!("_{}", symbol.ident);
+            let warning =
+                Diagnostic::warning(format!("unused variable `{}`", symbol.ident)).with_span(span);
+            warnings.push(match symbol.statement {
+                Some(statement) if removable.contains(&SymbolId(n)) => warning
+                    .with_help(format!(
+                        "remove it, or if it's intentional, name it `{}`",
+                        ident
+                    ))
+                    .with_suggestion(statement, ""),
+                _ => warning
+                    .with_help(format!("if this is intentional, name it `{}`", ident))
+                    .with_suggestion(span, ident),
+            });
+        }
+    }
+}
+
+// The variables defined by a `let` whose value has no effect to compute
+fn pure_lets(block: &Block, found: &mut Vec<SymbolId>) {
+    for stmt in block.iter() {
+        match stmt {
+            Stmt::Let(id, value) if pure(value) => found.push(*id),
+            Stmt::If(cases) => {
+                for case in cases.iter() {
+                    pure_lets(&case.block, found);
+                }
+            }
+            Stmt::Switch(_, cases) => {
+                for case in cases.iter() {
+                    pure_lets(&case.block, found);
+                }
+            }
+            Stmt::Repeat(block, _) | Stmt::While(_, block, _) => pure_lets(block, found),
+            Stmt::FuncDef(func) => pure_lets(&func.block, found),
+            _ => {}
+        }
+    }
+}
+
+// Calls can do anything, and indexing, dividing and shifting can trap,
+// unless they're on constants that don't
+fn pure(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Var(_) | ExprKind::Lambda(_) => true,
+        ExprKind::Unary(_, operand) | ExprKind::Cast(operand) => pure(operand),
+        ExprKind::Logical(_, l, r) => pure(l) && pure(r),
+        ExprKind::Binary(op, l, r) => {
+            let traps = matches!(
+                op,
+                BinOp::Div | BinOp::Mod | BinOp::Pow | BinOp::Shl | BinOp::Shr
+            );
+            pure(l) && pure(r) && (!traps || consteval::eval(expr).is_some())
+        }
+        ExprKind::Index(..) | ExprKind::Call(..) => false,
+    }
+}
+
+fn lint_block(symbols: &Symbols, block: &Block, warnings: &mut Vec<Diagnostic>) {
+    // Locations for debugging don't separate the statements around them
+    let block: Vec<&Stmt> = block
+        .iter()
+        .filter(|stmt| !matches!(stmt, Stmt::Loc(_)))
+        .collect();
+    for (n, stmt) in block.iter().enumerate() {
+        if let Some(next) = block.get(n + 1) {
+            dead_store(symbols, stmt, next, warnings);
+        }
+        match stmt {
+            Stmt::If(cases) => {
+                for case in cases.iter() {
+                    if let Some(Value::Bool(b)) = case.condition.as_ref().and_then(consteval::eval)
+                    {
+                        warnings.push(
+                            Diagnostic::warning(format!("condition is always {}", b))
+                                .with_span(case.span),
+                        );
+                    }
+                    lint_block(symbols, &case.block, warnings);
+                }
+            }
+            Stmt::Switch(_, cases) => {
+                for case in cases.iter() {
+                    lint_block(symbols, &case.block, warnings);
+                }
+            }
+            Stmt::Repeat(block, _) | Stmt::While(_, block, _) => {
+                lint_block(symbols, block, warnings)
+            }
+            Stmt::FuncDef(func) => lint_block(symbols, &func.block, warnings),
+            _ => {}
+        }
+    }
+}
+
+// A value stored in a local is dead when the statement right after
+// overwrites it without reading it first. Globals and statics are left
+// alone since the functions called in between may read them.
+fn dead_store(symbols: &Symbols, stmt: &Stmt, next: &Stmt, warnings: &mut Vec<Diagnostic>) {
+    let (id, span) = match stmt {
+        Stmt::Let(id, _) => (*id, symbols.get(*id).span),
+        Stmt::Assign(id, _, span) => (*id, Some(*span)),
+        _ => return,
+    };
+    if !matches!(symbols.get(id).kind, SymbolKind::Local | SymbolKind::Param) {
+        return;
+    }
+    match next {
+        Stmt::Assign(next_id, value, _) if *next_id == id && !reads(value, id) => {
+            let mut warning = Diagnostic::warning(format!(
+                "value assigned to `{}` is never read",
+                symbols.ident(id)
+            ))
+            .with_help("it's overwritten by the assignment that follows");
+            warning.span = span;
+            warnings.push(warning);
+        }
+        _ => {}
+    }
+}
+
+fn reads(expr: &Expr, id: SymbolId) -> bool {
+    match &expr.kind {
+        ExprKind::Var(var) => *var == id,
+        ExprKind::Binary(_, l, r) | ExprKind::Logical(_, l, r) => reads(l, id) || reads(r, id),
+        ExprKind::Unary(_, u) | ExprKind::Cast(u) => reads(u, id),
+        ExprKind::Call(_, args) => args.iter().any(|arg| reads(arg, id)),
+        ExprKind::Index(array, index) => *array == id || reads(index, id),
+        // Lambdas can capture the variable, be conservative
+        ExprKind::Lambda(_) => true,
+        ExprKind::Literal(_) => false,
+    }
+}
@@ -0,0 +1,657 @@
+use crate::hir::{self, BinOp, ExprKind, LogicalOp, SymbolId, SymbolKind, UnOp};
+use crate::ir::{self, IRNode};
+use crate::semantic::BuildIRError;
+use crate::symbol::new_symbol;
+use crate::types::Type;
+use std::collections::{HashMap, HashSet};
+
+// Lowers the typed HIR into the flat, stack ordered IR consumed by the
+// backends. Expressions are emitted in postfix order: operands first,
+// followed by the Eval node that consumes them.
+pub struct LowerState<'a> {
+    symbols: &'a hir::Symbols,
+    pub build_stack: Vec<IRNode>,
+    scope_counter: usize,
+    // Ids of the loops enclosing the statement being lowered
+    loops: Vec<String>,
+    // Give every intermediate value a temporary of its own, so that the
+    // generated code is a sequence of small steps that can be read and
+    // stepped through in a debugger
+    flatten: bool,
+    // Locals that shadow a name they can see, renamed in the IR
+    shadowing: HashSet<SymbolId>,
+}
+
+// The locals declared where another variable or function of the same
+// name can be seen. Each binding is a symbol of its own, but the IR
+// names variables as the source does, and C can neither declare a name
+// twice in one block nor keep the variable shadowed in view for the new
+// one's initializer, so these get their SymbolId appended, `x__12`.
+fn shadowing(symbols: &hir::Symbols) -> HashSet<SymbolId> {
+    let mut seen: HashMap<&str, Vec<&hir::SymbolInfo>> = HashMap::new();
+    let mut shadowing = HashSet::new();
+    for (n, symbol) in symbols.symbols.iter().enumerate() {
+        if symbol.kind == SymbolKind::Builtin {
+            continue;
+        }
+        let earlier = seen.entry(&symbol.ident).or_default();
+        let local = matches!(symbol.kind, SymbolKind::Local | SymbolKind::Static);
+        if local
+            && earlier
+                .iter()
+                .any(|other| symbols.encloses(other.scope, symbol.scope))
+        {
+            shadowing.insert(SymbolId(n));
+        }
+        earlier.push(symbol);
+    }
+    shadowing
+}
+
+// Splits a name in the IR into the one in the source and, for a local
+// renamed for shadowing, its SymbolId
+pub fn source_ident(ident: &str) -> (&str, Option<usize>) {
+    match ident
+        .rsplit_once("__")
+        .map(|(source, id)| (source, id.parse()))
+    {
+        Some((source, Ok(id))) if !source.is_empty() => (source, Some(id)),
+        _ => (ident, None),
+    }
+}
+
+pub fn lower(module: &hir::Module, flatten: bool) -> Result<Vec<IRNode>, BuildIRError> {
+    let mut state = LowerState {
+        symbols: &module.symbols,
+        build_stack: vec![],
+        scope_counter: 0,
+        loops: vec![],
+        flatten,
+        shadowing: shadowing(&module.symbols),
+    };
+    state.lower_module(module)?;
+    Ok(state.build_stack)
+}
+
+impl<'a> LowerState<'a> {
+    fn get_new_scope(&mut self) -> usize {
+        let new_scope = self.scope_counter;
+        self.scope_counter += 1;
+        new_scope
+    }
+
+    fn symbol(&self, id: SymbolId) -> crate::symbol::Symbol {
+        new_symbol(self.ident(id))
+    }
+
+    fn ident(&self, id: SymbolId) -> String {
+        let ident = self.symbols.ident(id);
+        match self.shadowing.contains(&id) {
+            true => format!("{}__{}", ident, id.0),
+            false => ident.to_string(),
+        }
+    }
+
+    fn lower_module(&mut self, module: &hir::Module) -> Result<(), BuildIRError> {
+        self.build_stack.push(IRNode::GlobalSection);
+        // Backends declare the functions at the top level up front
+        for stmt in module.globals.iter() {
+            // The initializers of globals must stay constant expressions
+            let flatten = self.flatten && matches!(stmt, hir::Stmt::FuncDef(_));
+            let outer = std::mem::replace(&mut self.flatten, flatten);
+            self.lower_stmt(stmt)?;
+            self.flatten = outer;
+        }
+        self.build_stack.push(IRNode::EndGlobalSection);
+        if let Some(program) = &module.program {
+            self.build_stack.push(IRNode::Program);
+            self.lower_block(&program.block)?;
+        }
+        Ok(())
+    }
+
+    fn lower_block(&mut self, block: &hir::Block) -> Result<(), BuildIRError> {
+        for stmt in block {
+            if let hir::Stmt::FuncDef(func) = stmt {
+                if !func.external {
+                    let def = self.func_def(func);
+                    self.build_stack.push(IRNode::FuncDecl(def));
+                }
+            }
+        }
+        for stmt in block {
+            self.lower_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn lower_stmt(&mut self, stmt: &hir::Stmt) -> Result<(), BuildIRError> {
+        match stmt {
+            hir::Stmt::Let(id, expr) => {
+                self.lower_expr(expr)?;
+                let symbol = self.symbols.get(*id);
+                let assign = ir::Assign {
+                    type_t: symbol.type_t.clone(),
+                    symbol: self.symbol(*id),
+                };
+                self.build_stack.push(match symbol.kind {
+                    SymbolKind::Static => IRNode::StaticAssign(assign),
+                    _ => IRNode::Assign(assign),
+                });
+            }
+            hir::Stmt::Assign(id, expr, _) => {
+                self.lower_expr(expr)?;
+                self.build_stack.push(IRNode::Reassign(ir::Reassign {
+                    type_t: self.symbols.get(*id).type_t.clone(),
+                    symbol: self.symbol(*id),
+                }));
+            }
+            hir::Stmt::MultiAssign(pairs) => self.lower_multi_assign(pairs)?,
+            hir::Stmt::Declare(id) => {
+                self.build_stack.push(IRNode::Declare(ir::Assign {
+                    type_t: self.symbols.get(*id).type_t.clone(),
+                    symbol: self.symbol(*id),
+                }));
+            }
+            hir::Stmt::Store(id, index, op, value, _) => {
+                self.lower_store(*id, index, *op, value)?
+            }
+            hir::Stmt::If(cases) => self.lower_if_cases(cases)?,
+            hir::Stmt::Switch(scrutinee, cases) => self.lower_switch(scrutinee, cases)?,
+            hir::Stmt::Repeat(block, condition) => {
+                let repeat_ir_id = format!("_repeat_stmt_{}", self.get_new_scope());
+                self.build_stack.push(IRNode::Repeat(repeat_ir_id.clone()));
+                self.loops.push(repeat_ir_id.clone());
+                self.lower_block(block)?;
+                self.loops.pop();
+                if self.needs_temporaries(condition) {
+                    // The temporaries can't go in the condition of the
+                    // loop, so test it at the end of the block instead
+                    let if_ir_id = format!("_if_stmt_{}", self.get_new_scope());
+                    self.build_stack.push(IRNode::If(if_ir_id.clone()));
+                    self.lower_expr(condition)?;
+                    self.build_stack.push(IRNode::IfCase(if_ir_id.clone()));
+                    self.build_stack.push(IRNode::Break(repeat_ir_id.clone()));
+                    self.build_stack.push(IRNode::EndIf(if_ir_id));
+                    self.build_stack.push(IRNode::Term(ir::Term {
+                        type_t: condition.type_t.clone(),
+                        value: ir::Value::Bool(false),
+                    }));
+                } else {
+                    self.lower_expr(condition)?;
+                }
+                self.build_stack.push(IRNode::Until(repeat_ir_id));
+            }
+            hir::Stmt::While(condition, block, _) => {
+                let while_ir_id = format!("_while_stmt_{}", self.get_new_scope());
+                if self.needs_temporaries(condition) {
+                    // The temporaries can't go in the condition of the
+                    // loop, so test it at the start of the block instead
+                    self.build_stack.push(IRNode::Term(ir::Term {
+                        type_t: Type::Bool,
+                        value: ir::Value::Bool(true),
+                    }));
+                    self.build_stack.push(IRNode::While(while_ir_id.clone()));
+                    let if_ir_id = format!("_if_stmt_{}", self.get_new_scope());
+                    self.build_stack.push(IRNode::If(if_ir_id.clone()));
+                    self.lower_expr(condition)?;
+                    self.build_stack.push(IRNode::IfCase(if_ir_id.clone()));
+                    self.build_stack.push(IRNode::ElseCase(if_ir_id.clone()));
+                    self.build_stack.push(IRNode::Break(while_ir_id.clone()));
+                    self.build_stack.push(IRNode::EndIf(if_ir_id));
+                } else {
+                    self.lower_expr(condition)?;
+                    self.build_stack.push(IRNode::While(while_ir_id.clone()));
+                }
+                self.loops.push(while_ir_id.clone());
+                self.lower_block(block)?;
+                self.loops.pop();
+                self.build_stack.push(IRNode::EndWhile(while_ir_id));
+            }
+            hir::Stmt::Break(depth) => {
+                let loop_ir_id = self.loops[self.loops.len() - 1 - depth].clone();
+                self.build_stack.push(IRNode::Break(loop_ir_id));
+            }
+            hir::Stmt::Expr(expr) => {
+                self.lower_expr(expr)?;
+                self.build_stack.push(IRNode::Discard);
+            }
+            hir::Stmt::FuncDef(func) => self.lower_func(func)?,
+            hir::Stmt::Return(expr) => {
+                self.lower_expr(expr)?;
+                self.build_stack.push(IRNode::Return);
+            }
+            hir::Stmt::Loc(span) => self.build_stack.push(IRNode::Loc(span.start)),
+        }
+        Ok(())
+    }
+
+    // Each value is stored in a temporary first so that targets assigned
+    // early can still be read by the values that follow, as in a swap
+    fn lower_multi_assign(&mut self, pairs: &[(SymbolId, hir::Expr)]) -> Result<(), BuildIRError> {
+        let multi_ir_id = format!("_multi_assign_{}", self.get_new_scope());
+        for (n, (id, expr)) in pairs.iter().enumerate() {
+            self.lower_expr(expr)?;
+            self.build_stack.push(IRNode::Assign(ir::Assign {
+                type_t: self.symbols.get(*id).type_t.clone(),
+                symbol: new_symbol(format!("{}_{}", multi_ir_id, n)),
+            }));
+        }
+        for (n, (id, _)) in pairs.iter().enumerate() {
+            let type_t = self.symbols.get(*id).type_t.clone();
+            self.build_stack.push(IRNode::Term(ir::Term {
+                type_t: type_t.clone(),
+                value: ir::Value::Id(format!("{}_{}", multi_ir_id, n)),
+            }));
+            self.build_stack.push(IRNode::Reassign(ir::Reassign {
+                type_t,
+                symbol: self.symbol(*id),
+            }));
+        }
+        Ok(())
+    }
+
+    // A compound assignment reads the element it writes, so an index with
+    // effects is stored in a temporary first to evaluate it only once
+    fn lower_store(
+        &mut self,
+        id: SymbolId,
+        index: &hir::Expr,
+        op: Option<BinOp>,
+        value: &hir::Expr,
+    ) -> Result<(), BuildIRError> {
+        let mut index = index.clone();
+        if op.is_some() && Self::calls(&index) {
+            let temp = format!("_index_{}", self.get_new_scope());
+            self.lower_expr(&index)?;
+            self.build_stack.push(IRNode::Assign(ir::Assign {
+                type_t: index.type_t.clone(),
+                symbol: new_symbol(temp.clone()),
+            }));
+            index.kind = ExprKind::Literal(ir::Value::Id(temp));
+        }
+        let array_t = self.symbols.get(id).type_t.clone();
+        let Type::Array(element_t, _) = &array_t else {
+            unreachable!("only arrays are indexed, see semantic::hir_index")
+        };
+        let element_t = *element_t.clone();
+        let value = match op {
+            Some(op) => hir::Expr {
+                type_t: element_t.clone(),
+                kind: ExprKind::Binary(
+                    op,
+                    Box::new(hir::Expr {
+                        type_t: element_t,
+                        kind: ExprKind::Index(id, Box::new(index.clone())),
+                    }),
+                    Box::new(value.clone()),
+                ),
+            },
+            None => value.clone(),
+        };
+        let mut operands = self.sequence_operands(&[&index, &value])?.into_iter();
+        self.push_expr(&operands.next().unwrap())?;
+        self.push_expr(&operands.next().unwrap())?;
+        self.build_stack.push(IRNode::Store(ir::Access {
+            type_t: array_t,
+            symbol: self.symbol(id),
+        }));
+        Ok(())
+    }
+
+    fn lower_if_cases(&mut self, cases: &[hir::IfCase]) -> Result<(), BuildIRError> {
+        let if_ir_id = format!("_if_stmt_{}", self.get_new_scope());
+        self.build_stack.push(IRNode::If(if_ir_id.clone()));
+        for (n, case) in cases.iter().enumerate() {
+            match &case.condition {
+                // The temporaries for an `else if` condition can't go
+                // between the cases, so the rest of the chain is nested
+                // in an `else`
+                Some(condition) if n > 0 && self.needs_temporaries(condition) => {
+                    self.build_stack.push(IRNode::ElseCase(if_ir_id.clone()));
+                    self.lower_if_cases(&cases[n..])?;
+                    break;
+                }
+                Some(condition) => {
+                    self.lower_expr(condition)?;
+                    if n == 0 {
+                        self.build_stack.push(IRNode::IfCase(if_ir_id.clone()));
+                    } else {
+                        self.build_stack.push(IRNode::ElseIfCase(if_ir_id.clone()));
+                    }
+                }
+                None => self.build_stack.push(IRNode::ElseCase(if_ir_id.clone())),
+            }
+            self.lower_block(&case.block)?;
+        }
+        self.build_stack.push(IRNode::EndIf(if_ir_id));
+        Ok(())
+    }
+
+    fn lower_switch(
+        &mut self,
+        scrutinee: &hir::Expr,
+        cases: &[hir::SwitchCase],
+    ) -> Result<(), BuildIRError> {
+        let switch_ir_id = format!("_switch_stmt_{}", self.get_new_scope());
+        self.lower_expr(scrutinee)?;
+        self.build_stack.push(IRNode::Switch(switch_ir_id.clone()));
+        for case in cases {
+            self.build_stack.push(match &case.labels {
+                Some(labels) => IRNode::SwitchCase(switch_ir_id.clone(), labels.clone()),
+                None => IRNode::DefaultCase(switch_ir_id.clone()),
+            });
+            self.lower_block(&case.block)?;
+        }
+        self.build_stack.push(IRNode::EndSwitch(switch_ir_id));
+        Ok(())
+    }
+
+    fn func_def(&self, func: &hir::Func) -> ir::FuncDef {
+        ir::FuncDef {
+            symbol: self.symbol(func.symbol),
+            return_t: func.return_t.clone(),
+            params_t: func
+                .params
+                .iter()
+                .map(|p| {
+                    let param = self.symbols.get(*p);
+                    (param.ident.clone(), param.type_t.clone())
+                })
+                .collect(),
+        }
+    }
+
+    fn lower_func(&mut self, func: &hir::Func) -> Result<(), BuildIRError> {
+        let def = self.func_def(func);
+        if let Some(ident) = &func.intrinsic {
+            self.build_stack.push(IRNode::Intrinsic(def, ident.clone()));
+            return Ok(());
+        }
+        if func.external {
+            self.build_stack.push(IRNode::ExternFuncDef(def));
+            return Ok(());
+        }
+        let func_ir_id = format!("_func_def_{}", self.get_new_scope());
+        self.build_stack
+            .push(IRNode::FuncDef(def, func_ir_id.clone()));
+        self.lower_block(&func.block)?;
+        self.build_stack.push(IRNode::EndFuncDef(func_ir_id));
+        Ok(())
+    }
+
+    // Call arguments and the operands of an operator are evaluated left
+    // to right. C leaves their order unspecified, so any operand whose
+    // value could depend on that order is first stored in a temporary,
+    // ahead of the rest of the expression.
+    fn lower_expr(&mut self, expr: &hir::Expr) -> Result<(), BuildIRError> {
+        let expr = self.sequence(expr)?;
+        self.push_expr(&expr)
+    }
+
+    // Only calls have effects, and those can only reach globals and
+    // statics: locals and parameters read the same in any order. Logical
+    // operators are evaluated ahead of the expression they're in, just
+    // like a call that needs a temporary.
+    fn calls(expr: &hir::Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Call(..) | ExprKind::Logical(..) => true,
+            ExprKind::Binary(_, l, r) => Self::calls(l) || Self::calls(r),
+            ExprKind::Unary(_, u) | ExprKind::Cast(u) | ExprKind::Index(_, u) => Self::calls(u),
+            ExprKind::Literal(_) | ExprKind::Var(_) | ExprKind::Lambda(_) => false,
+        }
+    }
+
+    fn order_sensitive(&self, expr: &hir::Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Var(id) => matches!(
+                self.symbols.get(*id).kind,
+                SymbolKind::Global | SymbolKind::Static
+            ),
+            ExprKind::Binary(_, l, r) => self.order_sensitive(l) || self.order_sensitive(r),
+            ExprKind::Unary(_, u) | ExprKind::Cast(u) => self.order_sensitive(u),
+            ExprKind::Index(id, u) => {
+                matches!(
+                    self.symbols.get(*id).kind,
+                    SymbolKind::Global | SymbolKind::Static
+                ) || self.order_sensitive(u)
+            }
+            ExprKind::Call(..) | ExprKind::Logical(..) => true,
+            ExprKind::Literal(_) | ExprKind::Lambda(_) => false,
+        }
+    }
+
+    // Which of the operands go in temporaries: those before the last one
+    // with a call that are sensitive to order, and that last one as well
+    // if an operand after it is. When flattening, every operand that
+    // computes a value does.
+    fn hoisted(&self, operands: &[&hir::Expr]) -> Vec<bool> {
+        let last_call = operands.iter().rposition(|operand| Self::calls(operand));
+        operands
+            .iter()
+            .enumerate()
+            .map(|(n, operand)| match last_call {
+                _ if self.flatten && Self::computed(operand) => true,
+                Some(last) if n < last => self.order_sensitive(operand),
+                Some(last) if n == last => operands[n + 1..]
+                    .iter()
+                    .any(|after| self.order_sensitive(after)),
+                _ => false,
+            })
+            .collect()
+    }
+
+    // Whether the expression is an operation with a value that can be
+    // stored, rather than a literal or a variable
+    fn computed(expr: &hir::Expr) -> bool {
+        matches!(
+            expr.kind,
+            ExprKind::Binary(..) | ExprKind::Unary(..) | ExprKind::Call(..) | ExprKind::Cast(..)
+        ) && !matches!(expr.type_t, Type::Nil | Type::Never)
+    }
+
+    // Whether lowering the expression stores anything in temporaries
+    fn needs_temporaries(&self, expr: &hir::Expr) -> bool {
+        let operands: Vec<&hir::Expr> = match &expr.kind {
+            ExprKind::Logical(..) => return true,
+            ExprKind::Binary(_, l, r) => vec![l, r],
+            ExprKind::Call(_, args) => args.iter().collect(),
+            ExprKind::Unary(_, u) | ExprKind::Cast(u) | ExprKind::Index(_, u) => vec![u],
+            ExprKind::Literal(_) | ExprKind::Var(_) | ExprKind::Lambda(_) => vec![],
+        };
+        self.hoisted(&operands).into_iter().any(|h| h)
+            || operands
+                .iter()
+                .any(|operand| self.needs_temporaries(operand))
+    }
+
+    // Lowers the operands that need it into temporaries, in order, and
+    // returns the expression with them replaced by the temporaries
+    fn sequence(&mut self, expr: &hir::Expr) -> Result<hir::Expr, BuildIRError> {
+        let kind = match &expr.kind {
+            ExprKind::Binary(op, l, r) => {
+                let mut operands = self.sequence_operands(&[l, r])?.into_iter();
+                let (l, r) = (operands.next().unwrap(), operands.next().unwrap());
+                ExprKind::Binary(*op, Box::new(l), Box::new(r))
+            }
+            ExprKind::Call(id, args) => {
+                let args: Vec<&hir::Expr> = args.iter().collect();
+                ExprKind::Call(*id, self.sequence_operands(&args)?)
+            }
+            ExprKind::Unary(op, u) => {
+                let u = self.sequence_operands(&[u])?.remove(0);
+                ExprKind::Unary(*op, Box::new(u))
+            }
+            ExprKind::Cast(u) => {
+                let u = self.sequence_operands(&[u])?.remove(0);
+                ExprKind::Cast(Box::new(u))
+            }
+            ExprKind::Index(id, u) => {
+                let u = self.sequence_operands(&[u])?.remove(0);
+                ExprKind::Index(*id, Box::new(u))
+            }
+            ExprKind::Logical(op, l, r) => self.lower_logical(*op, l, r)?,
+            other => other.clone(),
+        };
+        Ok(hir::Expr {
+            type_t: expr.type_t.clone(),
+            kind,
+        })
+    }
+
+    fn sequence_operands(
+        &mut self,
+        operands: &[&hir::Expr],
+    ) -> Result<Vec<hir::Expr>, BuildIRError> {
+        let hoisted = self.hoisted(operands);
+        let mut sequenced = vec![];
+        for (operand, hoist) in operands.iter().zip(hoisted) {
+            let operand = self.sequence(operand)?;
+            if hoist {
+                let temp = format!("_eval_{}", self.get_new_scope());
+                self.push_expr(&operand)?;
+                self.build_stack.push(IRNode::Assign(ir::Assign {
+                    type_t: operand.type_t.clone(),
+                    symbol: new_symbol(temp.clone()),
+                }));
+                sequenced.push(hir::Expr {
+                    type_t: operand.type_t,
+                    kind: ExprKind::Literal(ir::Value::Id(temp)),
+                });
+            } else {
+                sequenced.push(operand);
+            }
+        }
+        Ok(sequenced)
+    }
+
+    // The result goes in a temporary, which the right operand is only
+    // evaluated to overwrite when the left one doesn't decide it:
+    //
+    //   _logical = l; if (_logical) { _logical = r; }
+    //
+    // with the test negated for `or`
+    fn lower_logical(
+        &mut self,
+        op: LogicalOp,
+        l: &hir::Expr,
+        r: &hir::Expr,
+    ) -> Result<ExprKind, BuildIRError> {
+        let temp = format!("_logical_{}", self.get_new_scope());
+        let l = self.sequence(l)?;
+        self.push_expr(&l)?;
+        self.build_stack.push(IRNode::Assign(ir::Assign {
+            type_t: Type::Bool,
+            symbol: new_symbol(temp.clone()),
+        }));
+        let if_ir_id = format!("_if_stmt_{}", self.get_new_scope());
+        self.build_stack.push(IRNode::If(if_ir_id.clone()));
+        self.build_stack.push(IRNode::Term(ir::Term {
+            type_t: Type::Bool,
+            value: ir::Value::Id(temp.clone()),
+        }));
+        if op == LogicalOp::Or {
+            self.build_stack
+                .push(IRNode::Eval(ir::Func::Not(ir::new_sig(
+                    "Not",
+                    vec![Type::Bool],
+                    Type::Bool,
+                ))));
+        }
+        self.build_stack.push(IRNode::IfCase(if_ir_id.clone()));
+        let r = self.sequence(r)?;
+        self.push_expr(&r)?;
+        self.build_stack.push(IRNode::Reassign(ir::Reassign {
+            type_t: Type::Bool,
+            symbol: new_symbol(temp.clone()),
+        }));
+        self.build_stack.push(IRNode::EndIf(if_ir_id));
+        Ok(ExprKind::Literal(ir::Value::Id(temp)))
+    }
+
+    fn push_expr(&mut self, expr: &hir::Expr) -> Result<(), BuildIRError> {
+        match &expr.kind {
+            ExprKind::Literal(value) => {
+                self.build_stack.push(IRNode::Term(ir::Term {
+                    type_t: expr.type_t.clone(),
+                    value: value.clone(),
+                }));
+            }
+            ExprKind::Var(id) => {
+                self.build_stack.push(IRNode::Term(ir::Term {
+                    type_t: expr.type_t.clone(),
+                    value: ir::Value::Id(self.ident(*id)),
+                }));
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                self.push_expr(lhs)?;
+                self.push_expr(rhs)?;
+                let params_t = vec![lhs.type_t.clone(), rhs.type_t.clone()];
+                let return_t = expr.type_t.clone();
+                let func = match op {
+                    BinOp::Add => ir::Func::Add(ir::new_sig("Add", params_t, return_t)),
+                    BinOp::Sub => ir::Func::Sub(ir::new_sig("Sub", params_t, return_t)),
+                    BinOp::Mult => ir::Func::Mult(ir::new_sig("Mult", params_t, return_t)),
+                    BinOp::Div => ir::Func::Div(ir::new_sig("Div", params_t, return_t)),
+                    BinOp::Mod => ir::Func::Mod(ir::new_sig("Mod", params_t, return_t)),
+                    BinOp::Pow => ir::Func::Pow(ir::new_sig("Pow", params_t, return_t)),
+                    BinOp::BitAnd => ir::Func::BitAnd(ir::new_sig("BitAnd", params_t, return_t)),
+                    BinOp::BitOr => ir::Func::BitOr(ir::new_sig("BitOr", params_t, return_t)),
+                    BinOp::BitXor => ir::Func::BitXor(ir::new_sig("BitXor", params_t, return_t)),
+                    BinOp::Shl => ir::Func::Shl(ir::new_sig("Shl", params_t, return_t)),
+                    BinOp::Shr => ir::Func::Shr(ir::new_sig("Shr", params_t, return_t)),
+                    BinOp::Eq => ir::Func::Eq(ir::new_sig("Eq", params_t, return_t)),
+                    BinOp::Neq => ir::Func::Neq(ir::new_sig("Neq", params_t, return_t)),
+                    BinOp::Leq => ir::Func::Leq(ir::new_sig("Leq", params_t, return_t)),
+                    BinOp::Geq => ir::Func::Geq(ir::new_sig("Geq", params_t, return_t)),
+                    BinOp::Lt => ir::Func::Lt(ir::new_sig("Lt", params_t, return_t)),
+                    BinOp::Gt => ir::Func::Gt(ir::new_sig("Gt", params_t, return_t)),
+                };
+                self.build_stack.push(IRNode::Eval(func));
+            }
+            ExprKind::Unary(op, u) => {
+                self.push_expr(u)?;
+                let params_t = vec![u.type_t.clone()];
+                let return_t = expr.type_t.clone();
+                let func = match op {
+                    UnOp::Not => ir::Func::Not(ir::new_sig("Not", params_t, return_t)),
+                    UnOp::Neg => ir::Func::Neg(ir::new_sig("Neg", params_t, return_t)),
+                    UnOp::BitNot => ir::Func::BitNot(ir::new_sig("BitNot", params_t, return_t)),
+                };
+                self.build_stack.push(IRNode::Eval(func));
+            }
+            ExprKind::Call(id, args) => {
+                for arg in args {
+                    self.push_expr(arg)?;
+                }
+                self.build_stack
+                    .push(IRNode::Eval(ir::Func::Func(ir::Signature {
+                        symbol: self.symbol(*id),
+                        params_t: args.iter().map(|a| a.type_t.clone()).collect(),
+                        return_t: expr.type_t.clone(),
+                    })));
+            }
+            ExprKind::Cast(u) => {
+                self.push_expr(u)?;
+                self.build_stack
+                    .push(IRNode::Eval(ir::Func::Cast(ir::new_sig(
+                        "Cast",
+                        vec![u.type_t.clone()],
+                        expr.type_t.clone(),
+                    ))));
+            }
+            ExprKind::Index(id, index) => {
+                self.push_expr(index)?;
+                self.build_stack.push(IRNode::Load(ir::Access {
+                    type_t: self.symbols.get(*id).type_t.clone(),
+                    symbol: self.symbol(*id),
+                }));
+            }
+            ExprKind::Lambda(_) => {
+                return Err(BuildIRError::Unsupported("Lambda functions".into()));
+            }
+            ExprKind::Logical(..) => unreachable!("logical operators are lowered by sequence"),
+        }
+        Ok(())
+    }
+}
@@ -1,37 +1,44 @@
 use std::{
+    env,
     fs::{self, File},
-    io::Write,
+    io::{self, IsTerminal, Write},
+    panic,
     path::Path,
+    process::{self, ExitCode},
+    sync::Mutex,
 };
 
 use clap::{Parser, ValueEnum};
-use lalrpop_util::lalrpop_mod;
 use thiserror::Error;
 
-pub mod ast;
-pub mod backends;
-pub mod codegen;
-pub mod infer;
-pub mod ir;
-pub mod semantic;
-pub mod symbol;
-pub mod traverse;
-pub mod types;
-
-use backends::{c::CGenContext, wasm::WasmGenContext};
-use codegen::CodeGen;
-use semantic::ProgramState;
+use rascalc::ast::{Span, Stmt};
+use rascalc::backends::{c::CGenContext, wasm::WasmGenContext};
+use rascalc::codegen::{self, CodeGen, CodeGenError};
+use rascalc::diagnostics::{self, Diagnostic, Report, ISSUES_URL};
+use rascalc::hir::{SymbolKind, Symbols};
+use rascalc::ir::{self, IRNode};
+use rascalc::lexer::{self, Lexer};
+use rascalc::metrics::{self, FunctionMetrics};
+use rascalc::semantic::{self, ProgramState};
+use rascalc::session::{Edition, Session};
+use rascalc::types::TypeMap;
+use rascalc::{cache, debugger, infer, ircheck, link, parser, selftest};
 
 /// Compiler for the Rascal language
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Input Rascal source file
-    infile: String,
+    /// Input Rascal source files, and objects to link with them
+    #[arg(required = true)]
+    infiles: Vec<String>,
 
-    /// Name of output binary
-    #[arg(short = 'o', long = "outfile", default_value = "a.out")]
-    outfile: String,
+    /// Name of output binary, or of the object when compiling one unit with -c
+    #[arg(short = 'o', long = "outfile")]
+    outfile: Option<String>,
+
+    /// Compile each unit to an object, named after it, without linking
+    #[arg(short = 'c', default_value = "false")]
+    compile_only: bool,
 
     /// Skip the WASM Validation after codegen
     #[arg(long = "skip-validation", default_value = "false")]
@@ -41,73 +48,580 @@ struct Args {
     #[arg(short = 'b', long = "backend", value_enum, default_value_t = BackendArgs::C)]
     backend: BackendArgs,
 
-    // Emit: options will be any or both of ir, or C for dumping intermediate reps to file
+    /// Disable implicit numeric widening (e.g. int32 to int64)
+    #[arg(long = "strict-numerics", default_value = "false")]
+    strict_numerics: bool,
+
+    /// Language edition the program is written for
+    #[arg(long = "edition", value_enum, default_value_t = EditionArgs::E2024)]
+    edition: EditionArgs,
+
+    /// Reject what's only accepted for compatibility: warnings become errors, numbers aren't widened and conditions must be bools
+    #[arg(long = "strict", default_value = "false")]
+    strict: bool,
+
+    /// Generate code that's easier to read and debug: every intermediate value is stored in a named temporary, and runtime errors print the Rascal calls they happened in
+    #[arg(long = "debug", default_value = "false")]
+    debug: bool,
+
+    /// How much to optimize the IR: 0 not at all, 1 and 2 propagate constant globals and turn tail calls into loops, and 3 also unrolls loops with small constant trip counts
+    #[arg(short = 'O', default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=3))]
+    opt_level: u8,
+
+    /// Let gcc optimize float arithmetic as if NaNs and infinities never occurred, giving up IEEE semantics
+    #[arg(long = "fast-math", default_value = "false")]
+    fast_math: bool,
+
+    /// How deeply statements and expressions may nest [default: 256]
+    #[arg(long = "nesting-limit")]
+    nesting_limit: Option<usize>,
+
+    /// Print each statement and expression semantic analysis reaches, as a tree with the types it resolved, to stderr
+    #[arg(long = "debug-semantic", default_value = "false")]
+    debug_semantic: bool,
+
+    /// C compiler to build and link with
+    #[arg(long = "cc", default_value = "gcc")]
+    cc: String,
+
+    /// Always run gcc, rather than reusing what it built from the same C before
+    #[arg(long = "no-cache", default_value = "false")]
+    no_cache: bool,
+
+    /// How to print diagnostics: rendered for people, or as JSON for tools, a report a line
+    #[arg(long = "error-format", value_enum, default_value_t = ErrorFormatArgs::Human)]
+    error_format: ErrorFormatArgs,
+
+    /// When to color the IR printed by --emit ir: auto colors it for a terminal unless NO_COLOR is set
+    #[arg(long = "color", value_enum, default_value_t = ColorArgs::Auto)]
+    color: ColorArgs,
+
+    /// Apply the fixes suggested by diagnostics to the input file instead of building it, as `rascalc fix` does
+    #[arg(long = "fix", default_value = "false", hide = true)]
+    fix: bool,
+
+    // Emit: options will be any of tokens, types, symbols, symtab, ir, or C for dumping intermediate reps to file, or metrics for the size of each function. The IR is also printed in a readable form.
     #[arg(short = 'e', long = "emit", value_parser, value_delimiter = ',')]
     emit: Option<Vec<EmitArgs>>,
 }
 
+/// Run a Rascal program in the interpreter, pausing at breakpoints
+#[derive(Parser, Debug)]
+#[command(name = "rascalc debug")]
+struct DebugArgs {
+    /// Input Rascal source file
+    infile: String,
+
+    /// Disable implicit numeric widening (e.g. int32 to int64)
+    #[arg(long = "strict-numerics", default_value = "false")]
+    strict_numerics: bool,
+
+    /// Language edition the program is written for
+    #[arg(long = "edition", value_enum, default_value_t = EditionArgs::E2024)]
+    edition: EditionArgs,
+}
+
+/// Rewrite Rascal source files with the fixes suggested by their diagnostics applied
+#[derive(Parser, Debug)]
+#[command(name = "rascalc fix")]
+struct FixArgs {
+    /// Input Rascal source files, checked together as a build of them would be
+    #[arg(required = true)]
+    infiles: Vec<String>,
+
+    /// Disable implicit numeric widening (e.g. int32 to int64)
+    #[arg(long = "strict-numerics", default_value = "false")]
+    strict_numerics: bool,
+
+    /// Language edition the program is written for
+    #[arg(long = "edition", value_enum, default_value_t = EditionArgs::E2024)]
+    edition: EditionArgs,
+}
+
+impl FixArgs {
+    // Fixing runs the front end as a build of the same files does, and
+    // stops before code generation
+    fn into_build(self) -> Args {
+        let argv = ["rascalc", "--"].into_iter().map(String::from);
+        let mut args = Args::parse_from(argv.chain(self.infiles));
+        args.fix = true;
+        args.strict_numerics = self.strict_numerics;
+        args.edition = self.edition;
+        args
+    }
+}
+
+/// Run Rascal programs in the interpreter and as the checked C binary, and check that they end the same way and print the same output
+#[derive(Parser, Debug)]
+#[command(name = "rascalc selftest")]
+struct SelftestArgs {
+    /// Input Rascal source files [default: samples/*.ras]
+    infiles: Vec<String>,
+
+    /// Disable implicit numeric widening (e.g. int32 to int64)
+    #[arg(long = "strict-numerics", default_value = "false")]
+    strict_numerics: bool,
+
+    /// Language edition the programs are written for
+    #[arg(long = "edition", value_enum, default_value_t = EditionArgs::E2024)]
+    edition: EditionArgs,
+
+    /// C compiler to build with
+    #[arg(long = "cc", default_value = "gcc")]
+    cc: String,
+}
+
 #[derive(Clone, Debug, ValueEnum)]
 enum BackendArgs {
     C,
     WASM,
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ColorArgs {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum ErrorFormatArgs {
+    Human,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum EditionArgs {
+    #[value(name = "2024")]
+    E2024,
+    #[value(name = "2025")]
+    E2025,
+}
+
+impl Args {
+    fn session(&self) -> Session {
+        Session {
+            edition: match self.edition {
+                EditionArgs::E2024 => Edition::E2024,
+                EditionArgs::E2025 => Edition::E2025,
+            },
+            strict: self.strict,
+            strict_numerics: self.strict_numerics,
+            nesting_limit: self.nesting_limit,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, ValueEnum)]
 enum EmitArgs {
+    Tokens,
+    Types,
+    Symbols,
+    Symtab,
     Ir,
     C,
+    Metrics,
 }
 
-lalrpop_mod!(pub rascal);
-
 #[derive(Error, Debug)]
 pub enum BuildError {
     #[error("There was an issue with the input file: {0}")]
     Input(String),
     #[error("There was a problem creating the output: {0}")]
     Output(String),
+    // A diagnostic, the file it's about if it's about one, and its
+    // rendering
+    #[error("{2}")]
+    Diagnostic(Box<Diagnostic>, Option<String>, String),
+}
+
+// Where the source and flags of a build that panicked are saved
+const ICE_REPORT_FILENAME: &str = "rascalc-ice.txt";
+
+// The pass the build is in, reported if the compiler panics
+static PASS: Mutex<&str> = Mutex::new("startup");
+
+fn enter_pass(pass: &'static str) {
+    *PASS.lock().unwrap_or_else(|err| err.into_inner()) = pass;
+}
+
+// Replaces the default panic message with one saying that the compiler
+// has a bug, and saves what's needed to reproduce it
+fn report_internal_errors(src_file: String) {
+    panic::set_hook(Box::new(move |info| {
+        let pass = *PASS.lock().unwrap_or_else(|err| err.into_inner());
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".into());
+        let flags: Vec<String> = env::args().collect();
+        let report = format!(
+            "rascalc {}\n{}\n\n{}",
+            env!("CARGO_PKG_VERSION"),
+            flags.join(" "),
+            src_file
+        );
+        eprintln!("error: internal compiler error: {}", message);
+        eprintln!(
+            " = note: rascalc {} panicked during {}{}",
+            env!("CARGO_PKG_VERSION"),
+            pass,
+            info.location()
+                .map(|l| format!(" at {}:{}", l.file(), l.line()))
+                .unwrap_or_default()
+        );
+        if fs::write(ICE_REPORT_FILENAME, report).is_ok() {
+            eprintln!(
+                " = note: the source and flags to reproduce it were saved to {}",
+                ICE_REPORT_FILENAME
+            );
+        }
+        eprintln!(
+            " = help: this is a bug in rascalc, please file an issue at {}",
+            ISSUES_URL
+        );
+    }));
+}
+
+fn main() -> ExitCode {
+    // `rascalc debug foo.ras` debugs rather than builds, `rascalc selftest`
+    // runs programs under both backends to compare them, and `rascalc fix
+    // foo.ras` rewrites foo.ras with the fixes suggested for it applied
+    let subcommand = || {
+        env::args()
+            .enumerate()
+            .filter(|(n, _)| *n != 1)
+            .map(|(_, arg)| arg)
+    };
+    let args = match env::args().nth(1).as_deref() {
+        Some("debug") => return debug(DebugArgs::parse_from(subcommand())),
+        Some("selftest") => return selftest(SelftestArgs::parse_from(subcommand())),
+        Some("fix") => FixArgs::parse_from(subcommand()).into_build(),
+        _ => Args::parse(),
+    };
+    let error_format = args.error_format;
+    match build(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if error_format == ErrorFormatArgs::Json => {
+            let report = match err {
+                BuildError::Diagnostic(diagnostic, file, _) => {
+                    Report::new(file.as_deref(), vec![*diagnostic])
+                }
+                err => Report::new(None, vec![Diagnostic::error(err.to_string())]),
+            };
+            eprintln!("{}", report.to_json());
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprint!("{}", err);
+            if !matches!(err, BuildError::Diagnostic(..)) {
+                eprintln!();
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn debug(args: DebugArgs) -> ExitCode {
+    let src_file = match fs::read_to_string(&args.infile) {
+        Ok(src_file) => src_file,
+        Err(err) => {
+            eprintln!("{}", BuildError::Input(err.to_string()));
+            return ExitCode::FAILURE;
+        }
+    };
+    let session = Session {
+        edition: match args.edition {
+            EditionArgs::E2024 => Edition::E2024,
+            EditionArgs::E2025 => Edition::E2025,
+        },
+        strict: false,
+        strict_numerics: args.strict_numerics,
+        nesting_limit: None,
+    };
+    println!("Debugging {}, type help for the commands", args.infile);
+    let stdin = std::io::stdin().lock();
+    match debugger::debug(&src_file, &session, stdin, std::io::stdout()) {
+        Ok(Ok(code)) => ExitCode::from(code as u8),
+        Ok(Err(_)) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn selftest(args: SelftestArgs) -> ExitCode {
+    let infiles = if args.infiles.is_empty() {
+        let mut samples: Vec<String> = fs::read_dir("samples")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path().to_string_lossy().into_owned())
+            .filter(|path| path.ends_with(".ras"))
+            .collect();
+        samples.sort();
+        samples
+    } else {
+        args.infiles.clone()
+    };
+    let session = Session {
+        edition: match args.edition {
+            EditionArgs::E2024 => Edition::E2024,
+            EditionArgs::E2025 => Edition::E2025,
+        },
+        strict: false,
+        strict_numerics: args.strict_numerics,
+        nesting_limit: None,
+    };
+    let mut failed = 0;
+    for infile in infiles.iter() {
+        let src = match fs::read_to_string(infile) {
+            Ok(src) => src,
+            Err(err) => {
+                failed += 1;
+                println!("FAIL  {}: {}", infile, BuildError::Input(err.to_string()));
+                continue;
+            }
+        };
+        // Only the backends are being compared, a program the front end
+        // rejects never reaches them
+        match selftest::check(&src, &session, &args.cc) {
+            Ok(Some(outcome)) => println!("ok    {}: {}", infile, outcome),
+            Ok(None) => println!("skip  {}: no program to run", infile),
+            Err(selftest::SelftestError::Compile(err)) => println!("skip  {}: {}", infile, err),
+            Err(err) => {
+                failed += 1;
+                println!("FAIL  {}: {}", infile, err.to_string().trim_end());
+            }
+        }
+    }
+    println!("{} of {} failed", failed, infiles.len());
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn build(args: Args) -> Result<(), BuildError> {
+    let (objects, units): (Vec<String>, Vec<String>) = args
+        .infiles
+        .iter()
+        .cloned()
+        .partition(|infile| infile.ends_with(".o"));
+    if args.compile_only && !objects.is_empty() {
+        return Err(BuildError::Input(
+            "objects can only be linked, not compiled with -c".into(),
+        ));
+    }
+    if (args.compile_only || args.infiles.len() > 1) && matches!(args.backend, BackendArgs::WASM) {
+        return Err(BuildError::Input(
+            "only the C backend can compile units separately".into(),
+        ));
+    }
+    // Everything but fixing ends with the C compiler, so it's best to know
+    // it's there before starting
+    if matches!(args.backend, BackendArgs::C) && !args.fix {
+        link::find_compiler(&args.cc).map_err(backend_error)?;
+    }
+    let outfile = args.outfile.clone().unwrap_or_else(|| "a.out".into());
+    if units.len() == 1 && objects.is_empty() && !args.compile_only {
+        return build_unit(&args, &units[0], &outfile, false, &[]);
+    }
+
+    // Each unit declares the functions of the others
+    let mut interfaces = vec![];
+    let mut programs = vec![];
+    for unit in units.iter() {
+        if unit.ends_with(".ir") {
+            interfaces.push(vec![]);
+            continue;
+        }
+        let src_file =
+            fs::read_to_string(unit).map_err(|err| BuildError::Input(err.to_string()))?;
+        let root = parser::parse(&src_file).map_err(|err| parse_error(err, unit, &src_file))?;
+        if root.program.is_some() {
+            programs.push(unit.clone());
+        }
+        interfaces.push(link::interface(&root));
+    }
+    if !args.compile_only && !args.fix {
+        match programs.as_slice() {
+            [] if objects.is_empty() && !units.iter().any(|unit| unit.ends_with(".ir")) => {
+                return Err(BuildError::Input(
+                    "none of the units has a program block".into(),
+                ))
+            }
+            [first, second, ..] => {
+                return Err(BuildError::Input(format!(
+                    "{} and {} both have a program block",
+                    first, second
+                )))
+            }
+            _ => {}
+        }
+    }
+
+    let mut linked = objects.clone();
+    let mut temporaries = vec![];
+    for (n, unit) in units.iter().enumerate() {
+        let externs: Vec<_> = interfaces
+            .iter()
+            .enumerate()
+            .filter(|(m, _)| *m != n)
+            .flat_map(|(_, interface)| interface.iter().cloned())
+            .collect();
+        let object = match (&args.outfile, units.len()) {
+            (Some(outfile), 1) if args.compile_only => outfile.clone(),
+            _ if args.compile_only => link::object_path(unit),
+            _ => {
+                let object = env::temp_dir()
+                    .join(format!("rascalc-{}-{}.o", process::id(), n))
+                    .to_string_lossy()
+                    .into_owned();
+                temporaries.push(object.clone());
+                object
+            }
+        };
+        let built = build_unit(&args, unit, &object, true, &externs);
+        if built.is_err() {
+            for temporary in temporaries.iter() {
+                let _ = fs::remove_file(temporary);
+            }
+        }
+        built?;
+        linked.push(object);
+    }
+    if args.compile_only || args.fix {
+        return Ok(());
+    }
+    enter_pass("linking");
+    let result = link::link(&args.cc, &linked, &outfile);
+    for temporary in temporaries.iter() {
+        let _ = fs::remove_file(temporary);
+    }
+    result.map_err(backend_error)
 }
-fn main() -> Result<(), BuildError> {
-    let args = Args::parse();
+
+// Builds a single unit, into an executable or with `compile_only` into
+// an object. `externs` declares the functions of the units it's linked
+// with.
+fn build_unit(
+    args: &Args,
+    infile: &str,
+    outfile: &str,
+    compile_only: bool,
+    externs: &[Box<Stmt>],
+) -> Result<(), BuildError> {
     let save_c: bool;
     let save_ir: bool;
-    if let Some(emit) = args.emit {
+    let save_tokens: bool;
+    let save_types: bool;
+    let save_symbols: bool;
+    let save_symtab: bool;
+    let save_metrics: bool;
+    if let Some(emit) = &args.emit {
         save_c = emit.iter().any(|x| matches!(x, EmitArgs::C));
         save_ir = emit.iter().any(|x| matches!(x, EmitArgs::Ir));
+        save_tokens = emit.iter().any(|x| matches!(x, EmitArgs::Tokens));
+        save_types = emit.iter().any(|x| matches!(x, EmitArgs::Types));
+        save_symbols = emit.iter().any(|x| matches!(x, EmitArgs::Symbols));
+        save_symtab = emit.iter().any(|x| matches!(x, EmitArgs::Symtab));
+        save_metrics = emit.iter().any(|x| matches!(x, EmitArgs::Metrics));
     } else {
-        (save_c, save_ir) = (false, false);
+        (
+            save_c,
+            save_ir,
+            save_tokens,
+            save_types,
+            save_symbols,
+            save_symtab,
+            save_metrics,
+        ) = (false, false, false, false, false, false, false);
     }
-    let src_file =
-        fs::read_to_string(&args.infile).map_err(|err| BuildError::Input(err.to_string()))?;
-    let file_extension = Path::new(&args.infile)
+    let src_file = fs::read_to_string(infile).map_err(|err| BuildError::Input(err.to_string()))?;
+    report_internal_errors(src_file.clone());
+    let file_extension = Path::new(infile)
         .extension()
         .ok_or(BuildError::Input("Problem with filename".to_string()))?;
+    if args.fix {
+        return fix(args, infile, &src_file, externs);
+    }
+    let mut analyzed = None;
     let build_stack = if file_extension == "ir" {
-        serde_json::from_str(&src_file).map_err(|err| BuildError::Input(err.to_string()))?
+        let build_stack: Vec<IRNode> =
+            serde_json::from_str(&src_file).map_err(|err| BuildError::Input(err.to_string()))?;
+        ircheck::check(&build_stack).map_err(|err| BuildError::Input(err.to_string()))?;
+        build_stack
     } else {
-        let mut root = rascal::RootParser::new()
-            .parse(&src_file)
-            .map_err(|err| BuildError::Input(err.to_string()))?;
+        if save_tokens {
+            enter_pass("lexing");
+            let tokens =
+                lexer::tokenize(&src_file).map_err(|err| BuildError::Input(err.to_string()))?;
+            let serialized_tokens = serde_json::to_string(&tokens)
+                .map_err(|err| BuildError::Output(err.to_string()))?;
+            let mut file = File::create(Lexer::TOKENS_OUTPUT_FILENAME)
+                .map_err(|err| BuildError::Output(err.to_string()))?;
+            write!(&mut file, "{serialized_tokens}")
+                .map_err(|err| BuildError::Output(err.to_string()))?;
+        }
 
-        let mut typing_state = infer::TypingState::new();
-        typing_state
-            .augment(&mut root)
-            .map_err(|err| BuildError::Output(err.to_string()))?;
-        let mut infer_state = infer::InferState::new();
-        infer_state
-            .constrain(&mut root)
-            .map_err(|err| BuildError::Output(err.to_string()))?;
-        infer_state
-            .resolve()
-            .map_err(|err| BuildError::Output(err.to_string()))?;
-        let mut sub_state = infer::SubState::new(infer_state.get_type_mapping());
-        let _sub_gen_result = sub_state.substitute(&mut root);
+        let state = analyze(args, infile, &src_file, externs)?;
+        if !compile_only && state.ast.program.is_none() {
+            return Err(BuildError::Input(format!(
+                "{} has no program block, compile it with -c to link it with one that does",
+                infile
+            )));
+        }
+        match args.error_format {
+            ErrorFormatArgs::Human => {
+                for warning in state.warnings.iter() {
+                    eprint!("{}", warning.render(infile, &src_file));
+                }
+            }
+            ErrorFormatArgs::Json if !state.warnings.is_empty() => {
+                eprintln!(
+                    "{}",
+                    Report::new(Some(infile), state.warnings.clone()).to_json()
+                );
+            }
+            ErrorFormatArgs::Json => {}
+        }
+        if args.session().strict && !state.warnings.is_empty() {
+            return Err(BuildError::Input(format!(
+                "{} warnings, which --strict treats as errors",
+                state.warnings.len()
+            )));
+        }
 
-        // Perform semantic checks and type checking
-        let mut state = semantic::ProgramState::new(root.clone());
-        state
-            .build_ir()
-            .map_err(|err| BuildError::Output(err.to_string()))?;
+        if save_types {
+            let types: Vec<_> = state.types.iter().collect();
+            let serialized_types =
+                serde_json::to_string(&types).map_err(|err| BuildError::Output(err.to_string()))?;
+            let mut file = File::create(TypeMap::TYPES_OUTPUT_FILENAME)
+                .map_err(|err| BuildError::Output(err.to_string()))?;
+            write!(&mut file, "{serialized_types}")
+                .map_err(|err| BuildError::Output(err.to_string()))?;
+        }
+
+        if save_symbols {
+            let serialized_symbols = serde_json::to_string(&state.symbols.symbols)
+                .map_err(|err| BuildError::Output(err.to_string()))?;
+            let mut file = File::create(Symbols::SYMBOLS_OUTPUT_FILENAME)
+                .map_err(|err| BuildError::Output(err.to_string()))?;
+            write!(&mut file, "{serialized_symbols}")
+                .map_err(|err| BuildError::Output(err.to_string()))?;
+        }
+
+        if save_symtab {
+            let symtab = state.symbols.render_scopes(&src_file);
+            let mut file = File::create(Symbols::SYMTAB_OUTPUT_FILENAME)
+                .map_err(|err| BuildError::Output(err.to_string()))?;
+            write!(&mut file, "{symtab}").map_err(|err| BuildError::Output(err.to_string()))?;
+        }
 
         if save_ir {
             let serialized_ir = serde_json::to_string(&state.build_stack)
@@ -116,20 +630,210 @@ fn main() -> Result<(), BuildError> {
                 .map_err(|err| BuildError::Output(err.to_string()))?;
             write!(&mut file, "{serialized_ir}")
                 .map_err(|err| BuildError::Output(err.to_string()))?;
+            let color = match args.color {
+                ColorArgs::Auto => {
+                    std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none()
+                }
+                ColorArgs::Always => true,
+                ColorArgs::Never => false,
+            };
+            print!("{}", ir::render(&state.build_stack, color));
         }
-        state.build_stack
+        let build_stack = state.build_stack.clone();
+        analyzed = Some(state);
+        build_stack
     };
 
     // Generate code
-    let ctx = codegen::new(build_stack, args.outfile, args.skip_validation);
+    enter_pass("code generation");
+    let mut ctx = codegen::new(build_stack, outfile.to_string(), args.skip_validation);
+    ctx.compile_only = compile_only;
+    ctx.fast_math = args.fast_math;
+    ctx.cc = args.cc.clone();
+    if !args.no_cache {
+        ctx.cache_dir = cache::dir();
+    }
+    if args.debug && analyzed.is_some() {
+        ctx.debug_src = Some(src_file.clone());
+    }
+    let mut c_lines = None;
     let build_result = match args.backend {
-        BackendArgs::C => CGenContext::from(ctx).gen(),
+        BackendArgs::C => {
+            let mut cgen = CGenContext::from(ctx);
+            let result = cgen.gen();
+            c_lines = Some(cgen.c_lines());
+            result
+        }
         BackendArgs::WASM => WasmGenContext::from(ctx).gen(),
     };
-    if !save_c {
-        fs::remove_file(CGenContext::C_OUTPUT_FILENAME)
-            .map_err(|_| BuildError::Output("Cannot delete C output file".to_string()))?;
+    // The C isn't written when the backend rejects the IR up front
+    if !save_c && matches!(args.backend, BackendArgs::C) {
+        match fs::remove_file(CGenContext::C_OUTPUT_FILENAME) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                return Err(BuildError::Output(
+                    "Cannot delete C output file".to_string(),
+                ))
+            }
+            _ => {}
+        }
+    }
+    build_result.map_err(|err| {
+        let diagnostic = err.diagnostic();
+        let span = match (&err, &analyzed) {
+            (CodeGenError::InvalidC { function, .. }, Some(state)) => {
+                function_span(state, function.as_deref())
+            }
+            _ => None,
+        };
+        match span {
+            Some(span) => diagnostic_error(diagnostic.with_span(span), infile, &src_file),
+            None => diagnostic_error(diagnostic, infile, &src_file),
+        }
+    })?;
+
+    // Metrics need the HIR, which IR read from a file doesn't come with
+    if let (true, Some(module)) = (save_metrics, analyzed.and_then(|state| state.hir)) {
+        let mut function_metrics = metrics::collect(&module);
+        if let Some(c_lines) = &c_lines {
+            let program = module
+                .program
+                .as_ref()
+                .map(|program| module.symbols.ident(program.symbol));
+            metrics::add_c_lines(&mut function_metrics, c_lines, program);
+        }
+        let serialized_metrics = serde_json::to_string(&function_metrics)
+            .map_err(|err| BuildError::Output(err.to_string()))?;
+        let mut file = File::create(FunctionMetrics::METRICS_OUTPUT_FILENAME)
+            .map_err(|err| BuildError::Output(err.to_string()))?;
+        write!(&mut file, "{serialized_metrics}")
+            .map_err(|err| BuildError::Output(err.to_string()))?;
+    }
+    Ok(())
+}
+
+// gcc rejecting the C we generate is a bug in the compiler rather than
+// in the program, but pointing at the function involved helps narrow it
+// down
+fn function_span(state: &ProgramState, function: Option<&str>) -> Option<Span> {
+    match function {
+        Some(function) => state
+            .symbols
+            .symbols
+            .iter()
+            .find(|symbol| symbol.ident == function && symbol.kind == SymbolKind::Function)
+            .and_then(|symbol| symbol.span),
+        None => state.ast.program.as_ref().map(|program| program.2),
+    }
+}
+
+fn diagnostic_error(diagnostic: Diagnostic, infile: &str, src_file: &str) -> BuildError {
+    let rendered = diagnostic.render(infile, src_file);
+    BuildError::Diagnostic(Box::new(diagnostic), Some(infile.to_string()), rendered)
+}
+
+// A backend failing outside of any one unit, like when linking
+fn backend_error(err: CodeGenError) -> BuildError {
+    let diagnostic = err.diagnostic();
+    let rendered = diagnostic.render("", "");
+    BuildError::Diagnostic(Box::new(diagnostic), None, rendered)
+}
+
+fn parse_error(err: parser::ParseError, infile: &str, src_file: &str) -> BuildError {
+    match parser::diagnostic(&err) {
+        Some(diagnostic) => diagnostic_error(diagnostic, infile, src_file),
+        None => BuildError::Input(format!("{}: {}", infile, err)),
+    }
+}
+
+// Runs the front end over Rascal source, up to and including the IR
+fn analyze(
+    args: &Args,
+    infile: &str,
+    src_file: &str,
+    externs: &[Box<Stmt>],
+) -> Result<ProgramState, BuildError> {
+    enter_pass("parsing");
+    let mut root = parser::parse(src_file).map_err(|err| parse_error(err, infile, src_file))?;
+    root.preblock.splice(0..0, externs.iter().cloned());
+    parser::check_nesting(&root, args.session().nesting_limit())
+        .map_err(|err| parse_error(err, infile, src_file))?;
+
+    let type_error = |err: infer::TypeError| {
+        let diagnostic = err.diagnostic(src_file);
+        diagnostic_error(diagnostic, infile, src_file)
+    };
+    enter_pass("type inference");
+    let mut typing_state = infer::TypingState::new();
+    typing_state.augment(&mut root).map_err(type_error)?;
+    let mut infer_state = infer::InferState::new();
+    let session = args.session();
+    infer_state.strict_numerics = session.strict_numerics();
+    infer_state.edition = session.edition();
+    infer_state.constrain(&mut root).map_err(type_error)?;
+    infer_state.resolve().map_err(type_error)?;
+    let mut sub_state = infer::SubState::new(infer_state.get_type_mapping());
+    let _sub_gen_result = sub_state.substitute(&mut root);
+
+    // Perform semantic checks and type checking
+    enter_pass("semantic analysis");
+    let mut state = semantic::ProgramState::new(root);
+    state.flatten_exprs = args.debug;
+    state.debug_info = args.debug;
+    state.opt_level = args.opt_level;
+    if args.debug_semantic {
+        state.trace = Some(vec![]);
+    }
+    let built = state.build_ir();
+    // Printed even when the analysis fails, since that's when it helps
+    if args.debug_semantic {
+        eprint!("{}", state.render_trace(src_file));
+    }
+    built.map_err(|err| {
+        let diagnostic = err.diagnostic();
+        diagnostic_error(diagnostic, infile, src_file)
+    })?;
+    Ok(state)
+}
+
+// Rewrites the input file with the suggestions of the diagnostics found
+// in it applied. A type error stops analysis, so only the first one is
+// fixed in a run.
+fn fix(args: &Args, infile: &str, src_file: &str, externs: &[Box<Stmt>]) -> Result<(), BuildError> {
+    let found = match analyze(args, infile, src_file, externs) {
+        Ok(state) => state.warnings,
+        Err(BuildError::Diagnostic(diagnostic, ..)) => vec![*diagnostic],
+        Err(err) => return Err(err),
+    };
+    let (fixed, applied) = diagnostics::apply_suggestions(src_file, &found);
+    if applied > 0 {
+        fs::write(infile, fixed).map_err(|err| BuildError::Output(err.to_string()))?;
     }
-    build_result.map_err(|err| BuildError::Output(err.to_string()))?;
+    eprintln!("applied {} fixes to {}", applied, infile);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_rejects_deep_nesting() {
+        let infile = env::temp_dir().join(format!("rascalc-deep-{}.ras", process::id()));
+        let n = 200_000;
+        for src in [
+            format!("program p return 1{}; end", " + 1".repeat(n)),
+            format!("program p return {}1{}; end", "(".repeat(n), ")".repeat(n)),
+        ] {
+            fs::write(&infile, src).unwrap();
+            let args = Args::parse_from(["rascalc", "--no-cache", infile.to_str().unwrap()]);
+            match build(args) {
+                Err(BuildError::Diagnostic(diagnostic, ..)) => {
+                    assert_eq!(diagnostic.message, "expression too deeply nested")
+                }
+                Err(err) => panic!("{}", err),
+                Ok(()) => panic!("built a program nested {} deep", n),
+            }
+        }
+        let _ = fs::remove_file(&infile);
+    }
+}
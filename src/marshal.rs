@@ -0,0 +1,137 @@
+use crate::backends::interp::{HostFn, Trap};
+use crate::ir::Value;
+use crate::types::{FunctionType, Type};
+use std::rc::Rc;
+
+// Converting between Rust values and the interpreter's, so host
+// functions can be written as ordinary Rust functions. Each Rust type
+// stands for exactly one Rascal type, which gives a host function a
+// signature that scripts are checked against.
+
+// A Rust type with a Rascal counterpart
+pub trait Marshal: Sized {
+    fn type_t() -> Type;
+    fn into_value(self) -> Value;
+    // None if the value isn't of the counterpart type
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+macro_rules! marshal {
+    ($rust:ty, $variant:ident) => {
+        impl Marshal for $rust {
+            fn type_t() -> Type {
+                Type::$variant
+            }
+
+            fn into_value(self) -> Value {
+                Value::$variant(self)
+            }
+
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    Value::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+marshal!(i32, Int32);
+marshal!(i64, Int64);
+marshal!(u32, UInt32);
+marshal!(u64, UInt64);
+marshal!(f32, Float32);
+marshal!(f64, Float64);
+marshal!(bool, Bool);
+marshal!(String, String);
+
+// What a host function can return: a value, nothing as for Nil
+// functions, or either of those or a message saying why it failed
+pub trait Returns {
+    fn type_t() -> Type;
+    fn into_result(self) -> Result<Option<Value>, String>;
+}
+
+impl<T: Marshal> Returns for T {
+    fn type_t() -> Type {
+        T::type_t()
+    }
+
+    fn into_result(self) -> Result<Option<Value>, String> {
+        Ok(Some(self.into_value()))
+    }
+}
+
+impl Returns for () {
+    fn type_t() -> Type {
+        Type::Nil
+    }
+
+    fn into_result(self) -> Result<Option<Value>, String> {
+        Ok(None)
+    }
+}
+
+impl<T: Returns> Returns for Result<T, String> {
+    fn type_t() -> Type {
+        T::type_t()
+    }
+
+    fn into_result(self) -> Result<Option<Value>, String> {
+        self.and_then(T::into_result)
+    }
+}
+
+// A Rust closure that can be registered as a host function. `Args` is
+// the tuple of its parameter types, which only tells the impls apart.
+pub trait HostFunction<Args> {
+    fn signature() -> FunctionType;
+    fn into_host(self, ident: &str) -> HostFn;
+}
+
+macro_rules! host_function {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> HostFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: Returns,
+            $($arg: Marshal),*
+        {
+            fn signature() -> FunctionType {
+                FunctionType {
+                    params_t: vec![$($arg::type_t()),*],
+                    return_t: Box::new(R::type_t()),
+                }
+            }
+
+            // Functions without parameters take nothing from `args`
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_host(self, ident: &str) -> HostFn {
+                let ident = ident.to_string();
+                Rc::new(move |args| {
+                    let trap = |message: String| Trap::Host {
+                        function: ident.clone(),
+                        message,
+                    };
+                    let mut args = args.iter().cloned();
+                    $(
+                        let $arg = args
+                            .next()
+                            .and_then($arg::from_value)
+                            .ok_or_else(|| trap(format!("expected {}", $arg::type_t())))?;
+                    )*
+                    self($($arg),*).into_result().map_err(trap)
+                })
+            }
+        }
+    };
+}
+
+host_function!();
+host_function!(A);
+host_function!(A, B);
+host_function!(A, B, C);
+host_function!(A, B, C, D);
+host_function!(A, B, C, D, E);
+host_function!(A, B, C, D, E, G);
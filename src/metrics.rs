@@ -0,0 +1,194 @@
+use crate::hir::{Block, Expr, ExprKind, Module, Stmt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// How big and how tangled each function of a unit is, to point at the
+// ones that have grown out of hand. Counts come from the HIR, and the C
+// line counts from the code the C backend generated for the function.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionMetrics {
+    pub function: String,
+    // Statements in the body, including those in ifs, switches and loops
+    // but not those of the functions defined in it, which have their own
+    pub statements: usize,
+    // How deeply ifs, switches and loops nest, 0 for a body without any
+    pub depth: usize,
+    // Operands and operators in the largest expression
+    pub largest_expr: usize,
+    // Lines of C, a statement a line. `None` until the C is generated.
+    pub c_lines: Option<usize>,
+}
+
+impl FunctionMetrics {
+    pub const METRICS_OUTPUT_FILENAME: &'static str = "out.metrics";
+}
+
+// The functions of the unit, nested ones included, in the order they're
+// defined, followed by its program block
+pub fn collect(module: &Module) -> Vec<FunctionMetrics> {
+    let mut metrics = vec![];
+    for stmt in module.globals.iter() {
+        if let Stmt::FuncDef(func) = stmt {
+            if !func.external {
+                measure(
+                    module,
+                    module.symbols.ident(func.symbol),
+                    &func.block,
+                    &mut metrics,
+                );
+            }
+        }
+    }
+    if let Some(program) = &module.program {
+        let ident = module.symbols.ident(program.symbol);
+        measure(module, ident, &program.block, &mut metrics);
+    }
+    metrics
+}
+
+// Fills in the C line counts, which are keyed by function, with the
+// program block under None
+pub fn add_c_lines(
+    metrics: &mut [FunctionMetrics],
+    c_lines: &HashMap<Option<String>, usize>,
+    program: Option<&str>,
+) {
+    for function in metrics.iter_mut() {
+        let key = match program {
+            Some(program) if program == function.function => None,
+            _ => Some(function.function.clone()),
+        };
+        function.c_lines = Some(c_lines.get(&key).copied().unwrap_or(0));
+    }
+}
+
+fn measure(module: &Module, ident: &str, block: &Block, metrics: &mut Vec<FunctionMetrics>) {
+    let mut function = FunctionMetrics {
+        function: ident.to_string(),
+        statements: 0,
+        depth: 0,
+        largest_expr: 0,
+        c_lines: None,
+    };
+    let mut nested = vec![];
+    measure_block(module, block, 0, &mut function, &mut nested);
+    metrics.push(function);
+    metrics.extend(nested);
+}
+
+fn measure_block(
+    module: &Module,
+    block: &Block,
+    depth: usize,
+    function: &mut FunctionMetrics,
+    nested: &mut Vec<FunctionMetrics>,
+) {
+    function.depth = function.depth.max(depth);
+    for stmt in block {
+        let exprs: Vec<&Expr> = match stmt {
+            Stmt::Loc(_) => continue,
+            Stmt::FuncDef(func) => {
+                measure(
+                    module,
+                    module.symbols.ident(func.symbol),
+                    &func.block,
+                    nested,
+                );
+                continue;
+            }
+            Stmt::Let(_, expr)
+            | Stmt::Assign(_, expr, _)
+            | Stmt::Expr(expr)
+            | Stmt::Return(expr) => {
+                vec![expr]
+            }
+            Stmt::MultiAssign(pairs) => pairs.iter().map(|(_, expr)| expr).collect(),
+            Stmt::Store(_, index, _, value, _) => vec![index, value],
+            Stmt::If(cases) => {
+                for case in cases {
+                    measure_block(module, &case.block, depth + 1, function, nested);
+                }
+                cases
+                    .iter()
+                    .filter_map(|case| case.condition.as_ref())
+                    .collect()
+            }
+            Stmt::Switch(scrutinee, cases) => {
+                for case in cases {
+                    measure_block(module, &case.block, depth + 1, function, nested);
+                }
+                vec![scrutinee]
+            }
+            Stmt::Repeat(block, condition) | Stmt::While(condition, block, _) => {
+                measure_block(module, block, depth + 1, function, nested);
+                vec![condition]
+            }
+            Stmt::Declare(_) | Stmt::Break(_) => vec![],
+        };
+        function.statements += 1;
+        for expr in exprs {
+            function.largest_expr = function.largest_expr.max(expr_size(expr));
+        }
+    }
+}
+
+// Lambdas count as one operand, their bodies aren't evaluated where
+// they're written
+fn expr_size(expr: &Expr) -> usize {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Var(_) | ExprKind::Lambda(_) => 1,
+        ExprKind::Binary(_, l, r) | ExprKind::Logical(_, l, r) => 1 + expr_size(l) + expr_size(r),
+        ExprKind::Unary(_, u) | ExprKind::Cast(u) | ExprKind::Index(_, u) => 1 + expr_size(u),
+        ExprKind::Call(_, args) => 1 + args.iter().map(expr_size).sum::<usize>(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::session::Session;
+
+    #[test]
+    fn measure_functions() {
+        let src = "fun f(n: int32) -> int32
+    fun g() -> int32 return 1; end
+    let x = n * 2 + g();
+    if x > 2 then
+        repeat
+            x = x - 1;
+        until x < 2;
+    end
+    return x;
+end
+
+program p
+    return f(3);
+end";
+        let state = compile::analyze(src, &Session::default()).unwrap();
+        let mut metrics = collect(state.hir.as_ref().unwrap());
+        let c_lines = HashMap::from([(Some("f".to_string()), 9), (None, 3)]);
+        add_c_lines(&mut metrics, &c_lines, Some("p"));
+        let summary: Vec<_> = metrics
+            .iter()
+            .map(|m| {
+                (
+                    m.function.as_str(),
+                    m.statements,
+                    m.depth,
+                    m.largest_expr,
+                    m.c_lines,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("f", 5, 2, 5, Some(9)),
+                ("g", 1, 0, 1, Some(0)),
+                ("p", 1, 0, 2, Some(3)),
+            ]
+        );
+    }
+}
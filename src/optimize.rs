@@ -0,0 +1,592 @@
+use crate::ast::Span;
+use crate::consteval;
+use crate::hir::{self, Expr, ExprKind, Stmt, SymbolId, SymbolKind, Symbols};
+use crate::ir::{Assign, Func, FuncDef, IRNode, Reassign, Term, Value};
+use crate::symbol::new_symbol;
+use crate::types::Type;
+use std::collections::{HashMap, HashSet};
+
+// Passes that rewrite the program without changing what it does. Most
+// run on IR that has already been checked, and leave it well formed.
+// Unrolling runs on the HIR, so that each copy of a block is lowered
+// with ids of its own.
+
+// Replaces every read of a global that's initialized to a literal and
+// never assigned again with the literal, dropping the global. Globals
+// that are never read are dropped along with them. Names are only
+// resolved by spelling in the IR, so a global whose name is declared
+// anywhere else too, where it could be shadowed, is left alone.
+pub fn propagate_globals(nodes: Vec<IRNode>) -> Vec<IRNode> {
+    let mut declared: HashMap<&str, usize> = HashMap::new();
+    let mut reassigned: HashSet<&str> = HashSet::new();
+    for node in nodes.iter() {
+        match node {
+            IRNode::Assign(assign) | IRNode::StaticAssign(assign) => {
+                *declared.entry(&assign.symbol.ident).or_default() += 1;
+            }
+            IRNode::FuncDef(def, _)
+            | IRNode::ExternFuncDef(def)
+            | IRNode::Intrinsic(def, _)
+            | IRNode::FuncDecl(def) => {
+                *declared.entry(&def.symbol.ident).or_default() += 1;
+                for (param, _) in def.params_t.iter() {
+                    *declared.entry(param).or_default() += 1;
+                }
+            }
+            IRNode::Reassign(reassign) => {
+                reassigned.insert(&reassign.symbol.ident);
+            }
+            _ => {}
+        }
+    }
+
+    // The globals whose whole initializer is a literal, by name, and
+    // where they're assigned. Functions are skipped, their lets aren't
+    // globals.
+    let mut values: HashMap<String, Term> = HashMap::new();
+    let mut constants: HashSet<usize> = HashSet::new();
+    let mut depth = 0;
+    for (n, node) in nodes.iter().enumerate() {
+        match node {
+            IRNode::FuncDef(..) => depth += 1,
+            IRNode::EndFuncDef(_) => depth -= 1,
+            IRNode::EndGlobalSection => break,
+            IRNode::Assign(assign) if depth == 0 && n >= 2 => {
+                let ident = assign.symbol.ident.as_str();
+                let whole = !matches!(
+                    nodes[n - 2],
+                    IRNode::Term(_) | IRNode::Eval(_) | IRNode::Load(_)
+                );
+                match &nodes[n - 1] {
+                    IRNode::Term(term)
+                        if whole
+                            && !matches!(term.value, Value::Id(_))
+                            && declared.get(ident) == Some(&1)
+                            && !reassigned.contains(ident) =>
+                    {
+                        values.insert(ident.to_string(), term.clone());
+                        constants.insert(n);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut propagated = Vec::with_capacity(nodes.len());
+    for (n, node) in nodes.into_iter().enumerate() {
+        if constants.contains(&n) {
+            // The literal assigned was pushed last
+            propagated.pop();
+            continue;
+        }
+        propagated.push(match node {
+            IRNode::Term(Term {
+                value: Value::Id(ident),
+                type_t,
+            }) => match values.get(&ident) {
+                Some(term) => IRNode::Term(term.clone()),
+                None => IRNode::Term(Term {
+                    value: Value::Id(ident),
+                    type_t,
+                }),
+            },
+            node => node,
+        });
+    }
+    propagated
+}
+
+// Turns each call a function makes to itself and returns straight away
+// into a jump back to its start, with the parameters reassigned the
+// arguments, so that recursing deeply doesn't grow the stack. The body
+// is wrapped in a loop to jump back through. A function that defines
+// functions of its own, or declares its own name or a parameter's again,
+// where the call or the reassignment could mean something else, is left
+// alone.
+pub fn eliminate_tail_calls(nodes: Vec<IRNode>) -> Vec<IRNode> {
+    let mut rewritten = Vec::with_capacity(nodes.len());
+    let mut n = 0;
+    while n < nodes.len() {
+        if let IRNode::FuncDef(def, id) = &nodes[n] {
+            let end = nodes[n..]
+                .iter()
+                .position(|node| matches!(node, IRNode::EndFuncDef(end) if end == id));
+            if let Some(end) = end.map(|end| n + end) {
+                if let Some(body) = loop_tail_calls(def, id, &nodes[n + 1..end]) {
+                    rewritten.push(nodes[n].clone());
+                    rewritten.extend(body);
+                    n = end;
+                    continue;
+                }
+            }
+        }
+        rewritten.push(nodes[n].clone());
+        n += 1;
+    }
+    rewritten
+}
+
+// The body of the function with its tail calls turned into jumps, None
+// if there are none or the function is left alone
+fn loop_tail_calls(def: &FuncDef, id: &str, body: &[IRNode]) -> Option<Vec<IRNode>> {
+    let own = |ident: &str| {
+        ident == def.symbol.ident || def.params_t.iter().any(|(param, _)| param == ident)
+    };
+    let redeclares = body.iter().any(|node| match node {
+        IRNode::FuncDef(..)
+        | IRNode::ExternFuncDef(_)
+        | IRNode::Intrinsic(..)
+        | IRNode::FuncDecl(_) => true,
+        IRNode::Assign(assign) | IRNode::StaticAssign(assign) => own(&assign.symbol.ident),
+        _ => false,
+    });
+    let tail = |n: usize| {
+        matches!(&body[n], IRNode::Eval(Func::Func(sig)) if sig.symbol.ident == def.symbol.ident)
+            && matches!(body.get(n + 1), Some(IRNode::Return))
+    };
+    if redeclares || !(0..body.len()).any(tail) {
+        return None;
+    }
+
+    let loop_id = format!("{}_tail", id);
+    let mut looped = vec![
+        IRNode::Term(Term {
+            value: Value::Bool(true),
+            type_t: Type::Bool,
+        }),
+        IRNode::While(loop_id.clone()),
+    ];
+    let mut calls = 0;
+    let mut n = 0;
+    while n < body.len() {
+        let starts = match tail(n) {
+            true => arguments(body, n, def.params_t.len()),
+            false => None,
+        };
+        let Some(starts) = starts else {
+            looped.push(body[n].clone());
+            n += 1;
+            continue;
+        };
+        // The arguments were pushed as they were, and are replaced by
+        // their assignment to temporaries
+        looped.truncate(looped.len() - (n - starts.first().copied().unwrap_or(n)));
+        let ends = starts.iter().skip(1).copied().chain([n]);
+        let mut reassigned = vec![];
+        for (i, ((start, end), (param, type_t))) in
+            starts.iter().zip(ends).zip(def.params_t.iter()).enumerate()
+        {
+            let arg = &body[*start..end];
+            // Passed on as it is
+            if let [IRNode::Term(Term {
+                value: Value::Id(ident),
+                ..
+            })] = arg
+            {
+                if ident == param {
+                    continue;
+                }
+            }
+            let temp = format!("{}_arg_{}_{}", loop_id, calls, i);
+            looped.extend(arg.iter().cloned());
+            looped.push(IRNode::Assign(Assign {
+                type_t: type_t.clone(),
+                symbol: new_symbol(temp.clone()),
+            }));
+            reassigned.push((temp, param, type_t));
+        }
+        for (temp, param, type_t) in reassigned {
+            looped.push(IRNode::Term(Term {
+                value: Value::Id(temp),
+                type_t: type_t.clone(),
+            }));
+            looped.push(IRNode::Reassign(Reassign {
+                type_t: type_t.clone(),
+                symbol: new_symbol(param.clone()),
+            }));
+        }
+        looped.push(IRNode::Continue(loop_id.clone()));
+        calls += 1;
+        // Past the return
+        n += 2;
+    }
+    looped.push(IRNode::EndWhile(loop_id));
+    Some(looped)
+}
+
+// Where each of the `count` arguments to the call at `n` starts, each
+// running up to the next, or None if they aren't all plain expressions
+fn arguments(body: &[IRNode], n: usize, count: usize) -> Option<Vec<usize>> {
+    let mut starts = Vec::with_capacity(count);
+    // The values still to be found for the argument being walked back
+    // through
+    let mut wanted = 1;
+    let mut m = n;
+    while starts.len() < count {
+        m = m.checked_sub(1)?;
+        wanted += match &body[m] {
+            IRNode::Term(_) => 0,
+            IRNode::Eval(func) => func.signature().1.params_t.len(),
+            _ => return None,
+        };
+        wanted -= 1;
+        if wanted == 0 {
+            starts.push(m);
+            wanted = 1;
+        }
+    }
+    starts.reverse();
+    Some(starts)
+}
+
+// The most iterations a loop is fully unrolled for, and the most copies
+// of its block `@unroll` can ask for
+pub const MAX_UNROLL: usize = 16;
+
+// Unrolls the loops marked `@unroll(n)`, running n copies of the block in
+// each iteration, each copy after the first only while the condition
+// still holds. With `full`, a loop that runs a known number of times, up
+// to MAX_UNROLL, is replaced by that many copies of its block. That's
+// known for a loop counting a local, initialized to a constant in the
+// same block, that the block assigns last and nowhere else. Functions
+// that define functions, which could assign the local too, are left
+// alone.
+pub fn unroll_loops(module: &mut hir::Module, full: bool) {
+    let hir::Module {
+        symbols,
+        globals,
+        program,
+        ..
+    } = module;
+    unroll_block(globals, symbols, full);
+    if let Some(program) = program {
+        let full = full && !defines_functions(&program.block);
+        unroll_block(&mut program.block, symbols, full);
+    }
+}
+
+fn unroll_block(block: &mut hir::Block, symbols: &Symbols, full: bool) {
+    for stmt in block.iter_mut() {
+        match stmt {
+            Stmt::If(cases) => {
+                for case in cases.iter_mut() {
+                    unroll_block(&mut case.block, symbols, full);
+                }
+            }
+            Stmt::Switch(_, cases) => {
+                for case in cases.iter_mut() {
+                    unroll_block(&mut case.block, symbols, full);
+                }
+            }
+            Stmt::Repeat(block, _) | Stmt::While(_, block, _) => unroll_block(block, symbols, full),
+            Stmt::FuncDef(func) => {
+                let full = full && !defines_functions(&func.block);
+                unroll_block(&mut func.block, symbols, full);
+            }
+            _ => {}
+        }
+    }
+    let mut n = 0;
+    while n < block.len() {
+        if let Some(trips) = trip_count(block, n, symbols).filter(|_| full) {
+            let Stmt::While(_, body, _) = block.remove(n) else {
+                unreachable!("only loops have a trip count")
+            };
+            block.splice(n..n, (0..trips).map(|_| scoped(body.clone())));
+            n += trips;
+            continue;
+        }
+        if let Stmt::While(condition, body, copies) = &mut block[n] {
+            if *copies > 1 && copyable(body, symbols) {
+                // Nested from the last copy out, each in an `if` on the
+                // condition
+                let mut unrolled = body.clone();
+                for _ in 2..*copies {
+                    let mut copy = body.clone();
+                    copy.push(guarded(condition, unrolled));
+                    unrolled = copy;
+                }
+                body.push(guarded(condition, unrolled));
+                *copies = 1;
+            }
+        }
+        n += 1;
+    }
+}
+
+// How many times the loop at `n` in the block runs, if it's a while loop
+// that can be fully unrolled
+fn trip_count(block: &hir::Block, n: usize, symbols: &Symbols) -> Option<usize> {
+    let Stmt::While(condition, body, _) = &block[n] else {
+        return None;
+    };
+    // The counter is the variable the block assigns last
+    let Some(Stmt::Assign(counter, step, _)) = body.last() else {
+        return None;
+    };
+    let counter = *counter;
+    let local = matches!(
+        symbols.get(counter).kind,
+        SymbolKind::Local | SymbolKind::Param
+    );
+    if !local
+        || assigns(&body[..body.len() - 1], counter)
+        || hir::breaks(body, 0)
+        || !copyable(body, symbols)
+    {
+        return None;
+    }
+    // Its initial value, from the last statement before the loop
+    // assigning it
+    let init = block[..n].iter().rev().find_map(|stmt| match stmt {
+        Stmt::Let(id, value) | Stmt::Assign(id, value, _) if *id == counter => Some(Some(value)),
+        stmt if assigns(std::slice::from_ref(stmt), counter) => Some(None),
+        _ => None,
+    });
+    let mut value = consteval::eval(init??)?;
+    let mut trips = 0;
+    while consteval::eval(&substitute(condition, counter, &value))? == Value::Bool(true) {
+        trips += 1;
+        if trips > MAX_UNROLL || trips * body.len() > MAX_UNROLL * 4 {
+            return None;
+        }
+        value = consteval::eval(&substitute(step, counter, &value))?;
+    }
+    Some(trips)
+}
+
+// Whether any of the statements assigns the variable
+fn assigns(block: &[Stmt], id: SymbolId) -> bool {
+    block.iter().any(|stmt| match stmt {
+        Stmt::Let(target, _)
+        | Stmt::Assign(target, ..)
+        | Stmt::Declare(target)
+        | Stmt::Store(target, ..) => *target == id,
+        Stmt::MultiAssign(pairs) => pairs.iter().any(|(target, _)| *target == id),
+        Stmt::If(cases) => cases.iter().any(|case| assigns(&case.block, id)),
+        Stmt::Switch(_, cases) => cases.iter().any(|case| assigns(&case.block, id)),
+        Stmt::Repeat(block, _) | Stmt::While(_, block, _) => assigns(block, id),
+        Stmt::FuncDef(func) => assigns(&func.block, id),
+        Stmt::Break(_) | Stmt::Expr(_) | Stmt::Return(_) | Stmt::Loc(_) => false,
+    })
+}
+
+fn defines_functions(block: &[Stmt]) -> bool {
+    block.iter().any(|stmt| match stmt {
+        Stmt::FuncDef(_) => true,
+        Stmt::If(cases) => cases.iter().any(|case| defines_functions(&case.block)),
+        Stmt::Switch(_, cases) => cases.iter().any(|case| defines_functions(&case.block)),
+        Stmt::Repeat(block, _) | Stmt::While(_, block, _) => defines_functions(block),
+        _ => false,
+    })
+}
+
+// Whether the block means the same copied, which it doesn't if it
+// defines a function or a static variable, as each copy would be another
+fn copyable(block: &[Stmt], symbols: &Symbols) -> bool {
+    block.iter().all(|stmt| match stmt {
+        Stmt::FuncDef(_) => false,
+        Stmt::Let(id, _) => symbols.get(*id).kind != SymbolKind::Static,
+        Stmt::If(cases) => cases.iter().all(|case| copyable(&case.block, symbols)),
+        Stmt::Switch(_, cases) => cases.iter().all(|case| copyable(&case.block, symbols)),
+        Stmt::Repeat(block, _) | Stmt::While(_, block, _) => copyable(block, symbols),
+        _ => true,
+    })
+}
+
+// The expression with the variable replaced by a value
+fn substitute(expr: &Expr, id: SymbolId, value: &Value) -> Expr {
+    let sub = |expr: &Expr| Box::new(substitute(expr, id, value));
+    let kind = match &expr.kind {
+        ExprKind::Var(var) if *var == id => ExprKind::Literal(value.clone()),
+        ExprKind::Binary(op, l, r) => ExprKind::Binary(*op, sub(l), sub(r)),
+        ExprKind::Logical(op, l, r) => ExprKind::Logical(*op, sub(l), sub(r)),
+        ExprKind::Unary(op, u) => ExprKind::Unary(*op, sub(u)),
+        ExprKind::Cast(u) => ExprKind::Cast(sub(u)),
+        ExprKind::Index(array, index) => ExprKind::Index(*array, sub(index)),
+        kind => kind.clone(),
+    };
+    Expr {
+        type_t: expr.type_t.clone(),
+        kind,
+    }
+}
+
+// A copy of a loop's block, in a scope of its own
+fn scoped(block: hir::Block) -> Stmt {
+    let always = Expr {
+        type_t: Type::Bool,
+        kind: ExprKind::Literal(Value::Bool(true)),
+    };
+    guarded(&always, block)
+}
+
+fn guarded(condition: &Expr, block: hir::Block) -> Stmt {
+    Stmt::If(vec![hir::IfCase {
+        condition: Some(condition.clone()),
+        block,
+        span: Span::default(),
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::interp::Interpreter;
+    use crate::compile;
+    use crate::ircheck;
+    use crate::lower;
+    use crate::session::Session;
+
+    #[test]
+    fn propagate_constant_globals() {
+        let src = "let limit = 10;
+let unused = 2.5;
+let counter = 0;
+let shadowed = 1;
+let derived = limit + 1;
+
+fun bump() -> int32
+    counter = counter + 1;
+    return counter;
+end
+
+fun f(shadowed: int32) -> int32
+    return shadowed + limit;
+end
+
+program p
+    bump();
+    return f(limit) + counter + shadowed + derived;
+end";
+        let nodes = compile::analyze(src, &Session::default())
+            .unwrap()
+            .build_stack;
+        ircheck::check(&nodes).unwrap();
+        let globals: Vec<&str> = nodes
+            .iter()
+            .take_while(|node| !matches!(node, IRNode::FuncDef(..)))
+            .filter_map(|node| match node {
+                IRNode::Assign(assign) => Some(assign.symbol.ident.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(globals, ["counter", "shadowed", "derived"]);
+        let reads_limit = nodes.iter().any(|node| {
+            matches!(node, IRNode::Term(Term { value: Value::Id(ident), .. }) if ident == "limit")
+        });
+        assert!(!reads_limit);
+        let interpreter = Interpreter::new(nodes, HashMap::new()).unwrap();
+        interpreter.init().unwrap();
+        assert_eq!(interpreter.run(), Ok(33));
+    }
+
+    #[test]
+    fn loop_tail_calls() {
+        let src = "fun down(n: int32, acc: int32, step: int32) -> int32
+    if n == 0 then
+        return acc;
+    end
+    if n > 5 then
+        return down(n - step, acc + 1, step);
+    else then
+        return down(n - 1, acc, step);
+    end
+end
+
+fun fib(n: int32) -> int32
+    if n < 2 then
+        return n;
+    end
+    return fib(n - 1) + fib(n - 2);
+end
+
+program p
+    return down(100000, 3, 1) - 100000 + fib(10);
+end";
+        let nodes = compile::analyze(src, &Session::default())
+            .unwrap()
+            .build_stack;
+        ircheck::check(&nodes).unwrap();
+        let calls = |ident: &str| {
+            nodes
+                .iter()
+                .filter(|node| {
+                    matches!(node, IRNode::Eval(Func::Func(sig)) if sig.symbol.ident == ident)
+                })
+                .count()
+        };
+        // Only the call from the program is left, fib's calls aren't
+        // returned as they are
+        assert_eq!(calls("down"), 1);
+        assert_eq!(calls("fib"), 3);
+        let continues = nodes
+            .iter()
+            .filter(|node| matches!(node, IRNode::Continue(_)))
+            .count();
+        assert_eq!(continues, 2);
+        // The step is passed on as it is, and not reassigned
+        let reassigned = nodes.iter().any(
+            |node| matches!(node, IRNode::Reassign(reassign) if reassign.symbol.ident == "step"),
+        );
+        assert!(!reassigned);
+        // Far deeper than the interpreter could recurse
+        let interpreter = Interpreter::new(nodes, HashMap::new()).unwrap();
+        interpreter.init().unwrap();
+        assert_eq!(interpreter.run(), Ok(53));
+    }
+
+    #[test]
+    fn unroll_small_loops() {
+        let src = "fun sum_to(n: int32) -> int32
+    let s = 0;
+    let i = 0;
+    @unroll(4)
+    while i < n do
+        i = i + 1;
+        if i > 50 then
+            break;
+        end
+        s = s + i;
+    end
+    return s;
+end
+
+program p
+    let squares = 0;
+    let i = 1;
+    while i <= 4 do
+        let square = i * i;
+        squares = squares + square;
+        i = i + 1;
+    end
+    return squares + i + sum_to(10) - sum_to(100) + 1000;
+end";
+        let state = compile::analyze(src, &Session::default()).unwrap();
+        let whiles = |nodes: &[IRNode]| {
+            nodes
+                .iter()
+                .filter(|node| matches!(node, IRNode::While(_)))
+                .count()
+        };
+        // Each copy of sum_to's block after the first is in an `if`
+        assert_eq!(whiles(&state.build_stack), 2);
+        let ifs = state
+            .build_stack
+            .iter()
+            .filter(|node| matches!(node, IRNode::If(_)))
+            .count();
+        assert_eq!(ifs, 4 + 3);
+
+        let mut module = state.hir.unwrap();
+        unroll_loops(&mut module, true);
+        let nodes = lower::lower(&module, false).unwrap();
+        ircheck::check(&nodes).unwrap();
+        // sum_to's bound isn't known
+        assert_eq!(whiles(&nodes), 1);
+        let interpreter = Interpreter::new(nodes, HashMap::new()).unwrap();
+        interpreter.init().unwrap();
+        assert_eq!(interpreter.run(), Ok(30 + 5 + 55 - 1275 + 1000));
+    }
+}
@@ -0,0 +1,381 @@
+use crate::ast::{Expr, Root, Span, Stmt, Term, TypedExpr};
+use crate::diagnostics::Diagnostic;
+use crate::lexer::{keyword, Lexer, LexicalError, Spanned, Tok};
+use crate::rascal;
+use crate::types::Type;
+use thiserror::Error;
+
+// What the parser rejects besides tokens out of place: tokens that don't
+// lex, and what the grammar can express but the language doesn't allow
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SyntaxError {
+    #[error(transparent)]
+    Lexical(#[from] LexicalError),
+    // The start and end of the identifier, and the limit
+    #[error("Identifier at {0} is longer than {2} characters")]
+    IdentifierTooLong(usize, usize, usize),
+    #[error("Comparison operators can't be chained at {0}; parenthesize one side or compare in separate steps")]
+    ChainedComparison(usize),
+    #[error("Assignment at {0} has {1} targets but {2} values")]
+    AssignmentArity(usize, usize, usize),
+    #[error("`{1}` is assigned more than once at {0}")]
+    DuplicateTarget(usize, String),
+    #[error("`{1}` is a reserved keyword and can't be used as a name, at {0}")]
+    ReservedKeyword(usize, String),
+    // The start and end of the expression, and the limit
+    #[error("Expression at {0} is nested more than {2} deep")]
+    TooDeeplyNested(usize, usize, usize),
+    // The start and end of the length
+    #[error("Array length at {0} isn't a positive integer")]
+    ArrayLength(usize, usize),
+}
+
+// The entry points into the grammar. A source file is parsed as a whole
+// with `parse`; tools working on fragments, like a REPL, can parse one
+// expression or one statement (with its `;`) at a time.
+pub type ParseError = lalrpop_util::ParseError<usize, Tok, SyntaxError>;
+
+// The tokens of the source, as the grammar takes them
+pub fn tokens(src: &str) -> impl Iterator<Item = Spanned<Tok, usize, SyntaxError>> + '_ {
+    Lexer::new(src).map(|token| token.map_err(SyntaxError::from))
+}
+
+pub fn parse(src: &str) -> Result<Box<Root>, ParseError> {
+    rascal::RootParser::new()
+        .parse(tokens(src))
+        .map_err(|err| reserved(src, err))
+}
+
+pub fn parse_expr(src: &str) -> Result<Box<TypedExpr>, ParseError> {
+    rascal::ExprParser::new()
+        .parse(tokens(src))
+        .map_err(|err| reserved(src, err))
+}
+
+pub fn parse_stmt(src: &str) -> Result<Box<Stmt>, ParseError> {
+    rascal::StmtParser::new()
+        .parse(tokens(src))
+        .map_err(|err| reserved(src, err))
+}
+
+// A keyword where a name could be, and no keyword could, is most likely
+// meant as a name, so rather than list the tokens that were expected,
+// say it's reserved
+fn reserved(src: &str, err: ParseError) -> ParseError {
+    match &err {
+        ParseError::UnrecognizedToken {
+            token: (start, _, end),
+            expected,
+        } if keyword(&src[*start..*end]).is_some()
+            && expected.iter().any(|e| e == "\"identifier\"")
+            && !expected
+                .iter()
+                .any(|e| keyword(e.trim_matches('"')).is_some()) =>
+        {
+            ParseError::User {
+                error: SyntaxError::ReservedKeyword(*start, src[*start..*end].to_string()),
+            }
+        }
+        _ => err,
+    }
+}
+
+// How long an identifier may be, in characters. It's generous for
+// anything written by hand, and keeps generated names and messages
+// quoting them a sensible size.
+pub const IDENT_LIMIT: usize = 255;
+
+// How deeply statements and expressions can nest unless the session
+// says otherwise. The passes after parsing recurse through them, and
+// this keeps them well within the 8 MiB stack of a main thread, even in
+// a debug build.
+pub const NESTING_LIMIT: usize = 256;
+
+// Rejects a program nested more than `limit` deep, blaming the first
+// expression past the limit, before the passes that recurse through it
+// can run out of stack. Each expression is a level, as is each block.
+// The walk keeps its own worklist, so it can't run out itself.
+pub fn check_nesting(root: &Root, limit: usize) -> Result<(), ParseError> {
+    enum Node<'a> {
+        Stmt(&'a Stmt),
+        Expr(&'a TypedExpr),
+    }
+    let program = root.program.iter().flat_map(|program| program.1.iter());
+    let top: Vec<_> = root
+        .preblock
+        .iter()
+        .chain(program)
+        .chain(root.postblock.iter())
+        .collect();
+    // Children are pushed last first, so they're visited in source order
+    let mut work: Vec<(Node, usize)> = top.iter().rev().map(|s| (Node::Stmt(s), 1)).collect();
+    while let Some((node, depth)) = work.pop() {
+        let mut blocks = vec![];
+        let mut exprs: Vec<&Box<TypedExpr>> = vec![];
+        match node {
+            Node::Expr(expr) => {
+                if depth > limit {
+                    return Err(ParseError::User {
+                        error: SyntaxError::TooDeeplyNested(expr.span.start, expr.span.end, limit),
+                    });
+                }
+                match &expr.expr {
+                    Expr::Term(term) => {
+                        if let Term::Expr(inner) = &term.term {
+                            exprs.push(inner);
+                        }
+                    }
+                    Expr::Add(l, r)
+                    | Expr::Sub(l, r)
+                    | Expr::Mult(l, r)
+                    | Expr::Div(l, r)
+                    | Expr::Mod(l, r)
+                    | Expr::Pow(l, r)
+                    | Expr::BitAnd(l, r)
+                    | Expr::BitOr(l, r)
+                    | Expr::BitXor(l, r)
+                    | Expr::Shl(l, r)
+                    | Expr::Shr(l, r)
+                    | Expr::Eq(l, r)
+                    | Expr::Neq(l, r)
+                    | Expr::Leq(l, r)
+                    | Expr::Geq(l, r)
+                    | Expr::LessThan(l, r)
+                    | Expr::GreaterThan(l, r)
+                    | Expr::And(l, r)
+                    | Expr::Or(l, r) => exprs.extend([l, r]),
+                    Expr::Not(u) | Expr::Neg(u) | Expr::BitNot(u) | Expr::TypeName(u) => {
+                        exprs.push(u)
+                    }
+                    Expr::Convert(_, u) => exprs.push(u),
+                    Expr::Call(_, args) => exprs.extend(args),
+                    Expr::Index(_, _, index) => exprs.push(index),
+                    Expr::LambdaFunc(lf) => blocks.push(&lf.block),
+                    Expr::SizeOf(_) => {}
+                }
+            }
+            Node::Stmt(stmt) => match stmt {
+                Stmt::Assign(_, _, expr, ..) | Stmt::Reassign(_, _, _, expr, _) => exprs.push(expr),
+                Stmt::Return(expr) | Stmt::StaticAssert(expr, ..) => exprs.push(expr),
+                Stmt::Store(_, index, _, value, _) => exprs.extend([index, value]),
+                Stmt::MultiAssign(_, args) | Stmt::Call(_, args, _) => exprs.extend(args),
+                Stmt::If(cases) => {
+                    for case in cases {
+                        exprs.push(&case.condition);
+                        blocks.push(&case.block);
+                    }
+                }
+                Stmt::Switch(scrutinee, cases, _) => {
+                    exprs.push(scrutinee);
+                    for case in cases {
+                        exprs.extend(&case.labels);
+                        blocks.push(&case.block);
+                    }
+                }
+                Stmt::Repeat(block, condition, _) | Stmt::While(condition, block, _) => {
+                    blocks.push(block);
+                    exprs.push(condition);
+                }
+                Stmt::Bench(_, count, block, _) => {
+                    exprs.extend(count);
+                    blocks.push(block);
+                }
+                Stmt::FuncDef(func) => blocks.push(&func.block),
+                // Only a wrapper, not a level of its own
+                Stmt::Defer(inner) | Stmt::Static(inner) | Stmt::Attributed(_, inner) => {
+                    work.push((Node::Stmt(inner), depth));
+                }
+                Stmt::Break(..) | Stmt::Declare(..) => {}
+            },
+        }
+        let stmts = blocks.into_iter().flatten().map(|s| Node::Stmt(s));
+        let exprs = exprs.into_iter().map(|e| Node::Expr(e));
+        let children: Vec<_> = exprs.chain(stmts).collect();
+        work.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+    }
+    Ok(())
+}
+
+// The parse errors that are worth pointing at in the source
+pub fn diagnostic(err: &ParseError) -> Option<Diagnostic> {
+    match err {
+        ParseError::User {
+            error: SyntaxError::ReservedKeyword(start, word),
+        } => Some(
+            Diagnostic::error(format!("`{}` is a reserved keyword", word))
+                .with_span(Span::new(*start, start + word.len()))
+                .with_label("can't be used as a name")
+                .with_help(format!("rename it, to `{}_` for instance", word)),
+        ),
+        ParseError::User {
+            error: SyntaxError::TooDeeplyNested(start, end, limit),
+        } => Some(
+            Diagnostic::error("expression too deeply nested")
+                .with_span(Span::new(*start, *end))
+                .with_label(format!("more than {} levels deep", limit))
+                .with_help("split it up with variables, or raise the limit with --nesting-limit"),
+        ),
+        ParseError::User {
+            error: SyntaxError::Lexical(LexicalError::NumberTooLarge(start, end, type_t)),
+        } => {
+            let greatest = match type_t.integer_range() {
+                Some((_, max)) => max.to_string(),
+                None if *type_t == Type::Float32 => format!("about {:e}", f32::MAX),
+                None => format!("about {:e}", f64::MAX),
+            };
+            Some(
+                Diagnostic::error(format!("number too large for {}", type_t))
+                    .with_span(Span::new(*start, *end))
+                    .with_label(format!("doesn't fit in {}", type_t))
+                    .with_note(format!("{} holds numbers up to {}", type_t, greatest)),
+            )
+        }
+        ParseError::User {
+            error: SyntaxError::ArrayLength(start, end),
+        } => Some(
+            Diagnostic::error("array length must be a positive integer")
+                .with_span(Span::new(*start, *end))
+                .with_label("not a positive integer"),
+        ),
+        ParseError::User {
+            error: SyntaxError::IdentifierTooLong(start, end, limit),
+        } => Some(
+            Diagnostic::error("identifier too long")
+                .with_span(Span::new(*start, *end))
+                .with_label(format!("more than {} characters", limit))
+                .with_help("shorten it"),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Expr;
+
+    #[test]
+    fn parse_fragments() {
+        let expr = parse_expr("1 + f(x) * 2").unwrap();
+        assert!(matches!(expr.expr, Expr::Add(..)));
+        assert_eq!((expr.span.start, expr.span.end), (0, 12));
+        assert!(matches!(
+            *parse_stmt("let x = 1;").unwrap(),
+            Stmt::Assign(..)
+        ));
+        assert!(matches!(
+            *parse_stmt("if x then return 1; end").unwrap(),
+            Stmt::If(_)
+        ));
+        assert!(parse_expr("let x = 1;").is_err());
+        assert!(parse_stmt("x = 1; x = 2;").is_err());
+        assert!(parse("program p return 0; end").is_ok());
+    }
+
+    #[test]
+    fn reject_keywords_as_names() {
+        let src = "program p\n    let end = 1;\n    return 0;\nend";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::User {
+                error: SyntaxError::ReservedKeyword(18, "end".into())
+            }
+        );
+        let diagnostic = diagnostic(&err).unwrap();
+        assert_eq!(diagnostic.span, Some(Span::new(18, 21)));
+        assert!(matches!(
+            parse("fun repeat() -> int32 return 1; end").unwrap_err(),
+            ParseError::User {
+                error: SyntaxError::ReservedKeyword(4, _)
+            }
+        ));
+        assert!(matches!(
+            parse("fun f(a: int32, if: int32) -> int32 return a; end").unwrap_err(),
+            ParseError::User {
+                error: SyntaxError::ReservedKeyword(16, _)
+            }
+        ));
+        // Where a keyword could be too, it's an ordinary syntax error
+        for src in [
+            "program p return 0; else end",
+            "program p return 1 + int32; end",
+        ] {
+            assert!(matches!(
+                parse(src).unwrap_err(),
+                ParseError::UnrecognizedToken { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn reject_long_identifiers() {
+        let name = "é".repeat(IDENT_LIMIT);
+        assert!(parse_stmt(&format!("let {} = 1;", name)).is_ok());
+        let err = parse_stmt(&format!("let {}x = 1;", name)).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::User {
+                error: SyntaxError::IdentifierTooLong(4, 4 + name.len() + 1, IDENT_LIMIT)
+            }
+        );
+        assert_eq!(diagnostic(&err).unwrap().message, "identifier too long");
+    }
+
+    #[test]
+    fn limit_nesting() {
+        let nested = |n: usize| {
+            let src = format!("program p return {}1{}; end", "(".repeat(n), ")".repeat(n));
+            check_nesting(&parse(&src).unwrap(), 8)
+        };
+        // The return is a level, the parenthesized expressions and the 1
+        assert!(nested(6).is_ok());
+        assert_eq!(
+            nested(7).unwrap_err(),
+            ParseError::User {
+                error: SyntaxError::TooDeeplyNested(24, 25, 8)
+            }
+        );
+        let src = "fun f() -> int32
+    if true then
+        repeat
+            return 1 + 2;
+        until false;
+    end
+    return 0;
+end";
+        let span = |limit| match check_nesting(&parse(src).unwrap(), limit) {
+            Err(err) => diagnostic(&err).unwrap().span,
+            Ok(()) => None,
+        };
+        assert_eq!(span(4), Some(Span::new(68, 73)));
+        assert_eq!(span(5), Some(Span::new(68, 69)));
+        assert_eq!(span(6), None);
+    }
+
+    #[test]
+    fn reject_deep_nesting_without_recursing() {
+        // Far deeper than the passes after parsing could recurse, or the
+        // tree could be built or dropped recursively
+        let n = 200_000;
+        for src in [
+            format!("program p return 1{}; end", " + 1".repeat(n)),
+            format!("program p return {}1{}; end", "(".repeat(n), ")".repeat(n)),
+            format!("program p return {}1; end", "-".repeat(n)),
+            format!(
+                "program p {}return 1;{} return 0; end",
+                "if true then ".repeat(n),
+                " end".repeat(n)
+            ),
+        ] {
+            assert!(check_nesting(&parse(&src).unwrap(), NESTING_LIMIT).is_err());
+            assert!(matches!(
+                crate::compile::compile_to_string(&src, &Default::default()),
+                Err(crate::compile::CompileError::Parse(_))
+            ));
+        }
+        // Or fails to parse after building a deep tree
+        let src = format!("program p return 1{} end", " + 1".repeat(n));
+        assert!(parse(&format!("{})", src)).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use crate::backends::c::CGenContext;
+use crate::backends::interp::Interpreter;
+use crate::codegen::{self, CodeGenError};
+use crate::compile::{self, CompileError};
+use crate::ir::IRNode;
+use crate::parser;
+use crate::session::Session;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thiserror::Error;
+
+// Differential testing of the backends. A program is run by the
+// interpreter, and as the checked binary the C backend builds from the
+// same IR, and the two have to end the same way and print the same
+// output. Where they don't, one of them has a bug.
+
+// How a run ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ending {
+    // As the OS reports an exit code, its low 8 bits
+    Exit(u8),
+    // A runtime error, with its message
+    Trap(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub ending: Ending,
+    pub stdout: String,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.ending {
+            Ending::Exit(code) => write!(f, "exited with {}", code)?,
+            Ending::Trap(message) => write!(f, "trapped: {}", message)?,
+        }
+        if !self.stdout.is_empty() {
+            write!(f, ", printing {:?}", self.stdout)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SelftestError {
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+    #[error(transparent)]
+    CodeGen(#[from] CodeGenError),
+    #[error("Couldn't run the binary: {0}")]
+    Run(std::io::Error),
+    #[error("The interpreter {interp}, but the C binary {c}")]
+    Mismatch { interp: Outcome, c: Outcome },
+}
+
+// Builds made at the same time, as tests are, each get their own files
+static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+// Runs the program under both backends, returning how it ended if they
+// agree. A program there's nothing to run of, without a program block or
+// calling `@extern` functions only a host provides, gives None.
+pub fn check(src: &str, session: &Session, cc: &str) -> Result<Option<Outcome>, SelftestError> {
+    let root = parser::parse(src).map_err(|err| CompileError::Parse(err.to_string()))?;
+    let state = compile::analyze_root(root, session, true)?;
+    let interpreter = Interpreter::new(state.build_stack.clone(), HashMap::new())?;
+    if !interpreter.has_program() || !interpreter.externs().is_empty() {
+        return Ok(None);
+    }
+    // Nothing the interpreter runs writes to stdout
+    let interp = Outcome {
+        ending: match interpreter.init().and_then(|_| interpreter.run()) {
+            Ok(code) => Ending::Exit(code as u8),
+            Err(trap) => Ending::Trap(trap.to_string()),
+        },
+        stdout: String::new(),
+    };
+    let c = run_c(&state.build_stack, src, cc)?;
+    if interp != c {
+        return Err(SelftestError::Mismatch { interp, c });
+    }
+    Ok(Some(interp))
+}
+
+fn run_c(build_stack: &[IRNode], src: &str, cc: &str) -> Result<Outcome, SelftestError> {
+    let n = BUILDS.fetch_add(1, Ordering::Relaxed);
+    let binary = std::env::temp_dir().join(format!("rascalc-selftest-{}-{}", process::id(), n));
+    let c_file = binary.with_extension("c");
+    let result = build_c(build_stack, src, cc, &binary, &c_file)
+        .and_then(|_| Command::new(&binary).output().map_err(SelftestError::Run));
+    let _ = fs::remove_file(&binary);
+    let _ = fs::remove_file(&c_file);
+    let output = result?;
+    // Checked binaries print what went wrong first, and then abort
+    let ending = match output.status.code() {
+        Some(code) => Ending::Exit(code as u8),
+        None => Ending::Trap(
+            String::from_utf8_lossy(&output.stderr)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+    };
+    Ok(Outcome {
+        ending,
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+    })
+}
+
+// Generates the C itself rather than through the backend's `gen`, which
+// writes it to out.c in the working directory, where builds made at the
+// same time would overwrite each other's
+fn build_c(
+    build_stack: &[IRNode],
+    src: &str,
+    cc: &str,
+    binary: &Path,
+    c_file: &Path,
+) -> Result<(), SelftestError> {
+    let mut ctx = codegen::new(build_stack.to_vec(), binary.to_string_lossy().into(), false);
+    ctx.debug_src = Some(src.to_string());
+    let c = CGenContext::from(ctx).gen_source()?;
+    fs::write(c_file, c).map_err(|source| CodeGenError::Io {
+        action: format!("write {}", c_file.display()),
+        source,
+    })?;
+    let output = Command::new(cc)
+        .arg(c_file)
+        .arg("-o")
+        .arg(binary)
+        .arg("-lm")
+        .output()
+        .map_err(|err| CodeGenError::spawn(cc, err))?;
+    if !output.status.success() {
+        return Err(CodeGenError::ToolFailed {
+            tool: cc.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_agree_across_backends() {
+        let mut ran = 0;
+        let mut paths: Vec<_> = fs::read_dir("samples")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ras"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let src = fs::read_to_string(&path).unwrap();
+            // Some samples show off errors, and have nothing to run
+            match check(&src, &Session::default(), "gcc") {
+                Ok(outcome) => ran += usize::from(outcome.is_some()),
+                Err(SelftestError::Compile(_)) => {}
+                Err(err) => panic!("{}: {}", path.display(), err),
+            }
+        }
+        assert!(ran >= 20, "only {} samples ran", ran);
+    }
+
+    #[test]
+    fn traps_agree_across_backends() {
+        for src in [
+            "fun quotient(a: int32, b: int32) -> int32 return a / b; end
+             program p return quotient(1, 0); end",
+            // A negative power is a division by the base
+            "fun power(a: int32, b: int32) -> int32 return a ** b; end
+             program p return power(0, -1); end",
+        ] {
+            let outcome = check(src, &Session::default(), "gcc").unwrap().unwrap();
+            assert_eq!(outcome.ending, Ending::Trap("Division by zero".into()));
+        }
+    }
+
+    #[test]
+    fn shadowing_agrees_across_backends() {
+        let src = "fun f(x: int32) -> int32
+                     let x = x + 1;
+                     let x = x * 2;
+                     return x;
+                   end
+                   program p
+                     let y = f(1);
+                     let y = y + 1;
+                     if y > 0 then
+                       let y = y * 10;
+                       return y;
+                     end
+                     return 0;
+                   end";
+        let outcome = check(src, &Session::default(), "gcc").unwrap().unwrap();
+        assert_eq!(outcome.ending, Ending::Exit(50));
+    }
+
+    #[test]
+    fn strings_end_at_nul_across_backends() {
+        let src = "program p
+                     let s = \"ab\\0cd\";
+                     let t = s + \"e\";
+                     if s == \"ab\" and t == \"abe\" then
+                       return int32(byte_len(s) * 10u64 + char_len(t));
+                     end
+                     return 1;
+                   end";
+        let outcome = check(src, &Session::default(), "gcc").unwrap().unwrap();
+        assert_eq!(outcome.ending, Ending::Exit(23));
+    }
+
+    #[test]
+    fn conversions_agree_across_backends() {
+        // Floats out of range saturate and NaN converts to 0, where a C
+        // cast would be undefined
+        let src = "program p
+                     let big: float64 = 100000000000000000000000.0;
+                     let nan: float64 = 0.0 / 0.0;
+                     let a: int64 = 5000000007i64;
+                     let b: int32 = int32(a);
+                     let c: float32 = float32(b) / 2.0f32;
+                     let d: int64 = int64(big) / 4611686018427387904i64;
+                     let e: uint64 = uint64(-big);
+                     let f: int32 = int32(nan) + int32(-2.7);
+                     return int32(int64(b) - 705032700i64 + int64(c) - 352516353i64 + d + int64(e)) + f;
+                   end";
+        let outcome = check(src, &Session::default(), "gcc").unwrap().unwrap();
+        assert_eq!(outcome.ending, Ending::Exit(9));
+    }
+}
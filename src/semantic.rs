@@ -1,12 +1,21 @@
 use std::collections::HashMap;
 
 use crate::ast::{
-    AssignOp, Block, Expr, Func, IfCases, Node, Program, Root, Stmt, Term, TypedExpr, TypedTerm,
+    AssignOp, Block, Docs, Expr, Func, IfCases, Label, LambdaFunc, Root, Span, Stmt, SwitchCase,
+    Term, TypedExpr, TypedTerm,
 };
+use crate::builtins;
+use crate::consteval;
+use crate::diagnostics::{line_col, Diagnostic};
+use crate::hir::{self, diverges, BinOp, ExprKind, LogicalOp, ScopeId, SymbolId, SymbolKind, UnOp};
+use crate::intrinsics;
 use crate::ir::{self, IRNode};
-use crate::symbol::{new_symbol, new_var, IdentMapping, Symbol, Symbolic, Var};
-use crate::traverse::Traverse;
-use crate::types::{self, Type};
+use crate::ircheck::{self, IRTypeError};
+use crate::lint;
+use crate::lower;
+use crate::optimize;
+use crate::symbol::{Symbol, Symbolic, Var};
+use crate::types::{FunctionType, Type, TypeMap};
 
 use thiserror::Error;
 
@@ -14,6 +23,128 @@ use thiserror::Error;
 pub enum BuildIRError {
     #[error("Couldn't build IR: {0}")]
     SomeError(String),
+    #[error("No definition for `{0}`")]
+    UnresolvedSymbol(String),
+    #[error("Couldn't determine the type of {0}")]
+    UnresolvedType(String),
+    #[error("`{0}` is not a function")]
+    NotCallable(String),
+    #[error("`{function}` takes {} but {} given", arguments(*expected), were(*found))]
+    Arity {
+        function: String,
+        expected: usize,
+        found: usize,
+        call: Span,
+        // The name at the definition, `None` for builtins
+        definition: Option<Span>,
+    },
+    #[error("{0} can't be lowered to IR yet")]
+    Unsupported(String),
+    #[error("`{0}` has no size")]
+    NoSize(Type),
+    #[error("`{0}` is declared `never` but may return")]
+    MayReturn(String),
+    #[error("Can only switch on integers, found `{0}`")]
+    SwitchType(Type),
+    #[error("Switch cases must be integer constants")]
+    CaseLabel,
+    #[error("Duplicate switch case `{0}`")]
+    DuplicateCase(String),
+    #[error("A switch can only have one `default` case")]
+    DuplicateDefault,
+    #[error("`break` outside of a loop")]
+    BreakOutsideLoop,
+    #[error("No enclosing loop is labeled `{0}`")]
+    UnknownLabel(String),
+    #[error("The loop label `{0}` is already used by an enclosing loop")]
+    DuplicateLabel(String),
+    #[error("`defer` can only be used inside a block")]
+    DeferOutsideBlock,
+    #[error("`{0}` statements can't be deferred")]
+    NotDeferrable(&'static str),
+    #[error("`static` variables can only be declared inside functions")]
+    StaticOutsideFunction,
+    #[error("The static variable `{0}` must be initialized with a literal")]
+    StaticInitializer(String),
+    #[error("Unknown attribute `@{0}`")]
+    UnknownAttribute(String),
+    #[error("`@unroll` only applies to `while` loops")]
+    UnrollNotLoop,
+    #[error(
+        "`@unroll` takes how many copies of the block to make, from 2 to {}",
+        optimize::MAX_UNROLL
+    )]
+    UnrollCount,
+    #[error("The condition of a static assertion must be a constant")]
+    NotConstant,
+    #[error("Static assertion failed: {0}")]
+    StaticAssertFailed(String),
+    #[error("The extern function `{0}` can't have a body")]
+    ExternBody(String),
+    #[error("Unknown intrinsic `{0}`")]
+    UnknownIntrinsic(String),
+    #[error(
+        "`{function}` is declared `{declared}`, but the intrinsic `{intrinsic}` is `{expected}`"
+    )]
+    IntrinsicSignature {
+        function: String,
+        intrinsic: String,
+        declared: Type,
+        expected: Type,
+    },
+    #[error("Arrays can only hold numbers and booleans, not `{0}`")]
+    ArrayElement(Type),
+    #[error("`{0}` is an array, only its elements are values")]
+    ArrayValue(String),
+    #[error("Index {index} is out of range for `{ident}`, which has {length} elements")]
+    IndexOutOfRange {
+        ident: String,
+        index: i128,
+        length: usize,
+    },
+    #[error("Internal compiler error, the IR is ill-typed: {0}")]
+    IllTyped(#[from] IRTypeError),
+}
+
+fn arguments(n: usize) -> String {
+    match n {
+        1 => "1 argument".into(),
+        n => format!("{} arguments", n),
+    }
+}
+
+fn were(n: usize) -> String {
+    match n {
+        1 => "1 was".into(),
+        n => format!("{} were", n),
+    }
+}
+
+impl BuildIRError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            BuildIRError::Arity {
+                function,
+                expected,
+                call,
+                definition,
+                ..
+            } => {
+                let diagnostic = Diagnostic::error(self.to_string())
+                    .with_span(*call)
+                    .with_label(format!("expected {}", arguments(*expected)));
+                match definition {
+                    Some(span) => {
+                        diagnostic.with_secondary(*span, format!("`{}` is defined here", function))
+                    }
+                    None => diagnostic,
+                }
+            }
+            BuildIRError::UnknownIntrinsic(_) => Diagnostic::error(self.to_string())
+                .with_help("name a C math function, as in `@intrinsic(\"sqrt\")`"),
+            _ => Diagnostic::error(self.to_string()),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -45,477 +176,1913 @@ pub fn sinsert(stack: &mut SymbolStack, symbol: Symbol, var: Var) -> Option<Var>
         .insert(symbol, var)
 }
 
+fn is_resolved(type_t: &Type) -> bool {
+    match type_t {
+        Type::Unknown | Type::TypeVar(_) => false,
+        Type::Function(func) => {
+            func.params_t.iter().all(is_resolved) && is_resolved(&func.return_t)
+        }
+        _ => true,
+    }
+}
+
+// The reason a function is deprecated, `Some("")` if it is without one
+fn deprecation(func: &Func) -> Result<Option<String>, BuildIRError> {
+    let mut deprecated = None;
+    for attr in func.attrs.iter() {
+        match attr.name.as_str() {
+            "deprecated" => deprecated = Some(attr.args.first().cloned().unwrap_or_default()),
+            "extern" | "intrinsic" => {}
+            _ => return Err(BuildIRError::UnknownAttribute(attr.name.clone())),
+        }
+    }
+    Ok(deprecated)
+}
+
+// The C function a function declared `@intrinsic` stands for, which
+// must be one there is and have the same signature
+fn intrinsic(func: &Func) -> Result<Option<String>, BuildIRError> {
+    let Some(ident) = func.intrinsic() else {
+        return Ok(None);
+    };
+    let intrinsic =
+        intrinsics::lookup(ident).ok_or_else(|| BuildIRError::UnknownIntrinsic(ident.into()))?;
+    let declared = func_type(func);
+    if declared != intrinsic.func_type() {
+        return Err(BuildIRError::IntrinsicSignature {
+            function: func.ident.clone(),
+            intrinsic: ident.into(),
+            declared,
+            expected: intrinsic.func_type(),
+        });
+    }
+    Ok(Some(ident.into()))
+}
+
+fn func_type(func: &Func) -> Type {
+    Type::Function(FunctionType {
+        params_t: func.params.iter().map(|p| p.type_t.clone()).collect(),
+        return_t: Box::new(func.return_t.clone()),
+    })
+}
+
+// Makes the widening of `expr` to a declared type explicit
+fn widen(expr: hir::Expr, to: &Type) -> hir::Expr {
+    if expr.type_t.widens_to(to) {
+        hir::Expr {
+            type_t: to.clone(),
+            kind: ExprKind::Cast(Box::new(expr)),
+        }
+    } else {
+        expr
+    }
+}
+
+// Roughly where a statement starts, for stepping through it in a
+// debugger. Statements that only hold others, or that run nothing
+// themselves, have none.
+fn stmt_span(stmt: &Stmt) -> Option<Span> {
+    match stmt {
+        Stmt::Assign(_, _, _, _, span, _)
+        | Stmt::Reassign(.., span)
+        | Stmt::Store(.., span)
+        | Stmt::Switch(.., span)
+        | Stmt::Bench(.., span)
+        | Stmt::Break(_, span)
+        | Stmt::Call(.., span) => Some(*span),
+        Stmt::MultiAssign(targets, _) => targets.first().map(|(_, span)| *span),
+        Stmt::If(cases) => cases.first().map(|case| case.condition.span),
+        Stmt::Static(stmt) => stmt_span(stmt),
+        Stmt::Return(expr) => Some(expr.span),
+        // The statements in a loop have their own
+        Stmt::Repeat(..) => None,
+        Stmt::While(condition, ..) => Some(condition.span),
+        Stmt::Declare(..)
+        | Stmt::Defer(_)
+        | Stmt::StaticAssert(..)
+        | Stmt::Attributed(..)
+        | Stmt::FuncDef(_) => None,
+    }
+}
+
+// What a statement or expression is called in the trace
+fn stmt_node(stmt: &Stmt) -> (String, Option<Span>) {
+    let node = match stmt {
+        Stmt::Assign(symbol, ..) => format!("let {}", symbol.ident),
+        Stmt::Reassign(symbol, ..) => format!("assign {}", symbol.ident),
+        Stmt::Declare(symbol, ..) => format!("let {}", symbol.ident),
+        Stmt::Store(symbol, ..) => format!("assign {}[ ]", symbol.ident),
+        Stmt::MultiAssign(..) => "assign".into(),
+        Stmt::If(_) => "if".into(),
+        Stmt::Switch(..) => "switch".into(),
+        Stmt::Repeat(..) => "repeat".into(),
+        Stmt::While(..) => "while".into(),
+        Stmt::Bench(label, ..) => format!("bench {:?}", label),
+        Stmt::Break(..) => "break".into(),
+        Stmt::Defer(_) => "defer".into(),
+        Stmt::Static(_) => "static".into(),
+        Stmt::StaticAssert(..) => "static_assert".into(),
+        Stmt::Attributed(..) => "attributed".into(),
+        Stmt::Call(symbol, ..) => format!("call {}", symbol.ident),
+        Stmt::FuncDef(func) => return (format!("fun {}", func.ident), Some(func.span)),
+        Stmt::Return(_) => "return".into(),
+    };
+    (node, stmt_span(stmt))
+}
+
+fn expr_node(expr: &Expr) -> String {
+    match expr {
+        Expr::Term(term) => match &term.term {
+            Term::Id(ident) => return format!("var {}", ident),
+            Term::Expr(_) => "( )",
+            Term::Num(..) | Term::Bool(_) | Term::String(_) => "literal",
+        },
+        Expr::Add(..) => "+",
+        Expr::Sub(..) | Expr::Neg(_) => "-",
+        Expr::Mult(..) => "*",
+        Expr::Div(..) => "/",
+        Expr::Mod(..) => "%",
+        Expr::Pow(..) => "**",
+        Expr::BitAnd(..) => "&",
+        Expr::BitOr(..) => "|",
+        Expr::BitXor(..) => "^",
+        Expr::Shl(..) => "<<",
+        Expr::Shr(..) => ">>",
+        Expr::BitNot(_) => "~",
+        Expr::Eq(..) => "==",
+        Expr::Neq(..) => "!=",
+        Expr::Leq(..) => "<=",
+        Expr::Geq(..) => ">=",
+        Expr::LessThan(..) => "<",
+        Expr::GreaterThan(..) => ">",
+        Expr::And(..) => "and",
+        Expr::Or(..) => "or",
+        Expr::Not(_) => "!",
+        Expr::Call(symbol, _) => return format!("call {}", symbol.ident),
+        Expr::Index(symbol, ..) => return format!("{}[ ]", symbol.ident),
+        Expr::LambdaFunc(_) => "lambda",
+        Expr::SizeOf(_) => "size_of",
+        Expr::TypeName(_) => "type_name",
+        Expr::Convert(..) => "conversion",
+    }
+    .into()
+}
+
+// A statement or expression the analysis reached. Expressions get their
+// type once they've been analyzed, so the last entry without one, if
+// the analysis failed, is where it did.
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    pub depth: usize,
+    pub node: String,
+    pub span: Option<Span>,
+    pub type_t: Option<Type>,
+}
+
+// Semantic analysis resolves the type-substituted AST into the typed
+// HIR (see hir.rs), which is then lowered to IR by lower.rs
 #[derive(Debug)]
 pub struct ProgramState {
-    pub stack: SymbolStack,
     pub ast: Box<Root>,
+    pub symbols: hir::Symbols,
+    pub scopes: Vec<HashMap<String, SymbolId>>,
+    // The ids of `scopes`, see hir::Scope
+    scope_ids: Vec<ScopeId>,
+    pub hir: Option<hir::Module>,
+    // Types of the expressions checked so far, for diagnostics and tooling
+    pub types: TypeMap,
     pub build_stack: Vec<IRNode>,
-    pub scope_counter: usize,
+    // Name of the function (or program) currently being analyzed
+    enclosing: String,
+    // Return type of the function currently being analyzed
+    return_t: Option<Type>,
+    // Labels of the loops enclosing the statement being analyzed,
+    // innermost last, with the number of defer frames open outside each
+    loops: Vec<(Option<String>, usize)>,
+    // Statements deferred in each enclosing block, innermost last
+    defers: Vec<Vec<hir::Stmt>>,
+    // Lints allowed by the attributes of the statement being analyzed
+    allowed: Vec<String>,
+    // Problems that don't stop the build
+    pub warnings: Vec<Diagnostic>,
+    // Store every intermediate value in a temporary, see lower.rs
+    pub flatten_exprs: bool,
+    // Mark where each statement starts in the source, for debuggers
+    pub debug_info: bool,
+    // 0 leaves the IR as lowered, from 1 globals are propagated and tail
+    // calls looped, and 3 unrolls small loops too
+    pub opt_level: u8,
+    // Record the statements and expressions analyzed, for debugging the
+    // analysis itself. None unless asked for.
+    pub trace: Option<Vec<TraceEntry>>,
+    trace_depth: usize,
 }
 
-impl Traverse for ProgramState {
-    type Error = BuildIRError;
+impl ProgramState {
+    pub const IR_OUTPUT_FILENAME: &'static str = "out.ir";
 
-    fn visit_root(&mut self, root: &mut Root) -> Result<(), Self::Error> {
-        let Root {
-            preblock,
-            program,
-            postblock,
-        } = root;
-        self.build_stack.push(IRNode::GlobalSection);
-        self.visit_preblock(preblock)?;
-        self.visit_postblock(postblock)?;
-        self.build_stack.push(IRNode::EndGlobalSection);
-        self.visit_program(program)?;
-        Ok(())
+    pub fn new(ast: Box<Root>) -> ProgramState {
+        ProgramState {
+            ast,
+            symbols: hir::Symbols::default(),
+            scopes: vec![],
+            scope_ids: vec![],
+            hir: None,
+            types: TypeMap::new(),
+            build_stack: vec![],
+            enclosing: String::new(),
+            return_t: None,
+            loops: vec![],
+            defers: vec![],
+            allowed: vec![],
+            warnings: vec![],
+            flatten_exprs: false,
+            debug_info: false,
+            opt_level: 2,
+            trace: None,
+            trace_depth: 0,
+        }
     }
 
-    fn visit_preblock(&mut self, preblock: &mut Block) -> Result<(), Self::Error> {
-        self.visit_block(preblock)
+    // Opens a scope for what `name` says the block is
+    pub fn spush(&mut self, name: impl Into<String>) {
+        let id = self.symbols.add_scope(name, self.scope_ids.last().copied());
+        self.scope_ids.push(id);
+        self.scopes.push(HashMap::new());
     }
 
-    fn visit_postblock(&mut self, postblock: &mut Block) -> Result<(), Self::Error> {
-        self.visit_block(postblock)
+    pub fn spop(&mut self) -> Option<HashMap<String, SymbolId>> {
+        self.scope_ids.pop();
+        self.scopes.pop()
     }
 
-    fn visit_program(&mut self, program: &mut Program) -> Result<(), Self::Error> {
-        self.visit_block(&mut program.1)
+    pub fn build_ir(&mut self) -> Result<(), BuildIRError> {
+        let mut module = self.build_hir()?;
+        self.warnings.extend(lint::lint(&module));
+        optimize::unroll_loops(&mut module, self.opt_level >= 3 && !self.debug_info);
+        self.build_stack = lower::lower(&module, self.flatten_exprs)?;
+        // Globals stay put when debugging, for the debugger to show, as
+        // do the calls runtime errors print
+        if self.opt_level >= 1 && !self.debug_info {
+            self.build_stack = optimize::propagate_globals(std::mem::take(&mut self.build_stack));
+            self.build_stack =
+                optimize::eliminate_tail_calls(std::mem::take(&mut self.build_stack));
+        }
+        ircheck::check(&self.build_stack)?;
+        self.hir = Some(module);
+        Ok(())
     }
 
-    fn visit_block(&mut self, block: &mut Block) -> Result<(), Self::Error> {
-        self.spush()?;
-        for s in block {
-            self.visit_stmt(s)?;
+    pub fn build_hir(&mut self) -> Result<hir::Module, BuildIRError> {
+        self.spush("global");
+        for builtin in builtins::builtins() {
+            self.declare(
+                builtin.ident,
+                builtin.func_type(),
+                SymbolKind::Builtin,
+                None,
+            );
         }
-        let _ = self.spop();
-        Ok(())
+        // The runtime's helpers have symbols for the calls generated to
+        // them, but no scope to be looked up in
+        let scope = *self.scope_ids.last().expect("No scopes in symbol table!");
+        for helper in builtins::runtime() {
+            self.symbols.add(
+                helper.ident.to_string(),
+                helper.func_type(),
+                SymbolKind::Builtin,
+                scope,
+                None,
+            );
+        }
+        // Find the signature of the `program` block, if the unit has one
+        let program_symbol = self.program_signature_discovery()?;
+        // Discover the functions and variables in the global scope
+        //  -> but, don't parse function bodies
+        self.global_ident_discovery()?;
+        // check the program
+        let ast = self.ast.clone();
+        let mut globals = vec![];
+        for stmt in ast.preblock.iter().chain(ast.postblock.iter()) {
+            self.hir_block_stmt(stmt, &mut globals)?;
+        }
+        let program = match (&ast.program, program_symbol) {
+            (Some(program), Some(symbol)) => {
+                self.enclosing = program.0.ident.clone();
+                Some(hir::Program {
+                    symbol,
+                    block: self.hir_block(&program.1, &format!("program {}", program.0.ident))?,
+                })
+            }
+            _ => None,
+        };
+        self.spop();
+        Ok(hir::Module {
+            docs: ast.docs.clone(),
+            symbols: self.symbols.clone(),
+            globals,
+            program,
+        })
     }
 
-    fn visit_stmt(&mut self, stmt: &mut Stmt) -> Result<(), Self::Error> {
-        match stmt {
-            Stmt::If(ifcases) => {
-                self.visit_if_cases(ifcases)?;
-            }
-            Stmt::Call(symbol, args) => {
-                self.visit_args(args);
-                let resolved_ret_t = match slookup(&self.stack, symbol.clone()) {
-                    Some(var) => &var.type_t,
-                    None => {
-                        return Err(BuildIRError::SomeError("Missing symbol".into()));
-                    }
-                };
-                self.build_stack
-                    .push(IRNode::Eval(ir::Func::Func(ir::Signature {
-                        symbol: symbol.clone(),
-                        params_t: args.iter().map(|p| p.type_t.clone()).collect(),
-                        return_t: resolved_ret_t.clone(),
-                    })));
-            }
-            Stmt::Assign(symbol, var, expr) => {
-                self.visit_expr(expr)?;
-                sinsert(
-                    &mut self.stack,
-                    symbol.clone(),
-                    new_var(expr.type_t.clone(), Node::Null),
-                );
-                self.build_stack.push(IRNode::Assign(ir::Assign {
-                    type_t: var.type_t.clone(),
-                    symbol: symbol.clone(),
-                }));
-            }
-            Stmt::Reassign(symbol, var, assign_op, expr) => {
-                let mut new_expr = match assign_op {
-                    AssignOp::Assign => expr.clone(),
-                    AssignOp::AddAssign => Box::new(TypedExpr {
-                        type_t: expr.type_t.clone(),
-                        expr: Expr::Add(
-                            Box::new(TypedExpr {
-                                type_t: expr.type_t.clone(),
-                                expr: Expr::Term(Box::new(TypedTerm {
-                                    type_t: expr.type_t.clone(),
-                                    term: Term::Id(symbol.ident.clone()),
-                                })),
-                            }),
-                            expr.clone(),
-                        ),
-                    }),
-                    AssignOp::SubAssign => Box::new(TypedExpr {
-                        type_t: expr.type_t.clone(),
-                        expr: Expr::Sub(
-                            Box::new(TypedExpr {
-                                type_t: expr.type_t.clone(),
-                                expr: Expr::Term(Box::new(TypedTerm {
-                                    type_t: expr.type_t.clone(),
-                                    term: Term::Id(symbol.ident.clone()),
-                                })),
-                            }),
-                            expr.clone(),
-                        ),
-                    }),
-                    AssignOp::MultAssign => Box::new(TypedExpr {
-                        type_t: expr.type_t.clone(),
-                        expr: Expr::Mult(
-                            Box::new(TypedExpr {
-                                type_t: expr.type_t.clone(),
-                                expr: Expr::Term(Box::new(TypedTerm {
-                                    type_t: expr.type_t.clone(),
-                                    term: Term::Id(symbol.ident.clone()),
-                                })),
-                            }),
-                            expr.clone(),
-                        ),
-                    }),
-                    AssignOp::DivAssign => Box::new(TypedExpr {
-                        type_t: expr.type_t.clone(),
-                        expr: Expr::Div(
-                            Box::new(TypedExpr {
-                                type_t: expr.type_t.clone(),
-                                expr: Expr::Term(Box::new(TypedTerm {
-                                    type_t: expr.type_t.clone(),
-                                    term: Term::Id(symbol.ident.clone()),
-                                })),
-                            }),
-                            expr.clone(),
-                        ),
-                    }),
-                };
-                self.visit_expr(&mut new_expr)?;
-                sinsert(
-                    &mut self.stack,
-                    symbol.clone(),
-                    new_var(new_expr.type_t, Node::Null),
-                );
-                self.build_stack.push(IRNode::Reassign(ir::Reassign {
-                    type_t: var.type_t.clone(),
-                    symbol: symbol.clone(),
-                }));
-            }
-            Stmt::FuncDef(func) => {
-                self.visit_func(func)?;
+    // The trace as a tree, children indented under the statement or
+    // expression they belong to:
+    //
+    //   return 2:12
+    //     * 2:12: float32
+    //       var x 2:12: float32
+    pub fn render_trace(&self, src: &str) -> String {
+        let mut out = String::new();
+        for entry in self.trace.iter().flatten() {
+            out.push_str(&format!("{:1$}{2}", "", entry.depth * 2, entry.node));
+            if let Some(span) = entry.span {
+                let (line, col) = line_col(src, span.start);
+                out.push_str(&format!(" {}:{}", line, col));
             }
-            Stmt::Return(expr) => {
-                self.visit_expr(expr)?;
-                self.build_stack.push(IRNode::Return);
+            if let Some(type_t) = &entry.type_t {
+                out.push_str(&format!(": {}", type_t));
             }
+            out.push('\n');
+        }
+        out
+    }
+
+    // Returns the index of the entry, to finish it with `trace_leave`
+    fn trace_enter(&mut self, node: impl FnOnce() -> (String, Option<Span>)) -> Option<usize> {
+        let trace = self.trace.as_mut()?;
+        let (node, span) = node();
+        trace.push(TraceEntry {
+            depth: self.trace_depth,
+            node,
+            span,
+            type_t: None,
+        });
+        self.trace_depth += 1;
+        Some(trace.len() - 1)
+    }
+
+    fn trace_leave(&mut self, entry: Option<usize>, type_t: Option<&Type>) {
+        if let (Some(trace), Some(entry)) = (self.trace.as_mut(), entry) {
+            trace[entry].type_t = type_t.cloned();
+            self.trace_depth -= 1;
+        }
+    }
+
+    fn program_signature_discovery(&mut self) -> Result<Option<SymbolId>, BuildIRError> {
+        let Some(program) = self.ast.program.clone() else {
+            return Ok(None);
         };
-        Ok(())
+        match program.get_symbol() {
+            Some(prog_symbol) => Ok(Some(self.declare(
+                &prog_symbol.symbol.ident,
+                prog_symbol.var.type_t,
+                SymbolKind::Program,
+                Some(program.2),
+            ))),
+            None => Err(BuildIRError::SomeError("No program block found".into())),
+        }
     }
 
-    fn visit_expr(&mut self, expr: &mut TypedExpr) -> Result<(), Self::Error> {
-        match expr.expr.clone() {
-            Expr::Add(_, _) => self.binary_op(expr.clone()),
-            Expr::Sub(_, _) => self.binary_op(expr.clone()),
-            Expr::Mult(_, _) => self.binary_op(expr.clone()),
-            Expr::Div(_, _) => self.binary_op(expr.clone()),
-            Expr::Eq(_, _) => self.binary_op(expr.clone()),
-            Expr::Neq(_, _) => self.binary_op(expr.clone()),
-            Expr::Leq(_, _) => self.binary_op(expr.clone()),
-            Expr::Geq(_, _) => self.binary_op(expr.clone()),
-            Expr::LessThan(_, _) => self.binary_op(expr.clone()),
-            Expr::GreaterThan(_, _) => self.binary_op(expr.clone()),
-            Expr::Not(_) => self.unary_op(expr.clone()),
-            Expr::Neg(_) => self.unary_op(expr.clone()),
-            Expr::Term(mut term) => self.visit_term(&mut term),
-            Expr::Call(symbol, mut args) => {
-                self.visit_args(&mut args);
-                self.build_stack
-                    .push(IRNode::Eval(ir::Func::Func(ir::Signature {
-                        symbol,
-                        params_t: args.iter().map(|p| p.type_t.clone()).collect(),
-                        return_t: expr.type_t.clone(),
-                    })));
-                Ok(())
-            }
-            Expr::LambdaFunc(mut lf) => self.visit_lambda_func(&mut lf),
-        }
-    }
-
-    fn visit_term(&mut self, term: &mut TypedTerm) -> Result<(), Self::Error> {
-        match term.term.clone() {
-            Term::Id(ident) => {
-                self.build_stack.push(IRNode::Term(ir::Term {
-                    type_t: term.type_t.clone(),
-                    value: ir::Value::Id(ident),
-                }));
-            }
-            Term::Expr(mut expr) => {
-                self.visit_expr(&mut expr);
-            }
-            Term::Bool(b) => {
-                self.build_stack.push(IRNode::Term(ir::Term {
-                    type_t: term.type_t.clone(),
-                    value: ir::Value::Bool(b),
-                }));
-            }
-            Term::String(s) => {
-                self.build_stack.push(IRNode::Term(ir::Term {
-                    type_t: term.type_t.clone(),
-                    value: ir::Value::String(s),
-                }));
-            }
-            Term::Num(num) => {
-                self.build_stack.push(IRNode::Term(ir::Term {
-                    type_t: term.type_t.clone(),
-                    value: num.try_into().unwrap(),
-                }));
+    fn global_ident_discovery(&mut self) -> Result<(), BuildIRError> {
+        // First pass: discover types and signatures of global identifiers
+        let global_stmts: Vec<Box<Stmt>> = self
+            .ast
+            .preblock
+            .iter()
+            .chain(self.ast.postblock.iter())
+            .cloned()
+            .collect();
+        for stmt in global_stmts {
+            if let Some(mapping) = stmt.get_symbol() {
+                let kind = match *stmt {
+                    Stmt::FuncDef(_) => SymbolKind::Function,
+                    _ => SymbolKind::Global,
+                };
+                let id = self.declare(&mapping.symbol.ident, mapping.var.type_t, kind, None);
+                if let Stmt::FuncDef(func) = stmt.as_ref() {
+                    self.symbols.symbols[id.0].deprecated = deprecation(func)?;
+                }
             }
         }
         Ok(())
     }
 
-    fn visit_func(&mut self, func: &mut Func) -> Result<(), Self::Error> {
-        let func_ir_num = self.get_new_scope();
-        let func_ir_id = format!("_func_def_{}", func_ir_num);
-        let return_type = func.return_t.clone();
-        let param_types: Vec<Type> = func.params.iter().map(|p| p.type_t.clone()).collect();
-        let func_symbol = new_symbol(func.ident.clone());
-        sinsert(
-            &mut self.stack,
-            func_symbol.clone(),
-            new_var(
-                Type::Function(types::FunctionType {
-                    params_t: param_types.clone(),
-                    return_t: Box::new(return_type.clone()),
-                }),
-                Node::Null,
-            ),
-        );
-        self.build_stack.push(IRNode::FuncDef(
-            ir::FuncDef {
-                symbol: func_symbol,
-                return_t: return_type.clone(),
-                params_t: func
-                    .params
-                    .iter()
-                    .map(|p| (p.ident.clone(), p.type_t.clone()))
-                    .collect(),
-            },
-            func_ir_id.clone(),
-        ));
-        self.visit_block(&mut func.block)?;
-        self.build_stack.push(IRNode::EndFuncDef(func_ir_id));
-        Ok(())
+    fn declare(
+        &mut self,
+        ident: &str,
+        type_t: Type,
+        kind: SymbolKind,
+        span: Option<Span>,
+    ) -> SymbolId {
+        let scope = *self.scope_ids.last().expect("No scopes in symbol table!");
+        let id = self
+            .symbols
+            .add(ident.to_string(), type_t, kind, scope, span);
+        self.scopes
+            .last_mut()
+            .expect("No scopes in symbol table!")
+            .insert(ident.to_string(), id);
+        id
     }
 
-    fn visit_if_cases(&mut self, if_cases: &mut IfCases) -> Result<(), Self::Error> {
-        let if_ir_num = self.get_new_scope();
-        let if_ir_id = format!("_if_stmt_{}", if_ir_num);
-        self.build_stack.push(IRNode::If(if_ir_id.clone()));
-        let num_cases = if_cases.len();
-        for (n, if_case) in if_cases.into_iter().enumerate() {
-            self.visit_expr(&mut if_case.condition.clone())?;
-            if n == 0 {
-                self.build_stack.push(IRNode::IfCase(if_ir_id.clone()));
-            } else if if_case.is_else {
-                self.build_stack.push(IRNode::ElseCase(if_ir_id.clone()));
-            } else {
-                self.build_stack.push(IRNode::ElseIfCase(if_ir_id.clone()));
+    // Globals and top level functions were declared up front by
+    // global_ident_discovery, so their definitions reuse those symbols
+    fn define(
+        &mut self,
+        ident: &str,
+        type_t: Type,
+        kind: SymbolKind,
+        docs: &Docs,
+        span: Span,
+    ) -> Result<SymbolId, BuildIRError> {
+        let type_t = self.resolved_type(&type_t, || format!("`{}`", ident))?;
+        let declared = self
+            .scopes
+            .last()
+            .and_then(|scope| scope.get(ident))
+            .copied();
+        let predeclared = declared.filter(|id| {
+            let symbol = self.symbols.get(*id);
+            self.scopes.len() == 1
+                || (kind == SymbolKind::Function
+                    && symbol.kind == SymbolKind::Function
+                    && symbol.span.is_none())
+        });
+        let id = match predeclared {
+            Some(id) => {
+                self.symbols.symbols[id.0].type_t = type_t;
+                self.symbols.symbols[id.0].span = Some(span);
+                id
             }
-            self.visit_block(&mut if_case.block)?;
-        }
-        self.build_stack.push(IRNode::EndIf(if_ir_id.clone()));
-        Ok(())
+            _ => self.declare(ident, type_t, kind, Some(span)),
+        };
+        self.symbols.symbols[id.0].docs = docs.clone();
+        Ok(id)
     }
-}
 
-impl ProgramState {
-    pub const IR_OUTPUT_FILENAME: &'static str = "out.ir";
+    // Resolves a use of `ident` at `span` and records the reference
+    fn resolve(&mut self, ident: &str, span: Span) -> Result<SymbolId, BuildIRError> {
+        let id = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(ident))
+            .copied()
+            .ok_or(BuildIRError::UnresolvedSymbol(ident.to_string()))?;
+        self.symbols.add_reference(id, span);
+        Ok(id)
+    }
 
-    pub fn new(ast: Box<Root>) -> ProgramState {
-        ProgramState {
-            stack: vec![],
-            build_stack: vec![],
-            ast,
-            scope_counter: 0,
+    fn resolved_type(
+        &self,
+        type_t: &Type,
+        describe: impl Fn() -> String,
+    ) -> Result<Type, BuildIRError> {
+        if is_resolved(type_t) {
+            Ok(type_t.clone())
+        } else {
+            Err(BuildIRError::UnresolvedType(describe()))
         }
     }
 
-    pub fn spush(&mut self) -> Result<(), BuildIRError> {
-        self.stack.push(new_empty_symbol_table());
+    // Starts analyzing the block of a loop, which the caller ends by
+    // popping it off `loops`
+    fn enter_loop(&mut self, label: &Option<Label>) -> Result<(), BuildIRError> {
+        let label = label.as_ref().map(|l| l.ident.clone());
+        let used = self.loops.iter().any(|(l, _)| *l == label);
+        if let Some(ident) = label.as_ref().filter(|_| used) {
+            return Err(BuildIRError::DuplicateLabel(ident.clone()));
+        }
+        self.loops.push((label, self.defers.len()));
         Ok(())
     }
 
-    pub fn spop(&mut self) -> Option<SymbolTable> {
-        self.stack.pop()
+    // `name` says what the block belongs to, see hir::Scope
+    fn hir_block(&mut self, block: &Block, name: &str) -> Result<hir::Block, BuildIRError> {
+        self.spush(name);
+        let stmts = self.hir_stmts(block);
+        self.spop();
+        stmts
     }
 
-    pub fn get_new_scope(&mut self) -> usize {
-        let new_scope = self.scope_counter;
-        self.scope_counter += 1;
-        new_scope
+    // Deferred statements are desugared by copying them, innermost and
+    // latest first, to every point where control leaves their block:
+    // its end, and before any return or break out of it
+    fn hir_stmts(&mut self, block: &Block) -> Result<hir::Block, BuildIRError> {
+        // Functions can be called from anywhere in their block, so they're
+        // declared before any of it is analyzed
+        for stmt in block.iter() {
+            if let Stmt::FuncDef(func) = stmt.as_ref() {
+                let type_t =
+                    self.resolved_type(&func_type(func), || format!("`{}`", func.ident))?;
+                self.declare(&func.ident, type_t, SymbolKind::Function, None);
+            }
+        }
+        self.defers.push(vec![]);
+        let stmts = self.hir_deferring_stmts(block);
+        let deferred = self.defers.pop().unwrap_or_default();
+        let mut stmts = stmts?;
+        if !diverges(&stmts) {
+            stmts.extend(deferred.into_iter().rev());
+        }
+        Ok(stmts)
     }
 
-    pub fn build_ir(&mut self) -> Result<(), BuildIRError> {
-        self.spush();
-        // Find the signature of `program` blocks
-        //  -> if there are none, we can abort compilation :)
-        self.program_signature_discovery()?;
-        // Discover the functions and variables in the global scope
-        //  -> but, don't parse function bodies
-        self.global_ident_discovery()?;
-        // check the program
-        let mut ast = self.ast.clone();
-        self.visit_root(&mut ast)?;
+    fn hir_deferring_stmts(&mut self, block: &Block) -> Result<hir::Block, BuildIRError> {
+        let mut stmts = vec![];
+        for stmt in block.iter() {
+            self.hir_block_stmt(stmt, &mut stmts)?;
+        }
+        Ok(stmts)
+    }
+
+    // Adds the HIR for a statement of a block to `stmts`, which can take
+    // any number of statements, including none for a `defer`
+    fn hir_block_stmt(&mut self, stmt: &Stmt, stmts: &mut hir::Block) -> Result<(), BuildIRError> {
+        let entry = self.trace_enter(|| stmt_node(stmt));
+        self.hir_untraced_block_stmt(stmt, stmts)?;
+        self.trace_leave(entry, None);
         Ok(())
     }
 
-    fn program_signature_discovery(&mut self) -> Result<(), BuildIRError> {
-        let gen_prog_symbol = self.ast.program.get_symbol();
-        if let Some(prog_symbol) = gen_prog_symbol {
-            self.stack
-                .first_mut()
-                .expect("No base node found!")
-                .table
-                .insert(prog_symbol.symbol, prog_symbol.var);
+    fn hir_untraced_block_stmt(
+        &mut self,
+        stmt: &Stmt,
+        stmts: &mut hir::Block,
+    ) -> Result<(), BuildIRError> {
+        if self.debug_info {
+            if let Some(span) = stmt_span(stmt) {
+                stmts.push(hir::Stmt::Loc(span));
+            }
+        }
+        match stmt {
+            Stmt::Defer(deferred) => {
+                let kind = match deferred.as_ref() {
+                    Stmt::Assign(..) | Stmt::Declare(..) => Some("let"),
+                    Stmt::Return(_) => Some("return"),
+                    Stmt::Break(..) => Some("break"),
+                    Stmt::Defer(_) => Some("defer"),
+                    Stmt::StaticAssert(..) => Some("static_assert"),
+                    _ => None,
+                };
+                if let Some(kind) = kind {
+                    return Err(BuildIRError::NotDeferrable(kind));
+                }
+                let deferred = self.hir_stmt(deferred)?;
+                self.defers
+                    .last_mut()
+                    .ok_or(BuildIRError::DeferOutsideBlock)?
+                    .push(deferred);
+                return Ok(());
+            }
+            Stmt::StaticAssert(condition, message, _) => {
+                return self.static_assert(condition, message);
+            }
+            Stmt::Bench(label, count, block, span) => {
+                return self.hir_bench(label, count.as_deref(), block, *span, stmts);
+            }
+            Stmt::Attributed(attrs, stmt) => {
+                let allowed = self.allowed.len();
+                let mut unroll = None;
+                for attr in attrs.iter() {
+                    match attr.name.as_str() {
+                        "allow" => self.allowed.extend(attr.args.iter().cloned()),
+                        "unroll" if !matches!(**stmt, Stmt::While(..)) => {
+                            return Err(BuildIRError::UnrollNotLoop);
+                        }
+                        "unroll" => match attr.args.as_slice() {
+                            [count] => match count.parse() {
+                                Ok(count) if (2..=optimize::MAX_UNROLL).contains(&count) => {
+                                    unroll = Some(count)
+                                }
+                                _ => return Err(BuildIRError::UnrollCount),
+                            },
+                            _ => return Err(BuildIRError::UnrollCount),
+                        },
+                        _ => return Err(BuildIRError::UnknownAttribute(attr.name.clone())),
+                    }
+                }
+                let result = self.hir_block_stmt(stmt, stmts);
+                self.allowed.truncate(allowed);
+                // The loop is unrolled after linting, which only needs to
+                // see the block once
+                if let (Some(count), Some(hir::Stmt::While(_, _, copies))) =
+                    (unroll, stmts.last_mut())
+                {
+                    *copies = count;
+                }
+                return result;
+            }
+            _ => {}
+        }
+        match self.hir_stmt(stmt)? {
+            hir::Stmt::Return(value) => {
+                let pending = self.pending_defers(0);
+                if pending.is_empty() {
+                    stmts.push(hir::Stmt::Return(value));
+                } else {
+                    // The value is computed before the deferred
+                    // statements run, which may change what it reads
+                    let type_t = value.type_t.clone();
+                    let ident = format!("_defer_return_{}", self.symbols.symbols.len());
+                    let id = self.declare(&ident, type_t.clone(), SymbolKind::Local, None);
+                    stmts.push(hir::Stmt::Let(id, value));
+                    stmts.extend(pending);
+                    stmts.push(hir::Stmt::Return(hir::Expr {
+                        type_t,
+                        kind: ExprKind::Var(id),
+                    }));
+                }
+            }
+            hir::Stmt::Break(depth) => {
+                let (_, frames) = self.loops[self.loops.len() - 1 - depth];
+                stmts.extend(self.pending_defers(frames));
+                stmts.push(hir::Stmt::Break(depth));
+            }
+            other => stmts.push(other),
         }
         Ok(())
     }
 
-    fn global_ident_discovery(&mut self) -> Result<(), BuildIRError> {
-        // First pass: discover types and signatures of global identifiers
-        let pre_idents: Vec<IdentMapping> = self
-            .ast
-            .preblock
-            .iter()
-            .filter_map(|stmt| stmt.get_symbol())
-            .collect();
+    // A bench is desugared to a loop running the block, between two
+    // readings of the clock, and a call reporting how long it took:
+    //
+    //     let _bench_start = clock(); let _bench_count = n; let _bench_i = 0;
+    //     while _bench_i < _bench_count do block; _bench_i += 1; end
+    //     bench_report(label, _bench_count, clock() - _bench_start);
+    //
+    // As in a function, a `break` in the block can't leave it, so every
+    // iteration is counted.
+    fn hir_bench(
+        &mut self,
+        label: &str,
+        count: Option<&TypedExpr>,
+        block: &Block,
+        span: Span,
+        stmts: &mut hir::Block,
+    ) -> Result<(), BuildIRError> {
+        let uint64 = |kind| hir::Expr {
+            type_t: Type::UInt64,
+            kind,
+        };
+        let count = match count {
+            Some(count) => widen(self.hir_expr(count)?, &Type::UInt64),
+            None => uint64(ExprKind::Literal(ir::Value::UInt64(1))),
+        };
+        let n = self.symbols.symbols.len();
+        let mut local = |state: &mut Self, name: &str, value: hir::Expr| {
+            let ident = format!("_bench_{}_{}", name, n);
+            let id = state.declare(&ident, Type::UInt64, SymbolKind::Local, None);
+            stmts.push(hir::Stmt::Let(id, value));
+            id
+        };
+        let count = local(self, "count", count);
+        let i = local(self, "i", uint64(ExprKind::Literal(ir::Value::UInt64(0))));
+        // Started once the count is known, so evaluating it isn't timed
+        let now = uint64(ExprKind::Call(self.builtin_id("bench.now"), vec![]));
+        let start = local(self, "start", now.clone());
+        let var = |id| Box::new(uint64(ExprKind::Var(id)));
+        let outer_loops = std::mem::take(&mut self.loops);
+        let body = self.hir_block(block, "bench");
+        self.loops = outer_loops;
+        let mut body = body?;
+        let one = Box::new(uint64(ExprKind::Literal(ir::Value::UInt64(1))));
+        let next = uint64(ExprKind::Binary(BinOp::Add, var(i), one));
+        body.push(hir::Stmt::Assign(i, next, span));
+        let condition = hir::Expr {
+            type_t: Type::Bool,
+            kind: ExprKind::Binary(BinOp::Lt, var(i), var(count)),
+        };
+        stmts.push(hir::Stmt::While(condition, body, 1));
+        let elapsed = uint64(ExprKind::Binary(BinOp::Sub, Box::new(now), var(start)));
+        let args = vec![
+            hir::Expr {
+                type_t: Type::String,
+                kind: ExprKind::Literal(ir::Value::String(label.to_string())),
+            },
+            *var(count),
+            elapsed,
+        ];
+        let report = ExprKind::Call(self.builtin_id("bench.report"), args);
+        stmts.push(hir::Stmt::Expr(hir::Expr {
+            type_t: Type::Nil,
+            kind: report,
+        }));
+        Ok(())
+    }
 
-        let post_idents: Vec<IdentMapping> = self
-            .ast
-            .postblock
+    // The builtin itself, even where a name in scope shadows it
+    fn builtin_id(&self, ident: &str) -> SymbolId {
+        let found = self
+            .symbols
+            .symbols
             .iter()
-            .filter_map(|stmt| stmt.get_symbol())
-            .collect();
-
-        let base_node = self.stack.first_mut().expect("No base node!");
+            .position(|symbol| symbol.kind == SymbolKind::Builtin && symbol.ident == ident);
+        SymbolId(found.expect("builtins are declared before any block"))
+    }
 
-        for pre_ident in pre_idents {
-            base_node.table.insert(pre_ident.symbol, pre_ident.var);
+    // Static assertions leave nothing behind in the HIR
+    fn static_assert(&mut self, condition: &TypedExpr, message: &str) -> Result<(), BuildIRError> {
+        match consteval::eval(&self.hir_expr(condition)?) {
+            Some(ir::Value::Bool(true)) => Ok(()),
+            Some(_) => Err(BuildIRError::StaticAssertFailed(message.to_string())),
+            None => Err(BuildIRError::NotConstant),
         }
+    }
 
-        for post_ident in post_idents {
-            base_node.table.insert(post_ident.symbol, post_ident.var);
-        }
-        Ok(())
+    // Statements deferred in the blocks from the `from`th defer frame in,
+    // in the order they need to run
+    fn pending_defers(&self, from: usize) -> Vec<hir::Stmt> {
+        self.defers[from..]
+            .iter()
+            .rev()
+            .flat_map(|frame| frame.iter().rev().cloned())
+            .collect()
     }
 
-    fn binary_op(&mut self, operator: TypedExpr) -> Result<(), BuildIRError> {
-        // Resolve the signature of the function that should be added in the IR
-        let resolved_func = match operator.expr {
-            Expr::Add(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Add(ir::new_sig(
-                    "Add",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
-                ))
+    fn hir_stmt(&mut self, stmt: &Stmt) -> Result<hir::Stmt, BuildIRError> {
+        match stmt {
+            Stmt::Assign(symbol, var, expr, docs, span, extent) => {
+                let value = self.hir_expr(expr)?;
+                let kind = if self.scopes.len() == 1 {
+                    SymbolKind::Global
+                } else {
+                    SymbolKind::Local
+                };
+                let id = self.define(&symbol.ident, var.type_t.clone(), kind, docs, *span)?;
+                self.symbols.symbols[id.0].statement = Some(*extent).filter(|s| s.end > s.start);
+                let value = widen(value, &self.symbols.get(id).type_t);
+                Ok(hir::Stmt::Let(id, value))
             }
-            Expr::Sub(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Sub(ir::new_sig(
-                    "Sub",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
-                ))
+            Stmt::Declare(symbol, var, docs, span) => {
+                match &var.type_t {
+                    Type::Array(element_t, _)
+                        if element_t.is_numeric() || **element_t == Type::Bool => {}
+                    Type::Array(element_t, _) => {
+                        return Err(BuildIRError::ArrayElement(*element_t.clone()))
+                    }
+                    other => return Err(BuildIRError::ArrayElement(other.clone())),
+                }
+                let kind = if self.scopes.len() == 1 {
+                    SymbolKind::Global
+                } else {
+                    SymbolKind::Local
+                };
+                let id = self.define(&symbol.ident, var.type_t.clone(), kind, docs, *span)?;
+                Ok(hir::Stmt::Declare(id))
             }
-            Expr::Mult(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Mult(ir::new_sig(
-                    "Mult",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
-                ))
+            Stmt::Store(symbol, index, assign_op, expr, span) => {
+                let (id, element_t, index) = self.hir_index(symbol, *span, index)?;
+                let value = self.hir_expr(expr)?;
+                let op = match assign_op {
+                    AssignOp::Assign => None,
+                    AssignOp::AddAssign => Some(BinOp::Add),
+                    AssignOp::SubAssign => Some(BinOp::Sub),
+                    AssignOp::MultAssign => Some(BinOp::Mult),
+                    AssignOp::DivAssign => Some(BinOp::Div),
+                };
+                let value = match op {
+                    Some(_) => value,
+                    None => widen(value, &element_t),
+                };
+                Ok(hir::Stmt::Store(id, index, op, value, *span))
             }
-            Expr::Div(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Div(ir::new_sig(
-                    "Div",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
+            Stmt::Reassign(symbol, _, assign_op, expr, span) => {
+                let id = self.resolve(&symbol.ident, *span)?;
+                let type_t = self.symbols.get(id).type_t.clone();
+                let value = self.hir_expr(expr)?;
+                let op = match assign_op {
+                    AssignOp::Assign => {
+                        return Ok(hir::Stmt::Assign(id, widen(value, &type_t), *span))
+                    }
+                    AssignOp::AddAssign => BinOp::Add,
+                    AssignOp::SubAssign => BinOp::Sub,
+                    AssignOp::MultAssign => BinOp::Mult,
+                    AssignOp::DivAssign => BinOp::Div,
+                };
+                let current = hir::Expr {
+                    type_t: type_t.clone(),
+                    kind: ExprKind::Var(id),
+                };
+                Ok(hir::Stmt::Assign(
+                    id,
+                    hir::Expr {
+                        type_t,
+                        kind: ExprKind::Binary(op, Box::new(current), Box::new(value)),
+                    },
+                    *span,
                 ))
             }
-            Expr::Eq(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Eq(ir::new_sig(
-                    "Eq",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
-                ))
+            Stmt::MultiAssign(targets, values) => {
+                let mut pairs = vec![];
+                for ((symbol, span), value) in targets.iter().zip(values) {
+                    let id = self.resolve(&symbol.ident, *span)?;
+                    let value = self.hir_expr(value)?;
+                    pairs.push((id, widen(value, &self.symbols.get(id).type_t)));
+                }
+                Ok(hir::Stmt::MultiAssign(pairs))
             }
-            Expr::Neq(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Neq(ir::new_sig(
-                    "Neq",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
-                ))
+            Stmt::If(if_cases) => self.hir_if_cases(if_cases),
+            Stmt::Switch(scrutinee, cases, span) => self.hir_switch(scrutinee, cases, *span),
+            Stmt::Repeat(block, condition, label) => {
+                self.enter_loop(label)?;
+                let block = self.hir_block(block, "repeat");
+                self.loops.pop();
+                Ok(hir::Stmt::Repeat(block?, self.hir_expr(condition)?))
             }
-            Expr::Leq(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Leq(ir::new_sig(
-                    "Leq",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
-                ))
+            Stmt::While(condition, block, label) => {
+                let condition = self.hir_expr(condition)?;
+                self.enter_loop(label)?;
+                let block = self.hir_block(block, "while");
+                self.loops.pop();
+                Ok(hir::Stmt::While(condition, block?, 1))
             }
-            Expr::Geq(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Geq(ir::new_sig(
-                    "Geq",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
-                ))
+            Stmt::Break(label, _) => {
+                if self.loops.is_empty() {
+                    return Err(BuildIRError::BreakOutsideLoop);
+                }
+                let depth = match label {
+                    Some(label) => self
+                        .loops
+                        .iter()
+                        .rev()
+                        .position(|(l, _)| l.as_ref() == Some(label))
+                        .ok_or(BuildIRError::UnknownLabel(label.clone()))?,
+                    None => 0,
+                };
+                Ok(hir::Stmt::Break(depth))
             }
-            Expr::LessThan(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Lt(ir::new_sig(
-                    "Lt",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
-                ))
+            Stmt::Defer(_) => Err(BuildIRError::DeferOutsideBlock),
+            Stmt::StaticAssert(..) | Stmt::Attributed(..) | Stmt::Bench(..) => {
+                unreachable!("checked by the enclosing block, see hir_block_stmt")
             }
-            Expr::GreaterThan(mut lhs, mut rhs) => {
-                self.visit_expr(&mut lhs)?;
-                self.visit_expr(&mut rhs)?;
-                ir::Func::Gt(ir::new_sig(
-                    "Gt",
-                    vec![lhs.type_t.clone(), rhs.type_t.clone()],
-                    operator.type_t,
-                ))
+            Stmt::Static(stmt) => {
+                if self.return_t.is_none() {
+                    return Err(BuildIRError::StaticOutsideFunction);
+                }
+                let stmt = self.hir_stmt(stmt)?;
+                if let hir::Stmt::Let(id, value) = &stmt {
+                    // C requires the initializers of statics to be constant
+                    let literal = match &value.kind {
+                        ExprKind::Cast(inner) => &inner.kind,
+                        other => other,
+                    };
+                    if !matches!(literal, ExprKind::Literal(_)) {
+                        let ident = self.symbols.ident(*id).to_string();
+                        return Err(BuildIRError::StaticInitializer(ident));
+                    }
+                    self.symbols.symbols[id.0].kind = SymbolKind::Static;
+                }
+                Ok(stmt)
+            }
+            Stmt::Call(symbol, args, span) => {
+                let (id, func_type) = self.resolve_callee(symbol, *span)?;
+                let args = self.hir_args(id, args, &func_type.params_t, *span)?;
+                Ok(hir::Stmt::Expr(hir::Expr {
+                    type_t: *func_type.return_t,
+                    kind: ExprKind::Call(id, args),
+                }))
+            }
+            Stmt::FuncDef(func) => Ok(hir::Stmt::FuncDef(self.hir_func(func)?)),
+            Stmt::Return(expr) => {
+                let value = self.hir_expr(expr)?;
+                Ok(hir::Stmt::Return(match &self.return_t {
+                    Some(return_t) => widen(value, return_t),
+                    None => value,
+                }))
+            }
+        }
+    }
+
+    fn hir_if_cases(&mut self, if_cases: &IfCases) -> Result<hir::Stmt, BuildIRError> {
+        let mut cases = vec![];
+        for if_case in if_cases {
+            let condition = if if_case.is_else {
+                None
+            } else {
+                Some(self.hir_expr(&if_case.condition)?)
+            };
+            let block = self.hir_block(&if_case.block, "if")?;
+            cases.push(hir::IfCase {
+                condition,
+                block,
+                span: if_case.condition.span,
+            });
+        }
+        Ok(hir::Stmt::If(cases))
+    }
+
+    fn hir_switch(
+        &mut self,
+        scrutinee: &TypedExpr,
+        cases: &[SwitchCase],
+        span: Span,
+    ) -> Result<hir::Stmt, BuildIRError> {
+        let scrutinee = self.hir_expr(scrutinee)?;
+        if !scrutinee.type_t.is_integer() {
+            return Err(BuildIRError::SwitchType(scrutinee.type_t));
+        }
+        let mut seen: Vec<ir::Value> = vec![];
+        let mut hir_cases = vec![];
+        for case in cases {
+            let labels = if case.is_default {
+                if hir_cases
+                    .iter()
+                    .any(|c: &hir::SwitchCase| c.labels.is_none())
+                {
+                    return Err(BuildIRError::DuplicateDefault);
+                }
+                None
+            } else {
+                let mut labels = vec![];
+                for label in case.labels.iter() {
+                    // Labels are constants, such as `-1` or `1 << 4`, folded
+                    // to the values they compare with
+                    let value = match consteval::eval(&self.hir_expr(label)?) {
+                        Some(value) => value,
+                        None => return Err(BuildIRError::CaseLabel),
+                    };
+                    if seen.contains(&value) {
+                        return Err(BuildIRError::DuplicateCase(match value {
+                            ir::Value::Int32(n) => n.to_string(),
+                            ir::Value::Int64(n) => n.to_string(),
+                            ir::Value::UInt32(n) => n.to_string(),
+                            ir::Value::UInt64(n) => n.to_string(),
+                            other => format!("{:?}", other),
+                        }));
+                    }
+                    seen.push(value.clone());
+                    labels.push(value);
+                }
+                Some(labels)
+            };
+            let block = self.hir_block(&case.block, "case")?;
+            hir_cases.push(hir::SwitchCase { labels, block });
+        }
+        if !hir_cases.iter().any(|case| case.labels.is_none()) {
+            self.warnings.push(
+                Diagnostic::warning("switch has no `default` case")
+                    .with_span(span)
+                    .with_help("add `default then` to handle the remaining values"),
+            );
+        }
+        Ok(hir::Stmt::Switch(scrutinee, hir_cases))
+    }
+
+    fn hir_func(&mut self, func: &Func) -> Result<hir::Func, BuildIRError> {
+        let symbol = self.define(
+            &func.ident,
+            func_type(func),
+            SymbolKind::Function,
+            &func.docs,
+            func.span,
+        )?;
+        self.symbols.symbols[symbol.0].deprecated = deprecation(func)?;
+        let outer = std::mem::replace(&mut self.enclosing, func.ident.clone());
+        let outer_return_t = self.return_t.replace(func.return_t.clone());
+        // Loops can't be broken out of from a nested function
+        let outer_loops = std::mem::take(&mut self.loops);
+        let outer_defers = std::mem::take(&mut self.defers);
+        // Parameters share a scope with the function body
+        self.spush(format!("fun {}", func.ident));
+        let mut params = vec![];
+        for param in func.params.iter() {
+            let type_t = self.resolved_type(&param.type_t, || format!("`{}`", param.ident))?;
+            if let Type::Array(..) = type_t {
+                return Err(BuildIRError::ArrayValue(param.ident.clone()));
+            }
+            params.push(self.declare(&param.ident, type_t, SymbolKind::Param, Some(param.span)));
+        }
+        let block = self.hir_stmts(&func.block);
+        self.spop();
+        self.enclosing = outer;
+        self.return_t = outer_return_t;
+        self.loops = outer_loops;
+        self.defers = outer_defers;
+        let block = block?;
+        let intrinsic = intrinsic(func)?;
+        let external = func.is_extern() || intrinsic.is_some();
+        if external && !block.is_empty() {
+            return Err(BuildIRError::ExternBody(func.ident.clone()));
+        }
+        if func.return_t == Type::Never && !external && !diverges(&block) {
+            return Err(BuildIRError::MayReturn(func.ident.clone()));
+        }
+        Ok(hir::Func {
+            symbol,
+            params,
+            return_t: func.return_t.clone(),
+            block,
+            external,
+            intrinsic,
+        })
+    }
+
+    fn hir_lambda(&mut self, lf: &LambdaFunc) -> Result<hir::Lambda, BuildIRError> {
+        let outer_return_t = self.return_t.replace(lf.return_t.clone());
+        let outer_loops = std::mem::take(&mut self.loops);
+        let outer_defers = std::mem::take(&mut self.defers);
+        self.spush("lambda");
+        let mut params = vec![];
+        for param in lf.params.iter() {
+            let type_t = self.resolved_type(&param.type_t, || format!("`{}`", param.ident))?;
+            if let Type::Array(..) = type_t {
+                return Err(BuildIRError::ArrayValue(param.ident.clone()));
+            }
+            params.push(self.declare(&param.ident, type_t, SymbolKind::Param, Some(param.span)));
+        }
+        let block = self.hir_stmts(&lf.block);
+        self.spop();
+        self.return_t = outer_return_t;
+        self.loops = outer_loops;
+        self.defers = outer_defers;
+        Ok(hir::Lambda {
+            params,
+            return_t: self.resolved_type(&lf.return_t, || "a lambda's return value".into())?,
+            block: block?,
+        })
+    }
+
+    fn resolve_callee(
+        &mut self,
+        symbol: &Symbol,
+        span: Span,
+    ) -> Result<(SymbolId, FunctionType), BuildIRError> {
+        let id = self.resolve(&symbol.ident, span)?;
+        let allowed = self.allowed.iter().any(|lint| lint == "deprecated");
+        if let Some(reason) = self
+            .symbols
+            .get(id)
+            .deprecated
+            .as_ref()
+            .filter(|_| !allowed)
+        {
+            let mut message = format!("use of deprecated function `{}`", symbol.ident);
+            if !reason.is_empty() {
+                message = format!("{}: {}", message, reason);
+            }
+            self.warnings.push(
+                Diagnostic::warning(message)
+                    .with_span(span)
+                    .with_help("add `@allow(deprecated)` before the statement to silence this"),
+            );
+        }
+        match &self.symbols.get(id).type_t {
+            Type::Function(func) => Ok((id, func.clone())),
+            _ => Err(BuildIRError::NotCallable(symbol.ident.clone())),
+        }
+    }
+
+    fn hir_args(
+        &mut self,
+        callee: SymbolId,
+        args: &[Box<TypedExpr>],
+        params_t: &[Type],
+        call: Span,
+    ) -> Result<Vec<hir::Expr>, BuildIRError> {
+        if args.len() != params_t.len() {
+            let symbol = self.symbols.get(callee);
+            return Err(BuildIRError::Arity {
+                function: symbol.ident.clone(),
+                expected: params_t.len(),
+                found: args.len(),
+                call,
+                definition: symbol.span,
+            });
+        }
+        args.iter()
+            .zip(params_t)
+            .map(|(arg, param_t)| Ok(widen(self.hir_expr(arg)?, param_t)))
+            .collect()
+    }
+
+    fn hir_expr(&mut self, expr: &TypedExpr) -> Result<hir::Expr, BuildIRError> {
+        let entry = self.trace_enter(|| (expr_node(&expr.expr), Some(expr.span)));
+        let hir = self.hir_untraced_expr(expr)?;
+        self.trace_leave(entry, Some(&hir.type_t));
+        Ok(hir)
+    }
+
+    fn hir_untraced_expr(&mut self, expr: &TypedExpr) -> Result<hir::Expr, BuildIRError> {
+        let binary = |state: &mut Self, op, lhs: &TypedExpr, rhs: &TypedExpr| {
+            Ok::<ExprKind, BuildIRError>(ExprKind::Binary(
+                op,
+                Box::new(state.hir_expr(lhs)?),
+                Box::new(state.hir_expr(rhs)?),
+            ))
+        };
+        let kind = match &expr.expr {
+            Expr::Term(term) => return self.hir_term(term, expr.span),
+            Expr::Add(lhs, rhs) => binary(self, BinOp::Add, lhs, rhs)?,
+            Expr::Sub(lhs, rhs) => binary(self, BinOp::Sub, lhs, rhs)?,
+            Expr::Mult(lhs, rhs) => binary(self, BinOp::Mult, lhs, rhs)?,
+            Expr::Div(lhs, rhs) => binary(self, BinOp::Div, lhs, rhs)?,
+            Expr::Mod(lhs, rhs) => binary(self, BinOp::Mod, lhs, rhs)?,
+            Expr::Pow(lhs, rhs) => binary(self, BinOp::Pow, lhs, rhs)?,
+            Expr::BitAnd(lhs, rhs) => binary(self, BinOp::BitAnd, lhs, rhs)?,
+            Expr::BitOr(lhs, rhs) => binary(self, BinOp::BitOr, lhs, rhs)?,
+            Expr::BitXor(lhs, rhs) => binary(self, BinOp::BitXor, lhs, rhs)?,
+            Expr::Shl(lhs, rhs) => binary(self, BinOp::Shl, lhs, rhs)?,
+            Expr::Shr(lhs, rhs) => binary(self, BinOp::Shr, lhs, rhs)?,
+            Expr::Eq(lhs, rhs) => binary(self, BinOp::Eq, lhs, rhs)?,
+            Expr::Neq(lhs, rhs) => binary(self, BinOp::Neq, lhs, rhs)?,
+            Expr::Leq(lhs, rhs) => binary(self, BinOp::Leq, lhs, rhs)?,
+            Expr::Geq(lhs, rhs) => binary(self, BinOp::Geq, lhs, rhs)?,
+            Expr::LessThan(lhs, rhs) => binary(self, BinOp::Lt, lhs, rhs)?,
+            Expr::GreaterThan(lhs, rhs) => binary(self, BinOp::Gt, lhs, rhs)?,
+            Expr::And(lhs, rhs) => ExprKind::Logical(
+                LogicalOp::And,
+                Box::new(self.hir_expr(lhs)?),
+                Box::new(self.hir_expr(rhs)?),
+            ),
+            Expr::Or(lhs, rhs) => ExprKind::Logical(
+                LogicalOp::Or,
+                Box::new(self.hir_expr(lhs)?),
+                Box::new(self.hir_expr(rhs)?),
+            ),
+            Expr::Not(u) => ExprKind::Unary(UnOp::Not, Box::new(self.hir_expr(u)?)),
+            Expr::Neg(u) => ExprKind::Unary(UnOp::Neg, Box::new(self.hir_expr(u)?)),
+            Expr::BitNot(u) => ExprKind::Unary(UnOp::BitNot, Box::new(self.hir_expr(u)?)),
+            Expr::Call(symbol, args) => {
+                // A call expression starts with the callee's name
+                let span = Span::new(expr.span.start, expr.span.start + symbol.ident.len());
+                let (id, func_type) = self.resolve_callee(symbol, span)?;
+                ExprKind::Call(id, self.hir_args(id, args, &func_type.params_t, expr.span)?)
+            }
+            Expr::Index(symbol, span, index) => {
+                let (id, _, index) = self.hir_index(symbol, *span, index)?;
+                ExprKind::Index(id, Box::new(index))
+            }
+            Expr::LambdaFunc(lf) => ExprKind::Lambda(self.hir_lambda(lf)?),
+            Expr::SizeOf(type_t) => ExprKind::Literal(crate::ir::Value::UInt64(
+                type_t
+                    .size_of()
+                    .ok_or(BuildIRError::NoSize(type_t.clone()))?,
+            )),
+            Expr::TypeName(e) => {
+                let type_t = self.hir_expr(e)?.type_t;
+                ExprKind::Literal(crate::ir::Value::String(type_t.to_string()))
+            }
+            // A literal is converted here, so that it stays one, as the
+            // initializer of a static has to be
+            Expr::Convert(type_t, e) => {
+                let inner = self.hir_expr(e)?;
+                let literal = match &inner.kind {
+                    ExprKind::Literal(value) => value.convert(type_t),
+                    _ => None,
+                };
+                match literal {
+                    _ if inner.type_t == *type_t => inner.kind,
+                    Some(value) => ExprKind::Literal(value),
+                    None => ExprKind::Cast(Box::new(inner)),
+                }
             }
-            _ => panic!("Not sure how to represent {:?} in IR!", operator),
         };
-        self.build_stack.push(IRNode::Eval(resolved_func));
-        Ok(())
+        let type_t = self.resolved_type(&expr.type_t, || {
+            format!("an expression in `{}`", self.enclosing)
+        })?;
+        self.types.insert(expr.span, type_t.clone());
+        Ok(hir::Expr { type_t, kind })
     }
 
-    fn unary_op(&mut self, operator: TypedExpr) -> Result<(), BuildIRError> {
-        // Resolve the signature of the function that should be added in the IR
-        let resolved_func = match operator.expr {
-            Expr::Not(mut u) => {
-                self.visit_expr(&mut u)?;
-                ir::Func::Add(ir::new_sig("Not", vec![u.type_t.clone()], operator.type_t))
+    // Resolves the array indexed at `span`, giving its element type too.
+    // An index that's constant must be in range.
+    fn hir_index(
+        &mut self,
+        symbol: &Symbol,
+        span: Span,
+        index: &TypedExpr,
+    ) -> Result<(SymbolId, Type, hir::Expr), BuildIRError> {
+        let id = self.resolve(&symbol.ident, span)?;
+        let (element_t, length) = match &self.symbols.get(id).type_t {
+            Type::Array(element_t, length) => (*element_t.clone(), *length),
+            _ => {
+                return Err(BuildIRError::Unsupported(format!(
+                    "indexing `{}`",
+                    symbol.ident
+                )))
             }
-            Expr::Neg(mut u) => {
-                self.visit_expr(&mut u)?;
-                ir::Func::Add(ir::new_sig("Neg", vec![u.type_t.clone()], operator.type_t))
+        };
+        let index = self.hir_expr(index)?;
+        let constant = match consteval::eval(&index) {
+            Some(ir::Value::Int32(n)) => Some(n as i128),
+            Some(ir::Value::Int64(n)) => Some(n as i128),
+            Some(ir::Value::UInt32(n)) => Some(n as i128),
+            Some(ir::Value::UInt64(n)) => Some(n as i128),
+            _ => None,
+        };
+        match constant {
+            Some(n) if n < 0 || n >= length as i128 => Err(BuildIRError::IndexOutOfRange {
+                ident: symbol.ident.clone(),
+                index: n,
+                length,
+            }),
+            _ => Ok((id, element_t, index)),
+        }
+    }
+
+    fn hir_term(&mut self, term: &TypedTerm, span: Span) -> Result<hir::Expr, BuildIRError> {
+        let kind =
+            match &term.term {
+                Term::Expr(expr) => return self.hir_expr(expr),
+                Term::Id(ident) => ExprKind::Var(self.resolve(ident, span)?),
+                Term::Num(num, _) => ExprKind::Literal(num.clone().try_into().map_err(
+                    |err: crate::ast::ASTError| BuildIRError::SomeError(err.to_string()),
+                )?),
+                Term::Bool(b) => ExprKind::Literal(crate::ir::Value::Bool(*b)),
+                // A string ends at its first NUL, as C's do, so every
+                // backend sees only what's written before a `\0`
+                Term::String(s) => {
+                    let s = &s[..s.find('\0').unwrap_or(s.len())];
+                    ExprKind::Literal(crate::ir::Value::String(s.to_string()))
+                }
+            };
+        let type_t = match &kind {
+            ExprKind::Var(id) if matches!(self.symbols.get(*id).type_t, Type::Array(..)) => {
+                return Err(BuildIRError::ArrayValue(
+                    self.symbols.ident(*id).to_string(),
+                ))
             }
-            _ => panic!("Not sure how to represent {:?} in IR!", operator),
+            ExprKind::Var(id) => self.symbols.get(*id).type_t.clone(),
+            _ => self.resolved_type(&term.type_t, || {
+                format!("a literal in `{}`", self.enclosing)
+            })?,
         };
-        self.build_stack.push(IRNode::Eval(resolved_func));
-        Ok(())
+        self.types.insert(span, type_t.clone());
+        Ok(hir::Expr { type_t, kind })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arbitrary;
+    use crate::infer;
+    use crate::ir;
+    use crate::parser::tokens;
+    use crate::rascal;
+    use crate::symbol::new_symbol;
+    use proptest::prelude::*;
+
+    fn check(src: &str) -> ProgramState {
+        let mut state = ProgramState::new(typed(src));
+        state.build_ir().unwrap();
+        state
+    }
+
+    fn typed(src: &str) -> Box<Root> {
+        infer(rascal::RootParser::new().parse(tokens(src)).unwrap())
+    }
+
+    fn infer(mut root: Box<Root>) -> Box<Root> {
+        infer::TypingState::new().augment(&mut root).unwrap();
+        let mut infer_state = infer::InferState::new();
+        infer_state.constrain(&mut root).unwrap();
+        infer_state.resolve().unwrap();
+        infer::SubState::new(infer_state.get_type_mapping())
+            .substitute(&mut root)
+            .unwrap();
+        root
+    }
+
+    fn lower(src: &str) -> Vec<IRNode> {
+        check(src).build_stack
+    }
+
+    #[test]
+    fn record_expression_types_by_span() {
+        let src = "program p let x = 1u64; let y = (x + 2u64) == 3u64; return 0; end";
+        let state = check(src);
+        let at = |needle: &str| {
+            let offset = src.find(needle).unwrap();
+            state
+                .types
+                .type_at(offset)
+                .map(|(span, t)| (&src[span.start..span.end], t))
+        };
+        assert_eq!(at("1u64"), Some(("1u64", &Type::UInt64)));
+        assert_eq!(at("x +"), Some(("x", &Type::UInt64)));
+        assert_eq!(at("+ 2u64"), Some(("x + 2u64", &Type::UInt64)));
+        assert_eq!(at("== 3u64"), Some(("(x + 2u64) == 3u64", &Type::Bool)));
+        assert_eq!(at("let"), None);
+    }
+
+    #[test]
+    fn index_definitions_and_references() {
+        let src = "fun inc(n: int32) -> int32 return n + 1; end
+            program p let x = inc(2); x += inc(x); return x; end";
+        let state = check(src);
+        let text = |span: &Span| &src[span.start..span.end];
+        let symbol = |ident: &str| {
+            state
+                .symbols
+                .symbols
+                .iter()
+                .find(|s| s.ident == ident)
+                .unwrap()
+        };
+        let inc = symbol("inc");
+        assert_eq!(inc.kind, SymbolKind::Function);
+        assert_eq!(inc.span.as_ref().map(text), Some("inc"));
+        assert_eq!(inc.references.len(), 2);
+        assert!(inc.references.iter().all(|r| text(r) == "inc"));
+        let x = symbol("x");
+        assert_eq!(x.kind, SymbolKind::Local);
+        assert_eq!(x.references.len(), 3);
+        assert!(x.references.iter().all(|r| text(r) == "x"));
+        assert_eq!(symbol("n").kind, SymbolKind::Param);
+        assert_eq!(symbol("p").span.as_ref().map(text), Some("p"));
+    }
+
+    #[test]
+    fn record_scopes() {
+        let src = "fun f(n: int32) -> int32
+    if n > 2 then
+        let y = n - 1;
+        return y;
+    end
+    return n;
+end
+program p
+    let a = f(3);
+    repeat
+        a = a - 1;
+    until a < 0;
+    return a;
+end";
+        let state = check(src);
+        let expected = "\
+global
+  p: program, program at 8:9
+  f: (int32) -> int32, function at 1:5
+  fun f
+    n: int32, param at 1:7
+    if
+      y: int32, local at 3:13
+  program p
+    a: int32, local at 9:9
+";
+        assert_eq!(state.symbols.render_scopes(src), expected);
+        let scope = |ident: &str| {
+            let symbol = state.symbols.symbols.iter().find(|s| s.ident == ident);
+            &state.symbols.scopes[symbol.unwrap().scope.0]
+        };
+        assert_eq!(scope("y").name, "if");
+        assert_eq!(
+            state.symbols.scopes[scope("y").parent.unwrap().0].name,
+            "fun f"
+        );
+    }
+
+    #[test]
+    fn resolve_shadowed_names_to_distinct_symbols() {
+        let state = check(
+            "let x = 1;
+            program p
+              fun f(x: int32) -> int32
+                return x;
+              end
+              return f(x);
+            end",
+        );
+        let module = state.hir.unwrap();
+        let global = match &module.globals[0] {
+            hir::Stmt::Let(id, _) => *id,
+            other => panic!("expected a let, found {:?}", other),
+        };
+        let (param, returned) = match &module.program.as_ref().unwrap().block[0] {
+            hir::Stmt::FuncDef(func) => match &func.block[0] {
+                hir::Stmt::Return(hir::Expr {
+                    kind: ExprKind::Var(id),
+                    ..
+                }) => (func.params[0], *id),
+                other => panic!("expected a return, found {:?}", other),
+            },
+            other => panic!("expected a function, found {:?}", other),
+        };
+        assert_eq!(param, returned);
+        assert_ne!(param, global);
+        assert_eq!(module.symbols.get(global).kind, SymbolKind::Global);
+        assert_eq!(module.symbols.get(param).kind, SymbolKind::Param);
+    }
+
+    #[test]
+    fn lower_unicode_identifiers_and_strings() {
+        let ir = lower("program p let 名前 = \"héllo, 世界\"; return 0; end");
+        assert!(ir.contains(&IRNode::Term(ir::Term {
+            type_t: Type::String,
+            value: ir::Value::String("héllo, 世界".into()),
+        })));
+        assert!(ir.contains(&IRNode::Assign(ir::Assign {
+            type_t: Type::String,
+            symbol: new_symbol("名前".into()),
+        })));
+    }
+
+    #[test]
+    fn call_functions_before_their_definition() {
+        let nodes = lower(
+            "fun even(n: int32) -> bool
+                 if n == 0 then return true; end
+                 return odd(n - 1);
+             end
+             program p
+                 let x = later();
+                 fun later() -> int32 return 1; end
+                 return x;
+             end
+             fun odd(n: int32) -> bool
+                 if n == 0 then return false; end
+                 return even(n - 1);
+             end",
+        );
+        // Only functions nested in a block are declared in the IR
+        let declared: Vec<&str> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                IRNode::FuncDecl(def) => Some(def.symbol.ident.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(declared, ["later"]);
+        let program = nodes.iter().position(|node| *node == IRNode::Program);
+        let decl = nodes
+            .iter()
+            .position(|node| matches!(node, IRNode::FuncDecl(_)));
+        assert_eq!(decl, program.map(|n| n + 1));
+    }
+
+    #[test]
+    fn lower_operands_left_to_right() {
+        let assigned = |src: &str| -> Vec<String> {
+            lower(src)
+                .into_iter()
+                .filter_map(|node| match node {
+                    IRNode::Assign(assign) => Some(assign.symbol.ident),
+                    _ => None,
+                })
+                .collect()
+        };
+        let funcs = "let g = 0; fun f() -> int32 g = g + 1; return g; end
+            fun two(a: int32, b: int32) -> int32 return a - b; end";
+        // Only the earlier calls need a temporary
+        let temps = assigned(&format!(
+            "{} program p let l = 1; let x = two(f(), f()) + l; return x; end",
+            funcs
+        ));
+        assert_eq!(temps.len(), 4);
+        assert!(temps[2].starts_with("_eval_"));
+        // Globals read after a call are read after it
+        let temps = assigned(&format!(
+            "{} program p let x = f() - g; return x; end",
+            funcs
+        ));
+        assert!(temps[1].starts_with("_eval_"));
+        let temps = assigned(&format!(
+            "{} program p let l = 1; let x = l + f(); return x; end",
+            funcs
+        ));
+        assert_eq!(temps.len(), 3);
+    }
+
+    #[test]
+    fn flatten_expressions_into_temporaries() {
+        let src = "let g = 1 + 2 * 3;
+            program p let a = 1; let x = (a + 2) * (a - 3); return x; end";
+        let mut state = ProgramState::new(typed(src));
+        state.flatten_exprs = true;
+        state.build_ir().unwrap();
+        let temps: Vec<String> = state
+            .build_stack
+            .into_iter()
+            .filter_map(|node| match node {
+                IRNode::Assign(assign) => Some(assign.symbol.ident),
+                _ => None,
+            })
+            .collect();
+        // Every operation but the outermost gets its own temporary, except
+        // in the initializer of the global
+        assert_eq!(temps.len(), 5);
+        assert!(temps[2..4].iter().all(|temp| temp.starts_with("_eval_")));
+    }
+
+    proptest! {
+        // Inference is slow on large programs, keep the run short
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        // Type checking the generated programs succeeds, so lowering them
+        // must too
+        #[test]
+        fn lower_arbitrary_programs(root in arbitrary::root(), flatten in any::<bool>()) {
+            let mut state = ProgramState::new(infer(root));
+            state.flatten_exprs = flatten;
+            let built = state.build_ir();
+            prop_assert!(built.is_ok(), "{:?}", built);
+        }
+    }
+
+    #[test]
+    fn lower_logical_operators_to_branches() {
+        let ir = lower(
+            "program p let a = 10; let b = 0;
+                if b != 0 and a / b > 1 then return 1; end return 0; end",
+        );
+        let position = |found: fn(&IRNode) -> bool| ir.iter().position(found).unwrap();
+        let division = position(|node| matches!(node, IRNode::Eval(ir::Func::Div(_))));
+        // The division is only reached through the branch on the left
+        // operand, and the if statement tests the result
+        let branches: Vec<usize> = ir
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| matches!(node, IRNode::IfCase(_)))
+            .map(|(n, _)| n)
+            .collect();
+        assert_eq!(branches.len(), 2);
+        assert!(branches[0] < division && division < branches[1]);
+        assert!(position(|node| matches!(node, IRNode::Eval(ir::Func::Neq(_)))) < branches[0]);
+    }
+
+    #[test]
+    fn check_never_functions_diverge() {
+        let build = |body: &str| {
+            let src = format!(
+                "fun fail(code: int32) -> never {} end program p return 0; end",
+                body
+            );
+            ProgramState::new(typed(&src)).build_ir()
+        };
+        assert!(build("exit(code);").is_ok());
+        assert!(build("if code > 0 then exit(code); else then exit(1); end").is_ok());
+        assert!(matches!(
+            build("if code > 0 then exit(code); end"),
+            Err(BuildIRError::MayReturn(ident)) if ident == "fail"
+        ));
+    }
+
+    #[test]
+    fn check_call_arity() {
+        let src = "fun add(a: int32, b: int32) -> int32 return a + b; end
+            program p return add(1); end";
+        let err = ProgramState::new(typed(src)).build_ir().unwrap_err();
+        let diagnostic = err.diagnostic();
+        assert_eq!(
+            diagnostic.message,
+            "`add` takes 2 arguments but 1 was given"
+        );
+        let text = |span: Span| &src[span.start..span.end];
+        assert_eq!(diagnostic.span.map(text), Some("add(1)"));
+        assert_eq!(diagnostic.label.as_deref(), Some("expected 2 arguments"));
+        assert_eq!(diagnostic.secondary.len(), 1);
+        assert_eq!(text(diagnostic.secondary[0].span), "add");
+        assert!(src[diagnostic.secondary[0].span.start..].starts_with("add(a"));
+    }
+
+    #[test]
+    fn trace_analysis_as_a_tree() {
+        let src = "fun add(a: int32, b: int32) -> int32
+    return a + b;
+end
+program p
+    return add(1, (2));
+end";
+        let mut state = ProgramState::new(typed(src));
+        state.trace = Some(vec![]);
+        state.build_ir().unwrap();
+        let expected = "\
+fun add 1:5
+  return 2:12
+    + 2:12: int32
+      var a 2:12: int32
+      var b 2:16: int32
+return 5:12
+  call add 5:12: int32
+    literal 5:16: int32
+    ( ) 5:19: int32
+      literal 5:20: int32
+";
+        assert_eq!(state.render_trace(src), expected);
+
+        // The analysis stops in the entry left without a type
+        let src = "program p let x = 1; return add(x); end fun add(a: int32, b: int32) -> int32 return a; end";
+        let mut state = ProgramState::new(typed(src));
+        state.trace = Some(vec![]);
+        assert!(state.build_ir().is_err());
+        assert!(state
+            .render_trace(src)
+            .ends_with("return 1:29\n  call add 1:29\n"));
+    }
+
+    #[test]
+    fn check_switch_cases() {
+        let build = |cases: &str| {
+            let src = format!("program p let x = 1; switch x {} end return 0; end", cases);
+            let mut state = ProgramState::new(typed(&src));
+            state.build_ir().map(|_| state.warnings.len())
+        };
+        assert_eq!(
+            build("case 1, 2 then x = 3; default then x = 4;").ok(),
+            Some(0)
+        );
+        assert_eq!(build("case 1 then x = 3;").ok(), Some(1));
+        assert!(matches!(
+            build("case 1 then x = 3; case 2, 1 then x = 4;"),
+            Err(BuildIRError::DuplicateCase(label)) if label == "1"
+        ));
+        assert!(matches!(
+            build("default then x = 3; default then x = 4;"),
+            Err(BuildIRError::DuplicateDefault)
+        ));
+        assert_eq!(
+            build("case -1, 1 << 4 then x = 3; default then x = 4;").ok(),
+            Some(0)
+        );
+        assert!(matches!(
+            build("case -1 then x = 3; case 0 - 1 then x = 4;"),
+            Err(BuildIRError::DuplicateCase(label)) if label == "-1"
+        ));
+        assert!(matches!(
+            build("case x then x = 3;"),
+            Err(BuildIRError::CaseLabel)
+        ));
+    }
+
+    #[test]
+    fn check_arrays() {
+        let build = |body: &str| {
+            let src = format!("program p let xs: array[int32, 4]; {} return 0; end", body);
+            ProgramState::new(typed(&src)).build_ir()
+        };
+        assert!(build("let i = 3; xs[i] = 1; xs[0] += xs[i];").is_ok());
+        assert!(matches!(
+            build("xs[4] = 1;"),
+            Err(BuildIRError::IndexOutOfRange {
+                index: 4,
+                length: 4,
+                ..
+            })
+        ));
+        assert!(matches!(
+            build("let y = xs[1 - 2];"),
+            Err(BuildIRError::IndexOutOfRange { index: -1, .. })
+        ));
+        assert!(matches!(
+            build("let ys = xs;"),
+            Err(BuildIRError::ArrayValue(ident)) if ident == "xs"
+        ));
+        assert!(matches!(
+            build("let ss: array[string, 2];"),
+            Err(BuildIRError::ArrayElement(Type::String))
+        ));
+    }
+
+    #[test]
+    fn check_intrinsics() {
+        let build = |decl: &str| {
+            let src = format!("{} end program p return 0; end", decl);
+            ProgramState::new(typed(&src)).build_ir()
+        };
+        assert!(build("@intrinsic(\"sqrt\") fun root(x: float64) -> float64").is_ok());
+        assert!(matches!(
+            build("@intrinsic(\"sqrt\") fun root(x: float32) -> float64"),
+            Err(BuildIRError::IntrinsicSignature { function, expected, .. })
+                if function == "root" && expected.to_string() == "(float64) -> float64"
+        ));
+        assert!(matches!(
+            build("@intrinsic(\"printf\") fun p(x: float64) -> float64"),
+            Err(BuildIRError::UnknownIntrinsic(ident)) if ident == "printf"
+        ));
+        assert!(matches!(
+            build("@intrinsic fun p(x: float64) -> float64"),
+            Err(BuildIRError::UnknownIntrinsic(ident)) if ident.is_empty()
+        ));
+        assert!(matches!(
+            build("@intrinsic(\"fabs\") fun abs(x: float64) -> float64 return x;"),
+            Err(BuildIRError::ExternBody(ident)) if ident == "abs"
+        ));
+    }
+
+    #[test]
+    fn resolve_loop_labels() {
+        let build = |body: &str| {
+            let src = format!("program p let x = 1; {} return 0; end", body);
+            ProgramState::new(typed(&src)).build_ir()
+        };
+        assert!(build("a: repeat repeat break a; until true; until true;").is_ok());
+        assert!(matches!(
+            build("break;"),
+            Err(BuildIRError::BreakOutsideLoop)
+        ));
+        assert!(matches!(
+            build("a: repeat break b; until true;"),
+            Err(BuildIRError::UnknownLabel(label)) if label == "b"
+        ));
+        assert!(matches!(
+            build("a: repeat a: repeat x = 2; until true; until true;"),
+            Err(BuildIRError::DuplicateLabel(label)) if label == "a"
+        ));
+        assert!(matches!(
+            build("repeat fun f() -> int32 break; end until true;"),
+            Err(BuildIRError::BreakOutsideLoop)
+        ));
+        // Nor can it leave a bench, which would cut its count short
+        assert!(matches!(
+            build("repeat bench \"b\" do break; end until true;"),
+            Err(BuildIRError::BreakOutsideLoop)
+        ));
+    }
+
+    #[test]
+    fn bench_times_only_its_runs() {
+        let state = check(
+            "fun runs() -> uint64 return 3u64; end
+             program p bench \"b\", runs() do end return 0; end",
+        );
+        // The clock starts once the count has been evaluated
+        let ir = ir::render(&state.build_stack, false);
+        assert!(ir.find("runs()\n").unwrap() < ir.find("bench.now()").unwrap());
+        // and the helpers it reports with are out of the program's reach
+        let src = "program p bench_report(\"b\", 1u64, 2u64); return 0; end";
+        assert!(crate::compile::analyze(src, &Default::default()).is_err());
+    }
+
+    #[test]
+    fn desugar_deferred_statements() {
+        let state = check(
+            "program p let x = 1; defer x = 2; if x > 0 then defer x = 3; end
+                defer x = 4; return x; end",
+        );
+        let module = state.hir.unwrap();
+        let assigned = |stmt: &hir::Stmt| match stmt {
+            hir::Stmt::Assign(
+                _,
+                hir::Expr {
+                    kind: ExprKind::Literal(ir::Value::Int32(n)),
+                    ..
+                },
+                _,
+            ) => Some(*n),
+            _ => None,
+        };
+        let block = &module.program.as_ref().unwrap().block;
+        match &block[1] {
+            hir::Stmt::If(cases) => assert_eq!(assigned(&cases[0].block[0]), Some(3)),
+            other => panic!("expected an if, found {:?}", other),
+        }
+        assert!(matches!(block[2], hir::Stmt::Let(..)));
+        let deferred: Vec<_> = block[3..5].iter().map(assigned).collect();
+        assert_eq!(deferred, [Some(4), Some(2)]);
+        assert!(matches!(block[5], hir::Stmt::Return(_)));
+        assert_eq!(block.len(), 6);
+
+        let build = |body: &str| {
+            let src = format!("program p let x = 1; {} return 0; end", body);
+            ProgramState::new(typed(&src)).build_ir()
+        };
+        assert!(matches!(
+            build("defer return x;"),
+            Err(BuildIRError::NotDeferrable("return"))
+        ));
+    }
+
+    #[test]
+    fn check_static_locals() {
+        let build = |body: &str| {
+            let src = format!(
+                "fun f(n: int32) -> int32 {} return n; end program p return f(1); end",
+                body
+            );
+            let mut state = ProgramState::new(typed(&src));
+            state.build_ir().map(|_| state)
+        };
+        let state = build("static let calls = 0; calls += 1;").unwrap();
+        assert!(state.build_stack.iter().any(|node| matches!(
+            node,
+            IRNode::StaticAssign(ir::Assign { symbol, .. }) if symbol.ident == "calls"
+        )));
+        assert!(matches!(
+            build("static let calls = n;"),
+            Err(BuildIRError::StaticInitializer(ident)) if ident == "calls"
+        ));
+        let src = "program p static let calls = 0; return 0; end";
+        assert!(matches!(
+            ProgramState::new(typed(src)).build_ir(),
+            Err(BuildIRError::StaticOutsideFunction)
+        ));
+    }
+
+    #[test]
+    fn check_static_assertions() {
+        let build = |asserts: &str| {
+            let src = format!("{} program p let x = 1; return 0; end", asserts);
+            ProgramState::new(typed(&src)).build_ir()
+        };
+        assert!(build("static_assert(size_of(uint32) * 2u64 == 8u64, \"sized\");").is_ok());
+        // Unsuffixed literals take the type of the operand they're beside
+        assert!(build("static_assert(size_of(int64) == 8, \"sized\");").is_ok());
+        assert!(build("static_assert(2 * size_of(uint32) == (8), \"sized\");").is_ok());
+        assert!(build("static_assert(int32(2.5) + 1 < 4, \"converted\");").is_ok());
+        assert!(matches!(
+            build("static_assert(size_of(int32) == 8, \"sized\");"),
+            Err(BuildIRError::StaticAssertFailed(message)) if message == "sized"
+        ));
+        assert!(matches!(
+            build("static_assert(1 > 2, \"ordered\");"),
+            Err(BuildIRError::StaticAssertFailed(message)) if message == "ordered"
+        ));
+        assert!(matches!(
+            build("let y = 1; static_assert(y == 1, \"read\");"),
+            Err(BuildIRError::NotConstant)
+        ));
+    }
+
+    #[test]
+    fn warn_on_deprecated_calls() {
+        let src = "/// Doubles n
+            @deprecated(\"use `twice`\") fun dbl(n: int32) -> int32 return n * 2; end
+            program p let a = dbl(1); @allow(deprecated) let b = dbl(a); return b; end";
+        let state = check(src);
+        assert_eq!(state.warnings.len(), 1);
+        let warning = &state.warnings[0];
+        assert_eq!(
+            warning.message,
+            "use of deprecated function `dbl`: use `twice`"
+        );
+        let span = warning.span.unwrap();
+        assert_eq!(span.start, src.find("dbl(1)").unwrap());
+        assert_eq!(&src[span.start..span.end], "dbl");
+
+        let src = "@inline fun f() -> int32 return 1; end program p return f(); end";
+        assert!(matches!(
+            ProgramState::new(typed(src)).build_ir(),
+            Err(BuildIRError::UnknownAttribute(name)) if name == "inline"
+        ));
+    }
+
+    #[test]
+    fn lint_dead_stores_and_constant_conditions() {
+        let src = "program p
+                let x = 1; x = 2; x = x + 1;
+                if 2 > 1 then x = 0; else if x > 1 then x = 3; end
+                return x;
+            end";
+        let state = check(src);
+        let warnings: Vec<_> = state
+            .warnings
+            .iter()
+            .map(|w| (w.message.as_str(), w.span.unwrap().start))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (
+                    "value assigned to `x` is never read",
+                    src.find("x = 1").unwrap()
+                ),
+                ("condition is always true", src.find("2 > 1").unwrap()),
+            ]
+        );
     }
 
-    fn ins_label(&mut self, label: String) {
-        self.build_stack.push(IRNode::Label(ir::Label(label)));
+    #[test]
+    fn lint_unused_variables() {
+        let src = "fun f(n: int32) -> int32 return n; end
+            program p
+                /// Never read
+                let x = 1 + 2 * 3;
+                let y = f(1);
+                let z = 8 / (2 - 2);
+                return 0;
+            end";
+        let state = check(src);
+        let fixed = |ident: &str| {
+            let warning = state
+                .warnings
+                .iter()
+                .find(|w| w.message == format!("unused variable `{}`", ident))
+                .unwrap();
+            crate::diagnostics::apply_suggestions(src, std::slice::from_ref(warning)).0
+        };
+        // A `let` computing its value for nothing is removed, doc
+        // comments and all
+        assert!(!fixed("x").contains("Never read"));
+        assert!(!fixed("x").contains("let x"));
+        // but one with an effect, like a call or a trap, is kept
+        assert!(fixed("y").contains("let _y = f(1);"));
+        assert!(fixed("z").contains("let _z = 8 / (2 - 2);"));
+    }
+
+    #[test]
+    fn fold_introspection_to_constants() {
+        let ir = lower(
+            "program p let n = size_of(int32) + size_of(string);
+                let t = type_name(n == 12u64); return 0; end",
+        );
+        for (type_t, value) in [
+            (Type::UInt64, ir::Value::UInt64(4)),
+            (Type::UInt64, ir::Value::UInt64(8)),
+            (Type::String, ir::Value::String("bool".into())),
+        ] {
+            assert!(ir.contains(&IRNode::Term(ir::Term { type_t, value })));
+        }
     }
 }
@@ -0,0 +1,63 @@
+use crate::parser;
+use std::fmt;
+
+// Editions let the language change in ways that would break existing
+// programs. A program keeps the semantics of the edition it was written
+// for, and opts into the newer ones by moving to a later edition. The
+// default is the edition every existing program compiles under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Edition {
+    // Conditions may be numbers, which hold when they aren't zero
+    #[default]
+    E2024,
+    // Conditions must be bools
+    E2025,
+}
+
+impl Edition {
+    pub fn bool_conditions(&self) -> bool {
+        *self >= Edition::E2025
+    }
+}
+
+impl fmt::Display for Edition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Edition::E2024 => write!(f, "2024"),
+            Edition::E2025 => write!(f, "2025"),
+        }
+    }
+}
+
+// Options for a whole build that change which programs are accepted
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    pub edition: Edition,
+    // Reject anything that's only accepted for compatibility: warnings
+    // become errors, numbers are never widened implicitly and conditions
+    // must be bools
+    pub strict: bool,
+    // Disable implicit numeric widening, see Type::widens_to
+    pub strict_numerics: bool,
+    // How deeply statements and expressions can nest, `None` for
+    // parser::NESTING_LIMIT
+    pub nesting_limit: Option<usize>,
+}
+
+impl Session {
+    pub fn strict_numerics(&self) -> bool {
+        self.strict || self.strict_numerics
+    }
+
+    // Strict builds follow the rules of the latest edition
+    pub fn edition(&self) -> Edition {
+        match self.strict {
+            true => self.edition.max(Edition::E2025),
+            false => self.edition,
+        }
+    }
+
+    pub fn nesting_limit(&self) -> usize {
+        self.nesting_limit.unwrap_or(parser::NESTING_LIMIT)
+    }
+}
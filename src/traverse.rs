@@ -7,10 +7,13 @@ pub trait Traverse {
             preblock,
             program,
             postblock,
+            ..
         } = root;
         self.visit_preblock(preblock)?;
         self.visit_postblock(postblock)?;
-        self.visit_program(program)?;
+        if let Some(program) = program {
+            self.visit_program(program)?;
+        }
         Ok(())
     }
 
@@ -36,9 +39,42 @@ pub trait Traverse {
     fn visit_stmt(&mut self, stmt: &mut Stmt) -> Result<(), Self::Error> {
         match stmt {
             Stmt::If(cases) => self.visit_if_cases(cases),
-            Stmt::Assign(symbol, var, expr) => self.visit_expr(expr),
-            Stmt::Reassign(symbol, var, assign_op, expr) => self.visit_expr(expr),
-            Stmt::Call(symbol, args) => self.visit_args(args),
+            Stmt::Assign(symbol, var, expr, ..) => self.visit_expr(expr),
+            Stmt::Reassign(symbol, var, assign_op, expr, _) => self.visit_expr(expr),
+            Stmt::MultiAssign(_, values) => self.visit_args(values),
+            Stmt::Declare(..) => Ok(()),
+            Stmt::Store(_, index, _, value, _) => {
+                self.visit_expr(index)?;
+                self.visit_expr(value)
+            }
+            Stmt::Repeat(block, condition, _) => {
+                self.visit_block(block)?;
+                self.visit_expr(condition)
+            }
+            Stmt::While(condition, block, _) => {
+                self.visit_expr(condition)?;
+                self.visit_block(block)
+            }
+            Stmt::Bench(_, count, block, _) => {
+                if let Some(count) = count {
+                    self.visit_expr(count)?;
+                }
+                self.visit_block(block)
+            }
+            Stmt::Break(..) => Ok(()),
+            Stmt::StaticAssert(condition, ..) => self.visit_expr(condition),
+            Stmt::Defer(stmt) | Stmt::Static(stmt) | Stmt::Attributed(_, stmt) => {
+                self.visit_stmt(stmt)
+            }
+            Stmt::Switch(scrutinee, cases, _) => {
+                self.visit_expr(scrutinee)?;
+                for case in cases {
+                    self.visit_args(&mut case.labels)?;
+                    self.visit_block(&mut case.block)?;
+                }
+                Ok(())
+            }
+            Stmt::Call(symbol, args, _) => self.visit_args(args),
             Stmt::FuncDef(func) => self.visit_func(func),
             Stmt::Return(expr) => self.visit_expr(expr),
         }
@@ -61,7 +97,17 @@ pub trait Traverse {
                 self.visit_expr(&mut lhs)?;
                 self.visit_expr(&mut rhs)?;
             }
-            Expr::Div(mut lhs, mut rhs) => {
+            Expr::Div(mut lhs, mut rhs)
+            | Expr::Mod(mut lhs, mut rhs)
+            | Expr::Pow(mut lhs, mut rhs) => {
+                self.visit_expr(&mut lhs)?;
+                self.visit_expr(&mut rhs)?;
+            }
+            Expr::BitAnd(mut lhs, mut rhs)
+            | Expr::BitOr(mut lhs, mut rhs)
+            | Expr::BitXor(mut lhs, mut rhs)
+            | Expr::Shl(mut lhs, mut rhs)
+            | Expr::Shr(mut lhs, mut rhs) => {
                 self.visit_expr(&mut lhs)?;
                 self.visit_expr(&mut rhs)?;
             }
@@ -89,15 +135,26 @@ pub trait Traverse {
                 self.visit_expr(&mut lhs)?;
                 self.visit_expr(&mut rhs)?;
             }
+            Expr::And(mut lhs, mut rhs) | Expr::Or(mut lhs, mut rhs) => {
+                self.visit_expr(&mut lhs)?;
+                self.visit_expr(&mut rhs)?;
+            }
             Expr::Not(mut u) => {
                 self.visit_expr(&mut u)?;
             }
-            Expr::Neg(mut u) => {
+            Expr::Neg(mut u) | Expr::BitNot(mut u) => {
                 self.visit_expr(&mut u)?;
             }
             Expr::Call(_, mut args) => {
                 self.visit_args(&mut args)?;
             }
+            Expr::Index(_, _, mut index) => {
+                self.visit_expr(&mut index)?;
+            }
+            Expr::SizeOf(_) => {}
+            Expr::TypeName(mut e) | Expr::Convert(_, mut e) => {
+                self.visit_expr(&mut e)?;
+            }
             Expr::LambdaFunc(mut lf) => {
                 self.visit_lambda_func(&mut lf)?;
             }
@@ -109,7 +166,7 @@ pub trait Traverse {
         match term.term.clone() {
             Term::Expr(mut expr) => self.visit_expr(&mut expr),
             Term::Id(_) => Ok(()),
-            Term::Num(_) => Ok(()),
+            Term::Num(..) => Ok(()),
             Term::Bool(_) => Ok(()),
             Term::String(_) => Ok(()),
         }
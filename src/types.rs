@@ -1,3 +1,8 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::abi;
+use crate::ast::Span;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
@@ -11,8 +16,12 @@ pub enum Type {
     Float32,
     String,
     Bool,
+    // The return type of functions that never return, such as `exit`
+    Never,
     // User defined types
     Function(FunctionType),
+    // A fixed number of elements of the type, only held by variables
+    Array(Box<Type>, usize),
     Program,
     // Compiler and existence
     Unknown,
@@ -25,3 +34,130 @@ pub struct FunctionType {
     pub params_t: Vec<Type>,
     pub return_t: Box<Type>,
 }
+
+// Types are displayed as they're written in Rascal source
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int64 => write!(f, "int64"),
+            Type::Int32 => write!(f, "int32"),
+            Type::UInt64 => write!(f, "uint64"),
+            Type::UInt32 => write!(f, "uint32"),
+            Type::Float64 => write!(f, "float64"),
+            Type::Float32 => write!(f, "float32"),
+            Type::String => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Never => write!(f, "never"),
+            Type::Function(func) => {
+                let params: Vec<String> = func.params_t.iter().map(|p| p.to_string()).collect();
+                write!(f, "({}) -> {}", params.join(", "), func.return_t)
+            }
+            Type::Array(element_t, length) => write!(f, "array[{}, {}]", element_t, length),
+            Type::Program => write!(f, "program"),
+            Type::Unknown | Type::TypeVar(_) => write!(f, "_"),
+            Type::Nil => write!(f, "Nil"),
+        }
+    }
+}
+
+impl Type {
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Type::Int64 | Type::Int32 | Type::UInt64 | Type::UInt32 | Type::Float64 | Type::Float32
+        )
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Type::Int64 | Type::Int32 | Type::UInt64 | Type::UInt32
+        )
+    }
+
+    // The least and greatest values of an integer type
+    pub fn integer_range(&self) -> Option<(i128, i128)> {
+        match self {
+            Type::Int32 => Some((i32::MIN.into(), i32::MAX.into())),
+            Type::Int64 => Some((i64::MIN.into(), i64::MAX.into())),
+            Type::UInt32 => Some((0, u32::MAX.into())),
+            Type::UInt64 => Some((0, u64::MAX.into())),
+            _ => None,
+        }
+    }
+
+    // Size in bytes of a value of this type as laid out by the C
+    // backend on 64 bit targets, where strings and functions are
+    // pointers. Types without runtime values have no size.
+    pub fn size_of(&self) -> Option<u64> {
+        match self {
+            Type::Array(element_t, length) => element_t.size_of().map(|size| size * *length as u64),
+            _ => abi::classify(self).and_then(|mode| mode.size()),
+        }
+    }
+
+    // The numeric widening lattice. A value converts implicitly to a
+    // wider type only when every value of its own type is exactly
+    // representable in the wider one:
+    //
+    //   int32   -> int64, float64
+    //   uint32  -> uint64, float64
+    //   float32 -> float64
+    //
+    // Widening applies where a value flows into a declared type
+    // (arguments, returns, lets and assignments), never between the
+    // operands of an operator, and is disabled by --strict-numerics.
+    pub fn widens_to(&self, to: &Type) -> bool {
+        matches!(
+            (self, to),
+            (Type::Int32, Type::Int64 | Type::Float64)
+                | (Type::UInt32, Type::UInt64 | Type::Float64)
+                | (Type::Float32, Type::Float64)
+        )
+    }
+}
+
+// The checked type of every expression in a program, keyed by the span
+// of source text the expression was parsed from. Parenthesized
+// expressions are recorded under the span of their contents.
+#[derive(Debug, Clone, Default)]
+pub struct TypeMap {
+    types: BTreeMap<Span, Type>,
+}
+
+impl TypeMap {
+    pub const TYPES_OUTPUT_FILENAME: &'static str = "out.types";
+
+    pub fn new() -> TypeMap {
+        TypeMap::default()
+    }
+
+    pub fn insert(&mut self, span: Span, type_t: Type) -> Option<Type> {
+        self.types.insert(span, type_t)
+    }
+
+    pub fn get(&self, span: Span) -> Option<&Type> {
+        self.types.get(&span)
+    }
+
+    // The innermost expression containing the byte at `offset`
+    pub fn type_at(&self, offset: usize) -> Option<(Span, &Type)> {
+        self.types
+            .iter()
+            .filter(|(span, _)| span.contains(offset))
+            .min_by_key(|(span, _)| span.len())
+            .map(|(span, type_t)| (*span, type_t))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Span, &Type)> {
+        self.types.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+}